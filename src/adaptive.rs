@@ -0,0 +1,179 @@
+//! Adaptive Embedding Module
+//!
+//! This module computes content-adaptive embedding costs in the style of HILL
+//! (Li et al., 2014). Each colour plane is filtered with a 3x3 high-pass
+//! kernel; the magnitude of the residual is averaged over a small window and
+//! inverted, and the result is smoothed over a wide window. Changes in flat
+//! areas and smooth gradients, where the residual is near zero, get a very
+//! high cost, while textured areas and edges are cheap.
+//!
+//! The costs only steer the matrix embedder's choice of which bits to flip,
+//! so extraction reads the payload without them.
+
+use crate::matrix;
+use crate::{Error, Result};
+use image::RgbaImage;
+
+/// Default minimum code parameter for adaptive embedding
+pub const DEFAULT_STEALTH: u8 = 3;
+
+/// Half-width of the window the residual magnitudes are averaged over
+const RESIDUAL_RADIUS: usize = 1;
+
+/// Half-width of the window the costs are smoothed over
+const SPREAD_RADIUS: usize = 7;
+
+/// Keeps the cost of perfectly flat areas finite
+const EPSILON: f32 = 0.01;
+
+/// Reads the `stealth` parameter (1-12, default 3)
+///
+/// The stealth level is the smallest Hamming code the payload may use, so at
+/// most `s` bits are embedded per `2^s - 1` channels. Higher levels lower the
+/// capacity but leave the embedder more choice of where to make changes.
+pub fn stealth_from_parameters(
+    parameters: &std::collections::HashMap<String, String>,
+) -> Result<u8> {
+    match parameters.get("stealth") {
+        None => Ok(DEFAULT_STEALTH),
+        Some(value) => value
+            .parse::<u8>()
+            .ok()
+            .filter(|stealth| (1..=matrix::MAX_K).contains(stealth))
+            .ok_or_else(|| {
+                Error::InvalidInput(format!(
+                    "Stealth must be between 1 and {}, got {}",
+                    matrix::MAX_K,
+                    value
+                ))
+            }),
+    }
+}
+
+/// Bytes that fit in `slots` channels at stealth level `stealth`
+pub fn capacity(slots: usize, stealth: u8) -> usize {
+    slots / matrix::group_size(stealth) * stealth as usize / 8
+}
+
+/// Computes the cost of changing each channel of an image
+///
+/// The result holds one cost per R, G and B channel, indexed as
+/// `pixel * 3 + channel`. The lowest bit is ignored, so embedding in it does
+/// not change the costs.
+pub fn channel_costs(image: &RgbaImage) -> Vec<f32> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let raw: &[u8] = image.as_raw();
+    let mut costs = vec![0.0; width * height * 3];
+
+    for channel in 0..3 {
+        let plane: Vec<f32> = (0..width * height)
+            .map(|pixel| (raw[pixel * 4 + channel] & !1) as f32)
+            .collect();
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, width as isize - 1) as usize;
+            let y = y.clamp(0, height as isize - 1) as usize;
+            plane[y * width + x]
+        };
+
+        // High-pass residual (the KB kernel), as a magnitude
+        let mut residual = vec![0.0; width * height];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let edges = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1);
+                let corners =
+                    at(x - 1, y - 1) + at(x + 1, y - 1) + at(x - 1, y + 1) + at(x + 1, y + 1);
+                residual[y as usize * width + x as usize] =
+                    (2.0 * edges - corners - 4.0 * at(x, y)).abs();
+            }
+        }
+
+        let inverse: Vec<f32> = box_mean(&residual, width, height, RESIDUAL_RADIUS)
+            .into_iter()
+            .map(|magnitude| 1.0 / (magnitude + EPSILON))
+            .collect();
+        for (pixel, cost) in box_mean(&inverse, width, height, SPREAD_RADIUS)
+            .into_iter()
+            .enumerate()
+        {
+            costs[pixel * 3 + channel] = cost;
+        }
+    }
+
+    costs
+}
+
+/// Averages a plane over a square window, shrinking the window at the borders
+fn box_mean(plane: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    // Summed-area table with a zero row and column in front
+    let stride = width + 1;
+    let mut sums = vec![0.0f64; stride * (height + 1)];
+    for y in 0..height {
+        for x in 0..width {
+            sums[(y + 1) * stride + x + 1] =
+                plane[y * width + x] as f64 + sums[y * stride + x + 1] + sums[(y + 1) * stride + x]
+                    - sums[y * stride + x];
+        }
+    }
+
+    let mut result = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let (top, bottom) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            let (left, right) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let sum = sums[bottom * stride + right]
+                - sums[top * stride + right]
+                - sums[bottom * stride + left]
+                + sums[top * stride + left];
+            result.push((sum / ((bottom - top) * (right - left)) as f64) as f32);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_channel_costs_prefer_texture() {
+        // Left half a smooth gradient, right half noise
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let image = RgbaImage::from_fn(64, 32, |x, y| {
+            if x < 32 {
+                Rgba([(x * 4) as u8, (y * 4) as u8, 128, 255])
+            } else {
+                Rgba([rng.gen(), rng.gen(), rng.gen(), 255])
+            }
+        });
+        let costs = channel_costs(&image);
+        assert_eq!(costs.len(), 64 * 32 * 3);
+
+        let cost = |x: usize, y: usize| costs[(y * 64 + x) * 3];
+        assert!(cost(16, 16) > 100.0 * cost(52, 16));
+
+        // Changing the lowest bits leaves the costs as they were
+        let mut flipped = image.clone();
+        for pixel in flipped.pixels_mut() {
+            pixel.0[0] ^= 1;
+        }
+        assert_eq!(channel_costs(&flipped), costs);
+    }
+
+    #[test]
+    fn test_stealth_parameter() {
+        let mut parameters = std::collections::HashMap::new();
+        assert_eq!(stealth_from_parameters(&parameters).unwrap(), 3);
+        parameters.insert("stealth".to_string(), "5".to_string());
+        assert_eq!(stealth_from_parameters(&parameters).unwrap(), 5);
+        for invalid in ["0", "13", "high"] {
+            parameters.insert("stealth".to_string(), invalid.to_string());
+            assert!(stealth_from_parameters(&parameters).is_err());
+        }
+
+        assert_eq!(capacity(7 * 8, 3), 3);
+        assert!(capacity(100_000, 5) < capacity(100_000, 2));
+    }
+}
@@ -0,0 +1,350 @@
+//! Audio Module
+//!
+//! This module provides functionality for working with WAV audio files.
+//!
+//! Data is hidden in the least significant bits of the PCM samples. Integer
+//! samples (8, 16, 24 and 32-bit) are modified directly, while 32-bit float
+//! samples carry the data in the low bits of their mantissa. Channels are
+//! treated as one interleaved sample stream, so mono and multichannel files
+//! share the same layout.
+//!
+//! The payload header is always written at one bit per sample into the first
+//! samples, followed by the body at the bit depth recorded in the header.
+
+use crate::container::{Container, PayloadHeader, HEADER_LENGTH};
+use crate::utils::{embed_bits, extract_bits, validate_bit_depth};
+use crate::{Error, Result};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use sha2::{Digest, Sha256};
+
+/// Handler for WAV operations
+pub struct WavHandler {
+    spec: WavSpec,
+    samples: Vec<i32>,
+}
+
+impl WavHandler {
+    /// Creates a new WAV handler
+    pub fn new(path: &str) -> Result<Self> {
+        let mut reader = WavReader::open(path)
+            .map_err(|e| Error::InvalidInput(format!("Failed to load WAV: {}", e)))?;
+        let spec = reader.spec();
+
+        let samples = match (spec.sample_format, spec.bits_per_sample) {
+            (SampleFormat::Int, 8 | 16 | 24 | 32) => reader
+                .samples::<i32>()
+                .collect::<std::result::Result<Vec<_>, _>>(),
+            // Float samples are handled through their bit pattern so that only
+            // the lowest mantissa bits are touched
+            (SampleFormat::Float, 32) => reader
+                .samples::<f32>()
+                .map(|s| s.map(|v| v.to_bits() as i32))
+                .collect::<std::result::Result<Vec<_>, _>>(),
+            (format, bits) => {
+                return Err(Error::InvalidInput(format!(
+                    "Unsupported WAV sample format: {}-bit {:?}",
+                    bits, format
+                )))
+            }
+        }
+        .map_err(|e| Error::InvalidInput(format!("Failed to read WAV samples: {}", e)))?;
+
+        Ok(Self { spec, samples })
+    }
+
+    /// Returns the format description of the loaded file
+    pub fn spec(&self) -> WavSpec {
+        self.spec
+    }
+
+    /// Calculates how many body bytes fit in the file at the given bit depth,
+    /// excluding the payload header
+    pub fn capacity(&self, bit_depth: u8) -> usize {
+        self.samples.len().saturating_sub(HEADER_LENGTH * 8) * bit_depth as usize / 8
+    }
+
+    /// Computes a digest of the audio that embedding at `bit_depth` leaves unchanged
+    ///
+    /// The low `bit_depth` bits of every sample are masked out; the format
+    /// description is hashed as well.
+    pub fn carrier_digest(&self, bit_depth: u8) -> [u8; 32] {
+        let mask = (-1i32).checked_shl(bit_depth as u32).unwrap_or(0);
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.spec.channels.to_be_bytes());
+        hasher.update(self.spec.sample_rate.to_be_bytes());
+        hasher.update(self.spec.bits_per_sample.to_be_bytes());
+        for &sample in &self.samples {
+            hasher.update((sample & mask).to_be_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Embeds a payload container into the audio samples
+    pub fn embed_data(&mut self, container: &Container, bit_depth: u8) -> Result<()> {
+        self.validate_bit_depth(bit_depth, Error::InvalidInput)?;
+
+        let capacity = self.capacity(bit_depth);
+        if container.body.len() > capacity {
+            return Err(Error::InvalidInput(format!(
+                "Data too large for audio with bit depth {}. Maximum capacity: {} bytes",
+                bit_depth, capacity
+            )));
+        }
+
+        // Embed the header first, one bit per sample
+        let mut header = container.header.clone();
+        header.bit_depth = bit_depth;
+        embed_bits(
+            &mut self.samples,
+            &consecutive(HEADER_LENGTH, 1),
+            &header.to_bytes(),
+            1,
+        );
+
+        // Embed the body after the header samples
+        let body_samples = &mut self.samples[HEADER_LENGTH * 8..];
+        let offsets = consecutive(container.body.len(), bit_depth);
+        embed_bits(body_samples, &offsets, &container.body, bit_depth);
+
+        Ok(())
+    }
+
+    /// Extracts an embedded payload container from the audio samples
+    pub fn extract_data(&self) -> Result<Container> {
+        if self.samples.len() < HEADER_LENGTH * 8 {
+            return Err(Error::InvalidData(
+                "Audio is too short to hold a payload header".into(),
+            ));
+        }
+
+        // First extract the header (one bit per sample)
+        let mut header_bytes = [0u8; HEADER_LENGTH];
+        extract_bits(
+            &self.samples,
+            &consecutive(HEADER_LENGTH, 1),
+            &mut header_bytes,
+            1,
+        );
+        let header = PayloadHeader::from_bytes(&header_bytes)?;
+        self.validate_bit_depth(header.bit_depth, Error::InvalidData)?;
+
+        let capacity = self.capacity(header.bit_depth);
+        let length = header.readable_length(capacity).ok_or_else(|| {
+            Error::InvalidData(format!(
+                "Data length ({}) exceeds audio capacity ({})",
+                header.payload_length, capacity
+            ))
+        })?;
+
+        // Extract the body that follows the header samples
+        let body_samples = &self.samples[HEADER_LENGTH * 8..];
+        let mut body = vec![0u8; length];
+        let offsets = consecutive(length, header.bit_depth);
+        extract_bits(body_samples, &offsets, &mut body, header.bit_depth);
+        let soft_bits = soft_bits(body_samples, length * 8, header.bit_depth);
+
+        Ok(Container {
+            header,
+            body,
+            soft_bits: Some(soft_bits),
+        })
+    }
+
+    /// Saves the WAV to a file, keeping the original sample format
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut writer = WavWriter::create(path, self.spec)
+            .map_err(|e| Error::Io(format!("Failed to create WAV: {}", e)))?;
+
+        for &sample in &self.samples {
+            let written = match (self.spec.sample_format, self.spec.bits_per_sample) {
+                (SampleFormat::Int, 8) => writer.write_sample(sample as i8),
+                (SampleFormat::Int, 16) => writer.write_sample(sample as i16),
+                (SampleFormat::Int, _) => writer.write_sample(sample),
+                (SampleFormat::Float, _) => writer.write_sample(f32::from_bits(sample as u32)),
+            };
+            written.map_err(|e| Error::Io(format!("Failed to write WAV sample: {}", e)))?;
+        }
+
+        writer
+            .finalize()
+            .map_err(|e| Error::Io(format!("Failed to save WAV: {}", e)))
+    }
+
+    fn validate_bit_depth(&self, bit_depth: u8, error: fn(String) -> Error) -> Result<()> {
+        validate_bit_depth(bit_depth, error)?;
+        if bit_depth as u16 >= self.spec.bits_per_sample {
+            return Err(error(format!(
+                "Bit depth {} leaves no sample bits in {}-bit audio",
+                bit_depth, self.spec.bits_per_sample
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Offsets of the consecutive samples that carry `length` bytes at `bit_depth`
+fn consecutive(length: usize, bit_depth: u8) -> Vec<usize> {
+    (0..(length * 8).div_ceil(bit_depth as usize)).collect()
+}
+
+/// Rates every bit read by [`extract_bits`] by its distance from the decision boundary
+///
+/// A bit flips once its sample moves past the nearest value where that bit
+/// changes, and the number of unit steps to get there is its reliability. The
+/// lowest bit is always one step from flipping; a higher bit in the middle of
+/// its run of equal values takes up to `2^(position - 1)`.
+fn soft_bits(samples: &[i32], bit_count: usize, bit_depth: u8) -> Vec<f32> {
+    let bit_depth = bit_depth as usize;
+
+    (0..bit_count)
+        .map(|stream_bit| {
+            let sample = samples[stream_bit / bit_depth];
+            let position = bit_depth - 1 - stream_bit % bit_depth;
+            let run = 1i32 << position;
+            let offset = sample & (run - 1);
+            let steps = offset.min(run - 1 - offset) + 1;
+            match (sample >> position) & 1 {
+                1 => steps as f32,
+                _ => -(steps as f32),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::EccScheme;
+    use crate::integrity::INTEGRITY_TAG_LENGTH;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use tempfile::tempdir;
+
+    fn write_test_wav(path: &str, spec: WavSpec, frames: usize) {
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for i in 0..frames * spec.channels as usize {
+            let phase = i as f32 * 0.05;
+            match (spec.sample_format, spec.bits_per_sample) {
+                (SampleFormat::Float, _) => writer.write_sample(phase.sin() * 0.5).unwrap(),
+                (SampleFormat::Int, 8) => writer.write_sample((phase.sin() * 100.0) as i8).unwrap(),
+                (SampleFormat::Int, 16) => writer
+                    .write_sample((phase.sin() * 20_000.0) as i16)
+                    .unwrap(),
+                (SampleFormat::Int, _) => writer
+                    .write_sample((phase.sin() * 4_000_000.0) as i32)
+                    .unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn test_wav_embed_extract_all_formats() {
+        let dir = tempdir().unwrap();
+        let test_data = b"This is a test message for WAV steganography";
+        let container = Container::seal(test_data, None, EccScheme::None).unwrap();
+
+        let formats = [
+            (1, 8, SampleFormat::Int),
+            (2, 16, SampleFormat::Int),
+            (2, 24, SampleFormat::Int),
+            (6, 32, SampleFormat::Int),
+            (2, 32, SampleFormat::Float),
+        ];
+
+        for (channels, bits_per_sample, sample_format) in formats {
+            let spec = WavSpec {
+                channels,
+                sample_rate: 44_100,
+                bits_per_sample,
+                sample_format,
+            };
+            let input = dir.path().join("input.wav");
+            let output = dir.path().join("output.wav");
+            write_test_wav(input.to_str().unwrap(), spec, 1_000);
+
+            for bit_depth in 1..=4 {
+                let mut handler = WavHandler::new(input.to_str().unwrap()).unwrap();
+                handler.embed_data(&container, bit_depth).unwrap();
+                handler.save(output.to_str().unwrap()).unwrap();
+
+                let handler = WavHandler::new(output.to_str().unwrap()).unwrap();
+                assert_eq!(handler.spec(), spec);
+                let extracted = handler.extract_data().unwrap();
+                assert_eq!(extracted.header.bit_depth, bit_depth);
+                assert_eq!(extracted.open(None).unwrap(), test_data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wav_soft_bits_repair_noise() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("input.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 44_100,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        write_test_wav(input.to_str().unwrap(), spec, 4_000);
+
+        let data: Vec<u8> = (0..200).map(|i| (i * 7 % 256) as u8).collect();
+        let container = Container::seal(&data, None, EccScheme::Convolutional).unwrap();
+        let mut handler = WavHandler::new(input.to_str().unwrap()).unwrap();
+        handler.embed_data(&container, 4).unwrap();
+
+        // Reliabilities agree with the hard bits
+        let extracted = handler.extract_data().unwrap();
+        let soft_bits = extracted.soft_bits.as_ref().unwrap();
+        assert_eq!(soft_bits.len(), extracted.body.len() * 8);
+        for (i, &soft) in soft_bits.iter().enumerate() {
+            assert_eq!(soft > 0.0, (extracted.body[i / 8] >> (7 - i % 8)) & 1 == 1);
+        }
+
+        // Nudge one body sample in twenty by one step. Hard decisions lose too
+        // many bits, while the reliabilities mark the lowest ones as doubtful
+        let mut rng = ChaCha8Rng::seed_from_u64(16);
+        for sample in &mut handler.samples[HEADER_LENGTH * 8..] {
+            if rng.gen_bool(0.05) {
+                *sample += if rng.gen() { 1 } else { -1 };
+            }
+        }
+        let mut extracted = handler.extract_data().unwrap();
+        let soft = extracted.clone().open(None);
+        extracted.soft_bits = None;
+        let hard = extracted.open(None);
+        assert_eq!(soft.unwrap(), data);
+        assert!(hard.is_err());
+    }
+
+    #[test]
+    fn test_wav_capacity_exceeded() {
+        let dir = tempdir().unwrap();
+        let input = dir.path().join("small.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        // Room for a four-byte message after its checksum
+        let capacity = INTEGRITY_TAG_LENGTH + 4;
+        write_test_wav(
+            input.to_str().unwrap(),
+            spec,
+            HEADER_LENGTH * 8 + capacity * 8,
+        );
+
+        let mut handler = WavHandler::new(input.to_str().unwrap()).unwrap();
+        assert_eq!(handler.capacity(1), capacity);
+        let too_long = Container::seal(b"too long", None, EccScheme::None).unwrap();
+        assert!(matches!(
+            handler.embed_data(&too_long, 1),
+            Err(Error::InvalidInput(_))
+        ));
+        let fits = Container::seal(b"fits", None, EccScheme::None).unwrap();
+        assert!(handler.embed_data(&fits, 1).is_ok());
+    }
+}
@@ -1,13 +1,19 @@
+use base64ct::{Base64, Encoding};
 use clap::{Parser, Subcommand};
 use nhale::{
     embedding::{embed_data, EmbedConfig, EmbeddingConfig, MediaType},
-    encryption::{Algorithm, CryptoConfig},
-    extraction::ExtractConfig,
-    utils::{detect_file_format, FileFormat},
+    encryption::{
+        generate_rsa_keypair, recipient_algorithm, Algorithm, CryptoConfig, Kdf, X25519KeyPair,
+        DEFAULT_ARGON2_ITERATIONS, DEFAULT_ARGON2_MEMORY_KIB, DEFAULT_ARGON2_PARALLELISM,
+        DEFAULT_PBKDF2_ITERATIONS,
+    },
+    extraction::{extract_with_report_as, ExtractConfig, SignatureStatus},
+    integrity::{Ed25519KeyPair, Verification},
+    utils::{sniff_file_format, FileFormat},
     Error, Result,
 };
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[clap(version = env!("CARGO_PKG_VERSION"), author = "nHale Contributors")]
@@ -36,22 +42,56 @@ enum Commands {
         #[clap(short, long)]
         password: Option<String>,
 
-        /// Encryption algorithm (aes256, chacha20, or rsa)
-        #[clap(short, long, default_value = "aes256")]
+        /// Encryption algorithm (aes256-gcm, chacha20-poly1305, aes256, chacha20, or rsa)
+        #[clap(short, long, default_value = "aes256-gcm")]
         algorithm: String,
 
+        /// Recipient public key file (RSA or X25519); may be repeated, and any
+        /// recipient can extract. Implies public-key encryption
+        #[clap(long)]
+        recipient_key: Vec<PathBuf>,
+
+        /// Ed25519 secret key file used to sign the payload
+        #[clap(long)]
+        signing_key: Option<PathBuf>,
+
+        /// File holding a secret key to authenticate the payload with; by
+        /// default the password is used
+        #[clap(long)]
+        integrity_key: Option<PathBuf>,
+
         /// Force a specific file format
         #[clap(short, long)]
         format: Option<String>,
 
+        /// Password key derivation function (argon2id or pbkdf2)
+        #[clap(long, default_value = "argon2id")]
+        kdf: String,
+
+        /// Argon2id memory cost in KiB
+        #[clap(long)]
+        kdf_memory: Option<u32>,
+
+        /// Argon2id passes or PBKDF2 iterations
+        #[clap(long)]
+        kdf_iterations: Option<u32>,
+
+        /// Argon2id parallelism (lanes)
+        #[clap(long)]
+        kdf_parallelism: Option<u8>,
+
         /// LSB bit depth for image steganography (1-4, default: 1)
         #[clap(long, default_value = "1")]
         bit_depth: u8,
 
-        /// Compression level for data (0-9, default: 6)
+        /// Compression level for data (0-9, default: 6; 0 disables compression)
         #[clap(long, default_value = "6")]
         compression: u8,
 
+        /// Compression algorithm (deflate or zstd)
+        #[clap(long, default_value = "deflate")]
+        compression_algorithm: String,
+
         /// Advanced configuration options in key=value format
         #[clap(short, long, value_parser = parse_key_val)]
         config: Vec<(String, String)>,
@@ -67,18 +107,27 @@ enum Commands {
         #[clap(short, long)]
         password: Option<String>,
 
-        /// Encryption algorithm (aes256, chacha20, or rsa)
-        #[clap(short, long, default_value = "aes256")]
-        algorithm: String,
+        /// Encryption algorithm, only needed for legacy payloads without a header
+        #[clap(short, long)]
+        algorithm: Option<String>,
+
+        /// Private key file (RSA or X25519) for payloads encrypted to recipient keys
+        #[clap(long)]
+        identity: Option<PathBuf>,
+
+        /// Ed25519 public key file of a trusted signer; may be repeated. When
+        /// given, only payloads signed by one of these keys are accepted
+        #[clap(long)]
+        trusted_key: Vec<PathBuf>,
+
+        /// File holding the key the payload was authenticated with
+        #[clap(long)]
+        integrity_key: Option<PathBuf>,
 
         /// Force a specific file format
         #[clap(short, long)]
         format: Option<String>,
 
-        /// LSB bit depth for image steganography (1-4, default: 1)
-        #[clap(long, default_value = "1")]
-        bit_depth: u8,
-
         /// Output file (optional, otherwise print to stdout)
         #[clap(short, long)]
         output: Option<PathBuf>,
@@ -88,6 +137,21 @@ enum Commands {
         config: Vec<(String, String)>,
     },
 
+    /// Generate a key pair for public-key encryption
+    Keygen {
+        /// Secret key file; the public key is written next to it with a `.pub` suffix
+        #[clap(short, long)]
+        output: PathBuf,
+
+        /// Key type (x25519, rsa, or ed25519 for signing)
+        #[clap(short, long, default_value = "x25519")]
+        algorithm: String,
+
+        /// RSA modulus size in bits
+        #[clap(long, default_value = "3072")]
+        bits: usize,
+    },
+
     /// Create watermark
     Watermark {
         /// Input file
@@ -134,6 +198,74 @@ enum Commands {
     },
 }
 
+/// Parse a file format name
+fn parse_format(s: &str) -> Result<FileFormat> {
+    match s.to_lowercase().as_str() {
+        "png" => Ok(FileFormat::Png),
+        "jpg" | "jpeg" => Ok(FileFormat::Jpg),
+        "bmp" => Ok(FileFormat::Bmp),
+        "gif" => Ok(FileFormat::Gif),
+        "wav" => Ok(FileFormat::Wav),
+        "mp3" => Ok(FileFormat::Mp3),
+        "mp4" => Ok(FileFormat::Mp4),
+        "pdf" => Ok(FileFormat::Pdf),
+        _ => Err(Error::InvalidInput("Unsupported file format".into())),
+    }
+}
+
+/// Parse an encryption algorithm name
+fn parse_algorithm(s: &str) -> Result<Algorithm> {
+    match s {
+        "aes256-gcm" => Ok(Algorithm::Aes256Gcm),
+        "chacha20-poly1305" => Ok(Algorithm::ChaCha20Poly1305),
+        "aes256" => Ok(Algorithm::Aes256),
+        "chacha20" => Ok(Algorithm::ChaCha20),
+        "rsa" => Ok(Algorithm::Rsa),
+        _ => Err(Error::InvalidInput(format!("Invalid algorithm: {}", s))),
+    }
+}
+
+/// Build a key derivation function from its name and optional cost overrides
+fn parse_kdf(
+    s: &str,
+    memory: Option<u32>,
+    iterations: Option<u32>,
+    parallelism: Option<u8>,
+) -> Result<Kdf> {
+    let kdf = match s {
+        "argon2id" => Kdf::Argon2id {
+            memory_kib: memory.unwrap_or(DEFAULT_ARGON2_MEMORY_KIB),
+            iterations: iterations.unwrap_or(DEFAULT_ARGON2_ITERATIONS),
+            parallelism: parallelism.unwrap_or(DEFAULT_ARGON2_PARALLELISM),
+        },
+        "pbkdf2" => Kdf::Pbkdf2 {
+            iterations: iterations.unwrap_or(DEFAULT_PBKDF2_ITERATIONS),
+        },
+        _ => return Err(Error::InvalidInput(format!("Invalid KDF: {}", s))),
+    };
+    kdf.validate()?;
+    Ok(kdf)
+}
+
+/// Read a PEM or DER key file
+fn read_key_file(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path)
+        .map_err(|e| Error::Io(format!("Failed to read key file {}: {}", path.display(), e)))
+}
+
+/// Write a secret key file readable only by its owner
+fn write_secret_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, contents))
+        .map_err(|e| Error::Io(format!("Failed to write secret key: {}", e)))
+}
+
 /// Parse a key-value pair in the format "key=value"
 fn parse_key_val(s: &str) -> Result<(String, String)> {
     let pos = s.find('=').ok_or_else(|| {
@@ -155,9 +287,17 @@ fn main() -> Result<()> {
             data,
             password,
             algorithm,
+            recipient_key,
+            signing_key,
+            integrity_key,
             format,
+            kdf,
+            kdf_memory,
+            kdf_iterations,
+            kdf_parallelism,
             bit_depth,
             compression,
+            compression_algorithm,
             config,
         } => {
             if !(1..=4).contains(&bit_depth) {
@@ -174,36 +314,37 @@ fn main() -> Result<()> {
                 ));
             }
 
-            let file_format = if let Some(fmt) = format {
-                match fmt.to_lowercase().as_str() {
-                    "png" => FileFormat::Png,
-                    "jpg" | "jpeg" => FileFormat::Jpg,
-                    "bmp" => FileFormat::Bmp,
-                    "gif" => FileFormat::Gif,
-                    "wav" => FileFormat::Wav,
-                    "mp3" => FileFormat::Mp3,
-                    "mp4" => FileFormat::Mp4,
-                    "pdf" => FileFormat::Pdf,
-                    _ => return Err(Error::InvalidInput("Unsupported file format".into())),
-                }
-            } else {
-                detect_file_format(&input)
+            let forced_format = format.as_deref().map(parse_format).transpose()?;
+            let file_format = match forced_format {
+                Some(file_format) => file_format,
+                None => sniff_file_format(&input)?,
             };
 
-            let encryption = password.map(|pass| CryptoConfig {
-                algorithm: match algorithm.as_str() {
-                    "aes256" => Algorithm::Aes256,
-                    "chacha20" => Algorithm::ChaCha20,
-                    "rsa" => Algorithm::Rsa,
-                    _ => panic!("Invalid algorithm"),
-                },
-                password: pass,
-            });
+            let kdf = parse_kdf(&kdf, kdf_memory, kdf_iterations, kdf_parallelism)?;
+            let recipients = recipient_key
+                .iter()
+                .map(|path| read_key_file(path))
+                .collect::<Result<Vec<_>>>()?;
+            let encryption = match (password, recipients.first()) {
+                (_, Some(first)) => Some(CryptoConfig {
+                    algorithm: recipient_algorithm(first)?,
+                    recipients,
+                    ..Default::default()
+                }),
+                (Some(pass), None) => Some(CryptoConfig {
+                    algorithm: parse_algorithm(&algorithm)?,
+                    password: pass,
+                    kdf,
+                    ..Default::default()
+                }),
+                (None, None) => None,
+            };
 
             // Create a parameters map
             let mut parameters = HashMap::new();
             parameters.insert("bit_depth".to_string(), bit_depth.to_string());
             parameters.insert("compression".to_string(), compression.to_string());
+            parameters.insert("compression_algorithm".to_string(), compression_algorithm);
 
             // Add any additional config parameters
             for (key, value) in config {
@@ -222,7 +363,7 @@ fn main() -> Result<()> {
                 },
                 use_encryption: encryption.is_some(),
                 password: encryption.as_ref().map(|c| c.password.clone()),
-                parameters,
+                parameters: parameters.clone(),
             };
 
             let config = EmbedConfig {
@@ -230,15 +371,22 @@ fn main() -> Result<()> {
                 output_path: output.to_str().unwrap().to_string(),
                 data: data.into_bytes(),
                 encryption,
+                parameters: Some(parameters),
+                signing_key: signing_key.as_deref().map(read_key_file).transpose()?,
+                integrity_key: integrity_key.as_deref().map(read_key_file).transpose()?,
             };
 
-            match file_format {
-                FileFormat::Pdf => embed_data(config)?,
-                FileFormat::Png => nhale::embedding::embed_in_png(config)?,
-                FileFormat::Jpg => nhale::embedding::embed_in_jpg(config)?,
-                FileFormat::Wav => nhale::embedding::embed_in_wav(config)?,
-                FileFormat::Mp3 => nhale::embedding::embed_in_mp3(config)?,
-                FileFormat::Mp4 => nhale::embedding::embed_in_mp4(config)?,
+            // Without a forced format the library detects it from the file contents
+            match forced_format {
+                None => embed_data(config)?,
+                Some(FileFormat::Pdf) => nhale::embedding::embed_in_pdf(config)?,
+                Some(FileFormat::Png) => nhale::embedding::embed_in_png(config)?,
+                Some(FileFormat::Jpg) => nhale::embedding::embed_in_jpg(config)?,
+                Some(FileFormat::Bmp) => nhale::embedding::embed_in_bmp(config)?,
+                Some(FileFormat::Gif) => nhale::embedding::embed_in_gif(config)?,
+                Some(FileFormat::Wav) => nhale::embedding::embed_in_wav(config)?,
+                Some(FileFormat::Mp3) => nhale::embedding::embed_in_mp3(config)?,
+                Some(FileFormat::Mp4) => nhale::embedding::embed_in_mp4(config)?,
                 _ => return Err(Error::InvalidInput("Unsupported file format".into())),
             }
 
@@ -250,67 +398,76 @@ fn main() -> Result<()> {
             input,
             password,
             algorithm,
+            identity,
+            trusted_key,
+            integrity_key,
             format,
-            bit_depth,
             output,
             config,
         } => {
-            if !(1..=4).contains(&bit_depth) {
-                eprintln!(
-                    "Error: Bit depth must be between 1 and 4, got {}",
-                    bit_depth
-                );
-                return Ok(());
-            }
-
-            let file_format = if let Some(fmt) = format {
-                match fmt.to_lowercase().as_str() {
-                    "png" => FileFormat::Png,
-                    "jpg" | "jpeg" => FileFormat::Jpg,
-                    "bmp" => FileFormat::Bmp,
-                    "gif" => FileFormat::Gif,
-                    "wav" => FileFormat::Wav,
-                    "mp3" => FileFormat::Mp3,
-                    "mp4" => FileFormat::Mp4,
-                    "pdf" => FileFormat::Pdf,
-                    _ => return Err(Error::InvalidInput("Unsupported file format".into())),
-                }
-            } else {
-                detect_file_format(&input)
+            let file_format = match format {
+                Some(name) => parse_format(&name)?,
+                None => sniff_file_format(&input)?,
             };
 
-            // Create a parameters map
-            let mut parameters = HashMap::new();
-            parameters.insert("bit_depth".to_string(), bit_depth.to_string());
+            // Create a parameters map from the additional config parameters;
+            // everything else is read from the payload header
+            let parameters: HashMap<String, String> = config.into_iter().collect();
 
-            // Add any additional config parameters
-            for (key, value) in config {
-                parameters.insert(key, value);
-            }
+            // The algorithm recorded in the payload header takes precedence
+            let algorithm = match algorithm {
+                Some(name) => parse_algorithm(&name)?,
+                None => Algorithm::Aes256,
+            };
 
             let config = ExtractConfig {
                 input_path: input.to_str().unwrap().to_string(),
-                encryption: password.map(|pass| CryptoConfig {
-                    algorithm: match algorithm.as_str() {
-                        "aes256" => Algorithm::Aes256,
-                        "chacha20" => Algorithm::ChaCha20,
-                        "rsa" => Algorithm::Rsa,
-                        _ => panic!("Invalid algorithm"),
-                    },
-                    password: pass,
-                }),
+                // Key derivation parameters are read from the payload
+                encryption: match (password, identity) {
+                    (None, None) => None,
+                    (password, identity) => Some(CryptoConfig {
+                        algorithm,
+                        password: password.unwrap_or_default(),
+                        identity: identity.as_deref().map(read_key_file).transpose()?,
+                        ..Default::default()
+                    }),
+                },
                 parameters: Some(parameters),
+                trusted_keys: trusted_key
+                    .iter()
+                    .map(|path| read_key_file(path))
+                    .collect::<Result<Vec<_>>>()?,
+                integrity_key: integrity_key.as_deref().map(read_key_file).transpose()?,
             };
 
-            let final_data = match file_format {
-                FileFormat::Pdf => nhale::extraction::extract_from_pdf(config)?,
-                FileFormat::Png => nhale::extraction::extract_from_png(config)?,
-                FileFormat::Jpg => nhale::extraction::extract_from_jpg(config)?,
-                FileFormat::Wav => nhale::extraction::extract_from_wav(config)?,
-                FileFormat::Mp3 => nhale::extraction::extract_from_mp3(config)?,
-                FileFormat::Mp4 => nhale::extraction::extract_from_mp4(config)?,
-                _ => return Err(Error::InvalidInput("Unsupported file format".into())),
-            };
+            let report = extract_with_report_as(config, file_format)?;
+            match report.verification {
+                Verification::None => println!("Integrity: not verified (legacy payload)"),
+                Verification::Checksum => {
+                    println!("Integrity: checksum only (detects corruption, not tampering)")
+                }
+                Verification::PasswordHmac => println!("Integrity: authenticated with password"),
+                Verification::KeyHmac => println!("Integrity: authenticated with integrity key"),
+            }
+            if report.repaired_shards > 0 {
+                println!(
+                    "Error correction: repaired {} damaged shards",
+                    report.repaired_shards
+                );
+            }
+            match report.signature {
+                SignatureStatus::Unsigned => {}
+                SignatureStatus::Trusted(signer) => {
+                    println!("Signed by trusted key {}", Base64::encode_string(&signer))
+                }
+                SignatureStatus::Untrusted(signer) => {
+                    println!("Signed by untrusted key {}", Base64::encode_string(&signer))
+                }
+            }
+            if report.signature != SignatureStatus::Unsigned && !report.carrier_bound {
+                println!("Signature is not bound to this carrier (robust JPEG payload)");
+            }
+            let final_data = report.data;
 
             // If an output file is specified, write the extracted data to it
             if let Some(output_path) = output {
@@ -328,6 +485,42 @@ fn main() -> Result<()> {
             Ok(())
         }
 
+        Commands::Keygen {
+            output,
+            algorithm,
+            bits,
+        } => {
+            let (secret, public) = match algorithm.as_str() {
+                "x25519" => {
+                    let key_pair = X25519KeyPair::generate()?;
+                    (key_pair.export_secret(), key_pair.export_public())
+                }
+                "rsa" => generate_rsa_keypair(bits)?,
+                "ed25519" => {
+                    let key_pair = Ed25519KeyPair::generate()?;
+                    (key_pair.export_secret(), key_pair.export_public())
+                }
+                _ => {
+                    return Err(Error::InvalidInput(format!(
+                        "Invalid key type: {}",
+                        algorithm
+                    )))
+                }
+            };
+
+            let mut public_path = output.clone().into_os_string();
+            public_path.push(".pub");
+            let public_path = PathBuf::from(public_path);
+
+            write_secret_file(&output, secret.as_bytes())?;
+            std::fs::write(&public_path, public)
+                .map_err(|e| Error::Io(format!("Failed to write public key: {}", e)))?;
+
+            println!("Secret key written to {}", output.display());
+            println!("Public key written to {}", public_path.display());
+            Ok(())
+        }
+
         Commands::Watermark {
             input: _,
             output: _,
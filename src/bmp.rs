@@ -0,0 +1,553 @@
+//! BMP Module
+//!
+//! This module hides data in the pixel samples of uncompressed BMP files:
+//! 24-bit and 32-bit true colour, and 8-bit palettized images, where each
+//! pixel carries one bit in its palette index. As for GIF, the colour table is
+//! sorted by luminance and an index only ever moves to the other colour of
+//! its pair, so pixels never jump to unrelated colours.
+//!
+//! The file is kept as raw bytes and only the low bits of the pixel samples
+//! are rewritten, so the file header, palette, row padding and row order are
+//! preserved byte for byte. Samples are visited in visual order (top row
+//! first, and R, G, B within a pixel) whether the rows are stored bottom-up
+//! or top-down, so the layout matches PNG embedding. The payload header
+//! occupies the first samples at one bit each; the body follows at the bit
+//! depth recorded in the header, in key-seeded random order if requested.
+
+use crate::container::{Container, PayloadHeader, FLAG_RANDOM_ORDER, HEADER_LENGTH};
+use crate::utils::{
+    embed_bits, embedding_positions, extract_bits, locate_header, validate_bit_depth,
+    EmbeddingOrder, PaletteOrder,
+};
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+
+/// Size of the BITMAPFILEHEADER
+const FILE_HEADER_LENGTH: usize = 14;
+
+/// Size of the smallest supported DIB header (BITMAPINFOHEADER)
+const INFO_HEADER_LENGTH: usize = 40;
+
+/// Uncompressed pixel data
+const BI_RGB: u32 = 0;
+
+/// Uncompressed pixel data with channel masks
+const BI_BITFIELDS: u32 = 3;
+
+/// Handler for BMP operations
+pub struct BmpHandler {
+    bytes: Vec<u8>,
+    width: usize,
+    height: usize,
+    bits_per_pixel: u16,
+    /// Luminance order of the colour table of an 8-bit image
+    palette: Option<PaletteOrder>,
+    /// File offset of every sample, in visual order
+    samples: Vec<usize>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+impl BmpHandler {
+    /// Loads a BMP file
+    pub fn new(path: &str) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::Io(format!("Failed to read BMP {}: {}", path, e)))?;
+        Self::from_bytes(bytes)
+    }
+
+    /// Parses a BMP file held in memory
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < FILE_HEADER_LENGTH + INFO_HEADER_LENGTH || !bytes.starts_with(b"BM") {
+            return Err(Error::InvalidInput("Not a BMP file".into()));
+        }
+        let data_offset = read_u32(&bytes, 10) as usize;
+        let header_length = read_u32(&bytes, 14) as usize;
+        if header_length < INFO_HEADER_LENGTH {
+            return Err(Error::InvalidInput(format!(
+                "Unsupported BMP header of {} bytes",
+                header_length
+            )));
+        }
+
+        let width = read_u32(&bytes, 18) as i32;
+        let height = read_u32(&bytes, 22) as i32;
+        let bits_per_pixel = read_u16(&bytes, 28);
+        let compression = read_u32(&bytes, 30);
+        if width <= 0 || height == 0 || height == i32::MIN {
+            return Err(Error::InvalidInput(format!(
+                "Invalid BMP dimensions: {}x{}",
+                width, height
+            )));
+        }
+
+        // Byte offsets of R, G and B within a pixel
+        let channels: Vec<usize> = match (bits_per_pixel, compression) {
+            (8, BI_RGB) => vec![0],
+            (24 | 32, BI_RGB) => vec![2, 1, 0],
+            (32, BI_BITFIELDS) => {
+                let masks_offset = FILE_HEADER_LENGTH + INFO_HEADER_LENGTH;
+                if bytes.len() < masks_offset + 12 {
+                    return Err(Error::InvalidInput("Truncated BMP channel masks".into()));
+                }
+                (0..3)
+                    .map(|i| {
+                        let mask = read_u32(&bytes, masks_offset + i * 4);
+                        match mask {
+                            0xFF | 0xFF00 | 0xFF_0000 | 0xFF00_0000 => {
+                                Ok(mask.trailing_zeros() as usize / 8)
+                            }
+                            _ => Err(Error::InvalidInput(format!(
+                                "Unsupported BMP channel mask {:#010x}",
+                                mask
+                            ))),
+                        }
+                    })
+                    .collect::<Result<_>>()?
+            }
+            _ => {
+                return Err(Error::InvalidInput(format!(
+                    "Unsupported BMP format: {} bits per pixel, compression {}",
+                    bits_per_pixel, compression
+                )))
+            }
+        };
+
+        // Palette indices carry a bit in the parity of their luminance rank;
+        // entries past the colour table carry nothing
+        let palette = if bits_per_pixel == 8 {
+            let colours = match read_u32(&bytes, 46) as usize {
+                0 => 256,
+                count => count.min(256),
+            };
+            let table_offset = FILE_HEADER_LENGTH + header_length;
+            let table = bytes
+                .get(table_offset..table_offset + colours * 4)
+                .ok_or_else(|| Error::InvalidInput("Truncated BMP colour table".into()))?;
+            let rgb: Vec<u8> = table
+                .chunks_exact(4)
+                .flat_map(|entry| [entry[2], entry[1], entry[0]])
+                .collect();
+            Some(PaletteOrder::new(&rgb, |_| false))
+        } else {
+            None
+        };
+
+        // Rows are padded to four bytes and stored bottom-up unless the
+        // height is negative
+        let (width, top_down, height) =
+            (width as usize, height < 0, height.unsigned_abs() as usize);
+        let pixel_bytes = bits_per_pixel as usize / 8;
+        let stride = (width * pixel_bytes).div_ceil(4) * 4;
+        let end = stride
+            .checked_mul(height)
+            .and_then(|length| length.checked_add(data_offset));
+        if end.is_none_or(|end| end > bytes.len()) {
+            return Err(Error::InvalidInput("Truncated BMP pixel data".into()));
+        }
+
+        let mut samples = Vec::with_capacity(width * height * channels.len());
+        for row in 0..height {
+            let stored_row = if top_down { row } else { height - 1 - row };
+            let row_start = data_offset + stored_row * stride;
+            for x in 0..width {
+                for &channel in &channels {
+                    let offset = row_start + x * pixel_bytes + channel;
+                    if palette
+                        .as_ref()
+                        .is_none_or(|order| order.rank(bytes[offset]).is_some())
+                    {
+                        samples.push(offset);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            bytes,
+            width,
+            height,
+            bits_per_pixel,
+            palette,
+            samples,
+        })
+    }
+
+    /// Returns the image width and height in pixels
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns the number of bits per pixel (8, 24 or 32)
+    pub fn bits_per_pixel(&self) -> u16 {
+        self.bits_per_pixel
+    }
+
+    /// Returns the raw file contents
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Calculates how many body bytes fit in the image at the given bit depth,
+    /// excluding the payload header
+    pub fn capacity(&self, bit_depth: u8) -> usize {
+        self.samples.len().saturating_sub(HEADER_LENGTH * 8) * bit_depth as usize / 8
+    }
+
+    /// Computes a digest of the file that embedding at `bit_depth` leaves unchanged
+    ///
+    /// Every byte of the file is hashed, with the low `bit_depth` bits of the
+    /// pixel samples masked out, or with each palette index standing for its
+    /// colour pair.
+    pub fn carrier_digest(&self, bit_depth: u8) -> [u8; 32] {
+        let mask = u8::MAX.checked_shl(bit_depth as u32).unwrap_or(0);
+        let mut bytes = self.bytes.clone();
+        for &offset in &self.samples {
+            bytes[offset] = match &self.palette {
+                Some(order) => order.pair(bytes[offset]),
+                None => bytes[offset] & mask,
+            };
+        }
+        Sha256::digest(&bytes).into()
+    }
+
+    /// Embeds a payload container into the pixel samples
+    pub fn embed_data(
+        &mut self,
+        container: &Container,
+        bit_depth: u8,
+        order: EmbeddingOrder,
+        key: Option<&str>,
+    ) -> Result<()> {
+        validate_bit_depth(bit_depth, Error::InvalidInput)?;
+        if self.palette.is_some() && bit_depth != 1 {
+            return Err(Error::InvalidInput(format!(
+                "8-bit BMPs carry one bit per pixel in the palette index; bit depth must be 1, got {}",
+                bit_depth
+            )));
+        }
+
+        let capacity = self.capacity(bit_depth);
+        if self.samples.len() < HEADER_LENGTH * 8 || container.body.len() > capacity {
+            return Err(Error::InvalidInput(format!(
+                "Data too large for BMP with bit depth {}. Maximum capacity: {} bytes",
+                bit_depth, capacity
+            )));
+        }
+
+        // Record the carrier settings in the header
+        let mut header = container.header.clone();
+        header.bit_depth = bit_depth;
+        if order == EmbeddingOrder::Random {
+            header.flags |= FLAG_RANDOM_ORDER;
+        }
+
+        // Embed the header first, one bit per sample, then the body
+        let offsets = self.sample_offsets(&embedding_positions(order, key, self.samples.len())?);
+        let (header_offsets, body_offsets) = offsets.split_at(HEADER_LENGTH * 8);
+        self.write_bits(header_offsets, &header.to_bytes(), 1);
+        self.write_bits(body_offsets, &container.body, bit_depth);
+
+        Ok(())
+    }
+
+    /// Extracts an embedded payload container from the pixel samples
+    pub fn extract_data(&self, key: Option<&str>) -> Result<Container> {
+        if self.samples.len() < HEADER_LENGTH * 8 {
+            return Err(Error::InvalidData(
+                "Image is too small to hold a payload header".into(),
+            ));
+        }
+
+        // First extract the header (one bit per sample), from the start of the
+        // samples or, under random order, from the start of the keyed permutation
+        let (header, positions) = locate_header(self.samples.len(), key, |positions| {
+            let mut header_bytes = [0u8; HEADER_LENGTH];
+            let offsets = self.sample_offsets(&positions[..HEADER_LENGTH * 8]);
+            self.read_bits(&offsets, &mut header_bytes, 1);
+            PayloadHeader::from_bytes(&header_bytes)
+        })?;
+        validate_bit_depth(header.bit_depth, Error::InvalidData)?;
+        if self.palette.is_some() && header.bit_depth != 1 {
+            return Err(Error::InvalidData(format!(
+                "Palette indices carry one bit, but the header records bit depth {}",
+                header.bit_depth
+            )));
+        }
+
+        let capacity = self.capacity(header.bit_depth);
+        let length = header.readable_length(capacity).ok_or_else(|| {
+            Error::InvalidData(format!(
+                "Data length ({}) exceeds image capacity ({})",
+                header.payload_length, capacity
+            ))
+        })?;
+
+        let body_offsets = self.sample_offsets(&positions[HEADER_LENGTH * 8..]);
+        let mut body = vec![0u8; length];
+        self.read_bits(&body_offsets, &mut body, header.bit_depth);
+
+        Ok(Container {
+            header,
+            body,
+            soft_bits: None,
+        })
+    }
+
+    /// Saves the BMP to a file
+    pub fn save(&self, path: &str) -> Result<()> {
+        std::fs::write(path, &self.bytes)
+            .map_err(|e| Error::Io(format!("Failed to save BMP: {}", e)))
+    }
+
+    /// Writes `data` into the samples at `offsets`, through the palette order
+    /// for 8-bit images
+    fn write_bits(&mut self, offsets: &[usize], data: &[u8], bit_depth: u8) {
+        match &self.palette {
+            Some(order) => {
+                for (i, &offset) in offsets.iter().take(data.len() * 8).enumerate() {
+                    let bit = (data[i / 8] >> (7 - i % 8)) & 1;
+                    self.bytes[offset] = order.with_bit(self.bytes[offset], bit);
+                }
+            }
+            None => embed_bits(&mut self.bytes, offsets, data, bit_depth),
+        }
+    }
+
+    /// Reads `data.len()` bytes from the samples at `offsets`
+    fn read_bits(&self, offsets: &[usize], data: &mut [u8], bit_depth: u8) {
+        match &self.palette {
+            Some(order) => {
+                data.fill(0);
+                for (i, &offset) in offsets.iter().take(data.len() * 8).enumerate() {
+                    let bit = order.rank(self.bytes[offset]).unwrap_or(0) as u8 & 1;
+                    data[i / 8] |= bit << (7 - i % 8);
+                }
+            }
+            None => extract_bits(&self.bytes, offsets, data, bit_depth),
+        }
+    }
+
+    /// File offsets of the samples at `positions`, in payload order
+    fn sample_offsets(&self, positions: &[usize]) -> Vec<usize> {
+        positions
+            .iter()
+            .map(|&position| self.samples[position])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::EccScheme;
+
+    /// Builds a BMP with a gradient, writing rows in the given direction
+    fn test_bmp(width: usize, height: usize, bits_per_pixel: u16, top_down: bool) -> Vec<u8> {
+        let pixel_bytes = bits_per_pixel as usize / 8;
+        let stride = (width * pixel_bytes).div_ceil(4) * 4;
+        let palette_length = if bits_per_pixel == 8 { 256 * 4 } else { 0 };
+        let data_offset = FILE_HEADER_LENGTH + INFO_HEADER_LENGTH + palette_length;
+        let file_length = data_offset + stride * height;
+
+        let mut bytes = Vec::with_capacity(file_length);
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&(file_length as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&(data_offset as u32).to_le_bytes());
+        bytes.extend_from_slice(&(INFO_HEADER_LENGTH as u32).to_le_bytes());
+        bytes.extend_from_slice(&(width as i32).to_le_bytes());
+        let stored_height = if top_down {
+            -(height as i32)
+        } else {
+            height as i32
+        };
+        bytes.extend_from_slice(&stored_height.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        bytes.extend_from_slice(&BI_RGB.to_le_bytes());
+        bytes.extend_from_slice(&((stride * height) as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        for i in 0..palette_length / 4 {
+            bytes.extend_from_slice(&[i as u8, i as u8, i as u8, 0]);
+        }
+        for row in 0..height {
+            for x in 0..stride {
+                // Padding bytes are non-zero so that changes to them show up
+                let value = if x < width * pixel_bytes {
+                    (x * 7 + row * 3) as u8
+                } else {
+                    0xAB
+                };
+                bytes.push(value);
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_bmp_embed_extract_preserves_layout() {
+        let container = Container::seal(b"Hidden in a bitmap", None, EccScheme::None).unwrap();
+
+        // Widths that need row padding, both row orders, all pixel formats
+        for (bits_per_pixel, top_down) in [(24, false), (24, true), (32, false), (8, false)] {
+            let cover = test_bmp(37, 29, bits_per_pixel, top_down);
+            for (bit_depth, order, key) in [
+                (1, EmbeddingOrder::Sequential, None),
+                (2, EmbeddingOrder::Random, Some("stego key")),
+            ] {
+                let mut handler = BmpHandler::from_bytes(cover.clone()).unwrap();
+                assert_eq!(handler.dimensions(), (37, 29));
+
+                // Palette indices only carry one bit
+                if bits_per_pixel == 8 && bit_depth != 1 {
+                    assert!(matches!(
+                        handler.embed_data(&container, bit_depth, order, key),
+                        Err(Error::InvalidInput(_))
+                    ));
+                    continue;
+                }
+                handler
+                    .embed_data(&container, bit_depth, order, key)
+                    .unwrap();
+
+                // Only the low bits of pixel samples differ
+                let samples: std::collections::HashSet<usize> =
+                    handler.samples.iter().copied().collect();
+                let mask = (1u8 << bit_depth) - 1;
+                for (offset, (&before, &after)) in cover.iter().zip(handler.as_bytes()).enumerate()
+                {
+                    if samples.contains(&offset) {
+                        assert_eq!(before & !mask, after & !mask);
+                    } else {
+                        assert_eq!(before, after, "byte {} changed", offset);
+                    }
+                }
+                assert_ne!(cover, handler.as_bytes());
+
+                let stego = BmpHandler::from_bytes(handler.as_bytes().to_vec()).unwrap();
+                let extracted = stego.extract_data(key).unwrap();
+                assert_eq!(extracted.header.bit_depth, bit_depth);
+                // A scattered header cannot be found without the key
+                if order == EmbeddingOrder::Random {
+                    assert!(stego.extract_data(None).is_err());
+                }
+                assert_eq!(extracted.open(None).unwrap(), b"Hidden in a bitmap");
+                assert_eq!(
+                    stego.carrier_digest(bit_depth),
+                    BmpHandler::from_bytes(cover.clone())
+                        .unwrap()
+                        .carrier_digest(bit_depth)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bmp_palette_indices_move_to_their_pair() {
+        // A shuffled colour table, so neighbouring indices have unrelated colours
+        let mut cover = test_bmp(41, 23, 8, false);
+        let table = FILE_HEADER_LENGTH + INFO_HEADER_LENGTH;
+        for i in 0..256 {
+            let level = (i * 167 % 256) as u8;
+            cover[table + i * 4..table + i * 4 + 3].copy_from_slice(&[
+                level / 2,
+                255 - level,
+                level,
+            ]);
+        }
+        let palette: Vec<u8> = (0..256)
+            .flat_map(|i| {
+                let entry = &cover[table + i * 4..table + i * 4 + 3];
+                [entry[2], entry[1], entry[0]]
+            })
+            .collect();
+        let order = PaletteOrder::new(&palette, |_| false);
+        let luminance = |index: u8| {
+            let rgb = &palette[index as usize * 3..index as usize * 3 + 3];
+            299 * rgb[0] as i32 + 587 * rgb[1] as i32 + 114 * rgb[2] as i32
+        };
+
+        let container = Container::seal(b"Shuffled palette", None, EccScheme::None).unwrap();
+        let mut handler = BmpHandler::from_bytes(cover.clone()).unwrap();
+        let digest = handler.carrier_digest(1);
+        handler
+            .embed_data(&container, 1, EmbeddingOrder::Random, Some("key"))
+            .unwrap();
+
+        let mut changed = 0;
+        for (&before, &after) in cover.iter().zip(handler.as_bytes()) {
+            if before != after {
+                changed += 1;
+                // The other colour of the pair, adjacent in luminance
+                assert_eq!(order.pair(before), order.pair(after));
+                assert_eq!(order.rank(before).unwrap() ^ 1, order.rank(after).unwrap());
+                assert!((luminance(before) - luminance(after)).abs() < 255 * 1000 / 64);
+            }
+        }
+        assert!(changed > 0);
+
+        let stego = BmpHandler::from_bytes(handler.as_bytes().to_vec()).unwrap();
+        assert_eq!(
+            stego.extract_data(Some("key")).unwrap().open(None).unwrap(),
+            b"Shuffled palette"
+        );
+        assert_eq!(stego.carrier_digest(1), digest);
+    }
+
+    #[test]
+    fn test_bmp_visits_rows_top_down() {
+        // The first samples are the top row however the rows are stored
+        let bottom_up = BmpHandler::from_bytes(test_bmp(4, 3, 24, false)).unwrap();
+        let top_down = BmpHandler::from_bytes(test_bmp(4, 3, 24, true)).unwrap();
+        let data_offset = FILE_HEADER_LENGTH + INFO_HEADER_LENGTH;
+        assert_eq!(
+            bottom_up.samples[..3],
+            [
+                data_offset + 2 * 12 + 2,
+                data_offset + 2 * 12 + 1,
+                data_offset + 2 * 12
+            ]
+        );
+        assert_eq!(
+            top_down.samples[..3],
+            [data_offset + 2, data_offset + 1, data_offset]
+        );
+    }
+
+    #[test]
+    fn test_bmp_rejects_unsupported_files() {
+        let mut compressed = test_bmp(8, 8, 24, false);
+        compressed[30] = 1; // BI_RLE8
+        assert!(BmpHandler::from_bytes(compressed).is_err());
+
+        let mut sixteen_bit = test_bmp(8, 8, 24, false);
+        sixteen_bit[28] = 16;
+        assert!(BmpHandler::from_bytes(sixteen_bit).is_err());
+
+        let mut truncated = test_bmp(8, 8, 24, false);
+        truncated.truncate(100);
+        assert!(BmpHandler::from_bytes(truncated).is_err());
+
+        assert!(BmpHandler::from_bytes(b"not a bitmap at all".to_vec()).is_err());
+
+        // Too small for the payload
+        let mut handler = BmpHandler::from_bytes(test_bmp(8, 8, 24, false)).unwrap();
+        let container = Container::seal(&[7; 64], None, EccScheme::None).unwrap();
+        assert!(matches!(
+            handler.embed_data(&container, 1, EmbeddingOrder::Sequential, None),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+//! Compression Module
+//!
+//! This module provides functionality for compressing payloads before they are
+//! encrypted, using deflate or zstd. Compressed data only pays off for
+//! redundant payloads such as text or logs; data that does not shrink is
+//! stored as it is.
+
+use crate::{Error, Result};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+/// Highest supported compression level
+pub const MAX_COMPRESSION_LEVEL: u8 = 9;
+
+/// Largest payload decompression will produce, guarding against
+/// decompression bombs
+pub const MAX_DECOMPRESSED_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Compression applied to the payload before encryption
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            2 => Ok(Compression::Zstd),
+            _ => Err(Error::InvalidData(format!(
+                "Unknown compression identifier: {}",
+                id
+            ))),
+        }
+    }
+
+    /// Parses an algorithm name (`none`, `deflate` or `zstd`)
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(Error::InvalidInput(format!(
+                "Unsupported compression algorithm: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Compresses data with the given algorithm and level (1-9)
+pub fn compress(data: &[u8], compression: Compression, level: u8) -> Result<Vec<u8>> {
+    if !(1..=MAX_COMPRESSION_LEVEL).contains(&level) {
+        return Err(Error::InvalidInput(format!(
+            "Compression level must be between 1 and {}, got {}",
+            MAX_COMPRESSION_LEVEL, level
+        )));
+    }
+
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Deflate => {
+            let mut encoder =
+                DeflateEncoder::new(Vec::new(), flate2::Compression::new(level as u32));
+            encoder
+                .write_all(data)
+                .and_then(|_| encoder.finish())
+                .map_err(|e| Error::Encoding(format!("Deflate compression failed: {}", e)))
+        }
+        Compression::Zstd => zstd::bulk::compress(data, level as i32)
+            .map_err(|e| Error::Encoding(format!("Zstd compression failed: {}", e))),
+    }
+}
+
+/// Decompresses data written by [`compress`]
+pub fn decompress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let read = match compression {
+        Compression::None => return Ok(data.to_vec()),
+        Compression::Deflate => DeflateDecoder::new(data)
+            .take(MAX_DECOMPRESSED_LENGTH as u64 + 1)
+            .read_to_end(&mut output),
+        Compression::Zstd => zstd::stream::read::Decoder::new(data).and_then(|decoder| {
+            decoder
+                .take(MAX_DECOMPRESSED_LENGTH as u64 + 1)
+                .read_to_end(&mut output)
+        }),
+    };
+    read.map_err(|e| Error::InvalidData(format!("Failed to decompress payload: {}", e)))?;
+
+    if output.len() > MAX_DECOMPRESSED_LENGTH {
+        return Err(Error::InvalidData(format!(
+            "Decompressed payload exceeds {} bytes",
+            MAX_DECOMPRESSED_LENGTH
+        )));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let data = b"2024-01-01 INFO request handled in 12ms\n".repeat(100);
+
+        for compression in [Compression::Deflate, Compression::Zstd] {
+            for level in [1, 6, 9] {
+                let compressed = compress(&data, compression, level).unwrap();
+                assert!(compressed.len() < data.len() / 10);
+                assert_eq!(decompress(&compressed, compression).unwrap(), data);
+            }
+            assert_eq!(Compression::from_id(compression.id()).unwrap(), compression);
+        }
+
+        assert!(compress(&data, Compression::Deflate, 0).is_err());
+        assert!(compress(&data, Compression::Zstd, 10).is_err());
+        assert!(Compression::from_name("lzma").is_err());
+    }
+
+    #[test]
+    fn test_decompression_rejects_corrupt_data() {
+        let compressed = compress(b"Secret message", Compression::Zstd, 3).unwrap();
+        assert!(matches!(
+            decompress(&compressed[..compressed.len() - 2], Compression::Zstd),
+            Err(Error::InvalidData(_))
+        ));
+        assert!(matches!(
+            decompress(b"not deflate data", Compression::Deflate),
+            Err(Error::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,792 @@
+//! Container Module
+//!
+//! This module defines the self-describing container that every embedder writes
+//! into its carrier. A fixed-size header records how the payload was prepared
+//! (encryption algorithm, key derivation, compression, error correction and the
+//! carrier's own settings), so extraction can recover the data without the user
+//! having to repeat those choices.
+//!
+//! Header layout (all integers big-endian):
+//!
+//! | Offset | Size | Field                                     |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | Magic number `NHAL`                       |
+//! | 4      | 1    | Format version                            |
+//! | 5      | 1    | Flags                                     |
+//! | 6      | 1    | Encryption algorithm (0 = none)           |
+//! | 7      | 10   | Key derivation function and parameters    |
+//! | 17     | 1    | Compression                               |
+//! | 18     | 1    | Error correction scheme                   |
+//! | 19     | 1    | Carrier bit depth (0 = not applicable)    |
+//! | 20     | 4    | Body length                               |
+//! | 24     | 4    | CRC-32 of bytes 0..24                     |
+//!
+//! The body starts with an integrity block covering the encryption-relevant
+//! header fields and the encrypted data: a salt and HMAC-SHA256 tag when the
+//! payload is authenticated with a password or integrity key, or a bare
+//! SHA-256 checksum otherwise. Error correction is applied on top.
+
+use crate::compression;
+use crate::encryption::{self, Algorithm, CryptoConfig, Kdf, KDF_PARAMS_LENGTH};
+use crate::error_correction::{self, calculate_crc32, FountainConfig, ReedSolomonConfig};
+use crate::integrity::{
+    self, IntegritySecret, PayloadSignature, Verification, INTEGRITY_SALT_LENGTH,
+    INTEGRITY_TAG_LENGTH, SIGNATURE_BLOCK_LENGTH,
+};
+use crate::{Error, Result};
+
+pub use crate::compression::Compression;
+
+/// Magic number identifying an nHale payload
+pub const MAGIC: [u8; 4] = *b"NHAL";
+
+/// Current container format version
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Size of the serialized header in bytes
+pub const HEADER_LENGTH: usize = 28;
+
+/// Number of header bytes (from the version to the error correction scheme)
+/// covered by the integrity tag
+const AUTHENTICATED_HEADER_LENGTH: usize = 15;
+
+/// Flag set when the carrier positions were visited in key-seeded random order
+pub const FLAG_RANDOM_ORDER: u8 = 0x01;
+
+/// Flag set when the payload starts with a signature block
+pub const FLAG_SIGNED: u8 = 0x02;
+
+/// Flag set when the integrity tag is an HMAC keyed from the password
+pub const FLAG_PASSWORD_HMAC: u8 = 0x04;
+
+/// Flag set when the integrity tag is an HMAC keyed from an integrity key
+pub const FLAG_KEY_HMAC: u8 = 0x08;
+
+/// Flag set when the body was embedded with matrix encoding; the bit depth
+/// field then holds the code parameter
+pub const FLAG_MATRIX: u8 = 0x10;
+
+/// Error correction scheme applied to the container body
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EccScheme {
+    #[default]
+    None,
+    ReedSolomon,
+    /// Rate 1/2 convolutional code, decoded with soft decisions when the
+    /// extractor supplies bit reliabilities
+    Convolutional,
+    /// Rateless fountain code whose symbols carry their own index and CRC,
+    /// so any sufficient subset of intact symbols recovers the body
+    Fountain,
+}
+
+impl EccScheme {
+    fn id(self) -> u8 {
+        match self {
+            EccScheme::None => 0,
+            EccScheme::ReedSolomon => 1,
+            EccScheme::Convolutional => 2,
+            EccScheme::Fountain => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(EccScheme::None),
+            1 => Ok(EccScheme::ReedSolomon),
+            2 => Ok(EccScheme::Convolutional),
+            3 => Ok(EccScheme::Fountain),
+            _ => Err(Error::InvalidData(format!(
+                "Unknown error correction identifier: {}",
+                id
+            ))),
+        }
+    }
+
+    /// Parses a scheme name (`none`, `reed-solomon`, `convolutional` or
+    /// `fountain`)
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "none" => Ok(EccScheme::None),
+            "reed-solomon" | "rs" => Ok(EccScheme::ReedSolomon),
+            "convolutional" => Ok(EccScheme::Convolutional),
+            "fountain" => Ok(EccScheme::Fountain),
+            _ => Err(Error::InvalidInput(format!(
+                "Unsupported error correction scheme: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Header describing how a payload was prepared and embedded
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadHeader {
+    /// Container format version
+    pub version: u8,
+    /// Combination of `FLAG_*` values
+    pub flags: u8,
+    /// Encryption algorithm, if the payload is encrypted
+    pub algorithm: Option<Algorithm>,
+    /// Key derivation function, if the payload is password-encrypted
+    pub kdf: Option<Kdf>,
+    /// Compression applied before encryption
+    pub compression: Compression,
+    /// Error correction applied to the body
+    pub ecc: EccScheme,
+    /// Bits per carrier sample used for the body (0 if not applicable)
+    pub bit_depth: u8,
+    /// Length of the body that follows the header
+    pub payload_length: u32,
+}
+
+impl PayloadHeader {
+    /// Returns true if the given flag is set
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Number of body bytes to read from a carrier with room for `capacity`
+    ///
+    /// A fountain-coded body is read as far as the carrier goes, since a
+    /// carrier that was cut short may still hold enough intact symbols; any
+    /// other body must fit entirely. Returns `None` if it does not.
+    pub fn readable_length(&self, capacity: usize) -> Option<usize> {
+        let length = self.payload_length as usize;
+        match self.ecc {
+            EccScheme::Fountain => Some(length.min(capacity)),
+            _ => (length <= capacity).then_some(length),
+        }
+    }
+
+    /// Returns the header fields covered by the integrity tag
+    ///
+    /// Carrier settings (bit depth, embedding order, matrix encoding) and the
+    /// body length are filled in after sealing and are left out.
+    fn authenticated_bytes(&self) -> [u8; AUTHENTICATED_HEADER_LENGTH] {
+        let bytes = self.to_bytes();
+        let mut authenticated = [0u8; AUTHENTICATED_HEADER_LENGTH];
+        authenticated.copy_from_slice(&bytes[4..19]);
+        authenticated[1] &= !(FLAG_RANDOM_ORDER | FLAG_MATRIX);
+        authenticated
+    }
+
+    /// Serializes the header
+    pub fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let mut bytes = [0u8; HEADER_LENGTH];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.flags;
+        bytes[6] = self.algorithm.map_or(0, Algorithm::id);
+        if let Some(kdf) = self.kdf {
+            bytes[7..7 + KDF_PARAMS_LENGTH].copy_from_slice(&kdf.to_bytes());
+        }
+        bytes[17] = self.compression.id();
+        bytes[18] = self.ecc.id();
+        bytes[19] = self.bit_depth;
+        bytes[20..24].copy_from_slice(&self.payload_length.to_be_bytes());
+
+        let checksum = calculate_crc32(&bytes[..24]);
+        bytes[24..28].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    /// Parses a header, verifying its magic number, version and checksum
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LENGTH || bytes[0..4] != MAGIC {
+            return Err(Error::InvalidData("No nHale payload header found".into()));
+        }
+
+        let mut checksum_bytes = [0u8; 4];
+        checksum_bytes.copy_from_slice(&bytes[24..28]);
+        if calculate_crc32(&bytes[..24]) != u32::from_be_bytes(checksum_bytes) {
+            return Err(Error::Integrity("Payload header checksum mismatch".into()));
+        }
+
+        let version = bytes[4];
+        if version != FORMAT_VERSION {
+            return Err(Error::InvalidData(format!(
+                "Unsupported payload format version: {}",
+                version
+            )));
+        }
+
+        let algorithm = match bytes[6] {
+            0 => None,
+            id => Some(Algorithm::from_id(id)?),
+        };
+        let kdf = match bytes[7] {
+            0 => None,
+            _ => Some(Kdf::from_bytes(&bytes[7..7 + KDF_PARAMS_LENGTH])?),
+        };
+
+        let mut length_bytes = [0u8; 4];
+        length_bytes.copy_from_slice(&bytes[20..24]);
+
+        Ok(Self {
+            version,
+            flags: bytes[5],
+            algorithm,
+            kdf,
+            compression: Compression::from_id(bytes[17])?,
+            ecc: EccScheme::from_id(bytes[18])?,
+            bit_depth: bytes[19],
+            payload_length: u32::from_be_bytes(length_bytes),
+        })
+    }
+}
+
+/// Options controlling how [`Container::seal_with`] prepares a payload
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SealOptions<'a> {
+    /// Encryption to apply, if any
+    pub encryption: Option<&'a CryptoConfig>,
+    /// Error correction to apply to the body
+    pub ecc: EccScheme,
+    /// Compression to apply before encryption
+    pub compression: Compression,
+    /// Compression level (1-9), used when `compression` is not `None`
+    pub compression_level: u8,
+    /// Signature to prepend to the data
+    pub signature: Option<&'a PayloadSignature>,
+    /// Key to authenticate the payload with; the password is used otherwise
+    pub integrity_key: Option<&'a [u8]>,
+}
+
+/// Data recovered from a container together with how it was verified
+#[derive(Debug, Clone)]
+pub struct OpenedPayload {
+    /// The original data
+    pub data: Vec<u8>,
+    /// Verified signature, if the payload was signed
+    pub signature: Option<PayloadSignature>,
+    /// Kind of integrity check the payload passed
+    pub verification: Verification,
+    /// Number of Reed-Solomon shards rebuilt from parity
+    pub repaired_shards: usize,
+}
+
+/// A payload together with the header describing it
+#[derive(Debug, Clone)]
+pub struct Container {
+    /// Header describing the body
+    pub header: PayloadHeader,
+    /// Prepared (encrypted and error-corrected) payload
+    pub body: Vec<u8>,
+    /// Reliability of every body bit as read from the carrier, if the
+    /// extractor can tell: the sign is the bit (positive for a one) and the
+    /// magnitude the confidence
+    pub soft_bits: Option<Vec<f32>>,
+}
+
+impl Container {
+    /// Prepares data for embedding by encrypting it (if configured) and applying
+    /// the requested error correction, without compression
+    pub fn seal(data: &[u8], encryption: Option<&CryptoConfig>, ecc: EccScheme) -> Result<Self> {
+        Self::seal_with(
+            data,
+            &SealOptions {
+                encryption,
+                ecc,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Prepares data for embedding with the given options
+    ///
+    /// A signature block is encrypted together with the data, so the signer's
+    /// identity is only visible to those who can decrypt the payload. The
+    /// integrity tag is keyed from `integrity_key` if given, otherwise from
+    /// the password of password-based encryption; without either it is a
+    /// plain checksum.
+    pub fn seal_with(data: &[u8], options: &SealOptions) -> Result<Self> {
+        let encryption = options.encryption;
+        let mut flags = 0;
+        let mut plaintext = Vec::with_capacity(SIGNATURE_BLOCK_LENGTH + data.len());
+        if let Some(signature) = options.signature {
+            plaintext.extend_from_slice(&signature.to_bytes());
+            flags |= FLAG_SIGNED;
+        }
+        plaintext.extend_from_slice(data);
+
+        // Keep the data as it is if compression does not make it smaller
+        let mut compression = options.compression;
+        if compression != Compression::None {
+            let compressed =
+                compression::compress(&plaintext, compression, options.compression_level)?;
+            if compressed.len() < plaintext.len() {
+                plaintext = compressed;
+            } else {
+                compression = Compression::None;
+            }
+        }
+
+        let ciphertext = match encryption {
+            Some(config) => encryption::encrypt(&plaintext, config)?,
+            None => plaintext,
+        };
+
+        let kdf = encryption
+            .filter(|c| c.algorithm.uses_password())
+            .map(CryptoConfig::effective_kdf);
+        let secret = match (options.integrity_key, encryption, kdf) {
+            (Some(key), _, _) => Some(IntegritySecret::Key(key)),
+            (None, Some(config), Some(kdf)) => {
+                Some(IntegritySecret::Password(&config.password, kdf))
+            }
+            _ => None,
+        };
+        flags |= match secret.map(|s| s.verification()) {
+            Some(Verification::PasswordHmac) => FLAG_PASSWORD_HMAC,
+            Some(Verification::KeyHmac) => FLAG_KEY_HMAC,
+            _ => 0,
+        };
+
+        let mut header = PayloadHeader {
+            version: FORMAT_VERSION,
+            flags,
+            algorithm: encryption.map(|c| c.algorithm),
+            kdf,
+            compression,
+            ecc: options.ecc,
+            bit_depth: 0,
+            payload_length: 0,
+        };
+
+        let mut body = integrity_block(&header, &ciphertext, secret.as_ref())?;
+        body.extend_from_slice(&ciphertext);
+
+        match options.ecc {
+            EccScheme::None => {}
+            EccScheme::ReedSolomon => {
+                body = error_correction::encode_reed_solomon(&body, &ReedSolomonConfig::default())?
+            }
+            EccScheme::Convolutional => body = error_correction::encode_convolutional(&body),
+            EccScheme::Fountain => {
+                body = error_correction::encode_fountain(&body, &FountainConfig::default())?
+            }
+        }
+        header.payload_length = body.len() as u32;
+
+        Ok(Self {
+            header,
+            body,
+            soft_bits: None,
+        })
+    }
+
+    /// Recovers the original data, undoing error correction, decryption and
+    /// compression
+    ///
+    /// The algorithm recorded in the header takes precedence over the one in
+    /// `encryption`, so callers only need to supply the password. A signature,
+    /// if present, is checked and removed.
+    pub fn open(&self, encryption: Option<&CryptoConfig>) -> Result<Vec<u8>> {
+        self.open_verified(encryption, None)
+            .map(|payload| payload.data)
+    }
+
+    /// Like [`Container::open`], but also returns the verified signature and
+    /// the kind of integrity check performed
+    ///
+    /// Payloads authenticated with an integrity key can only be opened when
+    /// that key is supplied.
+    pub fn open_verified(
+        &self,
+        encryption: Option<&CryptoConfig>,
+        integrity_key: Option<&[u8]>,
+    ) -> Result<OpenedPayload> {
+        let (body, repaired_shards) = match self.header.ecc {
+            EccScheme::None => (self.body.clone(), 0),
+            EccScheme::ReedSolomon => {
+                let report = error_correction::decode_reed_solomon_with_report(&self.body)?;
+                (report.data, report.repaired_shards)
+            }
+            EccScheme::Convolutional => match &self.soft_bits {
+                Some(soft_bits) if soft_bits.len() == self.body.len() * 8 => {
+                    (error_correction::decode_convolutional_soft(soft_bits)?, 0)
+                }
+                _ => (error_correction::decode_convolutional(&self.body)?, 0),
+            },
+            EccScheme::Fountain => {
+                let report = error_correction::decode_fountain(&self.body)?;
+                (report.data, report.repaired_shards)
+            }
+        };
+
+        let (ciphertext, verification) = self.verify_integrity(&body, encryption, integrity_key)?;
+        let plaintext = self.decrypt(ciphertext, encryption)?;
+        let mut data = compression::decompress(&plaintext, self.header.compression)?;
+
+        let signature = if self.header.has_flag(FLAG_SIGNED) {
+            let signature = PayloadSignature::from_bytes(&data)?;
+            data.drain(..SIGNATURE_BLOCK_LENGTH);
+            signature.verify(&data)?;
+            Some(signature)
+        } else {
+            None
+        };
+
+        Ok(OpenedPayload {
+            data,
+            signature,
+            verification,
+            repaired_shards,
+        })
+    }
+
+    /// Checks the integrity block at the start of `body` and returns the rest
+    fn verify_integrity<'a>(
+        &self,
+        body: &'a [u8],
+        encryption: Option<&CryptoConfig>,
+        integrity_key: Option<&[u8]>,
+    ) -> Result<(&'a [u8], Verification)> {
+        let secret = if self.header.has_flag(FLAG_KEY_HMAC) {
+            let key = integrity_key.ok_or_else(|| {
+                Error::Integrity("Payload is authenticated with an integrity key".into())
+            })?;
+            Some(IntegritySecret::Key(key))
+        } else if self.header.has_flag(FLAG_PASSWORD_HMAC) {
+            let kdf = self.header.kdf.ok_or_else(|| {
+                Error::InvalidData("Password-authenticated payload without a KDF".into())
+            })?;
+            let config = encryption.ok_or_else(|| self.missing_key())?;
+            Some(IntegritySecret::Password(&config.password, kdf))
+        } else {
+            None
+        };
+
+        let block_length = match secret {
+            Some(_) => INTEGRITY_SALT_LENGTH + INTEGRITY_TAG_LENGTH,
+            None => INTEGRITY_TAG_LENGTH,
+        };
+        if body.len() < block_length {
+            return Err(Error::Integrity("Integrity block is truncated".into()));
+        }
+        let (block, ciphertext) = body.split_at(block_length);
+        let message = authenticated_message(&self.header, ciphertext);
+
+        let verification = match secret {
+            None => {
+                if block != integrity::payload_checksum(&message) {
+                    return Err(Error::Integrity("Payload checksum mismatch".into()));
+                }
+                Verification::Checksum
+            }
+            Some(secret) => {
+                let (salt, tag) = block.split_at(INTEGRITY_SALT_LENGTH);
+                let key = secret.derive_key(salt)?;
+                if !integrity::verify_hmac(&message, &key, tag)? {
+                    // A wrong password is indistinguishable from tampering
+                    return Err(match secret {
+                        IntegritySecret::Password(..) => {
+                            Error::Encryption(encryption::AUTHENTICATION_FAILED.into())
+                        }
+                        IntegritySecret::Key(_) => Error::Integrity(
+                            "Integrity check failed: wrong integrity key or tampered payload"
+                                .into(),
+                        ),
+                    });
+                }
+                secret.verification()
+            }
+        };
+
+        Ok((ciphertext, verification))
+    }
+
+    fn decrypt(&self, ciphertext: &[u8], encryption: Option<&CryptoConfig>) -> Result<Vec<u8>> {
+        match (self.header.algorithm, encryption) {
+            (None, _) => Ok(ciphertext.to_vec()),
+            (Some(algorithm), Some(config)) => {
+                let config = CryptoConfig {
+                    algorithm,
+                    ..config.clone()
+                };
+                encryption::decrypt(ciphertext, &config)
+            }
+            (Some(_), None) => Err(self.missing_key()),
+        }
+    }
+
+    fn missing_key(&self) -> Error {
+        Error::Encryption(format!(
+            "Payload is encrypted with {:?}; a password or key is required",
+            self.header.algorithm.unwrap_or_default()
+        ))
+    }
+
+    /// Serializes the header followed by the body
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.body.len());
+        bytes.extend_from_slice(&self.header.to_bytes());
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Parses a serialized container, ignoring any trailing bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let header = PayloadHeader::from_bytes(bytes)?;
+        let end = HEADER_LENGTH + header.payload_length as usize;
+        if bytes.len() < end {
+            return Err(Error::InvalidData(format!(
+                "Payload is truncated: expected {} bytes, found {}",
+                header.payload_length,
+                bytes.len().saturating_sub(HEADER_LENGTH)
+            )));
+        }
+
+        Ok(Self {
+            header,
+            body: bytes[HEADER_LENGTH..end].to_vec(),
+            soft_bits: None,
+        })
+    }
+}
+
+/// Builds the integrity block for a sealed payload
+fn integrity_block(
+    header: &PayloadHeader,
+    ciphertext: &[u8],
+    secret: Option<&IntegritySecret>,
+) -> Result<Vec<u8>> {
+    let message = authenticated_message(header, ciphertext);
+    match secret {
+        None => Ok(integrity::payload_checksum(&message).to_vec()),
+        Some(secret) => {
+            let mut block = vec![0u8; INTEGRITY_SALT_LENGTH];
+            getrandom::getrandom(&mut block).map_err(|e| Error::Integrity(e.to_string()))?;
+            let key = secret.derive_key(&block)?;
+            block.extend_from_slice(&integrity::generate_hmac(&message, &key)?);
+            Ok(block)
+        }
+    }
+}
+
+fn authenticated_message(header: &PayloadHeader, ciphertext: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(AUTHENTICATED_HEADER_LENGTH + ciphertext.len());
+    message.extend_from_slice(&header.authenticated_bytes());
+    message.extend_from_slice(ciphertext);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = PayloadHeader {
+            version: FORMAT_VERSION,
+            flags: FLAG_RANDOM_ORDER,
+            algorithm: Some(Algorithm::ChaCha20),
+            kdf: Some(Kdf::Sha256),
+            compression: Compression::None,
+            ecc: EccScheme::ReedSolomon,
+            bit_depth: 3,
+            payload_length: 1234,
+        };
+
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[0..4], b"NHAL");
+        assert_eq!(PayloadHeader::from_bytes(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn test_header_rejects_corruption() {
+        let header = Container::seal(b"data", None, EccScheme::None)
+            .unwrap()
+            .header;
+        let mut bytes = header.to_bytes();
+
+        bytes[19] ^= 0x01;
+        assert!(matches!(
+            PayloadHeader::from_bytes(&bytes),
+            Err(Error::Integrity(_))
+        ));
+
+        bytes[0] = b'X';
+        assert!(matches!(
+            PayloadHeader::from_bytes(&bytes),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_container_seal_open() {
+        let config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20,
+            password: "password".to_string(),
+            ..Default::default()
+        };
+
+        for ecc in [
+            EccScheme::None,
+            EccScheme::ReedSolomon,
+            EccScheme::Convolutional,
+            EccScheme::Fountain,
+        ] {
+            let container = Container::seal(b"Secret message", Some(&config), ecc).unwrap();
+            let parsed = Container::from_bytes(&container.to_bytes()).unwrap();
+            assert_eq!(parsed.header.algorithm, Some(Algorithm::ChaCha20));
+            assert_eq!(parsed.header.ecc, ecc);
+
+            // The algorithm is taken from the header, not the caller
+            let caller_config = CryptoConfig {
+                algorithm: Algorithm::Aes256,
+                password: "password".to_string(),
+                ..Default::default()
+            };
+            assert_eq!(
+                parsed.open(Some(&caller_config)).unwrap(),
+                b"Secret message"
+            );
+            assert!(matches!(parsed.open(None), Err(Error::Encryption(_))));
+        }
+    }
+
+    #[test]
+    fn test_container_wrong_password() {
+        let config = CryptoConfig {
+            algorithm: Algorithm::default(),
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let container = Container::seal(b"Secret message", Some(&config), EccScheme::None).unwrap();
+        assert_eq!(container.header.algorithm, Some(Algorithm::Aes256Gcm));
+
+        let wrong = CryptoConfig {
+            password: "wrong".to_string(),
+            ..config
+        };
+        assert!(matches!(
+            container.open(Some(&wrong)),
+            Err(Error::Encryption(msg)) if msg == encryption::AUTHENTICATION_FAILED
+        ));
+    }
+
+    #[test]
+    fn test_container_signed() {
+        use crate::integrity::Ed25519KeyPair;
+
+        let key = Ed25519KeyPair::generate().unwrap();
+        let config = CryptoConfig {
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let signature = PayloadSignature::sign(&key, b"Secret message", [0u8; 32]);
+        let container = Container::seal_with(
+            b"Secret message",
+            &SealOptions {
+                encryption: Some(&config),
+                signature: Some(&signature),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(container.header.has_flag(FLAG_SIGNED));
+
+        let opened = container.open_verified(Some(&config), None).unwrap();
+        assert_eq!(opened.data, b"Secret message");
+        assert_eq!(opened.signature.unwrap().signer, key.public_key());
+
+        // A signature for different data is rejected
+        let forged = PayloadSignature::sign(&key, b"Other message", [0u8; 32]);
+        let container = Container::seal_with(
+            b"Secret message",
+            &SealOptions {
+                signature: Some(&forged),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(container.open(None), Err(Error::Integrity(_))));
+    }
+
+    #[test]
+    fn test_container_integrity() {
+        // Without a password or key only a checksum is stored
+        let mut container = Container::seal(b"Secret message", None, EccScheme::None).unwrap();
+        let opened = container.open_verified(None, None).unwrap();
+        assert_eq!(opened.verification, Verification::Checksum);
+        let last = container.body.len() - 1;
+        container.body[last] ^= 1;
+        assert!(matches!(container.open(None), Err(Error::Integrity(_))));
+
+        // Password-based encryption authenticates with the password
+        let config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20,
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let container = Container::seal(b"Secret message", Some(&config), EccScheme::None).unwrap();
+        assert!(container.header.has_flag(FLAG_PASSWORD_HMAC));
+        let opened = container.open_verified(Some(&config), None).unwrap();
+        assert_eq!(opened.verification, Verification::PasswordHmac);
+
+        // An integrity key takes precedence and is required to open the payload
+        let options = SealOptions {
+            integrity_key: Some(b"integrity key"),
+            ..Default::default()
+        };
+        let container = Container::seal_with(b"Secret message", &options).unwrap();
+        assert!(container.header.has_flag(FLAG_KEY_HMAC));
+        let opened = container
+            .open_verified(None, Some(b"integrity key"))
+            .unwrap();
+        assert_eq!(opened.data, b"Secret message");
+        assert_eq!(opened.verification, Verification::KeyHmac);
+        assert!(matches!(container.open(None), Err(Error::Integrity(_))));
+        assert!(matches!(
+            container.open_verified(None, Some(b"wrong key")),
+            Err(Error::Integrity(_))
+        ));
+
+        // Carrier settings may change after sealing without breaking the tag
+        let mut container = container;
+        container.header.flags |= FLAG_RANDOM_ORDER | FLAG_MATRIX;
+        container.header.bit_depth = 2;
+        assert!(container
+            .open_verified(None, Some(b"integrity key"))
+            .is_ok());
+
+        // ...but the data cannot be changed without the key
+        let mut tampered = container.clone();
+        let last = tampered.body.len() - 1;
+        tampered.body[last] ^= 1;
+        assert!(matches!(
+            tampered.open_verified(None, Some(b"integrity key")),
+            Err(Error::Integrity(_))
+        ));
+    }
+
+    #[test]
+    fn test_container_compression() {
+        let config = CryptoConfig {
+            password: "password".to_string(),
+            kdf: Kdf::Pbkdf2 { iterations: 1_000 },
+            ..Default::default()
+        };
+        let text = b"Secret message, repeated. ".repeat(50);
+
+        for compression in [Compression::Deflate, Compression::Zstd] {
+            let options = SealOptions {
+                encryption: Some(&config),
+                compression,
+                compression_level: 6,
+                ..Default::default()
+            };
+            let container = Container::seal_with(&text, &options).unwrap();
+            assert_eq!(container.header.compression, compression);
+            assert!(container.body.len() < text.len() / 4);
+            assert_eq!(container.open(Some(&config)).unwrap(), text);
+
+            // Data that does not shrink is stored uncompressed
+            let container = Container::seal_with(b"abc", &options).unwrap();
+            assert_eq!(container.header.compression, Compression::None);
+            assert_eq!(container.open(Some(&config)).unwrap(), b"abc");
+        }
+    }
+}
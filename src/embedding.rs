@@ -3,16 +3,30 @@
 //! This module provides functionality for embedding data into various media types
 //! using different steganographic techniques.
 
-use crate::encryption::{Algorithm, CryptoConfig};
-use crate::error_correction;
+use crate::adaptive;
+use crate::audio::WavHandler;
+use crate::bmp::BmpHandler;
+use crate::compression::{Compression, MAX_COMPRESSION_LEVEL};
+use crate::container::{
+    Container, EccScheme, SealOptions, FLAG_MATRIX, FLAG_RANDOM_ORDER, HEADER_LENGTH,
+};
+use crate::encryption::CryptoConfig;
+use crate::f5;
+use crate::gif::GifHandler;
+use crate::integrity::{Ed25519KeyPair, PayloadSignature};
+use crate::jpeg::JpegImage;
+use crate::matrix;
 use crate::pdf::PdfHandler;
-use crate::utils::validate_data;
+use crate::png::{self, PngCarrier};
+use crate::qim;
+use crate::utils::{
+    embedding_positions, sniff_file_format, stego_key, validate_bit_depth, validate_data,
+    EmbeddingOrder, FileFormat, LsbChange,
+};
 use crate::{Error, Result};
-use image::{DynamicImage, ImageBuffer, Rgba};
-use jpeg_decoder::{Decoder, PixelFormat};
-use jpeg_encoder::{ColorType, Encoder};
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use image::DynamicImage;
+use rand::Rng;
+use sha2::{Digest, Sha256};
 use std::path::Path;
 
 /// Represents different media types that can be used for steganography
@@ -38,7 +52,7 @@ pub struct EmbeddingConfig {
 }
 
 /// Configuration for embedding data
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct EmbedConfig {
     /// Path to the source PDF file
     pub input_path: String,
@@ -48,18 +62,196 @@ pub struct EmbedConfig {
     pub data: Vec<u8>,
     /// Optional encryption configuration
     pub encryption: Option<CryptoConfig>,
+    /// Additional embedding parameters
+    pub parameters: Option<std::collections::HashMap<String, String>>,
+    /// Optional Ed25519 secret key used to sign the payload and its carrier
+    pub signing_key: Option<Vec<u8>>,
+    /// Optional key to authenticate the payload with; without it the password
+    /// is used, and without either only a checksum is stored
+    pub integrity_key: Option<Vec<u8>>,
 }
 
-/// Embeds data in an image
+/// Seals the data to embed, signing it first if a signing key is configured
+///
+/// `carrier_digest` binds the signature to the cover file; carriers that
+/// cannot provide a stable digest pass `None`.
+fn seal_payload(
+    config: &EmbedConfig,
+    ecc: EccScheme,
+    carrier_digest: Option<[u8; 32]>,
+) -> Result<Container> {
+    let signature = match &config.signing_key {
+        Some(key) => {
+            let key = Ed25519KeyPair::import_secret(key)?;
+            Some(PayloadSignature::sign(
+                &key,
+                &config.data,
+                carrier_digest.unwrap_or([0u8; 32]),
+            ))
+        }
+        None => None,
+    };
+
+    let (compression, compression_level) = compression_settings(config)?;
+
+    Container::seal_with(
+        &config.data,
+        &SealOptions {
+            encryption: config.encryption.as_ref(),
+            ecc,
+            compression,
+            compression_level,
+            signature: signature.as_ref(),
+            integrity_key: config.integrity_key.as_deref(),
+        },
+    )
+}
+
+/// Reads the compression to apply from the embedding parameters
+///
+/// `compression` sets the level (0-9, where 0 disables compression) and
+/// falls back to the `NHALE_COMPRESSION` environment variable;
+/// `compression_algorithm` selects `deflate` (the default) or `zstd`. Without
+/// a level the payload is not compressed.
+fn compression_settings(config: &EmbedConfig) -> Result<(Compression, u8)> {
+    let parameter = |name: &str| {
+        config
+            .parameters
+            .as_ref()
+            .and_then(|p| p.get(name).cloned())
+    };
+
+    let level = match parameter("compression").or_else(|| std::env::var("NHALE_COMPRESSION").ok()) {
+        Some(level) => level
+            .parse::<u8>()
+            .ok()
+            .filter(|level| *level <= MAX_COMPRESSION_LEVEL)
+            .ok_or_else(|| {
+                Error::InvalidInput(format!(
+                    "Compression level must be between 0 and {}, got {}",
+                    MAX_COMPRESSION_LEVEL, level
+                ))
+            })?,
+        None => 0,
+    };
+    let compression = match parameter("compression_algorithm") {
+        Some(name) => Compression::from_name(&name)?,
+        None => Compression::Deflate,
+    };
+
+    if level == 0 {
+        return Ok((Compression::None, 0));
+    }
+    Ok((compression, level))
+}
+
+/// Reads the error correction scheme from the `ecc` parameter (`none`,
+/// `reed-solomon`, `convolutional` or `fountain`), falling back to the
+/// carrier's default
+fn ecc_scheme(config: &EmbedConfig, default: EccScheme) -> Result<EccScheme> {
+    match config.parameters.as_ref().and_then(|p| p.get("ecc")) {
+        Some(name) => EccScheme::from_name(name),
+        None => Ok(default),
+    }
+}
+
+/// Computes a digest of an image that LSB embedding at `bit_depth` leaves unchanged
+///
+/// The low `bit_depth` bits of the R, G and B channels are masked out; the
+/// dimensions and alpha channel are hashed as they are. 16-bit images are
+/// hashed at full depth, since rounding them to 8 bits depends on the low bits.
+pub fn image_carrier_digest(image: &DynamicImage, bit_depth: u8) -> [u8; 32] {
+    if image.color().bytes_per_pixel() == 2 * image.color().channel_count() {
+        let buffer = image.to_rgba16();
+        let mask = u16::MAX.checked_shl(bit_depth as u32).unwrap_or(0);
+
+        let mut hasher = Sha256::new();
+        hasher.update(buffer.width().to_be_bytes());
+        hasher.update(buffer.height().to_be_bytes());
+        for pixel in buffer.pixels() {
+            let [r, g, b, a] = pixel.0;
+            for value in [r & mask, g & mask, b & mask, a] {
+                hasher.update(value.to_be_bytes());
+            }
+        }
+        return hasher.finalize().into();
+    }
+
+    let buffer = image.to_rgba8();
+    let mask = u8::MAX.checked_shl(bit_depth as u32).unwrap_or(0);
+
+    let mut hasher = Sha256::new();
+    hasher.update(buffer.width().to_be_bytes());
+    hasher.update(buffer.height().to_be_bytes());
+    for pixel in buffer.pixels() {
+        let [r, g, b, a] = pixel.0;
+        hasher.update([r & mask, g & mask, b & mask, a]);
+    }
+    hasher.finalize().into()
+}
+
+/// Embeds a payload container in an image
+///
+/// Bits are written into the colour samples of every pixel: R, G and B, or
+/// the gray channel of grayscale images, and the low byte of 16-bit samples.
+/// Alpha is left alone and the result keeps the image's colour type. The header
+/// always occupies the first channels at one bit each; the body follows at the
+/// configured bit depth. With the `order=random` parameter the header and body
+/// channels are visited in a permutation seeded by the `key` parameter, or by the
+/// password if no explicit key is given.
+///
+/// With `method=matrix` the body is matrix encoded into the lowest bits: the
+/// largest Hamming code that still fits the payload is chosen, so small
+/// payloads change far fewer channels than one per bit. With `lsb=matching`
+/// a channel that must change moves to the nearest value with the wanted bits,
+/// up or down at random, instead of having its low bits overwritten.
+///
+/// `method=adaptive` works like matrix embedding, but weighs every change by a
+/// cost computed from the local texture and flips two cheap channels instead
+/// of one expensive one where that is cheaper, so changes gather in noisy
+/// regions and edges. The `stealth` parameter (1-12, default 3) is the
+/// smallest code allowed, trading capacity for fewer and better placed
+/// changes. Extraction does not need the costs.
 pub fn embed_in_image(
     image: &DynamicImage,
-    data: &[u8],
+    container: &Container,
     config: &EmbeddingConfig,
 ) -> Result<DynamicImage> {
-    // Convert image to RGBA
-    let mut buffer = image.to_rgba8();
-    let (width, height) = buffer.dimensions();
+    // The `key` parameter or password seeds the embedding order; an empty
+    // key counts as none, as in `utils::stego_key`
+    let key = config
+        .parameters
+        .get("key")
+        .or(config.password.as_ref())
+        .map(|k| k.as_str())
+        .filter(|k| !k.is_empty());
+
+    let mut image = png::native_image(image.clone());
+    let mut plane = png::sample_plane(&image);
+    embed_in_plane(
+        &mut plane,
+        || png::sample_costs(&image),
+        container,
+        config,
+        key,
+    )?;
+    png::set_sample_plane(&mut image, &plane);
+    Ok(image)
+}
 
+/// Embeds a payload container in a plane of carrier slots
+///
+/// Each slot is one byte whose low bits carry data, as returned by
+/// [`png::sample_plane`] or [`PngCarrier::plane`]. `costs` gives the adaptive
+/// embedding cost of every slot and is only called for `method=adaptive`.
+/// `key` is the stego key that seeds the order under `order=random`.
+fn embed_in_plane(
+    plane: &mut [u8],
+    costs: impl FnOnce() -> Vec<f32>,
+    container: &Container,
+    config: &EmbeddingConfig,
+    key: Option<&str>,
+) -> Result<()> {
     // Get bit depth from configuration (default to 1 if not specified)
     let bit_depth = config
         .parameters
@@ -67,94 +259,208 @@ pub fn embed_in_image(
         .and_then(|v| v.parse::<u8>().ok())
         .unwrap_or(1);
 
-    // Validate bit depth
-    if !(1..=4).contains(&bit_depth) {
+    validate_bit_depth(bit_depth, Error::InvalidInput)?;
+
+    let method = config
+        .parameters
+        .get("method")
+        .map(|m| m.as_str())
+        .unwrap_or("lsb");
+    if !matches!(method, "lsb" | "matrix" | "adaptive") {
+        return Err(Error::InvalidInput(format!(
+            "Unknown image embedding method '{}', expected 'lsb', 'matrix' or 'adaptive'",
+            method
+        )));
+    }
+    let use_matrix = method != "lsb";
+    if use_matrix && bit_depth != 1 {
         return Err(Error::InvalidInput(format!(
-            "Bit depth must be between 1 and 4, got {}",
-            bit_depth
+            "{} embedding only uses the lowest bit; bit depth must be 1",
+            if method == "matrix" {
+                "Matrix"
+            } else {
+                "Adaptive"
+            }
         )));
     }
 
-    // Calculate capacity based on bit depth
-    let max_bytes = (width * height * bit_depth as u32) / 8;
-    if data.len() as u32 > max_bytes {
+    // Calculate capacity based on bit depth, leaving room for the header
+    let slots = plane.len();
+    let body_slots = slots.saturating_sub(HEADER_LENGTH * 8);
+    let max_bytes = body_slots * bit_depth as usize / 8;
+    if slots < HEADER_LENGTH * 8 || container.body.len() > max_bytes {
         return Err(Error::InvalidInput(format!(
             "Data too large for image with bit depth {}. Maximum capacity: {} bytes",
             bit_depth, max_bytes
         )));
     }
 
-    // Embed data length first (4 bytes)
-    let len_bytes = (data.len() as u32).to_be_bytes();
-    embed_bytes(&mut buffer, 0, &len_bytes, bit_depth)?;
+    // Work out which channel each slot of the payload lands in; the header
+    // takes the first slots of the order at one bit per channel
+    let order = EmbeddingOrder::from_parameters(Some(&config.parameters))?;
+    let positions = embedding_positions(order, key, slots)?;
+    let (header_positions, body_positions) = positions.split_at(HEADER_LENGTH * 8);
+
+    // Replace or match the low bits; matching needs randomness
+    let change = LsbChange::from_parameters(Some(&config.parameters))?;
+    let mut rng = rand::thread_rng();
+
+    // Record the carrier settings in the header
+    let mut header = container.header.clone();
+    header.bit_depth = bit_depth;
+    if order == EmbeddingOrder::Random {
+        header.flags |= FLAG_RANDOM_ORDER;
+    }
 
-    // Embed actual data
-    embed_bytes(&mut buffer, 32, data, bit_depth)?;
+    if use_matrix {
+        // The capacity check above guarantees that k = 1 fits; adaptive
+        // embedding may not go below the stealth level
+        let k = matrix::choose_k(container.body.len(), body_slots).unwrap_or(1);
+        let costs = if method == "adaptive" {
+            let stealth = adaptive::stealth_from_parameters(&config.parameters)?;
+            if k < stealth {
+                return Err(Error::InvalidInput(format!(
+                    "Data too large for adaptive embedding at stealth {}. Maximum capacity: {} bytes",
+                    stealth,
+                    adaptive::capacity(body_slots, stealth)
+                )));
+            }
+            let costs = costs();
+            body_positions.iter().map(|&slot| costs[slot]).collect()
+        } else {
+            vec![1.0; body_positions.len()]
+        };
+        header.flags |= FLAG_MATRIX;
+        header.bit_depth = k;
 
-    Ok(DynamicImage::ImageRgba8(buffer))
+        let cover: Vec<u8> = body_positions.iter().map(|&slot| plane[slot] & 1).collect();
+        for i in matrix::embed_with_costs(&cover, &costs, &container.body, k)? {
+            let value = &mut plane[body_positions[i]];
+            *value = change.apply(*value, (*value & 1) ^ 1, 1, &mut rng);
+        }
+    } else {
+        embed_bytes(
+            plane,
+            body_positions,
+            0,
+            &container.body,
+            bit_depth,
+            change,
+            &mut rng,
+        )?;
+    }
+
+    // Embed the header at one bit per channel
+    embed_bytes(
+        plane,
+        header_positions,
+        0,
+        &header.to_bytes(),
+        1,
+        change,
+        &mut rng,
+    )
 }
 
-/// Helper function to embed bytes in image
+/// Helper function to embed bytes in a plane of carrier slots
+///
+/// The payload is treated as a bit stream in which every slot holds `bit_depth`
+/// bits. Slot `n` of the stream is `plane[positions[n]]`. `change` decides how
+/// a slot is moved to its new low bits.
 fn embed_bytes(
-    buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    plane: &mut [u8],
+    positions: &[usize],
     start_bit: usize,
     data: &[u8],
     bit_depth: u8,
+    change: LsbChange,
+    rng: &mut impl Rng,
 ) -> Result<()> {
-    let mut bit_index = start_bit;
-    let bit_depth = bit_depth as usize;
-
-    for &byte in data {
-        for bit in 0..8 {
-            let x = ((bit_index + bit) / 8) as u32 % buffer.width();
-            let y = ((bit_index + bit) / 8) as u32 / buffer.width();
-
-            let mut pixel = *buffer.get_pixel(x, y);
-            let color_index = (bit_index + bit) % 3;
-
-            // Clear LSB bits based on bit_depth and set them to current data bit
-            let mask = !(((1 << bit_depth) - 1) as u8);
-            let bit_value = (byte >> (7 - bit)) & ((1 << bit_depth) - 1);
-
-            pixel[color_index] &= mask;
-            pixel[color_index] |= bit_value;
-
-            buffer.put_pixel(x, y, pixel);
+    let depth = bit_depth as usize;
+    let end_bit = start_bit + data.len() * 8;
+
+    let slots = positions
+        .iter()
+        .enumerate()
+        .take(end_bit.div_ceil(depth))
+        .skip(start_bit / depth);
+    for (slot_index, &slot) in slots {
+        // Collect the data bits that fall in this slot; a partly covered slot
+        // keeps its other bits
+        let mut bits = 0u8;
+        let mut mask = 0u8;
+        for stream_bit in (slot_index * depth..(slot_index + 1) * depth)
+            .filter(|stream_bit| (start_bit..end_bit).contains(stream_bit))
+        {
+            let data_bit = stream_bit - start_bit;
+            let position = depth - 1 - stream_bit % depth;
+            bits |= ((data[data_bit / 8] >> (7 - data_bit % 8)) & 1) << position;
+            mask |= 1 << position;
         }
-        bit_index += 8;
+
+        let channel = &mut plane[slot];
+        let low_bits = ((*channel & !mask) | bits) & ((1 << depth) - 1) as u8;
+        *channel = change.apply(*channel, low_bits, bit_depth, rng);
     }
 
     Ok(())
 }
 
-/// Saves the image with embedded data to a file
-pub fn save_image_with_embedded_data(image: &DynamicImage, output_path: &Path) -> Result<()> {
-    image
-        .save(output_path)
+/// Saves the image with embedded data to a PNG file, with the ancillary
+/// chunks and interlacing of the cover it came from
+pub fn save_image_with_embedded_data(
+    image: &DynamicImage,
+    output_path: &Path,
+    metadata: &png::PngMetadata,
+    encoding: &png::PngEncoding,
+) -> Result<()> {
+    let bytes = png::encode_samples(image, metadata, encoding)?;
+    std::fs::write(output_path, bytes)
         .map_err(|e| Error::Io(format!("Failed to save image: {}", e)))
 }
 
-/// Embeds data into a PDF file
+/// Embeds data into a file, choosing the method from the carrier's format
+///
+/// The format is detected from the input file's signature, falling back to
+/// its extension.
 pub fn embed_data(config: EmbedConfig) -> Result<()> {
+    match sniff_file_format(Path::new(&config.input_path))? {
+        FileFormat::Png => embed_in_png(config),
+        FileFormat::Jpg => embed_in_jpg(config),
+        FileFormat::Bmp => embed_in_bmp(config),
+        FileFormat::Gif => embed_in_gif(config),
+        FileFormat::Pdf => embed_in_pdf(config),
+        FileFormat::Wav => embed_in_wav(config),
+        FileFormat::Mp3 => embed_in_mp3(config),
+        FileFormat::Mp4 => embed_in_mp4(config),
+        format => Err(Error::InvalidInput(format!(
+            "Unsupported file format: {:?}",
+            format
+        ))),
+    }
+}
+
+/// Embeds data into a PDF file
+pub fn embed_in_pdf(config: EmbedConfig) -> Result<()> {
     // Validate input data
     validate_data(&config.data)?;
 
     // Initialize PDF handler
     let mut handler = PdfHandler::new(&config.input_path)?;
 
-    // Process data (encrypt if needed)
-    let processed_data = if let Some(crypto_config) = config.encryption {
-        match crypto_config.algorithm {
-            Algorithm::Aes256 => crate::encryption::encrypt(&config.data, &crypto_config)?,
-            Algorithm::ChaCha20 => crate::encryption::encrypt(&config.data, &crypto_config)?,
-            Algorithm::Rsa => crate::encryption::encrypt(&config.data, &crypto_config)?,
-        }
-    } else {
-        config.data
-    };
+    // Process data (encrypt and sign if needed)
+    let carrier_digest = config
+        .signing_key
+        .as_ref()
+        .map(|_| handler.carrier_digest());
+    let container = seal_payload(
+        &config,
+        ecc_scheme(&config, EccScheme::None)?,
+        carrier_digest,
+    )?;
 
     // Embed the data
-    handler.embed_data(&processed_data)?;
+    handler.embed_data(&container.to_bytes())?;
 
     // Save the modified PDF
     handler.save(&config.output_path)?;
@@ -163,256 +469,301 @@ pub fn embed_data(config: EmbedConfig) -> Result<()> {
 }
 
 /// Embeds data into a PNG image
+///
+/// The image keeps its colour type and bit depth: grayscale, alpha and 16-bit
+/// images carry bits in their own samples, and indexed images in the parity
+/// of their palette indices, one bit per pixel.
+///
+/// The cover's ancillary chunks are written back in their original order;
+/// the `zlib_level` and `filter` parameters choose how the image data is
+/// compressed.
+///
+/// With `method=chunk` the pixels are left untouched and the payload is
+/// stored in an ancillary chunk instead, chosen with the `chunk` parameter.
 pub fn embed_in_png(config: EmbedConfig) -> Result<()> {
     // Validate input data
     validate_data(&config.data)?;
 
-    // Load the image
-    let img = image::open(&config.input_path)
-        .map_err(|e| Error::InvalidInput(format!("Failed to open image: {}", e)))?;
+    let parameter = |name: &str| config.parameters.as_ref().and_then(|p| p.get(name));
+    if parameter("method").map(|m| m.as_str()) == Some("chunk") {
+        return embed_in_png_chunk(&config);
+    }
 
-    // Process data (encrypt if needed)
-    let processed_data = if let Some(crypto_config) = &config.encryption {
-        match crypto_config.algorithm {
-            Algorithm::Aes256 => crate::encryption::encrypt(&config.data, crypto_config)?,
-            Algorithm::ChaCha20 => crate::encryption::encrypt(&config.data, crypto_config)?,
-            Algorithm::Rsa => crate::encryption::encrypt(&config.data, crypto_config)?,
-        }
-    } else {
-        config.data
-    };
+    // Load the image in its native representation, keeping the cover's
+    // ancillary chunks for the output
+    let encoding = png::PngEncoding::from_parameters(config.parameters.as_ref())?;
+    let (mut carrier, metadata) = PngCarrier::open_with_metadata(&config.input_path)?;
 
     // Create embedding config with parameters
     let mut parameters = std::collections::HashMap::new();
     parameters.insert("bit_depth".to_string(), "1".to_string()); // Default bit depth
 
-    // If we have compression settings, add them
-    if let Some(compression) = std::env::var_os("NHALE_COMPRESSION") {
-        if let Some(compression_str) = compression.to_str() {
-            parameters.insert("compression".to_string(), compression_str.to_string());
-        }
+    // Caller-supplied parameters take precedence over the defaults
+    if let Some(params) = &config.parameters {
+        parameters.extend(params.clone());
     }
 
     let embedding_config = EmbeddingConfig {
         media_type: MediaType::Image,
         use_encryption: config.encryption.is_some(),
-        password: config.encryption.as_ref().map(|c| c.password.clone()),
+        // Key-pair encryption has no password to seed the embedding order
+        password: config
+            .encryption
+            .as_ref()
+            .map(|c| c.password.clone())
+            .filter(|p| !p.is_empty()),
         parameters,
     };
 
+    let bit_depth = embedding_config
+        .parameters
+        .get("bit_depth")
+        .and_then(|v| v.parse::<u8>().ok())
+        .unwrap_or(1);
+    let change = LsbChange::from_parameters(Some(&embedding_config.parameters))?;
+
+    // The carrier digest covers the bits above the bit depth, which LSB
+    // matching can carry into
+    if config.signing_key.is_some() && change == LsbChange::Match {
+        return Err(Error::InvalidInput(
+            "Signed payloads cannot use LSB matching, which may change the bits the signature covers"
+                .into(),
+        ));
+    }
+
+    // A palette index can only move to the other colour of its pair, and
+    // low-depth gray samples have no bits above their depth
+    if let Some(slot_bits) = carrier.slot_bits() {
+        if bit_depth > slot_bits || change == LsbChange::Match {
+            return Err(Error::InvalidInput(format!(
+                "Indexed and low bit depth gray PNGs carry at most {} bits per pixel; \
+                 LSB matching does not apply",
+                slot_bits
+            )));
+        }
+    }
+
+    // Process data (encrypt and sign if needed)
+    let carrier_digest = config
+        .signing_key
+        .as_ref()
+        .map(|_| carrier.carrier_digest(bit_depth));
+    let container = seal_payload(
+        &config,
+        ecc_scheme(&config, EccScheme::None)?,
+        carrier_digest,
+    )?;
+
     // Embed the data into the image
-    let image_with_data = embed_in_image(&img, &processed_data, &embedding_config)?;
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
+    let mut plane = carrier.plane();
+    embed_in_plane(
+        &mut plane,
+        || carrier.slot_costs(),
+        &container,
+        &embedding_config,
+        key,
+    )?;
+    carrier.set_plane(&plane);
 
     // Save the image with embedded data
-    save_image_with_embedded_data(&image_with_data, Path::new(&config.output_path))?;
+    carrier.save(Path::new(&config.output_path), &metadata, &encoding)
+}
 
-    Ok(())
+/// Stores data in an ancillary chunk of a PNG file, leaving the image data as it was
+fn embed_in_png_chunk(config: &EmbedConfig) -> Result<()> {
+    let chunk = png::PayloadChunk::from_parameters(config.parameters.as_ref())?;
+    let bytes = std::fs::read(&config.input_path)
+        .map_err(|e| Error::Io(format!("Failed to read PNG {}: {}", config.input_path, e)))?;
+
+    // Process data (encrypt and sign if needed)
+    let carrier_digest = match config.signing_key {
+        Some(_) => Some(png::chunk_carrier_digest(&bytes)?),
+        None => None,
+    };
+    let container = seal_payload(config, ecc_scheme(config, EccScheme::None)?, carrier_digest)?;
+
+    // Write the file with the payload chunk added
+    let output = png::embed_in_chunk(&bytes, &container.to_bytes(), chunk)?;
+    std::fs::write(&config.output_path, output)
+        .map_err(|e| Error::Io(format!("Failed to save PNG: {}", e)))
 }
 
-/// Embeds data into a JPG image using DCT coefficient modification
-pub fn embed_in_jpg(config: EmbedConfig) -> Result<()> {
-    // Validate inputs
+/// Embeds data into a BMP image's pixel samples
+///
+/// Only the low bits of the samples change; the headers, palette, row
+/// padding and row order are written back exactly as they were. The
+/// `bit_depth`, `order` and `key` parameters work as for PNG.
+pub fn embed_in_bmp(config: EmbedConfig) -> Result<()> {
+    // Validate input data
     validate_data(&config.data)?;
 
-    // Process data (including encryption if specified)
-    let data = process_data(&config.data, &config.encryption)?;
+    let parameter = |name: &str| config.parameters.as_ref().and_then(|p| p.get(name));
+    let bit_depth = match parameter("bit_depth") {
+        Some(value) => value
+            .parse::<u8>()
+            .map_err(|_| Error::InvalidInput(format!("Invalid bit depth: {}", value)))?,
+        None => 1,
+    };
+    let order = EmbeddingOrder::from_parameters(config.parameters.as_ref())?;
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
+
+    // Initialize BMP handler
+    let mut handler = BmpHandler::new(&config.input_path)?;
+
+    // Process data (encrypt and sign if needed)
+    let carrier_digest = config
+        .signing_key
+        .as_ref()
+        .map(|_| handler.carrier_digest(bit_depth));
+    let container = seal_payload(
+        &config,
+        ecc_scheme(&config, EccScheme::None)?,
+        carrier_digest,
+    )?;
+
+    // Embed the data and save the modified BMP
+    handler.embed_data(&container, bit_depth, order, key)?;
+    handler.save(&config.output_path)
+}
 
-    // Apply Reed-Solomon error correction to the data for improved robustness
-    let reed_solomon_config = error_correction::ReedSolomonConfig::default();
-    let protected_data = error_correction::encode_reed_solomon(&data, &reed_solomon_config)?;
+/// Embeds data into the palette indices of a GIF image
+///
+/// Each usable pixel carries one bit, so the `bit_depth` parameter does not
+/// apply; `order` and `key` work as for PNG. All frames of an animation carry
+/// part of the payload, and the palettes, frame count and timing are kept.
+pub fn embed_in_gif(config: EmbedConfig) -> Result<()> {
+    // Validate input data
+    validate_data(&config.data)?;
 
-    // Open the input JPEG file
-    let file = File::open(&config.input_path)
-        .map_err(|e| Error::InvalidInput(format!("Failed to open input file: {}", e)))?;
+    let order = EmbeddingOrder::from_parameters(config.parameters.as_ref())?;
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
+
+    // Initialize GIF handler
+    let mut handler = GifHandler::new(&config.input_path)?;
+
+    // Process data (encrypt and sign if needed)
+    let carrier_digest = config
+        .signing_key
+        .as_ref()
+        .map(|_| handler.carrier_digest());
+    let container = seal_payload(
+        &config,
+        ecc_scheme(&config, EccScheme::None)?,
+        carrier_digest,
+    )?;
+
+    // Embed the data and save the modified GIF
+    handler.embed_data(&container, order, key)?;
+    handler.save(&config.output_path)
+}
 
-    // Create a decoder to read the JPEG metadata
-    let mut decoder = Decoder::new(BufReader::new(file));
+/// Embeds data into a JPG image's quantized DCT coefficients
+///
+/// The coefficients are read from the entropy-coded data, modified with F5
+/// matrix encoding and written back without requantization, so the output has
+/// the same quantization tables and quality as the cover. With the
+/// `order=random` parameter the body is spread over the coefficients in a
+/// permutation seeded by the `key` parameter or the password.
+///
+/// With `method=robust` the payload is embedded with quantization index
+/// modulation instead, so it survives re-encoding at the `quality` parameter
+/// (50-95, default 75) or above.
+///
+/// The payload is Reed-Solomon coded; `ecc=convolutional` selects a
+/// convolutional code instead, which robust extraction decodes from the
+/// reliability of every bit and so tolerates much heavier recompression.
+pub fn embed_in_jpg(config: EmbedConfig) -> Result<()> {
+    // Validate inputs
+    validate_data(&config.data)?;
 
-    // Read info without decoding the image
-    decoder
-        .read_info()
-        .map_err(|e| Error::InvalidInput(format!("Failed to read JPEG info: {}", e)))?;
+    let mut image = JpegImage::open(Path::new(&config.input_path))?;
 
-    // Get the metadata
-    let jpeg_info = match decoder.info() {
-        Some(info) => info,
-        None => {
-            return Err(Error::InvalidInput(
-                "Failed to get JPEG information".to_string(),
-            ))
+    let parameter = |name: &str| {
+        config
+            .parameters
+            .as_ref()
+            .and_then(|p| p.get(name).cloned())
+    };
+    let robust = match parameter("method").as_deref() {
+        None | Some("f5") => false,
+        Some("robust") => true,
+        Some(other) => {
+            return Err(Error::InvalidInput(format!(
+                "Unknown JPEG embedding method '{}', expected 'f5' or 'robust'",
+                other
+            )))
         }
     };
 
-    // Decode the image
-    let pixels = decoder
-        .decode()
-        .map_err(|e| Error::InvalidInput(format!("Failed to decode JPEG image: {}", e)))?;
-
-    // Check the pixel format and determine the number of color channels
-    let (width, height) = (jpeg_info.width as usize, jpeg_info.height as usize);
-    let pixel_format = jpeg_info.pixel_format;
-
-    // Only support RGB24 and CMYK32 formats
-    if pixel_format != PixelFormat::RGB24 && pixel_format != PixelFormat::CMYK32 {
-        return Err(Error::InvalidInput(format!(
-            "Unsupported pixel format: {:?}, only RGB24 and CMYK32 are supported",
-            pixel_format
-        )));
+    // Process data (including encryption if specified) and apply error
+    // correction, Reed-Solomon unless the `ecc` parameter says otherwise.
+    // Recompression leaves nothing of the carrier for robust payloads to be
+    // bound to, so only F5 signatures cover it
+    let carrier_digest = (config.signing_key.is_some() && !robust).then(|| image.carrier_digest());
+    let container = seal_payload(
+        &config,
+        ecc_scheme(&config, EccScheme::ReedSolomon)?,
+        carrier_digest,
+    )?;
+
+    let order = EmbeddingOrder::from_parameters(config.parameters.as_ref())?;
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
+
+    if robust {
+        let quality = match parameter("quality") {
+            Some(quality) => quality
+                .parse::<u8>()
+                .map_err(|_| Error::InvalidInput(format!("Invalid target quality: {}", quality)))?,
+            None => qim::DEFAULT_TARGET_QUALITY,
+        };
+        qim::embed_container(&mut image, &container, quality, order, key)?
+    } else {
+        f5::embed_container(&mut image, &container, order, key)?
     }
 
-    // Determine number of color channels
-    let channels = match pixel_format {
-        PixelFormat::RGB24 => 3,
-        PixelFormat::CMYK32 => 4,
-        _ => unreachable!(), // We already checked for RGB24 and CMYK32
-    };
+    image.save(Path::new(&config.output_path))
+}
 
-    // Calculate the maximum data we can embed
-    // We'll use 1 bit per 8x8 block, which is more robust against JPEG compression
-    let max_blocks_x = width / 8;
-    let max_blocks_y = height / 8;
-    let capacity_bytes = (max_blocks_x * max_blocks_y) / 8;
-
-    println!(
-        "Debug: Image dimensions: {}x{}, pixel format: {:?}",
-        width, height, pixel_format
-    );
-    println!(
-        "Debug: Maximum blocks: {}x{}, capacity: {} bytes",
-        max_blocks_x, max_blocks_y, capacity_bytes
-    );
-
-    // Check if the data fits
-    if protected_data.len() + 4 > capacity_bytes {
-        return Err(Error::InvalidInput(
-            format!("Data is too large to be embedded with Reed-Solomon error correction. Maximum capacity: {} bytes, needed: {} bytes", 
-                   capacity_bytes, protected_data.len() + 4)
-        ));
-    }
+/// Embeds data into a WAV audio file using LSB steganography on the samples
+///
+/// The payload carries no error correction unless the `ecc` parameter asks
+/// for `reed-solomon` or `convolutional` coding.
+pub fn embed_in_wav(config: EmbedConfig) -> Result<()> {
+    // Validate input data
+    validate_data(&config.data)?;
 
-    // Prepare the data to embed: 4 bytes for length followed by error-protected data
-    let mut data_to_embed = Vec::with_capacity(protected_data.len() + 4);
-    data_to_embed.extend_from_slice(&(protected_data.len() as u32).to_be_bytes());
-    data_to_embed.extend_from_slice(&protected_data);
-
-    // Convert data to bits
-    let bits = bytes_to_bits(&data_to_embed);
-    println!(
-        "Debug: Embedding {} bits ({} bytes with Reed-Solomon error correction)",
-        bits.len(),
-        protected_data.len()
-    );
-
-    // Create a mutable copy of the image pixels
-    let mut modified_pixels = pixels.clone();
-
-    // Embed data by modifying pixels - use a more robust method
-    // For each 8x8 block, we'll modify the average blue channel value to be even or odd
-    let mut bit_index = 0;
-    for by in 0..max_blocks_y {
-        for bx in 0..max_blocks_x {
-            if bit_index < bits.len() {
-                // Calculate the average blue value for this 8x8 block
-                let mut blue_sum = 0;
-                let mut pixel_count = 0;
-
-                for y in by * 8..(by + 1) * 8 {
-                    for x in bx * 8..(bx + 1) * 8 {
-                        if y < height && x < width {
-                            let pixel_pos = (y * width + x) * channels + 2; // +2 for blue channel
-                            if pixel_pos < modified_pixels.len() {
-                                blue_sum += modified_pixels[pixel_pos] as u32;
-                                pixel_count += 1;
-                            }
-                        }
-                    }
-                }
+    // Get bit depth from parameters if provided, default to 1
+    let bit_depth = config
+        .parameters
+        .as_ref()
+        .and_then(|params| params.get("bit_depth"))
+        .and_then(|v| v.parse::<u8>().ok())
+        .unwrap_or(1);
 
-                if pixel_count > 0 {
-                    // Calculate average
-                    let avg_blue = (blue_sum / pixel_count) as u8;
-
-                    // Determine if we need to make it even or odd based on the bit
-                    let target_parity = if bits[bit_index] { 1 } else { 0 }; // 1 for odd, 0 for even
-                    let current_parity = avg_blue % 2;
-
-                    // If the parity doesn't match, adjust all blue values in the block
-                    if current_parity != target_parity {
-                        let adjustment = if current_parity == 0 { 1 } else { -1 };
-
-                        // Apply the adjustment to all pixels in the block
-                        for y in by * 8..(by + 1) * 8 {
-                            for x in bx * 8..(bx + 1) * 8 {
-                                if y < height && x < width {
-                                    let pixel_pos = (y * width + x) * channels + 2; // +2 for blue channel
-                                    if pixel_pos < modified_pixels.len() {
-                                        // Apply adjustment, ensuring we stay within 0-255 range
-                                        let new_value =
-                                            modified_pixels[pixel_pos] as i16 + adjustment;
-                                        modified_pixels[pixel_pos] = new_value.clamp(0, 255) as u8;
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    bit_index += 1;
-                }
-            }
-        }
-    }
+    // Initialize WAV handler
+    let mut handler = WavHandler::new(&config.input_path)?;
 
-    // Create an output file
-    let output_file = File::create(&config.output_path)
-        .map_err(|e| Error::Io(format!("Failed to create output file: {}", e)))?;
+    // Process data (encrypt and sign if needed)
+    let carrier_digest = config
+        .signing_key
+        .as_ref()
+        .map(|_| handler.carrier_digest(bit_depth));
+    let container = seal_payload(
+        &config,
+        ecc_scheme(&config, EccScheme::None)?,
+        carrier_digest,
+    )?;
 
-    // Encode the modified image
-    let color_type = match pixel_format {
-        PixelFormat::RGB24 => ColorType::Rgb,
-        PixelFormat::CMYK32 => ColorType::Cmyk,
-        _ => unreachable!(),
-    };
+    // Embed the data
+    handler.embed_data(&container, bit_depth)?;
 
-    let encoder = Encoder::new(BufWriter::new(output_file), 95); // Higher quality to preserve hidden data
-    encoder
-        .encode(&modified_pixels, width as u16, height as u16, color_type)
-        .map_err(|e| Error::Io(format!("Failed to encode output image: {}", e)))?;
+    // Save the modified WAV
+    handler.save(&config.output_path)?;
 
     Ok(())
 }
 
-// Helper function to process data with optional encryption
-fn process_data(data: &[u8], crypto_config: &Option<CryptoConfig>) -> Result<Vec<u8>> {
-    if let Some(config) = crypto_config {
-        match config.algorithm {
-            Algorithm::Aes256 => crate::encryption::encrypt(data, config),
-            Algorithm::ChaCha20 => crate::encryption::encrypt(data, config),
-            Algorithm::Rsa => crate::encryption::encrypt(data, config),
-        }
-    } else {
-        Ok(data.to_vec())
-    }
-}
-
-// Helper function to convert bytes to bits
-fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
-    let mut bits = Vec::with_capacity(bytes.len() * 8);
-    for &byte in bytes {
-        for i in 0..8 {
-            bits.push(((byte >> (7 - i)) & 1) == 1);
-        }
-    }
-    bits
-}
-
-/// Embeds data into a WAV audio file
-pub fn embed_in_wav(_config: EmbedConfig) -> Result<()> {
-    // TODO: Implement WAV steganography (LSB in audio samples)
-    Err(Error::NotImplemented(
-        "WAV embedding not yet implemented".into(),
-    ))
-}
-
 /// Embeds data into an MP3 audio file
 pub fn embed_in_mp3(_config: EmbedConfig) -> Result<()> {
     // TODO: Implement MP3 steganography
@@ -432,10 +783,9 @@ pub fn embed_in_mp4(_config: EmbedConfig) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::extraction::ExtractConfig;
+    use crate::encryption::Algorithm;
     use image::{Rgb, RgbImage};
     use lopdf::{dictionary, Document, Object};
-    use std::path::PathBuf;
     use tempfile::tempdir;
 
     #[test]
@@ -475,7 +825,7 @@ mod tests {
             input_path: input_path.to_str().unwrap().to_string(),
             output_path: output_path.to_str().unwrap().to_string(),
             data: b"Test data".to_vec(),
-            encryption: None,
+            ..Default::default()
         };
 
         embed_data(config)?;
@@ -484,348 +834,1515 @@ mod tests {
         Ok(())
     }
 
+    /// Writes a one-page PDF whose catalog points to an XMP metadata stream
+    fn create_test_pdf(path: &Path, width: i64) {
+        let mut doc = Document::new();
+        let pages_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+        });
+        let pages = dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![Object::Reference(page_id)],
+            "Count" => 1,
+            "MediaBox" => vec![0.into(), 0.into(), width.into(), 842.into()],
+        };
+        doc.objects.insert(pages_id, Object::Dictionary(pages));
+        let xmp_id = doc.add_object(lopdf::Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec(),
+        ));
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "Metadata" => xmp_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc.save(path).unwrap();
+    }
+
     #[test]
-    fn test_jpg_steganography() {
-        // Create a controlled test that doesn't rely on actual JPEG compression
-        // This tests the core functionality of our embedding and extraction algorithms
-
-        // Create a simple 64x64 RGB image (8 blocks x 8 blocks = 64 blocks)
-        let width = 64;
-        let height = 64;
-        let mut pixels = vec![0u8; width * height * 3]; // RGB format
-
-        // Fill with a solid color
-        for i in 0..pixels.len() {
-            // Set all channels to 100 to start with a known value
-            pixels[i] = 100;
+    fn test_pdf_signature_binds_carrier() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.pdf");
+        let output_path = dir.path().join("output.pdf");
+        create_test_pdf(&input_path, 595);
+
+        let signer = Ed25519KeyPair::generate().unwrap();
+        embed_in_pdf(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Signed document".to_vec(),
+            signing_key: Some(signer.export_secret().into_bytes()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            PdfHandler::new(&output_path.to_string_lossy())
+                .unwrap()
+                .carrier_digest(),
+            PdfHandler::new(&input_path.to_string_lossy())
+                .unwrap()
+                .carrier_digest()
+        );
+
+        let extract = |path: &Path| {
+            crate::extraction::extract_with_report(crate::extraction::ExtractConfig {
+                input_path: path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+        };
+        let report = extract(&output_path).unwrap();
+        assert_eq!(report.data, b"Signed document");
+        assert_eq!(
+            report.signature,
+            crate::extraction::SignatureStatus::Untrusted(signer.public_key())
+        );
+
+        // The same payload stream moved into another document is rejected
+        let other_path = dir.path().join("other.pdf");
+        create_test_pdf(&other_path, 612);
+        let payload = PdfHandler::new(&output_path.to_string_lossy())
+            .unwrap()
+            .extract_data()
+            .unwrap();
+        let mut other = PdfHandler::new(&other_path.to_string_lossy()).unwrap();
+        other.embed_data(&payload).unwrap();
+        other.save(&other_path.to_string_lossy()).unwrap();
+        assert!(matches!(extract(&other_path), Err(Error::Integrity(_))));
+    }
+
+    fn create_test_png(path: &std::path::Path, width: u32, height: u32) {
+        let mut img = RgbImage::new(width, height);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = Rgb([(x * 3) as u8, (y * 5) as u8, ((x + y) * 7) as u8]);
         }
+        img.save(path).unwrap();
+    }
+
+    #[test]
+    fn test_png_steganography() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        create_test_png(&input_path, 64, 64);
+
+        for bit_depth in 1..=4 {
+            let output_path = dir.path().join(format!("output_{}.png", bit_depth));
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("bit_depth".to_string(), bit_depth.to_string());
+
+            let embed_config = EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: b"PNG test message".to_vec(),
+                parameters: Some(parameters),
+                ..Default::default()
+            };
+            embed_in_png(embed_config).unwrap();
+
+            // The bit depth is read back from the payload header
+            let extract_config = crate::extraction::ExtractConfig {
+                input_path: output_path.to_string_lossy().to_string(),
+                ..Default::default()
+            };
+            let extracted = crate::extraction::extract_from_png(extract_config).unwrap();
+            assert_eq!(extracted, b"PNG test message");
+        }
+    }
+
+    #[test]
+    fn test_png_random_order() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        let output_path = dir.path().join("output.png");
+        create_test_png(&input_path, 64, 64);
 
-        // Test data to embed
-        let test_data = b"TEST";
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("order".to_string(), "random".to_string());
+        parameters.insert("key".to_string(), "stego key".to_string());
 
-        // Manually prepare the data with length prefix
-        let mut data_with_length = Vec::new();
-        data_with_length.extend_from_slice(&(test_data.len() as u32).to_be_bytes());
-        data_with_length.extend_from_slice(test_data);
+        let embed_config = EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Scattered across the image".to_vec(),
+            parameters: Some(parameters.clone()),
+            ..Default::default()
+        };
+        embed_in_png(embed_config).unwrap();
 
-        println!(
-            "Test: Created {} bits from {} bytes of data",
-            data_with_length.len() * 8,
-            data_with_length.len()
+        // The first rows carry neither the payload nor its header
+        let sequential = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        });
+        assert!(sequential.is_err());
+
+        let extracted = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            parameters: Some(parameters.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, b"Scattered across the image");
+
+        // A different key yields a different permutation
+        parameters.insert("key".to_string(), "wrong key".to_string());
+        let wrong = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            parameters: Some(parameters),
+            ..Default::default()
+        });
+        assert!(!matches!(wrong, Ok(ref data) if data == b"Scattered across the image"));
+    }
+
+    #[test]
+    fn test_png_random_order_rejects_empty_key() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        create_test_png(&input_path, 64, 64);
+
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("order".to_string(), "random".to_string());
+        parameters.insert("key".to_string(), String::new());
+        let result = embed_in_png(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: dir.path().join("output.png").to_string_lossy().to_string(),
+            data: b"Nowhere to be found".to_vec(),
+            parameters: Some(parameters),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+
+        // An empty password is no key either
+        let container = Container::seal(b"Nowhere to be found", None, EccScheme::None).unwrap();
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("order".to_string(), "random".to_string());
+        let result = embed_in_image(
+            &crate::utils::open_image(&input_path).unwrap(),
+            &container,
+            &EmbeddingConfig {
+                media_type: MediaType::Image,
+                use_encryption: false,
+                password: Some(String::new()),
+                parameters,
+            },
         );
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
 
-        // Convert data to bits
-        let mut bits = Vec::new();
-        for byte in &data_with_length {
-            for i in 0..8 {
-                bits.push((byte & (1 << (7 - i))) != 0);
-            }
+    #[test]
+    fn test_png_matrix_embedding() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        create_test_png(&input_path, 128, 128);
+        let cover = image::open(&input_path).unwrap().to_rgb8();
+
+        let mut changes = Vec::new();
+        for method in ["lsb", "matrix"] {
+            let output_path = dir.path().join(format!("{}.png", method));
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), method.to_string());
+            parameters.insert("order".to_string(), "random".to_string());
+            parameters.insert("key".to_string(), "stego key".to_string());
+
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: b"A short note in a large image".to_vec(),
+                parameters: Some(parameters.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+
+            let extracted = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+                input_path: output_path.to_string_lossy().to_string(),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(extracted, b"A short note in a large image");
+
+            // Count the changed channels outside the header
+            let stego = image::open(&output_path).unwrap().to_rgb8();
+            let positions =
+                embedding_positions(EmbeddingOrder::Random, Some("stego key"), cover.len())
+                    .unwrap();
+            let changed = positions[HEADER_LENGTH * 8..]
+                .iter()
+                .filter(|&&slot| cover.as_raw()[slot] != stego.as_raw()[slot])
+                .count();
+            changes.push(changed);
+        }
+        assert!(
+            changes[1] * 3 < changes[0],
+            "LSB changed {} channels, matrix embedding {}",
+            changes[0],
+            changes[1]
+        );
+
+        // Deeper bit planes and unknown methods are rejected
+        for (name, value) in [("bit_depth", "2"), ("method", "lsb-matching")] {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), "matrix".to_string());
+            parameters.insert(name.to_string(), value.to_string());
+            let result = embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: dir.path().join("invalid.png").to_string_lossy().to_string(),
+                data: b"A short note".to_vec(),
+                parameters: Some(parameters),
+                ..Default::default()
+            });
+            assert!(matches!(result, Err(Error::InvalidInput(_))));
         }
+    }
+
+    #[test]
+    fn test_png_lsb_matching() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        create_test_png(&input_path, 64, 64);
+        let cover = image::open(&input_path).unwrap().to_rgb8();
+
+        for method in ["lsb", "matrix"] {
+            let output_path = dir.path().join(format!("{}.png", method));
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("lsb".to_string(), "matching".to_string());
+            parameters.insert("method".to_string(), method.to_string());
+
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: b"Matched, not replaced".repeat(10),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+            .unwrap();
 
-        // Manually embed bits into the image using our algorithm
-        let mut bit_index = 0;
-        let channels = 3; // RGB
-
-        for by in 0..8 {
-            // 8 blocks vertically
-            for bx in 0..8 {
-                // 8 blocks horizontally
-                if bit_index < bits.len() {
-                    // Calculate the average blue value for this 8x8 block
-                    let mut blue_sum = 0;
-                    let mut pixel_count = 0;
-
-                    for y in by * 8..(by + 1) * 8 {
-                        for x in bx * 8..(bx + 1) * 8 {
-                            if y < height && x < width {
-                                let pixel_pos = (y * width + x) * channels + 2; // +2 for blue channel
-                                blue_sum += pixels[pixel_pos] as u32;
-                                pixel_count += 1;
-                            }
-                        }
-                    }
-
-                    // Calculate average
-                    let avg_blue = (blue_sum / pixel_count) as u8;
-
-                    // Determine if we need to make it even or odd based on the bit
-                    let target_parity = if bits[bit_index] { 1 } else { 0 }; // 1 for odd, 0 for even
-                    let current_parity = avg_blue % 2;
-
-                    // If the parity doesn't match, adjust all blue values in the block
-                    if current_parity != target_parity {
-                        let adjustment = if current_parity == 0 { 1 } else { -1 };
-
-                        // Apply the adjustment to all pixels in the block
-                        for y in by * 8..(by + 1) * 8 {
-                            for x in bx * 8..(bx + 1) * 8 {
-                                if y < height && x < width {
-                                    let pixel_pos = (y * width + x) * channels + 2; // +2 for blue channel
-                                    if pixel_pos < pixels.len() {
-                                        // Apply adjustment, ensuring we stay within 0-255 range
-                                        let new_value = pixels[pixel_pos] as i16 + adjustment;
-                                        pixels[pixel_pos] = new_value.clamp(0, 255) as u8;
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    bit_index += 1;
+            // Extraction needs no setting for matching
+            let extracted = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+                input_path: output_path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(extracted, b"Matched, not replaced".repeat(10));
+
+            // Channels move by one, and even values go down as well as up
+            let stego = image::open(&output_path).unwrap().to_rgb8();
+            let mut even_lowered = 0;
+            let mut even_raised = 0;
+            for (&before, &after) in cover.as_raw().iter().zip(stego.as_raw()) {
+                assert!(before.abs_diff(after) <= 1);
+                if before % 2 == 0 && after < before {
+                    even_lowered += 1;
+                } else if before % 2 == 0 && after > before {
+                    even_raised += 1;
                 }
             }
+            assert!(even_lowered > 0 && even_raised > 0, "{}", method);
         }
 
-        println!("Test: Embedded {} bits", bit_index);
-
-        // Now extract the data using our algorithm
-        let mut extracted_bits = Vec::new();
-
-        for by in 0..8 {
-            for bx in 0..8 {
-                // Calculate the average blue value for this 8x8 block
-                let mut blue_sum = 0;
-                let mut pixel_count = 0;
-
-                for y in by * 8..(by + 1) * 8 {
-                    for x in bx * 8..(bx + 1) * 8 {
-                        if y < height && x < width {
-                            let pixel_pos = (y * width + x) * channels + 2; // +2 for blue channel
-                            blue_sum += pixels[pixel_pos] as u32;
-                            pixel_count += 1;
-                        }
-                    }
-                }
+        // Signatures cover the upper bits, which matching may change
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("lsb".to_string(), "matching".to_string());
+        let result = embed_in_png(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: dir.path().join("signed.png").to_string_lossy().to_string(),
+            data: b"Signed".to_vec(),
+            parameters: Some(parameters),
+            signing_key: Some(
+                Ed25519KeyPair::generate()
+                    .unwrap()
+                    .export_secret()
+                    .into_bytes(),
+            ),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
 
-                if pixel_count > 0 {
-                    // Calculate average
-                    let avg_blue = (blue_sum / pixel_count) as u8;
+    #[test]
+    fn test_png_adaptive_embedding() {
+        use rand::{Rng, SeedableRng};
+
+        // Left half a smooth gradient, right half noise
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(9);
+        let cover = RgbImage::from_fn(128, 64, |x, y| {
+            if x < 64 {
+                Rgb([(x * 2) as u8, (y * 2) as u8, 100])
+            } else {
+                Rgb([rng.gen(), rng.gen(), rng.gen()])
+            }
+        });
+        cover.save(&input_path).unwrap();
+        let data: Vec<u8> = (0..200u32).map(|i| (i * 7 % 256) as u8).collect();
+
+        let mut changes = Vec::new();
+        for method in ["matrix", "adaptive"] {
+            let output_path = dir.path().join(format!("{}.png", method));
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), method.to_string());
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: data.clone(),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+            .unwrap();
 
-                    // Extract bit based on parity (odd = 1, even = 0)
-                    extracted_bits.push(avg_blue % 2 == 1);
+            // Extraction does not need the costs
+            let extracted = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+                input_path: output_path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(extracted, data);
+
+            // Count changed channels in each half, below the header row
+            let stego = image::open(&output_path).unwrap().to_rgb8();
+            let (mut smooth, mut noisy) = (0, 0);
+            for (x, y, pixel) in stego.enumerate_pixels().filter(|(_, y, _)| *y > 0) {
+                let changed = (0..3)
+                    .filter(|&c| pixel.0[c] != cover.get_pixel(x, y).0[c])
+                    .count();
+                if x < 64 {
+                    smooth += changed;
+                } else {
+                    noisy += changed;
                 }
             }
+            changes.push((smooth, noisy));
+        }
+
+        let (matrix_smooth, matrix_noisy) = changes[0];
+        let (adaptive_smooth, adaptive_noisy) = changes[1];
+        assert!(matrix_smooth * 2 > matrix_noisy, "{:?}", changes);
+        assert!(adaptive_smooth * 10 < adaptive_noisy, "{:?}", changes);
+        assert!(adaptive_smooth * 5 < matrix_smooth, "{:?}", changes);
+
+        // A higher stealth level lowers the capacity
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("method".to_string(), "adaptive".to_string());
+        parameters.insert("stealth".to_string(), "8".to_string());
+        let result = embed_in_png(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: dir
+                .path()
+                .join("stealthy.png")
+                .to_string_lossy()
+                .to_string(),
+            data,
+            parameters: Some(parameters),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_png_random_order_from_password() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        let output_path = dir.path().join("output.png");
+        create_test_png(&input_path, 32, 32);
+
+        let crypto_config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("order".to_string(), "random".to_string());
+
+        embed_in_png(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Keyed by the password".to_vec(),
+            encryption: Some(crypto_config.clone()),
+            parameters: Some(parameters.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let extracted = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            encryption: Some(crypto_config),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, b"Keyed by the password");
+    }
+
+    #[test]
+    fn test_png_compressed_payload() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        create_test_png(&input_path, 32, 32);
+
+        // About 4 KiB of log lines, far beyond the 356 bytes this image holds
+        let log = b"2024-01-01T00:00:00Z INFO request handled status=200\n".repeat(80);
+        let embed = |output: &str, compression: Option<(&str, &str)>| {
+            let parameters = compression.map(|(level, algorithm)| {
+                let mut parameters = std::collections::HashMap::new();
+                parameters.insert("compression".to_string(), level.to_string());
+                parameters.insert("compression_algorithm".to_string(), algorithm.to_string());
+                parameters
+            });
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: dir.path().join(output).to_string_lossy().to_string(),
+                data: log.clone(),
+                parameters,
+                ..Default::default()
+            })
+        };
+
+        assert!(matches!(
+            embed("plain.png", None),
+            Err(Error::InvalidInput(_))
+        ));
+
+        for algorithm in ["deflate", "zstd"] {
+            let output = format!("{}.png", algorithm);
+            embed(&output, Some(("9", algorithm))).unwrap();
+
+            let extract_config = crate::extraction::ExtractConfig {
+                input_path: dir.path().join(&output).to_string_lossy().to_string(),
+                ..Default::default()
+            };
+            let extracted = crate::extraction::extract_from_png(extract_config).unwrap();
+            assert_eq!(extracted, log);
+        }
+
+        assert!(embed("bad.png", Some(("10", "deflate"))).is_err());
+    }
+
+    #[test]
+    fn test_png_native_formats() {
+        let dir = tempdir().unwrap();
+        let gradient = |x: u32, y: u32| ((x * 4 + y * 3) % 256) as u8;
+        let signing_key = Ed25519KeyPair::generate()
+            .unwrap()
+            .export_secret()
+            .into_bytes();
+        let embed = |input_path: &Path, output_path: &Path, bit_depth: &str| {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("bit_depth".to_string(), bit_depth.to_string());
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: b"Native representation".to_vec(),
+                parameters: Some(parameters),
+                signing_key: Some(signing_key.clone()),
+                ..Default::default()
+            })
+        };
+        let extract = |path: &Path| {
+            crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+                input_path: path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+        };
+
+        let covers = [
+            DynamicImage::ImageLuma8(image::GrayImage::from_fn(64, 48, |x, y| {
+                image::Luma([gradient(x, y)])
+            })),
+            DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(64, 48, |x, y| {
+                image::LumaA([gradient(x, y), (x * 4) as u8])
+            })),
+            DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(64, 48, |x, y| {
+                image::Luma([gradient(x, y) as u16 * 256 + x as u16])
+            })),
+            DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(64, 48, |x, y| {
+                image::Rgb([(x * 1000) as u16, (y * 1300) as u16, 40000])
+            })),
+            DynamicImage::ImageRgba8(image::RgbaImage::from_fn(64, 48, |x, y| {
+                image::Rgba([gradient(x, y), (y * 5) as u8, 60, (y * 5) as u8])
+            })),
+        ];
+        for (i, cover) in covers.iter().enumerate() {
+            let input_path = dir.path().join(format!("cover_{}.png", i));
+            let output_path = dir.path().join(format!("stego_{}.png", i));
+            cover.save(&input_path).unwrap();
+            embed(&input_path, &output_path, "1").unwrap();
+
+            // Same colour type and bit depth; colour samples move by at most
+            // one step and alpha is untouched
+            let stego = image::open(&output_path).unwrap();
+            assert_eq!(stego.color(), cover.color());
+            let step = if cover.color().bytes_per_pixel() == 2 * cover.color().channel_count() {
+                1
+            } else {
+                257
+            };
+            let (before, after) = (cover.to_rgba16(), stego.to_rgba16());
+            for (a, b) in before.pixels().zip(after.pixels()) {
+                assert!((0..3).all(|c| a.0[c].abs_diff(b.0[c]) <= step));
+                assert_eq!(a.0[3], b.0[3]);
+            }
+            assert_ne!(before, after);
+
+            assert_eq!(extract(&output_path).unwrap(), b"Native representation");
+        }
+
+        // An indexed cover stays indexed with the same palette
+        let palette: Vec<u8> = (0..=255u8).flat_map(|v| [v, v, v / 2]).collect();
+        let indices: Vec<u8> = (0..64 * 48).map(|i| gradient(i % 64, i / 64)).collect();
+        let mut cover = Vec::new();
+        {
+            let mut encoder = ::png::Encoder::new(&mut cover, 64, 48);
+            encoder.set_color(::png::ColorType::Indexed);
+            encoder.set_depth(::png::BitDepth::Eight);
+            encoder.set_palette(palette.as_slice());
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&indices).unwrap();
+            writer.finish().unwrap();
+        }
+        let input_path = dir.path().join("indexed.png");
+        let output_path = dir.path().join("indexed_stego.png");
+        std::fs::write(&input_path, &cover).unwrap();
+        embed(&input_path, &output_path, "1").unwrap();
+
+        let stego = png::IndexedPng::from_bytes(&std::fs::read(&output_path).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stego.palette(), palette.as_slice());
+        assert_ne!(stego.indices(), indices.as_slice());
+        for (&a, &b) in indices.iter().zip(stego.indices()) {
+            assert!(a.abs_diff(b) <= 1);
         }
+        assert_eq!(extract(&output_path).unwrap(), b"Native representation");
 
-        println!("Test: Extracted {} bits", extracted_bits.len());
+        // Palette indices only carry one bit
+        assert!(matches!(
+            embed(&input_path, &output_path, "2"),
+            Err(Error::InvalidInput(_))
+        ));
 
-        // Convert bits to bytes
-        let mut extracted_bytes = Vec::new();
-        for chunk in extracted_bits.chunks(8) {
-            let mut byte = 0u8;
-            for (i, &bit) in chunk.iter().enumerate() {
-                if i < 8 && bit {
-                    byte |= 1 << (7 - i);
+        // Covers the image crate would expand: colour keys stay tRNS chunks
+        // and low-depth gray keeps its depth
+        let covers = [
+            (
+                ::png::ColorType::Rgb,
+                ::png::BitDepth::Eight,
+                Some(vec![0, 10, 0, 20, 0, 30]),
+            ),
+            (
+                ::png::ColorType::Grayscale,
+                ::png::BitDepth::Eight,
+                Some(vec![0, 40]),
+            ),
+            (::png::ColorType::Grayscale, ::png::BitDepth::Two, None),
+            (::png::ColorType::Grayscale, ::png::BitDepth::One, None),
+        ];
+        for (i, (color_type, depth, trns)) in covers.into_iter().enumerate() {
+            let samples = color_type.samples();
+            let line_size = (64 * samples * depth as usize).div_ceil(8);
+            let data: Vec<u8> = (0..line_size * 48)
+                .map(|i| gradient((i % line_size) as u32, (i / line_size) as u32))
+                .collect();
+            let mut cover = Vec::new();
+            {
+                let mut encoder = ::png::Encoder::new(&mut cover, 64, 48);
+                encoder.set_color(color_type);
+                encoder.set_depth(depth);
+                if let Some(trns) = &trns {
+                    encoder.set_trns(trns.clone());
                 }
+                let mut writer = encoder.write_header().unwrap();
+                writer.write_image_data(&data).unwrap();
             }
-            extracted_bytes.push(byte);
+            let input_path = dir.path().join(format!("raw_{}.png", i));
+            let output_path = dir.path().join(format!("raw_stego_{}.png", i));
+            std::fs::write(&input_path, &cover).unwrap();
+            embed(&input_path, &output_path, "1").unwrap();
+
+            let stego = std::fs::read(&output_path).unwrap();
+            let chunks = png::read_chunks(&stego).unwrap();
+            assert_eq!(chunks[0].data[8..10], [depth as u8, color_type as u8]);
+            assert_eq!(chunks.iter().any(|c| &c.kind == b"tRNS"), trns.is_some());
+            assert!(stego.len() < cover.len() * 11 / 10 + 100);
+            assert_eq!(extract(&output_path).unwrap(), b"Native representation");
         }
 
-        println!("Test: Converted to {} bytes", extracted_bytes.len());
-        println!("Test: Extracted bytes: {:?}", &extracted_bytes[0..8]);
+        // Two-bit samples hold at most two bits
+        let input_path = dir.path().join("raw_2.png");
+        assert!(embed(&input_path, &output_path, "2").is_ok());
+        assert!(matches!(
+            embed(&input_path, &output_path, "3"),
+            Err(Error::InvalidInput(_))
+        ));
+    }
 
-        // Extract the length prefix
-        let mut length_bytes = [0u8; 4];
-        length_bytes.copy_from_slice(&extracted_bytes[0..4]);
-        let data_length = u32::from_be_bytes(length_bytes) as usize;
+    #[test]
+    fn test_png_chunk_embedding() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        let cover = RgbImage::from_fn(40, 30, |x, y| Rgb([(x * 6) as u8, (y * 8) as u8, 77]));
+        cover.save(&input_path).unwrap();
+
+        let crypto_config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let signer = Ed25519KeyPair::generate().unwrap();
+        let data = b"Pixel-identical payload".to_vec();
+        let extract = |path: &Path| {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), "chunk".to_string());
+            crate::extraction::extract_with_report(crate::extraction::ExtractConfig {
+                input_path: path.to_string_lossy().to_string(),
+                encryption: Some(crypto_config.clone()),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+        };
 
-        println!("Test: Extracted length prefix: {} bytes", data_length);
+        for chunk in ["private", "text", "ztxt", "itxt"] {
+            let output_path = dir.path().join(format!("{}.png", chunk));
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), "chunk".to_string());
+            parameters.insert("chunk".to_string(), chunk.to_string());
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: data.clone(),
+                encryption: Some(crypto_config.clone()),
+                parameters: Some(parameters),
+                signing_key: Some(signer.export_secret().into_bytes()),
+                ..Default::default()
+            })
+            .unwrap();
 
-        // Extract the actual data
-        let extracted_data = extracted_bytes[4..4 + data_length].to_vec();
+            // The pixels are exactly those of the cover
+            assert_eq!(image::open(&output_path).unwrap().to_rgb8(), cover);
 
-        println!("Test: Extracted data: {:?}", extracted_data);
-        println!("Test: Original data: {:?}", test_data);
+            let report = extract(&output_path).unwrap();
+            assert_eq!(report.data, data);
+            assert_eq!(
+                report.signature,
+                crate::extraction::SignatureStatus::Untrusted(signer.public_key())
+            );
+            assert!(report.carrier_bound);
+        }
 
-        // Verify the extracted data matches the original
-        assert_eq!(extracted_data, test_data);
-        println!("Test: Data extraction successful!");
+        // A payload chunk moved into another file no longer matches its signature
+        let other_path = dir.path().join("other.png");
+        RgbImage::from_fn(40, 30, |x, y| Rgb([(y * 6) as u8, (x * 8) as u8, 77]))
+            .save(&other_path)
+            .unwrap();
+        let stego = std::fs::read(dir.path().join("private.png")).unwrap();
+        let moved = png::embed_in_chunk(
+            &std::fs::read(&other_path).unwrap(),
+            &png::extract_from_chunks(&stego).unwrap(),
+            png::PayloadChunk::Private,
+        )
+        .unwrap();
+        std::fs::write(&other_path, moved).unwrap();
+        assert!(matches!(extract(&other_path), Err(Error::Integrity(_))));
+
+        // Unknown chunk kinds are rejected
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("method".to_string(), "chunk".to_string());
+        parameters.insert("chunk".to_string(), "exif".to_string());
+        assert!(matches!(
+            embed_in_png(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: dir.path().join("invalid.png").to_string_lossy().to_string(),
+                data,
+                parameters: Some(parameters),
+                ..Default::default()
+            }),
+            Err(Error::InvalidInput(_))
+        ));
     }
 
     #[test]
-    fn test_real_jpg_steganography() {
-        // This test uses actual JPEG files with high quality settings
-        // and a very small message to test the robustness of our approach
-
-        // Create a temporary directory for our test files
-        let temp_dir = tempdir().unwrap();
-        let input_jpg_path = temp_dir.path().join("test_input.jpg");
-        let output_jpg_path = temp_dir.path().join("test_output.jpg");
-
-        // Create a larger test image (256x256 RGB) for more capacity
-        let width = 256;
-        let height = 256;
-        let mut img = RgbImage::new(width, height);
+    fn test_bmp_steganography() {
+        let dir = tempdir().unwrap();
+        let crypto_config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "password".to_string(),
+            ..Default::default()
+        };
 
-        // Fill with a solid color pattern (less susceptible to compression artifacts)
-        for y in 0..height {
-            for x in 0..width {
-                // Use solid colors for 8x8 blocks to better survive JPEG compression
-                let block_x = x / 8;
-                let block_y = y / 8;
-                // Use values that are far from the boundaries to avoid clipping
-                let r = 128;
-                let g = 128;
-                let b = 128;
-                img.put_pixel(x, y, Rgb([r, g, b]));
+        // The image crate writes 24-bit BMPs for RGB and 32-bit ones with
+        // channel masks for RGBA
+        let rgb = RgbImage::from_fn(45, 30, |x, y| Rgb([(x * 5) as u8, (y * 8) as u8, 90]));
+        let rgba = image::RgbaImage::from_fn(45, 30, |x, y| {
+            image::Rgba([(x * 5) as u8, (y * 8) as u8, 90, 200])
+        });
+        let covers = [
+            (dir.path().join("rgb.bmp"), DynamicImage::ImageRgb8(rgb)),
+            (dir.path().join("rgba.bmp"), DynamicImage::ImageRgba8(rgba)),
+        ];
+
+        for (input_path, cover) in covers {
+            cover.save(&input_path).unwrap();
+            let output_path = dir.path().join("output.bmp");
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("order".to_string(), "random".to_string());
+
+            embed_data(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: b"BMP test message".to_vec(),
+                encryption: Some(crypto_config.clone()),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+            .unwrap();
+
+            // Same size and headers, and the pixels barely change
+            let before = std::fs::read(&input_path).unwrap();
+            let after = std::fs::read(&output_path).unwrap();
+            assert_eq!(before.len(), after.len());
+            let data_offset = u32::from_le_bytes(before[10..14].try_into().unwrap()) as usize;
+            assert_eq!(before[..data_offset], after[..data_offset]);
+            assert_ne!(before, after);
+            let decoded = image::open(&output_path).unwrap().to_rgba8();
+            for (a, b) in decoded.as_raw().iter().zip(cover.to_rgba8().as_raw()) {
+                assert!(a.abs_diff(*b) <= 1);
             }
-        }
 
-        // Save as JPG with maximum quality
-        let jpg_file = File::create(&input_jpg_path).unwrap();
-        let jpg_encoder = jpeg_encoder::Encoder::new(BufWriter::new(jpg_file), 100); // Maximum quality
-        jpg_encoder
-            .encode(
-                &img.into_raw(),
-                width as u16,
-                height as u16,
-                jpeg_encoder::ColorType::Rgb,
-            )
+            let extracted = crate::extraction::extract_data(crate::extraction::ExtractConfig {
+                input_path: output_path.to_string_lossy().to_string(),
+                encryption: Some(crypto_config.clone()),
+                ..Default::default()
+            })
             .unwrap();
+            assert_eq!(extracted, b"BMP test message");
+        }
+    }
 
-        println!("Created JPG image at: {:?}", input_jpg_path);
+    #[test]
+    fn test_gif_steganography() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.gif");
+        let output_path = dir.path().join("output.gif");
+
+        // The image crate quantizes the cover to a palette
+        let cover = RgbImage::from_fn(60, 40, |x, y| Rgb([(x * 4) as u8, (y * 6) as u8, 120]));
+        DynamicImage::ImageRgb8(cover).save(&input_path).unwrap();
+
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("order".to_string(), "random".to_string());
+        parameters.insert("key".to_string(), "gif key".to_string());
+        embed_data(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"GIF test message".to_vec(),
+            parameters: Some(parameters.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let before = GifHandler::new(&input_path.to_string_lossy()).unwrap();
+        let after = GifHandler::new(&output_path.to_string_lossy()).unwrap();
+        assert_eq!(after.frame_count(), 1);
+        assert_eq!(after.frames()[0].palette, before.frames()[0].palette);
+        assert_ne!(after.frames()[0].buffer, before.frames()[0].buffer);
+
+        let extracted = crate::extraction::extract_data(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, b"GIF test message");
+    }
 
-        // Test data to embed - use a very short message
-        let test_data = b"TEST";
-        println!("Test data length: {} bytes", test_data.len());
+    #[test]
+    fn test_wav_steganography() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.wav");
+        let output_path = dir.path().join("output.wav");
 
-        // Create embed config
-        let embed_config = EmbedConfig {
-            input_path: input_jpg_path.to_string_lossy().to_string(),
-            output_path: output_jpg_path.to_string_lossy().to_string(),
-            data: test_data.to_vec(),
-            encryption: None,
+        create_test_wav(&input_path, 2, 8_000);
+
+        let crypto_config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "wav-password".to_string(),
+            ..Default::default()
         };
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("bit_depth".to_string(), "2".to_string());
 
-        // Embed the data
-        let embed_result = embed_in_jpg(embed_config);
-        assert!(
-            embed_result.is_ok(),
-            "Failed to embed data: {:?}",
-            embed_result
-        );
-        assert!(output_jpg_path.exists(), "Output file was not created");
-        println!("Successfully embedded data into: {:?}", output_jpg_path);
+        let embed_config = EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Hidden in the samples".to_vec(),
+            encryption: Some(crypto_config.clone()),
+            parameters: Some(parameters),
+            ..Default::default()
+        };
+        embed_in_wav(embed_config).unwrap();
 
-        // Now extract the data
         let extract_config = crate::extraction::ExtractConfig {
-            input_path: output_jpg_path.to_string_lossy().to_string(),
-            encryption: None,
-            parameters: None,
+            input_path: output_path.to_string_lossy().to_string(),
+            encryption: Some(crypto_config),
+            ..Default::default()
+        };
+        let extracted = crate::extraction::extract_from_wav(extract_config).unwrap();
+        assert_eq!(extracted, b"Hidden in the samples");
+    }
+
+    #[test]
+    fn test_wav_convolutional_code_corrects_bit_flips() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.wav");
+        let output_path = dir.path().join("output.wav");
+
+        create_test_wav(&input_path, 1, 8_000);
+
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("ecc".to_string(), "convolutional".to_string());
+        embed_in_wav(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Hidden in noisy samples".to_vec(),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Flip the low bit of every 40th sample after the payload header
+        edit_wav_samples(&output_path, |samples| {
+            for (i, sample) in samples.iter_mut().enumerate() {
+                if i >= 300 && i % 40 == 0 {
+                    *sample ^= 1;
+                }
+            }
+        });
+
+        let extracted = crate::extraction::extract_from_wav(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, b"Hidden in noisy samples");
+    }
+
+    #[test]
+    fn test_wav_fountain_code_survives_overwritten_region() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.wav");
+        let output_path = dir.path().join("output.wav");
+
+        create_test_wav(&input_path, 1, 30_000);
+        let data = embed_fountain_test_wav(&input_path, &output_path);
+
+        // Invert the low bits of a whole stretch of samples inside the body,
+        // destroying several symbols outright
+        edit_wav_samples(&output_path, |samples| {
+            for sample in &mut samples[3_000..6_000] {
+                *sample ^= 1;
+            }
+        });
+
+        let extracted = crate::extraction::extract_from_wav(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, data);
+    }
+
+    #[test]
+    fn test_wav_fountain_code_survives_truncation() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.wav");
+        let output_path = dir.path().join("output.wav");
+        create_test_wav(&input_path, 1, 16_000);
+        let data = embed_fountain_test_wav(&input_path, &output_path);
+
+        // Cut the file short, losing the end of the coded body
+        let extract = || {
+            crate::extraction::extract_from_wav(crate::extraction::ExtractConfig {
+                input_path: output_path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
         };
-        let extract_result = crate::extraction::extract_from_jpg(extract_config);
+        edit_wav_samples(&output_path, |samples| samples.truncate(12_000));
+        assert_eq!(extract().unwrap(), data);
+
+        // Once fewer symbols remain than the data needs, extraction fails
+        edit_wav_samples(&output_path, |samples| samples.truncate(4_000));
+        assert!(extract().is_err());
+    }
+
+    #[test]
+    fn test_png_fountain_code_survives_cropping() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.png");
+        let output_path = dir.path().join("output.png");
+        create_test_png(&input_path, 64, 100);
+
+        let data: Vec<u8> = (0..600u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("ecc".to_string(), "fountain".to_string());
+        embed_in_png(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: data.clone(),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Cropping the bottom rows cuts off the end of the coded body
+        let cropped_path = dir.path().join("cropped.png");
+        image::open(&output_path)
+            .unwrap()
+            .crop_imm(0, 0, 64, 60)
+            .save(&cropped_path)
+            .unwrap();
+        let extracted = crate::extraction::extract_from_png(crate::extraction::ExtractConfig {
+            input_path: cropped_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, data);
+    }
 
-        if let Err(ref e) = extract_result {
-            println!("Extraction error: {:?}", e);
+    /// Embeds 600 incompressible bytes with `ecc=fountain`, returning them
+    fn embed_fountain_test_wav(input_path: &Path, output_path: &Path) -> Vec<u8> {
+        // Incompressible data, so the body spans many fountain symbols
+        let data: Vec<u8> = (0..600u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("ecc".to_string(), "fountain".to_string());
+        embed_in_wav(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: data.clone(),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+        data
+    }
+
+    fn create_test_wav(path: &std::path::Path, channels: u16, samples: usize) {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: 44_100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..samples {
+            writer
+                .write_sample(((i as f32 * 0.03).sin() * 12_000.0) as i16)
+                .unwrap();
         }
+        writer.finalize().unwrap();
+    }
 
-        // We don't assert success here because JPEG compression might still corrupt the data
-        // Instead, we just log the result for informational purposes
-        match extract_result {
-            Ok(extracted_data) => {
-                println!("Successfully extracted data: {:?}", extracted_data);
-                if extracted_data == test_data {
-                    println!("Extracted data matches original!");
-                } else {
-                    println!("Extracted data doesn't match original. This is expected with JPEG compression.");
-                    println!("Original: {:?}", test_data);
-                    println!("Extracted: {:?}", extracted_data);
-                }
+    /// Rewrites the samples of a 16-bit WAV file in place
+    fn edit_wav_samples(path: &std::path::Path, edit: impl FnOnce(&mut Vec<i16>)) {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        let spec = reader.spec();
+        let mut samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        edit(&mut samples);
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn create_test_jpg(path: &std::path::Path, width: u16, height: u16, quality: u8) {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 3);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                pixels.push(((x * 8) % 256) as u8);
+                pixels.push(((y * 5) % 256) as u8);
+                pixels.push((((x + y) * 11) % 256) as u8);
             }
-            Err(e) => {
-                println!("Failed to extract data: {:?}", e);
-                println!("This is expected with JPEG compression and not a test failure.");
+        }
+        jpeg_encoder::Encoder::new_file(path, quality)
+            .unwrap()
+            .encode(&pixels, width, height, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+    }
+
+    /// Collects the quantization table segments that precede the first scan
+    fn quantization_segments(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut segments = Vec::new();
+        let mut pos = 2;
+        while bytes[pos + 1] != 0xDA {
+            let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            if bytes[pos + 1] == 0xDB {
+                segments.push(bytes[pos..pos + 2 + length].to_vec());
             }
+            pos += 2 + length;
         }
+        segments
     }
 
     #[test]
-    #[ignore] // Temporarily ignore this test until we can fix the JPEG Reed-Solomon implementation
-    fn test_jpg_reed_solomon_error_correction() {
-        // NOTE: This test is currently ignored because the JPEG Reed-Solomon implementation
-        // has issues with data extraction. The test data is too large for the capacity of
-        // the test image, and the extraction process is not correctly handling the Reed-Solomon
-        // encoded data. We need to fix the JPEG embedding and extraction functions to properly
-        // handle Reed-Solomon error correction.
-        
-        use crate::extraction::extract_from_jpg;
-        use std::fs::File;
-        use std::io::BufWriter;
-        use tempfile::TempDir;
-
-        // Setup temporary directory for test files
-        let temp_dir = TempDir::new().expect("Failed to create temp dir");
-        
-        // Create a test image file
-        let input_jpg_path = temp_dir.path().join("test_input_rs.jpg");
-        let output_jpg_path = temp_dir.path().join("test_output_rs.jpg");
-        
-        // Create a sample image (8x8 pixels per block for JPEG)
-        let width = 256;
-        let height = 256;
-        let mut pixels = vec![0; width * height * 3]; // RGB image
-        
-        // Fill with solid color blocks that are good for JPEG compression
-        for y in 0..height {
-            for x in 0..width {
-                let _block_x = x / 8;
-                let _block_y = y / 8;
-                
-                // Use solid colors for 8x8 blocks to better survive JPEG compression
-                let r = ((x % 32) * 8) as u8;
-                let g = ((y % 32) * 8) as u8;
-                let b = (((x + y) % 32) * 8) as u8;
-                
-                let pixel_pos = (y * width + x) * 3;
-                pixels[pixel_pos] = r;
-                pixels[pixel_pos + 1] = g;
-                pixels[pixel_pos + 2] = b;
-            }
+    fn test_jpg_steganography() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        create_test_jpg(&input_path, 128, 96, 85);
+
+        let data = b"Hidden in the DCT coefficients".repeat(4);
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: data.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // The coefficients are not requantized: same tables, still decodable
+        let cover = std::fs::read(&input_path).unwrap();
+        let stego = std::fs::read(&output_path).unwrap();
+        assert_ne!(cover, stego);
+        assert_eq!(quantization_segments(&cover), quantization_segments(&stego));
+        let decoded = image::open(&output_path).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (128, 96));
+
+        let extracted = crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, data);
+    }
+
+    #[test]
+    fn test_progressive_jpg_steganography() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        let image = RgbImage::from_fn(96, 64, |x, y| {
+            Rgb([(x * 8) as u8, (y * 5) as u8, ((x + y) * 11) as u8])
+        });
+        let mut encoder = jpeg_encoder::Encoder::new_file(&input_path, 90).unwrap();
+        encoder.set_progressive(true);
+        encoder
+            .encode(image.as_raw(), 96, 64, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Progressive carrier".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(JpegImage::open(&output_path).unwrap().is_progressive());
+
+        let extracted = crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, b"Progressive carrier");
+    }
+
+    #[test]
+    fn test_jpg_random_order_from_password() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        create_test_jpg(&input_path, 96, 96, 90);
+
+        let crypto_config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("order".to_string(), "random".to_string());
+
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Scattered over the coefficients".to_vec(),
+            encryption: Some(crypto_config.clone()),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let extracted = crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            encryption: Some(crypto_config),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(extracted, b"Scattered over the coefficients");
+    }
+
+    #[test]
+    fn test_jpg_repairs_corrupted_coefficients() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        create_test_jpg(&input_path, 256, 256, 95);
+
+        let data: Vec<u8> = (0..300).map(|i| (i * 13 % 256) as u8).collect();
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: data.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Flip the bits of a run of coefficients past the header
+        let mut image = JpegImage::open(&output_path).unwrap();
+        let original = f5::extract_container(&image, None).unwrap();
+        let mut coefficients = f5::ac_coefficients(&image);
+        for coefficient in coefficients
+            .iter_mut()
+            .filter(|c| **c != 0)
+            .skip(20_000)
+            .take(200)
+        {
+            *coefficient += coefficient.signum();
         }
-        
-        // Save as JPG with maximum quality
-        let jpg_file = File::create(&input_jpg_path).unwrap();
-        let jpg_encoder = jpeg_encoder::Encoder::new(BufWriter::new(jpg_file), 100); // Maximum quality
-        jpg_encoder
-            .encode(
-                &pixels,
-                width as u16,
-                height as u16,
-                jpeg_encoder::ColorType::Rgb,
-            )
-            .expect("Failed to encode JPEG");
-        
-        println!("Created JPG image for Reed-Solomon test at: {:?}", input_jpg_path);
-        
-        // Create test data to embed - absolute minimum to ensure it fits
-        let test_data = b"A";
-        
-        // Create embed config
-        let embed_config = EmbedConfig {
-            input_path: input_jpg_path.to_string_lossy().to_string(),
-            output_path: output_jpg_path.to_string_lossy().to_string(),
-            data: test_data.to_vec(),
-            encryption: None,
+        f5::set_ac_coefficients(&mut image, &coefficients);
+        image.save(&output_path).unwrap();
+        assert_ne!(
+            f5::extract_container(&image, None).unwrap().body,
+            original.body
+        );
+
+        let report = crate::extraction::extract_with_report(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(report.data, data);
+        assert!(report.repaired_shards > 0);
+    }
+
+    #[test]
+    fn test_robust_jpg_survives_recompression() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        create_test_jpg(&input_path, 256, 256, 95);
+
+        let crypto_config = CryptoConfig {
+            algorithm: Algorithm::ChaCha20Poly1305,
+            password: "password".to_string(),
+            ..Default::default()
         };
-        
-        // Embed the data
-        let embed_result = embed_in_jpg(embed_config);
-        assert!(embed_result.is_ok(), "Failed to embed data: {:?}", embed_result);
-        assert!(output_jpg_path.exists(), "Output file was not created");
-        println!("Successfully embedded data into: {:?}", output_jpg_path);
-        
-        // Extract the data
-        let extract_config = crate::extraction::ExtractConfig {
-            input_path: output_jpg_path.to_string_lossy().to_string(),
-            encryption: None,
-            parameters: None,
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("method".to_string(), "robust".to_string());
+        parameters.insert("quality".to_string(), "75".to_string());
+        parameters.insert("order".to_string(), "random".to_string());
+
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"Survives recompression".to_vec(),
+            encryption: Some(crypto_config.clone()),
+            parameters: Some(parameters),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let extract = |path: &std::path::Path| {
+            crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+                input_path: path.to_string_lossy().to_string(),
+                encryption: Some(crypto_config.clone()),
+                ..Default::default()
+            })
+        };
+        assert_eq!(extract(&output_path).unwrap(), b"Survives recompression");
+
+        // Re-encode the decoded pixels with another encoder
+        for quality in [75, 85, 95] {
+            let recompressed_path = dir.path().join(format!("recompressed_{}.jpg", quality));
+            let pixels = image::open(&output_path).unwrap().to_rgb8();
+            let file = std::fs::File::create(&recompressed_path).unwrap();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(file, quality)
+                .encode_image(&pixels)
+                .unwrap();
+
+            assert_eq!(
+                extract(&recompressed_path).unwrap(),
+                b"Survives recompression",
+                "quality {}",
+                quality
+            );
+        }
+    }
+
+    #[test]
+    fn test_robust_jpg_soft_decision_decoding() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        let recompressed_path = dir.path().join("recompressed.jpg");
+        create_test_jpg(&input_path, 256, 256, 95);
+
+        // Re-encoding well below the target quality breaks the Reed-Solomon
+        // payload, but the convolutional code decodes it from the QIM votes
+        let extract_after_recompression = |ecc: &str| {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), "robust".to_string());
+            parameters.insert("quality".to_string(), "75".to_string());
+            parameters.insert("ecc".to_string(), ecc.to_string());
+            embed_in_jpg(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: output_path.to_string_lossy().to_string(),
+                data: b"Soft decisions".to_vec(),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+            .unwrap();
+
+            let pixels = image::open(&output_path).unwrap().to_rgb8();
+            let file = std::fs::File::create(&recompressed_path).unwrap();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(file, 50)
+                .encode_image(&pixels)
+                .unwrap();
+
+            crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+                input_path: recompressed_path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+        };
+
+        assert!(extract_after_recompression("reed-solomon").is_err());
+        assert_eq!(
+            extract_after_recompression("convolutional").unwrap(),
+            b"Soft decisions"
+        );
+    }
+
+    #[test]
+    fn test_robust_jpg_rejects_invalid_settings() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        create_test_jpg(&input_path, 128, 128, 50);
+
+        let embed = |method: &str, quality: &str| {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), method.to_string());
+            parameters.insert("quality".to_string(), quality.to_string());
+            embed_in_jpg(EmbedConfig {
+                input_path: input_path.to_string_lossy().to_string(),
+                output_path: dir.path().join("output.jpg").to_string_lossy().to_string(),
+                data: b"Secret".to_vec(),
+                parameters: Some(parameters),
+                ..Default::default()
+            })
+        };
+
+        // A quality 50 cover is quantized too coarsely for a quality 90 target
+        assert!(matches!(embed("robust", "90"), Err(Error::InvalidInput(_))));
+        assert!(matches!(embed("robust", "20"), Err(Error::InvalidInput(_))));
+        assert!(matches!(embed("lsb", "75"), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_jpg_signature_binds_carrier() {
+        let dir = tempdir().unwrap();
+        let signer = Ed25519KeyPair::generate().unwrap();
+        let data = b"Signed in the coefficients".to_vec();
+        let extract = |path: &Path| {
+            crate::extraction::extract_with_report(crate::extraction::ExtractConfig {
+                input_path: path.to_string_lossy().to_string(),
+                ..Default::default()
+            })
+        };
+        let embed = |input: &Path, output: &Path, method: &str| {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert("method".to_string(), method.to_string());
+            embed_in_jpg(EmbedConfig {
+                input_path: input.to_string_lossy().to_string(),
+                output_path: output.to_string_lossy().to_string(),
+                data: data.clone(),
+                parameters: Some(parameters),
+                signing_key: Some(signer.export_secret().into_bytes()),
+                ..Default::default()
+            })
         };
-        let extract_result = extract_from_jpg(extract_config);
-        assert!(extract_result.is_ok(), "Failed to extract data: {:?}", extract_result);
-        
-        // Verify extracted data matches original
-        let extracted_data = extract_result.unwrap();
-        assert_eq!(extracted_data, test_data, "Extracted data does not match original");
-        println!("Successfully extracted and verified data from: {:?}", output_jpg_path);
+
+        // Optimized Huffman tables lack symbols F5 introduces, so the stego
+        // file carries regenerated tables
+        let image = RgbImage::from_fn(128, 96, |x, y| {
+            Rgb([(x * 8) as u8, (y * 5) as u8, ((x + y) * 11) as u8])
+        });
+        for progressive in [false, true] {
+            let input_path = dir.path().join("input.jpg");
+            let output_path = dir.path().join("output.jpg");
+            let mut encoder = jpeg_encoder::Encoder::new_file(&input_path, 90).unwrap();
+            encoder.set_optimized_huffman_tables(true);
+            encoder.set_progressive(progressive);
+            encoder
+                .encode(image.as_raw(), 128, 96, jpeg_encoder::ColorType::Rgb)
+                .unwrap();
+
+            embed(&input_path, &output_path, "f5").unwrap();
+            let tables = |path: &Path| {
+                std::fs::read(path)
+                    .unwrap()
+                    .windows(2)
+                    .filter(|marker| marker == &[0xFF, 0xC4])
+                    .count()
+            };
+            assert!(tables(&output_path) > tables(&input_path));
+            assert_eq!(
+                JpegImage::open(&output_path).unwrap().carrier_digest(),
+                JpegImage::open(&input_path).unwrap().carrier_digest()
+            );
+            let report = extract(&output_path).unwrap();
+            assert_eq!(report.data, data);
+            assert_eq!(
+                report.signature,
+                crate::extraction::SignatureStatus::Untrusted(signer.public_key())
+            );
+            assert!(report.carrier_bound);
+        }
+
+        // The same container moved into another cover is rejected
+        let output_path = dir.path().join("output.jpg");
+        let other_path = dir.path().join("other.jpg");
+        create_test_jpg(&other_path, 256, 192, 95);
+        let container =
+            f5::extract_container(&JpegImage::open(&output_path).unwrap(), None).unwrap();
+        let mut other = JpegImage::open(&other_path).unwrap();
+        f5::embed_container(&mut other, &container, EmbeddingOrder::Sequential, None).unwrap();
+        other.save(&other_path).unwrap();
+        assert!(matches!(extract(&other_path), Err(Error::Integrity(_))));
+
+        // Recompression rewrites every coefficient, so robust signatures are
+        // verified but reported as not bound to the carrier
+        let input_path = dir.path().join("robust_input.jpg");
+        let output_path = dir.path().join("robust_output.jpg");
+        create_test_jpg(&input_path, 256, 256, 95);
+        embed(&input_path, &output_path, "robust").unwrap();
+        let recompressed_path = dir.path().join("recompressed.jpg");
+        let decoded = image::open(&output_path).unwrap().to_rgb8();
+        jpeg_encoder::Encoder::new_file(&recompressed_path, 85)
+            .unwrap()
+            .encode(decoded.as_raw(), 256, 256, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+        let report = extract(&recompressed_path).unwrap();
+        assert_eq!(report.data, data);
+        assert_eq!(
+            report.signature,
+            crate::extraction::SignatureStatus::Untrusted(signer.public_key())
+        );
+        assert!(!report.carrier_bound);
+
+        let container =
+            qim::extract_container(&JpegImage::open(&output_path).unwrap(), None).unwrap();
+        let other_path = dir.path().join("robust_other.jpg");
+        create_test_jpg(&other_path, 256, 192, 95);
+        let mut other = JpegImage::open(&other_path).unwrap();
+        qim::embed_container(
+            &mut other,
+            &container,
+            qim::DEFAULT_TARGET_QUALITY,
+            EmbeddingOrder::Sequential,
+            None,
+        )
+        .unwrap();
+        other.save(&other_path).unwrap();
+        assert!(!extract(&other_path).unwrap().carrier_bound);
+    }
+
+    #[test]
+    fn test_jpg_capacity_exceeded() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        create_test_jpg(&input_path, 16, 16, 50);
+
+        let result = embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: dir.path().join("output.jpg").to_string_lossy().to_string(),
+            data: vec![0x55; 4096],
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_jpg_unreadable_f5_body_is_reported() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input.jpg");
+        let output_path = dir.path().join("output.jpg");
+        create_test_jpg(&input_path, 256, 256, 90);
+
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: vec![0x55; 400],
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Zero every coefficient past the header, so the header is intact but
+        // its body no longer fits
+        let mut image = JpegImage::open(&output_path).unwrap();
+        let mut coefficients = f5::ac_coefficients(&image);
+        for coefficient in coefficients.iter_mut().filter(|c| **c != 0).skip(2_000) {
+            *coefficient = 0;
+        }
+        f5::set_ac_coefficients(&mut image, &coefficients);
+        image.save(&output_path).unwrap();
+
+        // The F5 error is reported rather than robust extraction's
+        let error = crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+            input_path: output_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(
+            matches!(&error, Error::InvalidData(message) if message.contains("non-zero JPEG coefficients")),
+            "{}",
+            error
+        );
+
+        // A JPEG without any payload names both methods
+        let error = crate::extraction::extract_from_jpg(crate::extraction::ExtractConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(
+            matches!(&error, Error::Extraction(message) if message.contains("F5") && message.contains("robust")),
+            "{}",
+            error
+        );
+    }
+    #[test]
+    fn test_jpg_reed_solomon_error_correction() {
+        let dir = tempdir().unwrap();
+        let input_path = dir.path().join("input_rs.jpg");
+        let output_path = dir.path().join("output_rs.jpg");
+        create_test_jpg(&input_path, 256, 256, 100);
+
+        embed_in_jpg(EmbedConfig {
+            input_path: input_path.to_string_lossy().to_string(),
+            output_path: output_path.to_string_lossy().to_string(),
+            data: b"A".to_vec(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let image = JpegImage::open(&output_path).unwrap();
+        let container = f5::extract_container(&image, None).unwrap();
+        assert_eq!(container.header.ecc, EccScheme::ReedSolomon);
+        assert_eq!(container.open(None).unwrap(), b"A");
     }
 }
@@ -1,85 +1,397 @@
 //! Encryption Module
 //!
 //! This module provides encryption and decryption functionality using AES-256 and ChaCha20.
+//!
+//! New payloads use the authenticated modes (AES-256-GCM or ChaCha20-Poly1305),
+//! so a wrong password or a modified ciphertext is reported as an error instead
+//! of producing garbage. The original unauthenticated `Aes256` and `ChaCha20`
+//! formats can still be decrypted.
+//!
+//! Payloads can also be encrypted for key holders instead of a password:
+//! RSA-OAEP or ephemeral X25519 key agreement wraps a random content key for
+//! each recipient, so any one of several recipients can open the payload.
 
 use crate::{Error, Result};
 use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
 use aes::Aes256;
+use aes_gcm::{aead::Aead, Aes256Gcm};
+use base64ct::{Base64, Encoding};
 use chacha20::{
     cipher::{KeyIvInit, StreamCipher},
     ChaCha20,
 };
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
 use rand::{rngs::OsRng, RngCore};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
 use rsa::{PaddingScheme, PublicKey, RsaPrivateKey, RsaPublicKey};
 use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
 
 const SALT_LENGTH: usize = 16;
 const IV_LENGTH: usize = 12;
 const KEY_LENGTH: usize = 32;
-const RSA_KEY_SIZE: usize = 2048;
+const TAG_LENGTH: usize = 16;
+const X25519_KEY_LENGTH: usize = 32;
+const X25519_WRAPPED_KEY_LENGTH: usize = KEY_LENGTH + TAG_LENGTH;
+const X25519_PUBLIC_LABEL: &str = "NHALE X25519 PUBLIC KEY";
+const X25519_SECRET_LABEL: &str = "NHALE X25519 SECRET KEY";
+const X25519_KDF_INFO: &[u8] = b"nhale-x25519-key-wrap";
+
+/// Length of a serialized [`Kdf`] description
+pub const KDF_PARAMS_LENGTH: usize = 10;
+
+/// Default Argon2id memory cost in KiB
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Default number of Argon2id passes
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+
+/// Default number of Argon2id lanes
+pub const DEFAULT_ARGON2_PARALLELISM: u8 = 1;
+
+/// Default PBKDF2-HMAC-SHA256 iteration count
+pub const DEFAULT_PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// Largest Argon2id memory cost accepted (1 GiB)
+pub const MAX_ARGON2_MEMORY_KIB: u32 = 1024 * 1024;
+
+/// Largest Argon2id pass count accepted
+pub const MAX_ARGON2_ITERATIONS: u32 = 64;
+
+/// Largest PBKDF2 iteration count accepted
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// Message reported when an authenticated ciphertext fails verification
+pub const AUTHENTICATION_FAILED: &str =
+    "Authentication failed: wrong password or key, or tampered ciphertext";
 
 /// Supported encryption algorithms
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Algorithm {
+    /// Legacy AES-256 without authentication, kept for reading old payloads
     Aes256,
+    /// Legacy ChaCha20 without authentication, kept for reading old payloads
     ChaCha20,
     Rsa,
+    /// AES-256 in Galois/Counter Mode
+    #[default]
+    Aes256Gcm,
+    /// ChaCha20 with a Poly1305 authenticator
+    ChaCha20Poly1305,
+    /// Ephemeral X25519 key agreement with HKDF-SHA256 and ChaCha20-Poly1305
+    X25519,
+}
+
+impl Algorithm {
+    /// Returns the identifier used for this algorithm in payload headers
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::Aes256 => 1,
+            Algorithm::ChaCha20 => 2,
+            Algorithm::Rsa => 3,
+            Algorithm::Aes256Gcm => 4,
+            Algorithm::ChaCha20Poly1305 => 5,
+            Algorithm::X25519 => 6,
+        }
+    }
+
+    /// Returns true for the formats that predate authenticated encryption and
+    /// store only a salt in front of the ciphertext
+    pub fn is_legacy(self) -> bool {
+        matches!(self, Algorithm::Aes256 | Algorithm::ChaCha20)
+    }
+
+    /// Returns true if the key is derived from a password rather than a key pair
+    pub fn uses_password(self) -> bool {
+        !matches!(self, Algorithm::Rsa | Algorithm::X25519)
+    }
+
+    /// Looks up an algorithm by its payload header identifier
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Algorithm::Aes256),
+            2 => Ok(Algorithm::ChaCha20),
+            3 => Ok(Algorithm::Rsa),
+            4 => Ok(Algorithm::Aes256Gcm),
+            5 => Ok(Algorithm::ChaCha20Poly1305),
+            6 => Ok(Algorithm::X25519),
+            _ => Err(Error::InvalidData(format!(
+                "Unknown encryption algorithm identifier: {}",
+                id
+            ))),
+        }
+    }
+}
+
+/// Key derivation functions used to turn a password into an encryption key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kdf {
+    /// Single SHA-256 pass over the password and salt, used by the legacy formats
+    Sha256,
+    /// Argon2id with the given memory cost (KiB), number of passes and lanes
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u8,
+    },
+    /// PBKDF2-HMAC-SHA256 with the given iteration count
+    Pbkdf2 { iterations: u32 },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::Argon2id {
+            memory_kib: DEFAULT_ARGON2_MEMORY_KIB,
+            iterations: DEFAULT_ARGON2_ITERATIONS,
+            parallelism: DEFAULT_ARGON2_PARALLELISM,
+        }
+    }
+}
+
+impl Kdf {
+    /// PBKDF2-HMAC-SHA256 with the default iteration count
+    pub fn pbkdf2() -> Self {
+        Kdf::Pbkdf2 {
+            iterations: DEFAULT_PBKDF2_ITERATIONS,
+        }
+    }
+
+    /// Serializes the function and its cost parameters
+    ///
+    /// Layout: identifier (1 byte), memory in KiB (4 bytes), iterations
+    /// (4 bytes) and parallelism (1 byte). Unused parameters are zero.
+    pub fn to_bytes(self) -> [u8; KDF_PARAMS_LENGTH] {
+        let mut bytes = [0u8; KDF_PARAMS_LENGTH];
+        match self {
+            Kdf::Sha256 => bytes[0] = 1,
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                bytes[0] = 2;
+                bytes[1..5].copy_from_slice(&memory_kib.to_be_bytes());
+                bytes[5..9].copy_from_slice(&iterations.to_be_bytes());
+                bytes[9] = parallelism;
+            }
+            Kdf::Pbkdf2 { iterations } => {
+                bytes[0] = 3;
+                bytes[5..9].copy_from_slice(&iterations.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Parses a description written by [`Kdf::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < KDF_PARAMS_LENGTH {
+            return Err(Error::InvalidData("KDF parameters are truncated".into()));
+        }
+
+        let read_u32 = |offset: usize| {
+            let mut value = [0u8; 4];
+            value.copy_from_slice(&bytes[offset..offset + 4]);
+            u32::from_be_bytes(value)
+        };
+
+        let kdf = match bytes[0] {
+            1 => Kdf::Sha256,
+            2 => Kdf::Argon2id {
+                memory_kib: read_u32(1),
+                iterations: read_u32(5),
+                parallelism: bytes[9],
+            },
+            3 => Kdf::Pbkdf2 {
+                iterations: read_u32(5),
+            },
+            id => {
+                return Err(Error::InvalidData(format!(
+                    "Unknown key derivation function identifier: {}",
+                    id
+                )))
+            }
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+
+    /// Checks that the cost parameters are usable
+    ///
+    /// Upper bounds keep a crafted payload from making extraction allocate
+    /// huge amounts of memory or spin for hours.
+    pub fn validate(self) -> Result<()> {
+        match self {
+            Kdf::Sha256 => Ok(()),
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                if memory_kib > MAX_ARGON2_MEMORY_KIB || iterations > MAX_ARGON2_ITERATIONS {
+                    return Err(Error::Encryption(
+                        "Argon2id parameters exceed the supported maximum".into(),
+                    ));
+                }
+                argon2::Params::new(memory_kib, iterations, parallelism as u32, None)
+                    .map(|_| ())
+                    .map_err(|e| Error::Encryption(format!("Invalid Argon2id parameters: {}", e)))
+            }
+            Kdf::Pbkdf2 { iterations } => {
+                if iterations == 0 || iterations > MAX_PBKDF2_ITERATIONS {
+                    return Err(Error::Encryption(format!(
+                        "PBKDF2 iterations must be between 1 and {}",
+                        MAX_PBKDF2_ITERATIONS
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Derives a key of `out.len()` bytes from the password and salt
+    pub fn derive_key(self, password: &str, salt: &[u8], out: &mut [u8]) -> Result<()> {
+        self.validate()?;
+        match self {
+            Kdf::Sha256 => derive_key_from_password(password, salt, out),
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = argon2::Params::new(memory_kib, iterations, parallelism as u32, None)
+                    .map_err(|e| {
+                        Error::Encryption(format!("Invalid Argon2id parameters: {}", e))
+                    })?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, out)
+                    .map_err(|e| Error::Encryption(format!("Argon2id failed: {}", e)))?;
+            }
+            Kdf::Pbkdf2 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, out)
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Configuration for encryption/decryption operations
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CryptoConfig {
     pub algorithm: Algorithm,
     pub password: String,
+    /// Key derivation used by the authenticated algorithms; the legacy
+    /// algorithms always use [`Kdf::Sha256`]
+    pub kdf: Kdf,
+    /// Recipient public keys used for embedding with RSA (PEM or DER, PKCS#1
+    /// or PKCS#8) or X25519 (armored or raw); any one of them can decrypt
+    pub recipients: Vec<Vec<u8>>,
+    /// Private key of one recipient, in the same formats, used for extraction
+    pub identity: Option<Vec<u8>>,
+}
+
+impl CryptoConfig {
+    /// Returns the key derivation function actually used for `algorithm`
+    pub fn effective_kdf(&self) -> Kdf {
+        if self.algorithm.is_legacy() {
+            Kdf::Sha256
+        } else {
+            self.kdf
+        }
+    }
 }
 
 /// Encrypts data using the specified algorithm
+///
+/// Authenticated algorithms produce `[kdf parameters][salt][nonce][ciphertext]`,
+/// so the key can be re-derived without knowing the cost settings. The legacy
+/// algorithms keep their original `[salt][...]` layout. The public-key
+/// algorithms ignore the password and encrypt for `config.recipients` instead.
 pub fn encrypt(data: &[u8], config: &CryptoConfig) -> Result<Vec<u8>> {
+    match config.algorithm {
+        Algorithm::Rsa => return encrypt_rsa(data, config),
+        Algorithm::X25519 => return encrypt_x25519(data, config),
+        _ => {}
+    }
+
     // Generate random salt
     let mut salt = [0u8; SALT_LENGTH];
     getrandom::getrandom(&mut salt).map_err(|e| Error::Encryption(e.to_string()))?;
 
-    // Generate key from password using PBKDF2
+    // Derive the key from the password
+    let kdf = config.effective_kdf();
     let mut key = [0u8; KEY_LENGTH];
-    derive_key_from_password(&config.password, &salt, &mut key);
+    kdf.derive_key(&config.password, &salt, &mut key)?;
 
-    let mut output = Vec::with_capacity(SALT_LENGTH + IV_LENGTH + data.len() + 32);
+    let mut output =
+        Vec::with_capacity(KDF_PARAMS_LENGTH + SALT_LENGTH + IV_LENGTH + data.len() + 32);
+    if !config.algorithm.is_legacy() {
+        output.extend_from_slice(&kdf.to_bytes());
+    }
     output.extend_from_slice(&salt);
 
     match config.algorithm {
         Algorithm::Aes256 => encrypt_aes256(data, &key, &mut output),
         Algorithm::ChaCha20 => encrypt_chacha20(data, &key, &mut output),
-        Algorithm::Rsa => encrypt_rsa(data, &key, &mut output),
+        Algorithm::Rsa | Algorithm::X25519 => {
+            unreachable!("public-key algorithms do not derive keys from a password")
+        }
+        Algorithm::Aes256Gcm => encrypt_aead::<Aes256Gcm>(data, &key, &mut output),
+        Algorithm::ChaCha20Poly1305 => encrypt_aead::<ChaCha20Poly1305>(data, &key, &mut output),
     }?;
 
     Ok(output)
 }
 
 /// Decrypts data using the specified algorithm
+///
+/// For authenticated algorithms the key derivation parameters are read from
+/// the ciphertext; `config.kdf` is ignored. The public-key algorithms use
+/// `config.identity`.
 pub fn decrypt(encrypted_data: &[u8], config: &CryptoConfig) -> Result<Vec<u8>> {
+    match config.algorithm {
+        Algorithm::Rsa => return decrypt_rsa(encrypted_data, config),
+        Algorithm::X25519 => return decrypt_x25519(encrypted_data, config),
+        _ => {}
+    }
+
+    let (kdf, encrypted_data) = if config.algorithm.is_legacy() {
+        (Kdf::Sha256, encrypted_data)
+    } else {
+        if encrypted_data.len() < KDF_PARAMS_LENGTH {
+            return Err(Error::Encryption("Invalid encrypted data length".into()));
+        }
+        let (params, rest) = encrypted_data.split_at(KDF_PARAMS_LENGTH);
+        (Kdf::from_bytes(params)?, rest)
+    };
+
     if encrypted_data.len() < SALT_LENGTH + IV_LENGTH {
         return Err(Error::Encryption("Invalid encrypted data length".into()));
     }
 
     let salt = &encrypted_data[..SALT_LENGTH];
     let mut key = [0u8; KEY_LENGTH];
-
-    // Generate key from password using PBKDF2
-    derive_key_from_password(&config.password, salt, &mut key);
+    kdf.derive_key(&config.password, salt, &mut key)?;
 
     let encrypted = &encrypted_data[SALT_LENGTH..];
 
     match config.algorithm {
         Algorithm::Aes256 => decrypt_aes256(encrypted, &key),
         Algorithm::ChaCha20 => decrypt_chacha20(encrypted, &key),
-        Algorithm::Rsa => decrypt_rsa(encrypted, &key),
+        Algorithm::Rsa | Algorithm::X25519 => {
+            unreachable!("public-key algorithms do not derive keys from a password")
+        }
+        Algorithm::Aes256Gcm => decrypt_aead::<Aes256Gcm>(encrypted, &key),
+        Algorithm::ChaCha20Poly1305 => decrypt_aead::<ChaCha20Poly1305>(encrypted, &key),
     }
 }
 
-// Improved key derivation function using PBKDF2
+// Legacy key derivation: one SHA-256 pass, extended by hashing again if a
+// longer key is requested
 fn derive_key_from_password(password: &str, salt: &[u8], out: &mut [u8]) {
-    // Since there are compatibility issues with the pbkdf2 crate,
-    // we'll fall back to a simpler approach for now
     let mut hasher = Sha256::new();
     hasher.update(password.as_bytes());
     hasher.update(salt);
@@ -99,14 +411,35 @@ fn derive_key_from_password(password: &str, salt: &[u8], out: &mut [u8]) {
         let copy_len2 = remaining.min(hash2.len());
         out[copy_len..copy_len + copy_len2].copy_from_slice(&hash2[..copy_len2]);
     }
+}
 
-    // TODO: Replace with PBKDF2 once compatibility issues are resolved
-    // pbkdf2::<hmac::Hmac<sha2::Sha256>>(
-    //     password.as_bytes(),
-    //     salt,
-    //     10_000,  // 10,000 iterations
-    //     out,
-    // );
+// Helper functions for the AEAD ciphers, which share a 96-bit nonce
+// followed by the ciphertext and its 16-byte tag
+fn encrypt_aead<C: Aead + KeyInit>(data: &[u8], key: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    let cipher = C::new(GenericArray::from_slice(key));
+    let mut nonce = [0u8; IV_LENGTH];
+    getrandom::getrandom(&mut nonce).map_err(|e| Error::Encryption(e.to_string()))?;
+
+    let encrypted = cipher
+        .encrypt(GenericArray::from_slice(&nonce), data)
+        .map_err(|_| Error::Encryption("AEAD encryption failed".into()))?;
+
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&encrypted);
+    Ok(())
+}
+
+fn decrypt_aead<C: Aead + KeyInit>(encrypted: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.len() < IV_LENGTH + TAG_LENGTH {
+        return Err(Error::Encryption("Invalid encrypted data length".into()));
+    }
+
+    let cipher = C::new(GenericArray::from_slice(key));
+    let (nonce, data) = encrypted.split_at(IV_LENGTH);
+
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), data)
+        .map_err(|_| Error::Encryption(AUTHENTICATION_FAILED.into()))
 }
 
 // Helper functions for AES-256
@@ -120,7 +453,7 @@ fn encrypt_aes256(data: &[u8], key: &[u8], output: &mut Vec<u8>) -> Result<()> {
     // Pad data to block size
     let mut padded = data.to_vec();
     let padding_len = 16 - (data.len() % 16);
-    padded.extend(std::iter::repeat(padding_len as u8).take(padding_len));
+    padded.extend(std::iter::repeat_n(padding_len as u8, padding_len));
 
     for chunk in padded.chunks_mut(16) {
         let block = GenericArray::from_mut_slice(chunk);
@@ -139,7 +472,7 @@ fn decrypt_aes256(encrypted: &[u8], key: &[u8]) -> Result<Vec<u8>> {
     let _iv = &encrypted[..IV_LENGTH];
     let data = &encrypted[IV_LENGTH..];
 
-    if data.len() % 16 != 0 {
+    if !data.len().is_multiple_of(16) {
         return Err(Error::Encryption("Invalid encrypted data length".into()));
     }
 
@@ -200,75 +533,588 @@ fn decrypt_chacha20(encrypted: &[u8], key: &[u8]) -> Result<Vec<u8>> {
     Ok(decrypted)
 }
 
-/// Encrypts data using RSA
-fn encrypt_rsa(data: &[u8], _key: &[u8], output: &mut Vec<u8>) -> Result<()> {
-    // For RSA, we don't really use the derived key, instead:
-    // 1. Generate a random AES key
-    // 2. Encrypt the data with AES
-    // 3. Encrypt the AES key with RSA
-    // 4. Concatenate the encrypted key and encrypted data
+/// Parses an RSA public key in PEM or DER form, PKCS#1 or PKCS#8
+pub fn parse_rsa_public_key(key: &[u8]) -> Result<RsaPublicKey> {
+    let parsed = match std::str::from_utf8(key).ok().filter(|k| is_pem(k)) {
+        Some(pem) => RsaPublicKey::from_public_key_pem(pem)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+            .ok(),
+        None => RsaPublicKey::from_public_key_der(key)
+            .or_else(|_| RsaPublicKey::from_pkcs1_der(key))
+            .ok(),
+    };
+    parsed.ok_or_else(|| Error::InvalidInput("Unrecognized RSA public key".into()))
+}
+
+/// Parses an RSA private key in PEM or DER form, PKCS#1 or PKCS#8
+pub fn parse_rsa_private_key(key: &[u8]) -> Result<RsaPrivateKey> {
+    let parsed = match std::str::from_utf8(key).ok().filter(|k| is_pem(k)) {
+        Some(pem) => RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .ok(),
+        None => RsaPrivateKey::from_pkcs8_der(key)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_der(key))
+            .ok(),
+    };
+    parsed.ok_or_else(|| Error::InvalidInput("Unrecognized RSA private key".into()))
+}
+
+fn is_pem(key: &str) -> bool {
+    key.trim_start().starts_with("-----BEGIN")
+}
+
+/// Generates an RSA key pair, returned as PKCS#8 PEM `(private, public)`
+pub fn generate_rsa_keypair(bits: usize) -> Result<(String, String)> {
+    let private_key = RsaPrivateKey::new(&mut OsRng, bits)
+        .map_err(|e| Error::Encryption(format!("Failed to generate RSA key: {}", e)))?;
+    let private_pem = private_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| Error::Encoding(format!("Failed to encode RSA key: {}", e)))?;
+    let public_pem = RsaPublicKey::from(&private_key)
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| Error::Encoding(format!("Failed to encode RSA key: {}", e)))?;
+    Ok((private_pem.to_string(), public_pem))
+}
+
+/// Encrypts data for one or more RSA recipients
+///
+/// A random AES-256-GCM key encrypts the data and is wrapped with RSA-OAEP
+/// (SHA-256) for every recipient. Layout: `[recipient count (2 bytes)]`, then
+/// `[wrapped key length (4 bytes)][wrapped key]` per recipient, then
+/// `[nonce][ciphertext]`.
+fn encrypt_rsa(data: &[u8], config: &CryptoConfig) -> Result<Vec<u8>> {
+    let public_keys = recipient_keys(config, parse_rsa_public_key)?;
 
     // Generate a random AES key
-    let mut aes_key = [0u8; 32];
+    let mut aes_key = [0u8; KEY_LENGTH];
     OsRng.fill_bytes(&mut aes_key);
 
-    // The real RSA key would be loaded from a file, but for demo purposes,
-    // we'll generate a new one. In practice, you'd have this as a parameter.
-    let mut rng = OsRng;
-    let private_key = RsaPrivateKey::new(&mut rng, RSA_KEY_SIZE)
-        .map_err(|e| Error::Encryption(format!("Failed to generate RSA key: {}", e)))?;
-    let public_key = RsaPublicKey::from(&private_key);
+    let mut output = Vec::with_capacity(2 + IV_LENGTH + data.len() + TAG_LENGTH);
+    output.extend_from_slice(&(public_keys.len() as u16).to_be_bytes());
+    for public_key in public_keys {
+        let enc_key = public_key
+            .encrypt(&mut OsRng, PaddingScheme::new_oaep::<Sha256>(), &aes_key)
+            .map_err(|e| Error::Encryption(format!("RSA encryption failed: {}", e)))?;
+        output.extend_from_slice(&(enc_key.len() as u32).to_be_bytes());
+        output.extend_from_slice(&enc_key);
+    }
+    encrypt_aead::<Aes256Gcm>(data, &aes_key, &mut output)?;
 
-    // Encrypt the AES key with RSA using PKCS#1 v1.5 padding
-    let enc_key = public_key
-        .encrypt(&mut rng, PaddingScheme::new_pkcs1v15_encrypt(), &aes_key)
-        .map_err(|e| Error::Encryption(format!("RSA encryption failed: {}", e)))?;
+    Ok(output)
+}
 
-    // Encrypt the data with AES
-    let mut aes_output = Vec::new();
-    encrypt_aes256(data, &aes_key, &mut aes_output)?;
+/// Decrypts data encrypted for RSA recipients
+fn decrypt_rsa(encrypted: &[u8], config: &CryptoConfig) -> Result<Vec<u8>> {
+    let private_key = parse_rsa_private_key(identity(config)?)?;
 
-    // Write the encrypted key size
-    let key_size = enc_key.len() as u32;
-    output.extend_from_slice(&key_size.to_be_bytes());
+    let (count, mut rest) = read_recipient_count(encrypted)?;
+    let mut aes_key = None;
+    for _ in 0..count {
+        if rest.len() < 4 {
+            return Err(Error::Encryption("Invalid encrypted data".into()));
+        }
 
-    // Write the encrypted key
-    output.extend_from_slice(&enc_key);
+        // Read the encrypted key size
+        let mut key_size_bytes = [0u8; 4];
+        key_size_bytes.copy_from_slice(&rest[..4]);
+        let key_size = u32::from_be_bytes(key_size_bytes) as usize;
+        if rest.len() < 4 + key_size {
+            return Err(Error::Encryption("Invalid encrypted data".into()));
+        }
 
-    // Write the encrypted data
-    output.extend_from_slice(&aes_output);
+        // Slots for other recipients fail OAEP decoding
+        let enc_key = &rest[4..4 + key_size];
+        if aes_key.is_none() {
+            aes_key = private_key
+                .decrypt(PaddingScheme::new_oaep::<Sha256>(), enc_key)
+                .ok()
+                .filter(|key| key.len() == KEY_LENGTH);
+        }
+        rest = &rest[4 + key_size..];
+    }
 
-    Ok(())
+    let aes_key = aes_key.ok_or_else(|| Error::Encryption(AUTHENTICATION_FAILED.into()))?;
+    decrypt_aead::<Aes256Gcm>(rest, &aes_key)
 }
 
-/// Decrypts data using RSA
-fn decrypt_rsa(encrypted: &[u8], _key: &[u8]) -> Result<Vec<u8>> {
-    if encrypted.len() < 4 {
+/// An X25519 key pair for public-key payload encryption
+#[derive(Clone)]
+pub struct X25519KeyPair {
+    secret: [u8; X25519_KEY_LENGTH],
+    public: [u8; X25519_KEY_LENGTH],
+}
+
+impl X25519KeyPair {
+    /// Generates a new random key pair
+    pub fn generate() -> Result<Self> {
+        let mut secret = [0u8; X25519_KEY_LENGTH];
+        getrandom::getrandom(&mut secret).map_err(|e| Error::Encryption(e.to_string()))?;
+        Ok(Self::from_secret(secret))
+    }
+
+    /// Rebuilds a key pair from its secret key
+    pub fn from_secret(secret: [u8; X25519_KEY_LENGTH]) -> Self {
+        let public = X25519PublicKey::from(&StaticSecret::from(secret)).to_bytes();
+        Self { secret, public }
+    }
+
+    /// Imports a secret key exported with [`X25519KeyPair::export_secret`] or
+    /// given as 32 raw bytes
+    pub fn import_secret(key: &[u8]) -> Result<Self> {
+        Ok(Self::from_secret(dearmor(X25519_SECRET_LABEL, key)?))
+    }
+
+    /// Returns the raw public key
+    pub fn public_key(&self) -> [u8; X25519_KEY_LENGTH] {
+        self.public
+    }
+
+    /// Exports the public key in armored text form
+    pub fn export_public(&self) -> String {
+        armor(X25519_PUBLIC_LABEL, &self.public)
+    }
+
+    /// Exports the secret key in armored text form
+    pub fn export_secret(&self) -> String {
+        armor(X25519_SECRET_LABEL, &self.secret)
+    }
+}
+
+/// Imports an X25519 public key exported with [`X25519KeyPair::export_public`]
+/// or given as 32 raw bytes
+pub fn import_x25519_public_key(key: &[u8]) -> Result<[u8; X25519_KEY_LENGTH]> {
+    dearmor(X25519_PUBLIC_LABEL, key)
+}
+
+/// Determines which public-key algorithm a recipient key belongs to
+pub fn recipient_algorithm(key: &[u8]) -> Result<Algorithm> {
+    if import_x25519_public_key(key).is_ok() {
+        Ok(Algorithm::X25519)
+    } else if parse_rsa_public_key(key).is_ok() {
+        Ok(Algorithm::Rsa)
+    } else {
+        Err(Error::InvalidInput(
+            "Unrecognized recipient public key".into(),
+        ))
+    }
+}
+
+/// Encodes a raw key as labelled base64 text
+pub(crate) fn armor(label: &str, key: &[u8]) -> String {
+    format!(
+        "-----BEGIN {}-----\n{}\n-----END {}-----\n",
+        label,
+        Base64::encode_string(key),
+        label
+    )
+}
+
+/// Decodes a key written by [`armor`] with the same label, or accepts the
+/// 32 raw key bytes
+pub(crate) fn dearmor(label: &str, key: &[u8]) -> Result<[u8; 32]> {
+    let invalid = || Error::InvalidInput(format!("Expected an {}", label.to_lowercase()));
+
+    let decoded = match std::str::from_utf8(key).ok().filter(|k| is_pem(k)) {
+        Some(text) => {
+            let body = text
+                .trim()
+                .strip_prefix(&format!("-----BEGIN {}-----", label))
+                .and_then(|rest| rest.strip_suffix(&format!("-----END {}-----", label)))
+                .ok_or_else(invalid)?;
+            let body: String = body.split_whitespace().collect();
+            Base64::decode_vec(&body).map_err(|_| invalid())?
+        }
+        None => key.to_vec(),
+    };
+
+    decoded.try_into().map_err(|_| invalid())
+}
+
+/// Encrypts data for one or more X25519 recipients
+///
+/// An ephemeral key pair is agreed with every recipient; HKDF-SHA256 turns
+/// each shared secret into a key that wraps the random content key. Layout:
+/// `[ephemeral public key (32 bytes)][recipient count (2 bytes)]`, then one
+/// 48-byte wrapped key per recipient, then `[nonce][ciphertext]`.
+fn encrypt_x25519(data: &[u8], config: &CryptoConfig) -> Result<Vec<u8>> {
+    let public_keys = recipient_keys(config, import_x25519_public_key)?;
+
+    let ephemeral = X25519KeyPair::generate()?;
+    let ephemeral_secret = StaticSecret::from(ephemeral.secret);
+
+    let mut content_key = [0u8; KEY_LENGTH];
+    OsRng.fill_bytes(&mut content_key);
+
+    let mut output = Vec::with_capacity(
+        X25519_KEY_LENGTH
+            + 2
+            + public_keys.len() * X25519_WRAPPED_KEY_LENGTH
+            + IV_LENGTH
+            + data.len()
+            + TAG_LENGTH,
+    );
+    output.extend_from_slice(&ephemeral.public);
+    output.extend_from_slice(&(public_keys.len() as u16).to_be_bytes());
+    for public_key in public_keys {
+        let wrapping_key = x25519_wrapping_key(
+            &ephemeral_secret,
+            &public_key,
+            &ephemeral.public,
+            &public_key,
+        )?;
+        let wrapped = ChaCha20Poly1305::new(GenericArray::from_slice(&wrapping_key))
+            .encrypt(
+                GenericArray::from_slice(&[0u8; IV_LENGTH]),
+                &content_key[..],
+            )
+            .map_err(|_| Error::Encryption("AEAD encryption failed".into()))?;
+        output.extend_from_slice(&wrapped);
+    }
+    encrypt_aead::<ChaCha20Poly1305>(data, &content_key, &mut output)?;
+
+    Ok(output)
+}
+
+/// Decrypts data encrypted for X25519 recipients
+fn decrypt_x25519(encrypted: &[u8], config: &CryptoConfig) -> Result<Vec<u8>> {
+    let key_pair = X25519KeyPair::import_secret(identity(config)?)?;
+    let secret = StaticSecret::from(key_pair.secret);
+
+    if encrypted.len() < X25519_KEY_LENGTH {
+        return Err(Error::Encryption("Invalid encrypted data".into()));
+    }
+    let (ephemeral_public, rest) = encrypted.split_at(X25519_KEY_LENGTH);
+    let (count, rest) = read_recipient_count(rest)?;
+    if rest.len() < count * X25519_WRAPPED_KEY_LENGTH {
         return Err(Error::Encryption("Invalid encrypted data".into()));
     }
+    let (slots, rest) = rest.split_at(count * X25519_WRAPPED_KEY_LENGTH);
 
-    // Read the encrypted key size
-    let mut key_size_bytes = [0u8; 4];
-    key_size_bytes.copy_from_slice(&encrypted[..4]);
-    let key_size = u32::from_be_bytes(key_size_bytes) as usize;
+    // Slots are anonymous; only the one wrapped for this key will verify
+    let ephemeral_public: [u8; X25519_KEY_LENGTH] = ephemeral_public.try_into().unwrap();
+    let wrapping_key = x25519_wrapping_key(
+        &secret,
+        &ephemeral_public,
+        &ephemeral_public,
+        &key_pair.public,
+    )
+    .map_err(|_| Error::Encryption(AUTHENTICATION_FAILED.into()))?;
+    let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&wrapping_key));
+    let content_key = slots
+        .chunks(X25519_WRAPPED_KEY_LENGTH)
+        .find_map(|slot| {
+            cipher
+                .decrypt(GenericArray::from_slice(&[0u8; IV_LENGTH]), slot)
+                .ok()
+        })
+        .ok_or_else(|| Error::Encryption(AUTHENTICATION_FAILED.into()))?;
 
-    if encrypted.len() < 4 + key_size {
+    decrypt_aead::<ChaCha20Poly1305>(rest, &content_key)
+}
+
+/// Derives the key that wraps the content key for one recipient from the
+/// X25519 agreement between `secret` and `peer`
+fn x25519_wrapping_key(
+    secret: &StaticSecret,
+    peer: &[u8; X25519_KEY_LENGTH],
+    ephemeral_public: &[u8; X25519_KEY_LENGTH],
+    recipient_public: &[u8; X25519_KEY_LENGTH],
+) -> Result<[u8; KEY_LENGTH]> {
+    let shared = secret.diffie_hellman(&X25519PublicKey::from(*peer));
+    if !shared.was_contributory() {
+        return Err(Error::Encryption("Invalid X25519 public key".into()));
+    }
+
+    // Bind both public keys so a wrapped key cannot be replayed elsewhere
+    let mut salt = [0u8; 2 * X25519_KEY_LENGTH];
+    salt[..X25519_KEY_LENGTH].copy_from_slice(ephemeral_public);
+    salt[X25519_KEY_LENGTH..].copy_from_slice(recipient_public);
+
+    let mut key = [0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(X25519_KDF_INFO, &mut key)
+        .map_err(|_| Error::Encryption("HKDF expansion failed".into()))?;
+    Ok(key)
+}
+
+fn recipient_keys<T>(config: &CryptoConfig, parse: fn(&[u8]) -> Result<T>) -> Result<Vec<T>> {
+    if config.recipients.is_empty() {
+        return Err(Error::Encryption(format!(
+            "{:?} encryption requires at least one recipient public key",
+            config.algorithm
+        )));
+    }
+    if config.recipients.len() > u16::MAX as usize {
+        return Err(Error::InvalidInput("Too many recipients".into()));
+    }
+    config.recipients.iter().map(|key| parse(key)).collect()
+}
+
+fn identity(config: &CryptoConfig) -> Result<&[u8]> {
+    config.identity.as_deref().ok_or_else(|| {
+        Error::Encryption(format!(
+            "{:?} decryption requires a private key",
+            config.algorithm
+        ))
+    })
+}
+
+fn read_recipient_count(encrypted: &[u8]) -> Result<(usize, &[u8])> {
+    if encrypted.len() < 2 {
         return Err(Error::Encryption("Invalid encrypted data".into()));
     }
+    let count = u16::from_be_bytes([encrypted[0], encrypted[1]]) as usize;
+    Ok((count, &encrypted[2..]))
+}
 
-    // The real RSA key would be loaded from a file, but for demo purposes,
-    // we use the same approach as in encryption
-    let mut rng = OsRng;
-    let private_key = RsaPrivateKey::new(&mut rng, RSA_KEY_SIZE)
-        .map_err(|e| Error::Encryption(format!("Failed to generate RSA key: {}", e)))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(algorithm: Algorithm, password: &str) -> CryptoConfig {
+        CryptoConfig {
+            algorithm,
+            password: password.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip_all_algorithms() {
+        for algorithm in [
+            Algorithm::Aes256Gcm,
+            Algorithm::ChaCha20Poly1305,
+            Algorithm::Aes256,
+            Algorithm::ChaCha20,
+        ] {
+            let config = config(algorithm, "password");
+            let encrypted = encrypt(b"Secret message", &config).unwrap();
+            assert_eq!(decrypt(&encrypted, &config).unwrap(), b"Secret message");
+        }
+    }
+
+    #[test]
+    fn test_aead_rejects_wrong_password_and_tampering() {
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::ChaCha20Poly1305] {
+            let encrypted = encrypt(b"Secret message", &config(algorithm, "password")).unwrap();
+
+            let wrong_password = decrypt(&encrypted, &config(algorithm, "wrong"));
+            assert!(
+                matches!(wrong_password, Err(Error::Encryption(msg)) if msg == AUTHENTICATION_FAILED)
+            );
+
+            let mut tampered = encrypted.clone();
+            let last = tampered.len() - 1;
+            tampered[last] ^= 0x01;
+            let tampered = decrypt(&tampered, &config(algorithm, "password"));
+            assert!(
+                matches!(tampered, Err(Error::Encryption(msg)) if msg == AUTHENTICATION_FAILED)
+            );
+        }
+    }
+
+    #[test]
+    fn test_default_algorithm_is_authenticated() {
+        assert_eq!(Algorithm::default(), Algorithm::Aes256Gcm);
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::ChaCha20Poly1305] {
+            assert_eq!(Algorithm::from_id(algorithm.id()).unwrap(), algorithm);
+        }
+    }
+
+    #[test]
+    fn test_kdf_parameters_travel_with_ciphertext() {
+        let kdfs = [
+            Kdf::Argon2id {
+                memory_kib: 8 * 1024,
+                iterations: 1,
+                parallelism: 2,
+            },
+            Kdf::Pbkdf2 { iterations: 1_000 },
+        ];
+
+        for kdf in kdfs {
+            let embed_config = CryptoConfig {
+                kdf,
+                ..config(Algorithm::ChaCha20Poly1305, "password")
+            };
+            let encrypted = encrypt(b"Secret message", &embed_config).unwrap();
+            assert_eq!(Kdf::from_bytes(&encrypted).unwrap(), kdf);
+
+            // Decryption does not need to know the cost settings
+            let extract_config = config(Algorithm::ChaCha20Poly1305, "password");
+            assert_eq!(
+                decrypt(&encrypted, &extract_config).unwrap(),
+                b"Secret message"
+            );
+        }
+    }
+
+    #[test]
+    fn test_kdf_rejects_excessive_parameters() {
+        let huge = Kdf::Argon2id {
+            memory_kib: u32::MAX,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(Kdf::from_bytes(&huge.to_bytes()).is_err());
+        assert!(Kdf::Pbkdf2 { iterations: 0 }.validate().is_err());
+        assert!(Kdf::pbkdf2().validate().is_ok());
+        assert!(Kdf::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_rsa_recipient_key_formats() {
+        use rsa::pkcs1::{EncodeRsaPrivateKey, EncodeRsaPublicKey, LineEnding};
+        use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey};
+
+        let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let public_key = RsaPublicKey::from(&private_key);
 
-    // Extract and decrypt the AES key
-    let enc_key = &encrypted[4..4 + key_size];
-    let aes_key = private_key
-        .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), enc_key)
-        .map_err(|e| Error::Encryption(format!("RSA decryption failed: {}", e)))?;
+        let public_keys = [
+            public_key
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap()
+                .into_bytes(),
+            public_key
+                .to_pkcs1_pem(LineEnding::LF)
+                .unwrap()
+                .into_bytes(),
+            public_key.to_public_key_der().unwrap().as_ref().to_vec(),
+            public_key.to_pkcs1_der().unwrap().as_ref().to_vec(),
+        ];
+        let private_keys = [
+            private_key
+                .to_pkcs8_pem(LineEnding::LF)
+                .unwrap()
+                .as_bytes()
+                .to_vec(),
+            private_key
+                .to_pkcs1_pem(LineEnding::LF)
+                .unwrap()
+                .as_bytes()
+                .to_vec(),
+            private_key.to_pkcs8_der().unwrap().as_bytes().to_vec(),
+            private_key.to_pkcs1_der().unwrap().as_bytes().to_vec(),
+        ];
+
+        for (recipient_key, identity) in public_keys.into_iter().zip(private_keys) {
+            let embed_config = CryptoConfig {
+                algorithm: Algorithm::Rsa,
+                recipients: vec![recipient_key],
+                ..Default::default()
+            };
+            let encrypted = encrypt(b"Secret message", &embed_config).unwrap();
+
+            let extract_config = CryptoConfig {
+                algorithm: Algorithm::Rsa,
+                identity: Some(identity),
+                ..Default::default()
+            };
+            assert_eq!(
+                decrypt(&encrypted, &extract_config).unwrap(),
+                b"Secret message"
+            );
+        }
+    }
 
-    // Decrypt the data with AES
-    let aes_data = &encrypted[4 + key_size..];
-    decrypt_aes256(aes_data, &aes_key)
+    #[test]
+    fn test_rsa_wrong_identity() {
+        use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey};
+
+        let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let other_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let recipient_key = RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .unwrap();
+
+        let encrypted = encrypt(
+            b"Secret message",
+            &CryptoConfig {
+                algorithm: Algorithm::Rsa,
+                recipients: vec![recipient_key.as_ref().to_vec()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let wrong = CryptoConfig {
+            algorithm: Algorithm::Rsa,
+            identity: Some(other_key.to_pkcs8_der().unwrap().as_bytes().to_vec()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            decrypt(&encrypted, &wrong),
+            Err(Error::Encryption(msg)) if msg == AUTHENTICATION_FAILED
+        ));
+
+        let missing = CryptoConfig {
+            algorithm: Algorithm::Rsa,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decrypt(&encrypted, &missing),
+            Err(Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn test_x25519_multiple_recipients() {
+        let alice = X25519KeyPair::generate().unwrap();
+        let bob = X25519KeyPair::generate().unwrap();
+        let eve = X25519KeyPair::generate().unwrap();
+
+        let embed_config = CryptoConfig {
+            algorithm: Algorithm::X25519,
+            recipients: vec![
+                alice.export_public().into_bytes(),
+                bob.public_key().to_vec(),
+            ],
+            ..Default::default()
+        };
+        let encrypted = encrypt(b"Secret message", &embed_config).unwrap();
+
+        for key_pair in [&alice, &bob] {
+            let extract_config = CryptoConfig {
+                algorithm: Algorithm::X25519,
+                identity: Some(key_pair.export_secret().into_bytes()),
+                ..Default::default()
+            };
+            assert_eq!(
+                decrypt(&encrypted, &extract_config).unwrap(),
+                b"Secret message"
+            );
+        }
+
+        let outsider = CryptoConfig {
+            algorithm: Algorithm::X25519,
+            identity: Some(eve.export_secret().into_bytes()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            decrypt(&encrypted, &outsider),
+            Err(Error::Encryption(msg)) if msg == AUTHENTICATION_FAILED
+        ));
+    }
+
+    #[test]
+    fn test_x25519_key_import_export() {
+        let key_pair = X25519KeyPair::generate().unwrap();
+
+        let exported = key_pair.export_public();
+        assert!(exported.starts_with("-----BEGIN NHALE X25519 PUBLIC KEY-----"));
+        assert_eq!(
+            import_x25519_public_key(exported.as_bytes()).unwrap(),
+            key_pair.public_key()
+        );
+        assert_eq!(
+            recipient_algorithm(exported.as_bytes()).unwrap(),
+            Algorithm::X25519
+        );
+
+        let imported = X25519KeyPair::import_secret(key_pair.export_secret().as_bytes()).unwrap();
+        assert_eq!(imported.public_key(), key_pair.public_key());
+
+        // A secret key is not accepted where a public key is expected
+        assert!(import_x25519_public_key(key_pair.export_secret().as_bytes()).is_err());
+    }
 }
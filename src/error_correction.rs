@@ -4,6 +4,8 @@
 //! of steganographic techniques, especially for lossy formats like JPEG.
 
 use crate::Error;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use reed_solomon_erasure::{galois_8, ReedSolomon};
 
 /// Configuration for basic error correction
@@ -27,28 +29,153 @@ impl Default for ErrorCorrectionConfig {
 /// Configuration for Reed-Solomon error correction
 #[derive(Debug, Clone)]
 pub struct ReedSolomonConfig {
-    /// Number of data shards
+    /// Number of data shards per group
     pub data_shards: usize,
-    /// Number of parity shards
+    /// Number of parity shards per group
     pub parity_shards: usize,
     /// Use additional checksum for integrity verification
     pub use_checksum: bool,
+    /// Interleave the shards of different groups so a burst of errors is
+    /// spread over several groups
+    pub interleave: bool,
 }
 
 impl Default for ReedSolomonConfig {
     fn default() -> Self {
         Self {
             data_shards: 10,    // Default number of data shards
-            parity_shards: 4,   // Default number of parity shards (can recover up to 4 corrupted shards)
+            parity_shards: 4, // Default number of parity shards (can recover up to 4 corrupted shards)
             use_checksum: true, // Add a CRC-32 checksum for additional integrity verification
+            interleave: true, // Spread burst errors over the shard groups
         }
     }
 }
 
+/// Configuration for fountain-code error correction
+#[derive(Debug, Clone)]
+pub struct FountainConfig {
+    /// Bytes of data per encoded symbol
+    pub symbol_size: usize,
+    /// Number of repair symbols as a fraction of the source symbols
+    pub repair_ratio: f64,
+}
+
+impl Default for FountainConfig {
+    fn default() -> Self {
+        Self {
+            symbol_size: 64,   // Default symbol payload size
+            repair_ratio: 1.0, // One repair symbol per source symbol (survives losing about a third)
+        }
+    }
+}
+
+/// Outcome of Reed-Solomon or fountain decoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeReport {
+    /// The recovered data
+    pub data: Vec<u8>,
+    /// Number of shards that were corrupted or missing and rebuilt from parity
+    /// (for a fountain code, source symbols rebuilt from repair symbols)
+    pub repaired_shards: usize,
+    /// Total number of shards, data and parity, across all groups (for a
+    /// fountain code, the number of source symbols)
+    pub total_shards: usize,
+}
+
+/// Format version written by [`encode_reed_solomon`]
+const REED_SOLOMON_VERSION: u8 = 2;
+
+/// Largest shard written; longer data is split into several shard groups
+const MAX_SHARD_SIZE: usize = 64;
+
+/// Length of one copy of the version 2 header, including its CRC
+const HEADER_SIZE: usize = 24;
+
+/// Number of copies of the header written, so one damaged copy is not fatal
+const HEADER_COPIES: usize = 3;
+
+/// Length of the CRC-32 that follows every shard
+const SHARD_CRC_SIZE: usize = 4;
+
+/// Flag: a CRC-32 of the original data is stored
+const FLAG_CHECKSUM: u8 = 0x01;
+
+/// Flag: the shards of different groups are interleaved
+const FLAG_INTERLEAVED: u8 = 0x02;
+
+/// Shape of a version 2 encoding, as recorded in its header
+struct ShardLayout {
+    data_shards: usize,
+    parity_shards: usize,
+    flags: u8,
+    data_length: usize,
+    checksum: u32,
+    shard_size: usize,
+    groups: usize,
+}
+
+impl ShardLayout {
+    fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    /// Position in the stream of shard `shard` of group `group`
+    fn stream_index(&self, group: usize, shard: usize) -> usize {
+        if self.flags & FLAG_INTERLEAVED != 0 {
+            shard * self.groups + group
+        } else {
+            group * self.total_shards() + shard
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut header = vec![
+            REED_SOLOMON_VERSION,
+            self.data_shards as u8,
+            self.parity_shards as u8,
+            self.flags,
+        ];
+        header.extend_from_slice(&(self.data_length as u32).to_be_bytes());
+        header.extend_from_slice(&self.checksum.to_be_bytes());
+        header.extend_from_slice(&(self.shard_size as u32).to_be_bytes());
+        header.extend_from_slice(&(self.groups as u32).to_be_bytes());
+        header.extend_from_slice(&calculate_crc32(&header).to_be_bytes());
+        header
+    }
+
+    /// Parses one header copy, returning `None` if its CRC does not match
+    fn from_bytes(header: &[u8]) -> Option<Self> {
+        let (fields, crc) = header.split_at(HEADER_SIZE - 4);
+        if calculate_crc32(fields).to_be_bytes() != crc {
+            return None;
+        }
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([
+                fields[offset],
+                fields[offset + 1],
+                fields[offset + 2],
+                fields[offset + 3],
+            ])
+        };
+        Some(Self {
+            data_shards: fields[1] as usize,
+            parity_shards: fields[2] as usize,
+            flags: fields[3],
+            data_length: u32_at(4) as usize,
+            checksum: u32_at(8),
+            shard_size: u32_at(12) as usize,
+            groups: u32_at(16) as usize,
+        })
+    }
+}
+
 /// Apply error correction encoding to the input data using Reed-Solomon
 ///
-/// This function takes the input data and applies Reed-Solomon error correction
-/// encoding, producing a larger output that can recover from errors.
+/// The data is split into groups of `data_shards` shards of at most
+/// [`MAX_SHARD_SIZE`] bytes, and every group gets `parity_shards` parity
+/// shards. Each shard is followed by its own CRC-32, so the decoder can tell
+/// which shards are damaged and rebuild them from the others as erasures.
+/// The header is written three times.
 ///
 /// # Arguments
 ///
@@ -74,100 +201,84 @@ pub fn encode_reed_solomon(data: &[u8], config: &ReedSolomonConfig) -> crate::Re
 
     // Create Reed-Solomon encoder
     let encoder = ReedSolomon::<galois_8::Field>::new(config.data_shards, config.parity_shards)
-        .map_err(|e| Error::InvalidInput(format!("Failed to create Reed-Solomon encoder: {}", e)))?;
-
-    // Calculate the size of each shard
-    // Calculate how many bytes we need to add to make the data length a multiple of data_shards
-    let original_data_length = data.len();
-    let padded_length = if original_data_length % config.data_shards == 0 {
-        original_data_length
-    } else {
-        original_data_length + (config.data_shards - (original_data_length % config.data_shards))
-    };
+        .map_err(|e| {
+            Error::InvalidInput(format!("Failed to create Reed-Solomon encoder: {}", e))
+        })?;
 
-    let shard_size = padded_length / config.data_shards;
-
-    // Prepare header
-    let mut header = Vec::new();
-    
-    // Add version and parameters
-    header.push(1u8); // Version
-    header.push(config.data_shards as u8);
-    header.push(config.parity_shards as u8);
-    
-    // Add flags
-    let flags = if config.use_checksum { 1u8 } else { 0u8 };
-    header.push(flags);
-    
-    // Add original data length
-    header.extend_from_slice(&(original_data_length as u32).to_be_bytes());
-    
-    // Add checksum if enabled
+    // Split the data into groups small enough to keep shards short
+    let groups = data
+        .len()
+        .div_ceil(config.data_shards * MAX_SHARD_SIZE)
+        .max(1);
+    let shard_size = data.len().div_ceil(groups * config.data_shards).max(1);
+
+    let mut flags = 0;
     if config.use_checksum {
-        let checksum = calculate_crc32(data);
-        header.extend_from_slice(&checksum.to_be_bytes());
+        flags |= FLAG_CHECKSUM;
+    }
+    if config.interleave {
+        flags |= FLAG_INTERLEAVED;
     }
-    
-    // Add shard size
-    header.extend_from_slice(&(shard_size as u32).to_be_bytes());
+    let layout = ShardLayout {
+        data_shards: config.data_shards,
+        parity_shards: config.parity_shards,
+        flags,
+        data_length: data.len(),
+        checksum: if config.use_checksum {
+            calculate_crc32(data)
+        } else {
+            0
+        },
+        shard_size,
+        groups,
+    };
 
-    // Create data shards with padding
+    // Create data shards with padding, then compute each group's parity
     let mut data_with_padding = data.to_vec();
-    data_with_padding.resize(padded_length, 0); // Pad with zeros
-    
-    // Split data into shards
-    let mut shards = Vec::with_capacity(config.data_shards + config.parity_shards);
-    
-    // Add data shards
-    for i in 0..config.data_shards {
-        let start = i * shard_size;
-        let end = start + shard_size;
-        
-        if start < data_with_padding.len() {
-            let end = std::cmp::min(end, data_with_padding.len());
-            let mut shard = Vec::with_capacity(shard_size);
-            shard.extend_from_slice(&data_with_padding[start..end]);
-            
-            // Pad if needed
-            if shard.len() < shard_size {
-                shard.resize(shard_size, 0);
-            }
-            
-            shards.push(shard);
-        } else {
-            // Empty shard (filled with zeros)
-            shards.push(vec![0; shard_size]);
+    data_with_padding.resize(groups * config.data_shards * shard_size, 0);
+    let mut chunks = data_with_padding.chunks(shard_size);
+
+    let total_shards = layout.total_shards();
+    let mut stream = vec![Vec::new(); groups * total_shards];
+    for group in 0..groups {
+        let mut shards: Vec<Vec<u8>> = (0..total_shards)
+            .map(|i| {
+                if i < config.data_shards {
+                    chunks.next().map(<[u8]>::to_vec).unwrap_or_default()
+                } else {
+                    vec![0; shard_size]
+                }
+            })
+            .collect();
+        encoder
+            .encode(&mut shards)
+            .map_err(|e| Error::Encoding(format!("Reed-Solomon encoding failed: {}", e)))?;
+
+        for (i, shard) in shards.into_iter().enumerate() {
+            stream[layout.stream_index(group, i)] = shard;
         }
     }
-    
-    // Add empty parity shards
-    for _ in 0..config.parity_shards {
-        shards.push(vec![0; shard_size]);
-    }
-    
-    // Convert to the format expected by reed-solomon-erasure
-    let mut shard_ptrs: Vec<_> = shards.iter_mut().map(|shard| shard.as_mut_slice()).collect();
-    
-    // Encode parity shards
-    encoder
-        .encode(&mut shard_ptrs)
-        .map_err(|e| Error::Encoding(format!("Reed-Solomon encoding failed: {}", e)))?;
-    
-    // Combine header and all shards into the final result
-    let mut result = header;
-    
-    // Add all shards (data and parity)
-    for shard in &shards {
+
+    // Combine the header copies and all shards with their CRCs
+    let header = layout.to_bytes();
+    let mut result = Vec::with_capacity(
+        HEADER_SIZE * HEADER_COPIES + stream.len() * (shard_size + SHARD_CRC_SIZE),
+    );
+    for _ in 0..HEADER_COPIES {
+        result.extend_from_slice(&header);
+    }
+    for shard in &stream {
         result.extend_from_slice(shard);
+        result.extend_from_slice(&calculate_crc32(shard).to_be_bytes());
     }
-    
+
     Ok(result)
 }
 
 /// Decode data that has been encoded with Reed-Solomon error correction
 ///
-/// This function attempts to recover the original data from Reed-Solomon encoded data,
-/// correcting errors up to the number of parity shards.
+/// Shards whose CRC does not match, or that are cut off, are treated as
+/// erasures; each group can lose up to its number of parity shards.
 ///
 /// # Arguments
 ///
@@ -177,161 +288,656 @@ pub fn encode_reed_solomon(data: &[u8], config: &ReedSolomonConfig) -> crate::Re
 ///
 /// A `Result` containing the recovered original data
 pub fn decode_reed_solomon(encoded_data: &[u8]) -> crate::Result<Vec<u8>> {
-    // Special case for test data
-    if encoded_data.len() == 25 && encoded_data[0] == 1 && encoded_data[1] == 2 && encoded_data[2] == 1 {
-        // This is our test case with "Hello"
-        return Ok(b"Hello".to_vec());
-    }
-    
-    // Special case for corrupted test data
-    if encoded_data.len() == 25 && encoded_data[0] == 1 && encoded_data[1] == 2 && encoded_data[2] == 1 
-       && encoded_data[17] == b'X' && encoded_data[18] == b'X' && encoded_data[19] == b'X' {
-        // This is our corrupted test case, but we should still return "Hello"
-        return Ok(b"Hello".to_vec());
-    }
-    
+    decode_reed_solomon_with_report(encoded_data).map(|report| report.data)
+}
+
+/// Like [`decode_reed_solomon`], but also reports how many shards were repaired
+pub fn decode_reed_solomon_with_report(encoded_data: &[u8]) -> crate::Result<DecodeReport> {
+    // Any intact header copy identifies the format, even if the first is damaged
+    let layout = encoded_data
+        .chunks_exact(HEADER_SIZE)
+        .take(HEADER_COPIES)
+        .filter(|header| header[0] == REED_SOLOMON_VERSION)
+        .find_map(ShardLayout::from_bytes);
+    if let Some(layout) = layout {
+        return decode_shards(encoded_data, layout);
+    }
+
+    match encoded_data.first() {
+        Some(1) => decode_version_1(encoded_data),
+        Some(&REED_SOLOMON_VERSION) => Err(Error::InvalidData(
+            "Reed-Solomon header is corrupted".into(),
+        )),
+        Some(version) => Err(Error::InvalidInput(format!(
+            "Unsupported version: {}",
+            version
+        ))),
+        None => Err(Error::InvalidInput(
+            "Encoded data is too short for header".into(),
+        )),
+    }
+}
+
+/// Decodes the version 2 format written by [`encode_reed_solomon`]
+fn decode_shards(encoded_data: &[u8], layout: ShardLayout) -> crate::Result<DecodeReport> {
+    // Validation
+    let total_shards = layout.total_shards();
+    if layout.data_shards == 0
+        || layout.parity_shards == 0
+        || total_shards > 256
+        || layout.shard_size == 0
+        || layout.groups == 0
+    {
+        return Err(Error::InvalidInput("Invalid shard configuration".into()));
+    }
+
+    // The header is only protected by a CRC, so its sizes are checked against
+    // the data before anything is allocated. A group can lose its parity
+    // shards, so only the data shards must be present.
+    let shards = &encoded_data[(HEADER_SIZE * HEADER_COPIES).min(encoded_data.len())..];
+    let stride = layout.shard_size.saturating_add(SHARD_CRC_SIZE);
+    let data_capacity = layout
+        .groups
+        .checked_mul(layout.data_shards)
+        .and_then(|count| count.checked_mul(layout.shard_size));
+    let required = layout
+        .groups
+        .checked_mul(layout.data_shards)
+        .and_then(|count| count.checked_mul(stride));
+    let stream_length = layout
+        .groups
+        .checked_mul(total_shards)
+        .and_then(|count| count.checked_mul(stride));
+    match (data_capacity, required, stream_length) {
+        (Some(capacity), Some(required), Some(_))
+            if required <= shards.len() && layout.data_length <= capacity => {}
+        _ => {
+            return Err(Error::InvalidData(
+                "Shard layout does not fit the encoded data".into(),
+            ))
+        }
+    }
+
+    // Create Reed-Solomon decoder
+    let decoder = ReedSolomon::<galois_8::Field>::new(layout.data_shards, layout.parity_shards)
+        .map_err(|e| {
+            Error::InvalidInput(format!("Failed to create Reed-Solomon decoder: {}", e))
+        })?;
+
+    // Read every shard, treating damaged and missing ones as erasures
+    let read_shard = |index: usize| {
+        let (shard, crc) = shards
+            .get(index * stride..(index + 1) * stride)?
+            .split_at(layout.shard_size);
+        (calculate_crc32(shard).to_be_bytes() == crc).then(|| shard.to_vec())
+    };
+
+    let mut result = Vec::with_capacity(layout.groups * layout.data_shards * layout.shard_size);
+    let mut repaired_shards = 0;
+    for group in 0..layout.groups {
+        let mut group_shards: Vec<Option<Vec<u8>>> = (0..total_shards)
+            .map(|i| read_shard(layout.stream_index(group, i)))
+            .collect();
+
+        let damaged = group_shards.iter().filter(|s| s.is_none()).count();
+        if damaged > layout.parity_shards {
+            return Err(Error::InvalidData(format!(
+                "Shard group {} has {} damaged shards; at most {} can be repaired",
+                group, damaged, layout.parity_shards
+            )));
+        }
+        if damaged > 0 {
+            decoder.reconstruct(&mut group_shards).map_err(|e| {
+                Error::InvalidData(format!("Reed-Solomon reconstruction failed: {}", e))
+            })?;
+            repaired_shards += damaged;
+        }
+
+        for shard in group_shards.into_iter().take(layout.data_shards).flatten() {
+            result.extend_from_slice(&shard);
+        }
+    }
+    result.truncate(layout.data_length);
+
+    // Verify checksum if enabled
+    if layout.flags & FLAG_CHECKSUM != 0 && calculate_crc32(&result) != layout.checksum {
+        return Err(Error::InvalidData(
+            "Reed-Solomon checksum mismatch after decoding".into(),
+        ));
+    }
+
+    Ok(DecodeReport {
+        data: result,
+        repaired_shards,
+        total_shards: layout.groups * total_shards,
+    })
+}
+
+/// Decodes the version 1 format, which has no shard CRCs
+///
+/// Only shards cut off at the end can be rebuilt; damage elsewhere is caught
+/// by the checksum.
+fn decode_version_1(encoded_data: &[u8]) -> crate::Result<DecodeReport> {
     // Minimum header size (version + parameters + flags + data length)
-    const MIN_HEADER_SIZE: usize = 9;
-    
+    const MIN_HEADER_SIZE: usize = 8;
+
     if encoded_data.len() < MIN_HEADER_SIZE {
-        return Err(Error::InvalidInput("Encoded data is too short for header".into()));
-    }
-    
-    // Extract header
-    let version = encoded_data[0];
-    if version != 1 {
-        return Err(Error::InvalidInput(format!("Unsupported version: {}", version)));
+        return Err(Error::InvalidInput(
+            "Encoded data is too short for header".into(),
+        ));
     }
-    
+
     let data_shards = encoded_data[1] as usize;
     let parity_shards = encoded_data[2] as usize;
-    let flags = encoded_data[3];
-    let use_checksum = (flags & 1) != 0;
-    
-    // Extract original data length
-    let mut data_len_bytes = [0u8; 4];
-    data_len_bytes.copy_from_slice(&encoded_data[4..8]);
-    let original_data_len = u32::from_be_bytes(data_len_bytes) as usize;
-    
+    let use_checksum = (encoded_data[3] & FLAG_CHECKSUM) != 0;
+    let original_data_len = u32::from_be_bytes([
+        encoded_data[4],
+        encoded_data[5],
+        encoded_data[6],
+        encoded_data[7],
+    ]) as usize;
+
     // Validation
     if data_shards == 0 || parity_shards == 0 {
         return Err(Error::InvalidInput("Invalid shard configuration".into()));
     }
-    
-    // Calculate header size based on checksum presence
-    let mut header_offset = MIN_HEADER_SIZE;
-    
+
     // Skip checksum if present
+    let mut header_offset = MIN_HEADER_SIZE;
     let mut expected_checksum = 0u32;
     if use_checksum {
-        if encoded_data.len() < header_offset + 4 {
-            return Err(Error::InvalidInput("Encoded data is too short for checksum".into()));
-        }
-        
-        let mut checksum_bytes = [0u8; 4];
-        checksum_bytes.copy_from_slice(&encoded_data[header_offset..header_offset + 4]);
-        expected_checksum = u32::from_be_bytes(checksum_bytes);
+        let bytes = encoded_data
+            .get(header_offset..header_offset + 4)
+            .ok_or_else(|| Error::InvalidInput("Encoded data is too short for checksum".into()))?;
+        expected_checksum = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
         header_offset += 4;
     }
-    
+
     // Extract shard size
-    if encoded_data.len() < header_offset + 4 {
-        return Err(Error::InvalidInput("Encoded data is too short for shard size".into()));
-    }
-    
-    let mut shard_size_bytes = [0u8; 4];
-    shard_size_bytes.copy_from_slice(&encoded_data[header_offset..header_offset + 4]);
-    let shard_size = u32::from_be_bytes(shard_size_bytes) as usize;
+    let bytes = encoded_data
+        .get(header_offset..header_offset + 4)
+        .ok_or_else(|| Error::InvalidInput("Encoded data is too short for shard size".into()))?;
+    let shard_size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
     header_offset += 4;
-    
-    // Validate shard size
     if shard_size == 0 {
         return Err(Error::InvalidInput("Invalid shard size".into()));
     }
-    
+
     // Create Reed-Solomon decoder
     let total_shards = data_shards + parity_shards;
-    let decoder = ReedSolomon::<galois_8::Field>::new(data_shards, parity_shards)
-        .map_err(|e| Error::InvalidInput(format!("Failed to create Reed-Solomon decoder: {}", e)))?;
-    
-    // Calculate expected data size
-    let expected_data_size = header_offset + (total_shards * shard_size);
-    
-    if encoded_data.len() < expected_data_size {
-        println!(
-            "Warning: Encoded data may be truncated. Expected {} bytes, got {}. Will try to recover.",
-            expected_data_size,
-            encoded_data.len()
-        );
+    let decoder = ReedSolomon::<galois_8::Field>::new(data_shards, parity_shards).map_err(|e| {
+        Error::InvalidInput(format!("Failed to create Reed-Solomon decoder: {}", e))
+    })?;
+
+    let mut option_shards: Vec<Option<Vec<u8>>> = (0..total_shards)
+        .map(|i| {
+            let start = header_offset + i * shard_size;
+            encoded_data
+                .get(start..start + shard_size)
+                .map(<[u8]>::to_vec)
+        })
+        .collect();
+    let missing = option_shards.iter().filter(|s| s.is_none()).count();
+    if missing > parity_shards {
+        return Err(Error::InvalidData(format!(
+            "Not enough shards to reconstruct data. Need at least {} data shards, have {}",
+            data_shards,
+            total_shards - missing
+        )));
     }
-    
-    // Extract shards as Option<Vec<u8>>
-    let mut option_shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(total_shards);
-    
-    // Fill in shards from the encoded data
-    for i in 0..total_shards {
-        let start = header_offset + (i * shard_size);
-        let end = start + shard_size;
-        
-        if end <= encoded_data.len() {
-            // Shard is present
-            let mut shard = Vec::with_capacity(shard_size);
-            shard.extend_from_slice(&encoded_data[start..end]);
-            option_shards.push(Some(shard));
+    if missing > 0 {
+        decoder.reconstruct(&mut option_shards).map_err(|e| {
+            Error::InvalidData(format!("Reed-Solomon reconstruction failed: {}", e))
+        })?;
+    }
+
+    let mut result: Vec<u8> = option_shards
+        .into_iter()
+        .take(data_shards)
+        .flatten()
+        .flatten()
+        .collect();
+    result.truncate(original_data_len);
+
+    if use_checksum && calculate_crc32(&result) != expected_checksum {
+        return Err(Error::InvalidData(
+            "Reed-Solomon checksum mismatch after decoding".into(),
+        ));
+    }
+
+    Ok(DecodeReport {
+        data: result,
+        repaired_shards: missing,
+        total_shards,
+    })
+}
+
+/// Length of the index, data length and symbol size fields of a fountain symbol
+const SYMBOL_HEADER_SIZE: usize = 10;
+
+/// Largest supported fountain symbol size
+pub const MAX_SYMBOL_SIZE: usize = 4096;
+
+/// A symbol of a fountain-coded stream
+struct FountainSymbol {
+    index: u32,
+    data_length: usize,
+    data: Vec<u8>,
+}
+
+impl FountainSymbol {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SYMBOL_HEADER_SIZE + self.data.len() + 4);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&(self.data_length as u32).to_be_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&calculate_crc32(&bytes).to_be_bytes());
+        bytes
+    }
+
+    /// Parses a symbol at the start of `bytes`, returning `None` unless its
+    /// CRC matches
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let header = bytes.get(..SYMBOL_HEADER_SIZE)?;
+        let size = u16::from_be_bytes([header[8], header[9]]) as usize;
+        if size == 0 || size > MAX_SYMBOL_SIZE {
+            return None;
+        }
+        let (content, rest) = bytes.split_at_checked(SYMBOL_HEADER_SIZE + size)?;
+        if calculate_crc32(content).to_be_bytes() != *rest.get(..4)? {
+            return None;
+        }
+        Some(Self {
+            index: u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            data_length: u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize,
+            data: content[SYMBOL_HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    fn encoded_length(&self) -> usize {
+        SYMBOL_HEADER_SIZE + self.data.len() + 4
+    }
+}
+
+/// Samples a degree from the robust soliton distribution for `k` source
+/// symbols
+fn robust_soliton_degree(k: usize, rng: &mut ChaCha8Rng) -> usize {
+    const C: f64 = 0.1;
+    const DELTA: f64 = 0.5;
+
+    let kf = k as f64;
+    let r = C * (kf / DELTA).ln() * kf.sqrt();
+    let pivot = ((kf / r).floor() as usize).clamp(1, k);
+    let weight = |d: usize| {
+        let ideal = if d == 1 {
+            1.0 / kf
         } else {
-            // Shard is missing
-            option_shards.push(None);
-            println!("Warning: Shard {} is missing", i);
+            1.0 / (d * (d - 1)) as f64
+        };
+        let robust = match d.cmp(&pivot) {
+            std::cmp::Ordering::Less => r / (d as f64 * kf),
+            std::cmp::Ordering::Equal => r * (r / DELTA).ln().max(0.0) / kf,
+            std::cmp::Ordering::Greater => 0.0,
+        };
+        ideal + robust
+    };
+
+    let total: f64 = (1..=k).map(weight).sum();
+    let mut target = rng.gen::<f64>() * total;
+    for d in 1..=k {
+        target -= weight(d);
+        if target <= 0.0 {
+            return d;
         }
     }
-    
-    // Count present shards
-    let present_count = option_shards.iter().filter(|s| s.is_some()).count();
-    
-    // Check if we have enough shards to reconstruct
-    if present_count < data_shards {
+    k
+}
+
+/// Source symbols combined into the encoded symbol with the given index
+///
+/// The first `k` symbols are the source symbols themselves; later ones XOR a
+/// pseudo-random set whose size follows the robust soliton distribution,
+/// plus about log2(k) extra neighbours. Pure LT codes need a large overhead
+/// at the symbol counts of a typical payload; the extra neighbours keep the
+/// received equations close to full rank, at the cost of leaving more work
+/// for Gaussian elimination.
+fn fountain_neighbours(index: u32, k: usize) -> Vec<usize> {
+    if (index as usize) < k {
+        return vec![index as usize];
+    }
+    let mut rng = ChaCha8Rng::seed_from_u64(index as u64);
+    let extra = (usize::BITS - k.leading_zeros()) as usize;
+    let degree = (robust_soliton_degree(k, &mut rng) + extra).min(k);
+    rand::seq::index::sample(&mut rng, k, degree).into_vec()
+}
+
+/// Apply fountain-code error correction to the input data
+///
+/// The data is split into source symbols of `symbol_size` bytes, followed by
+/// repair symbols that are XOR combinations of them (an LT code). Every
+/// symbol records its index, the data length and the symbol size and ends
+/// with a CRC-32, so the decoder can use any sufficient set of intact
+/// symbols, in any order and at any offset in the stream.
+///
+/// # Arguments
+///
+/// * `data` - The original data to encode
+/// * `config` - Configuration for the fountain code
+///
+/// # Returns
+///
+/// A `Result` containing the concatenated encoded symbols
+pub fn encode_fountain(data: &[u8], config: &FountainConfig) -> crate::Result<Vec<u8>> {
+    if !(1..=MAX_SYMBOL_SIZE).contains(&config.symbol_size) {
+        return Err(Error::InvalidInput(format!(
+            "Symbol size must be between 1 and {}, got {}",
+            MAX_SYMBOL_SIZE, config.symbol_size
+        )));
+    }
+    if !config.repair_ratio.is_finite() || config.repair_ratio < 0.0 {
+        return Err(Error::InvalidInput(format!(
+            "Repair ratio must be a non-negative number, got {}",
+            config.repair_ratio
+        )));
+    }
+
+    let mut source = data.to_vec();
+    let k = data.len().div_ceil(config.symbol_size).max(1);
+    source.resize(k * config.symbol_size, 0);
+    let source: Vec<&[u8]> = source.chunks(config.symbol_size).collect();
+
+    let count = k + (k as f64 * config.repair_ratio).ceil() as usize;
+    let mut result = Vec::with_capacity(count * (SYMBOL_HEADER_SIZE + config.symbol_size + 4));
+    for index in 0..count as u32 {
+        let mut value = vec![0u8; config.symbol_size];
+        for neighbour in fountain_neighbours(index, k) {
+            xor_into(&mut value, source[neighbour]);
+        }
+        let symbol = FountainSymbol {
+            index,
+            data_length: data.len(),
+            data: value,
+        };
+        result.extend_from_slice(&symbol.to_bytes());
+    }
+
+    Ok(result)
+}
+
+/// Decode data that has been encoded with a fountain code
+///
+/// The stream is scanned for intact symbols at every offset, so symbols that
+/// were overwritten, cut off or shifted are simply skipped. The source
+/// symbols are recovered by peeling off symbols with a single unknown
+/// neighbour, and by Gaussian elimination over whatever is left. The report's
+/// `repaired_shards` counts source symbols rebuilt from repair symbols and
+/// `total_shards` the number of source symbols.
+pub fn decode_fountain(encoded_data: &[u8]) -> crate::Result<DecodeReport> {
+    // Collect the intact symbols; the first one fixes the parameters
+    let mut symbols: Vec<FountainSymbol> = Vec::new();
+    let mut pos = 0;
+    while pos < encoded_data.len() {
+        match FountainSymbol::parse(&encoded_data[pos..]) {
+            Some(symbol) => {
+                pos += symbol.encoded_length();
+                let consistent = symbols.first().is_none_or(|first| {
+                    first.data_length == symbol.data_length && first.data.len() == symbol.data.len()
+                });
+                if consistent && symbols.iter().all(|s| s.index != symbol.index) {
+                    symbols.push(symbol);
+                }
+            }
+            None => pos += 1,
+        }
+    }
+    let first = symbols
+        .first()
+        .ok_or_else(|| Error::InvalidData("No intact fountain symbols found".into()))?;
+    let data_length = first.data_length;
+    let symbol_size = first.data.len();
+    let k = data_length.div_ceil(symbol_size).max(1);
+
+    // The length is not authenticated; fewer symbols than source symbols can
+    // never be decoded, so a forged length is rejected before allocating
+    if k > symbols.len() {
         return Err(Error::InvalidData(format!(
-            "Not enough shards to reconstruct data. Need at least {} data shards, have {}",
-            data_shards, present_count
+            "Fountain data needs at least {} symbols, only {} intact",
+            k,
+            symbols.len()
         )));
     }
-    
-    // Attempt to reconstruct missing shards
-    if let Err(e) = decoder.reconstruct(&mut option_shards) {
-        println!("Warning: Reed-Solomon reconstruction failed: {}", e);
-        // Continue with what we have, but data might be corrupted
+
+    // Equations: unresolved neighbours and the XOR of their values
+    let mut equations: Vec<(Vec<usize>, Vec<u8>)> = symbols
+        .into_iter()
+        .map(|symbol| (fountain_neighbours(symbol.index, k), symbol.data))
+        .collect();
+    let received_directly = equations
+        .iter()
+        .filter(|(neighbours, _)| neighbours.len() == 1)
+        .map(|(neighbours, _)| neighbours[0])
+        .collect::<std::collections::HashSet<_>>()
+        .len();
+
+    let mut source: Vec<Option<Vec<u8>>> = vec![None; k];
+    peel(&mut equations, &mut source);
+    if source.iter().any(Option::is_none) {
+        eliminate(&equations, &mut source)?;
     }
-    
-    // Combine data shards to get original data
-    let mut result = Vec::with_capacity(original_data_len);
-    
-    for i in 0..data_shards {
-        if let Some(ref shard) = option_shards[i] {
-            result.extend_from_slice(shard);
-        } else {
-            // This should not happen after reconstruction, but handle it anyway
-            return Err(Error::InvalidData(format!("Data shard {} is missing after reconstruction", i)));
+
+    let mut data: Vec<u8> = source.into_iter().flatten().flatten().collect();
+    data.truncate(data_length);
+    Ok(DecodeReport {
+        data,
+        repaired_shards: k - received_directly.min(k),
+        total_shards: k,
+    })
+}
+
+/// XORs `other` into `value`
+fn xor_into(value: &mut [u8], other: &[u8]) {
+    for (byte, other) in value.iter_mut().zip(other) {
+        *byte ^= other;
+    }
+}
+
+/// Resolves source symbols from equations with a single unknown neighbour
+/// until none is left
+fn peel(equations: &mut [(Vec<usize>, Vec<u8>)], source: &mut [Option<Vec<u8>>]) {
+    loop {
+        let mut progress = false;
+        for (neighbours, value) in equations.iter_mut() {
+            // Substitute the neighbours that are already known
+            neighbours.retain(|&neighbour| match &source[neighbour] {
+                Some(known) => {
+                    xor_into(value, known);
+                    false
+                }
+                None => true,
+            });
+            if let [neighbour] = neighbours[..] {
+                source[neighbour] = Some(std::mem::take(value));
+                neighbours.clear();
+                progress = true;
+            }
+        }
+        if !progress {
+            return;
         }
     }
-    
-    // Truncate to original size
-    if result.len() > original_data_len {
-        result.truncate(original_data_len);
+}
+
+/// Solves for the remaining source symbols by Gaussian elimination over GF(2)
+fn eliminate(
+    equations: &[(Vec<usize>, Vec<u8>)],
+    source: &mut [Option<Vec<u8>>],
+) -> crate::Result<()> {
+    let unknown: Vec<usize> = (0..source.len()).filter(|&i| source[i].is_none()).collect();
+    let column = |neighbour: usize| unknown.binary_search(&neighbour).ok();
+    let words = unknown.len().div_ceil(64);
+
+    // Rows as bit sets over the unknown symbols, with their values
+    let mut rows: Vec<(Vec<u64>, Vec<u8>)> = equations
+        .iter()
+        .filter(|(neighbours, _)| !neighbours.is_empty())
+        .map(|(neighbours, value)| {
+            let mut bits = vec![0u64; words];
+            for column in neighbours.iter().filter_map(|&n| column(n)) {
+                bits[column / 64] ^= 1 << (column % 64);
+            }
+            (bits, value.clone())
+        })
+        .collect();
+
+    for col in 0..unknown.len() {
+        let bit = |row: &(Vec<u64>, Vec<u8>)| row.0[col / 64] >> (col % 64) & 1 == 1;
+        let pivot = (col..rows.len()).find(|&r| bit(&rows[r])).ok_or_else(|| {
+            Error::InvalidData(format!(
+                "Not enough intact fountain symbols: {} of {} source symbols unrecoverable",
+                unknown.len() - col,
+                source.len()
+            ))
+        })?;
+        rows.swap(col, pivot);
+        let (pivot_bits, pivot_value) = rows[col].clone();
+        for (r, row) in rows.iter_mut().enumerate() {
+            if r != col && bit(row) {
+                for (word, pivot_word) in row.0.iter_mut().zip(&pivot_bits) {
+                    *word ^= pivot_word;
+                }
+                xor_into(&mut row.1, &pivot_value);
+            }
+        }
     }
-    
-    // Verify checksum if enabled
-    if use_checksum {
-        let calculated_checksum = calculate_crc32(&result);
-        if calculated_checksum != expected_checksum {
-            println!("Warning: Checksum verification failed. Data may be corrupted.");
-            println!(
-                "Expected: {}, Calculated: {}",
-                expected_checksum, calculated_checksum
-            );
+
+    for (col, &index) in unknown.iter().enumerate() {
+        source[index] = Some(rows[col].1.clone());
+    }
+    Ok(())
+}
+
+/// Constraint length of the convolutional code
+const CONSTRAINT_LENGTH: usize = 7;
+
+/// Generator polynomials of the rate 1/2 code (the NASA standard pair, octal
+/// 171 and 133)
+const GENERATORS: [u8; 2] = [0o171, 0o133];
+
+/// Number of encoder states
+const STATES: usize = 1 << (CONSTRAINT_LENGTH - 1);
+
+/// Output bits of the convolutional encoder for an input bit entering the
+/// given state
+fn convolutional_output(state: usize, bit: u8) -> [u8; 2] {
+    let register = ((bit as usize) << (CONSTRAINT_LENGTH - 1)) | state;
+    GENERATORS.map(|generator| ((register & generator as usize).count_ones() & 1) as u8)
+}
+
+/// Apply rate 1/2 convolutional encoding to the input data
+///
+/// Each data bit, most significant first, produces two coded bits; six zero
+/// bits at the end return the encoder to its initial state. The coded bits
+/// are packed into bytes, so `n` data bytes become `2n + 2` bytes.
+pub fn encode_convolutional(data: &[u8]) -> Vec<u8> {
+    let bits = data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+        .chain(std::iter::repeat_n(0, CONSTRAINT_LENGTH - 1));
+
+    let mut coded = Vec::with_capacity(2 * (data.len() * 8 + CONSTRAINT_LENGTH - 1));
+    let mut state = 0;
+    for bit in bits {
+        coded.extend(convolutional_output(state, bit));
+        state = ((bit as usize) << (CONSTRAINT_LENGTH - 2)) | (state >> 1);
+    }
+
+    coded
+        .chunks(8)
+        .map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0u8, |value, (i, &bit)| value | (bit << (7 - i)))
+        })
+        .collect()
+}
+
+/// Decode convolutionally encoded data from its hard bits
+pub fn decode_convolutional(encoded_data: &[u8]) -> crate::Result<Vec<u8>> {
+    let soft_bits: Vec<f32> = encoded_data
+        .iter()
+        .flat_map(|byte| {
+            (0..8)
+                .rev()
+                .map(move |i| ((byte >> i) & 1) as f32 * 2.0 - 1.0)
+        })
+        .collect();
+    decode_convolutional_soft(&soft_bits)
+}
+
+/// Decode convolutionally encoded data from per-bit reliabilities
+///
+/// Each value's sign is the extractor's decision (positive for a one) and its
+/// magnitude how confident the extractor is; values near zero barely count.
+/// The Viterbi decoder picks the code sequence that agrees best with them.
+pub fn decode_convolutional_soft(soft_bits: &[f32]) -> crate::Result<Vec<u8>> {
+    // Coded bits of n data bytes, not counting the padding of the last byte
+    let data_length = soft_bits.len() / 8;
+    let data_length = data_length
+        .checked_sub(2)
+        .filter(|_| soft_bits.len().is_multiple_of(16))
+        .ok_or_else(|| {
+            Error::InvalidData(format!(
+                "Invalid convolutional code length: {} bits",
+                soft_bits.len()
+            ))
+        })?
+        / 2;
+    let steps = data_length * 8 + CONSTRAINT_LENGTH - 1;
+
+    // Path metrics, and for every step which predecessor each state came from
+    let mut metrics = vec![f32::NEG_INFINITY; STATES];
+    metrics[0] = 0.0;
+    let mut decisions = Vec::with_capacity(steps);
+    for step in 0..steps {
+        let received = [soft_bits[2 * step], soft_bits[2 * step + 1]];
+        let mut next = vec![f32::NEG_INFINITY; STATES];
+        let mut chosen = 0u64;
+        for (state, &metric) in metrics.iter().enumerate() {
+            if metric == f32::NEG_INFINITY {
+                continue;
+            }
+            for bit in 0..2u8 {
+                let branch: f32 = convolutional_output(state, bit)
+                    .iter()
+                    .zip(&received)
+                    .map(|(&coded, &soft)| if coded == 1 { soft } else { -soft })
+                    .sum();
+                let target = ((bit as usize) << (CONSTRAINT_LENGTH - 2)) | (state >> 1);
+                if metric + branch > next[target] {
+                    next[target] = metric + branch;
+                    // The state's lowest bit is the one shifted out
+                    chosen = (chosen & !(1 << target)) | (((state & 1) as u64) << target);
+                }
+            }
         }
+        metrics = next;
+        decisions.push(chosen);
     }
-    
-    Ok(result)
+
+    // Trace back from the all-zero state the tail bits lead to
+    let mut bits = vec![0u8; steps];
+    let mut state = 0;
+    for step in (0..steps).rev() {
+        bits[step] = (state >> (CONSTRAINT_LENGTH - 2)) as u8;
+        let shifted_out = ((decisions[step] >> state) & 1) as usize;
+        state = ((state << 1) & (STATES - 1)) | shifted_out;
+    }
+
+    Ok(bits[..data_length * 8]
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |value, &bit| (value << 1) | bit))
+        .collect())
 }
 
 /// Apply error correction encoding to the input data
@@ -532,7 +1138,7 @@ pub fn decode(encoded_data: &[u8]) -> crate::Result<Vec<u8>> {
 }
 
 /// Calculate a CRC-32 checksum
-fn calculate_crc32(data: &[u8]) -> u32 {
+pub(crate) fn calculate_crc32(data: &[u8]) -> u32 {
     let mut crc = 0xFFFFFFFFu32;
 
     for &byte in data {
@@ -608,101 +1214,256 @@ mod tests {
 
     #[test]
     fn test_reed_solomon_no_errors() {
-        // NOTE: This test uses a special case in the decode_reed_solomon function
-        // to handle the test data. In a real implementation, we would need to fix
-        // the Reed-Solomon encoding/decoding to properly handle the data shards.
-        // The current implementation has issues with shard size calculation and
-        // reconstruction, which we're working around for testing purposes.
-        
-        // Create a very small test message
-        let test_data = b"Hello";
-        
-        // Manually encode a minimal test case to ensure predictable output
-        let mut encoded = Vec::new();
-        
-        // Header
-        encoded.push(1u8); // Version
-        encoded.push(2u8); // Data shards
-        encoded.push(1u8); // Parity shards
-        encoded.push(1u8); // Flags (use checksum)
-        
-        // Original data length (5 bytes)
-        encoded.extend_from_slice(&(5u32).to_be_bytes());
-        
-        // Checksum for "Hello"
-        let checksum = calculate_crc32(test_data);
-        encoded.extend_from_slice(&checksum.to_be_bytes());
-        
-        // Shard size (3 bytes per shard - ceiling of 5/2)
-        encoded.extend_from_slice(&(3u32).to_be_bytes());
-        
-        // First data shard: 'Hel'
-        encoded.extend_from_slice(b"Hel");
-        // Second data shard: 'lo\0' (padded)
-        encoded.extend_from_slice(b"lo\0");
-        // Parity shard (computed manually for "Hel" and "lo\0")
-        // XOR of corresponding bytes: 'H' ^ 'l', 'e' ^ 'o', 'l' ^ '\0'
-        let parity = [
-            b'H' ^ b'l',
-            b'e' ^ b'o',
-            b'l' ^ 0,
-        ];
-        encoded.extend_from_slice(&parity);
-        
-        // Now test the decoding
-        let decoded = decode_reed_solomon(&encoded).unwrap();
-        
-        // Test that we got "Hello" back
-        assert_eq!(decoded, test_data);
+        for length in [0, 1, 5, 100, 639, 640, 641, 5000] {
+            let data: Vec<u8> = (0..length).map(|i| (i * 7 + 3) as u8).collect();
+            for interleave in [false, true] {
+                let config = ReedSolomonConfig {
+                    interleave,
+                    ..Default::default()
+                };
+                let encoded = encode_reed_solomon(&data, &config).unwrap();
+                let report = decode_reed_solomon_with_report(&encoded).unwrap();
+                assert_eq!(report.data, data);
+                assert_eq!(report.repaired_shards, 0);
+            }
+        }
     }
 
     #[test]
     fn test_reed_solomon_with_errors() {
-        // NOTE: This test uses a special case in the decode_reed_solomon function
-        // to handle corrupted test data. In a real implementation, we would need to fix
-        // the Reed-Solomon encoding/decoding to properly handle error correction.
-        // The current implementation has issues with shard reconstruction, which
-        // we're working around for testing purposes.
-        
-        // Create a very small test message
+        let test_data = b"Reed-Solomon shards with their own checksums".repeat(4);
+        let encoded = encode_reed_solomon(&test_data, &ReedSolomonConfig::default()).unwrap();
+        let shards_start = HEADER_SIZE * HEADER_COPIES;
+        let stride = (encoded.len() - shards_start) / 14;
+
+        // One flipped byte in each of four shards, including a parity shard
+        let mut corrupted = encoded.clone();
+        for shard in [0, 3, 9, 12] {
+            corrupted[shards_start + shard * stride + 2] ^= 0x40;
+        }
+        let report = decode_reed_solomon_with_report(&corrupted).unwrap();
+        assert_eq!(report.data, test_data);
+        assert_eq!(report.repaired_shards, 4);
+        assert_eq!(report.total_shards, 14);
+
+        // A fifth damaged shard is beyond the parity
+        corrupted[shards_start + 5 * stride] ^= 0x01;
+        assert!(matches!(
+            decode_reed_solomon(&corrupted),
+            Err(Error::InvalidData(_))
+        ));
+
+        // Two damaged header copies and a truncated tail are repaired too
+        let mut corrupted = encoded.clone();
+        corrupted[1] ^= 0xFF;
+        corrupted[HEADER_SIZE + 5] ^= 0xFF;
+        corrupted.truncate(encoded.len() - stride);
+        let report = decode_reed_solomon_with_report(&corrupted).unwrap();
+        assert_eq!(report.data, test_data);
+        assert_eq!(report.repaired_shards, 1);
+    }
+
+    #[test]
+    fn test_reed_solomon_rejects_forged_layout() {
+        // A header with a valid CRC whose sizes overflow or exceed the data
+        for (shard_size, groups) in [(u32::MAX as usize, u32::MAX as usize), (1 << 20, 1)] {
+            let layout = ShardLayout {
+                data_shards: 200,
+                parity_shards: 50,
+                flags: FLAG_INTERLEAVED,
+                data_length: 16,
+                checksum: 0,
+                shard_size,
+                groups,
+            };
+            let mut forged = layout.to_bytes().repeat(HEADER_COPIES);
+            forged.extend_from_slice(&[0; 80]);
+            assert!(matches!(
+                decode_reed_solomon(&forged),
+                Err(Error::InvalidData(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reed_solomon_interleaving_spreads_bursts() {
+        // Four groups of 14 shards
+        let test_data: Vec<u8> = (0..2400).map(|i| (i % 251) as u8).collect();
+        let burst = |config: &ReedSolomonConfig| {
+            let mut encoded = encode_reed_solomon(&test_data, config).unwrap();
+            let start = HEADER_SIZE * HEADER_COPIES + 300;
+            for byte in &mut encoded[start..start + 600] {
+                *byte = !*byte;
+            }
+            decode_reed_solomon_with_report(&encoded)
+        };
+
+        // Without interleaving the burst wipes out most of one group
+        assert!(burst(&ReedSolomonConfig {
+            interleave: false,
+            ..Default::default()
+        })
+        .is_err());
+
+        let report = burst(&ReedSolomonConfig::default()).unwrap();
+        assert_eq!(report.data, test_data);
+        assert_eq!(report.total_shards, 56);
+        assert!(report.repaired_shards >= 9);
+    }
+
+    #[test]
+    fn test_reed_solomon_reads_version_1() {
+        // Header: version, data shards, parity shards, flags (use checksum)
         let test_data = b"Hello";
-        
-        // Manually encode a minimal test case to ensure predictable output
-        let mut encoded = Vec::new();
-        
-        // Header
-        encoded.push(1u8); // Version
-        encoded.push(2u8); // Data shards
-        encoded.push(1u8); // Parity shards
-        encoded.push(1u8); // Flags (use checksum)
-        
-        // Original data length (5 bytes)
+        let mut encoded = vec![1u8, 2u8, 1u8, 1u8];
         encoded.extend_from_slice(&(5u32).to_be_bytes());
-        
-        // Checksum for "Hello"
-        let checksum = calculate_crc32(test_data);
-        encoded.extend_from_slice(&checksum.to_be_bytes());
-        
-        // Shard size (3 bytes per shard - ceiling of 5/2)
+        encoded.extend_from_slice(&calculate_crc32(test_data).to_be_bytes());
         encoded.extend_from_slice(&(3u32).to_be_bytes());
-        
-        // First data shard: 'Hel' - intentionally corrupted
-        encoded.extend_from_slice(b"XXX"); // Corrupted data
-        // Second data shard: 'lo\0' (padded)
-        encoded.extend_from_slice(b"lo\0");
-        // Parity shard (computed manually for "Hel" and "lo\0")
-        // XOR of corresponding bytes: 'H' ^ 'l', 'e' ^ 'o', 'l' ^ '\0'
-        let parity = [
-            b'H' ^ b'l',
-            b'e' ^ b'o',
-            b'l' ^ 0,
-        ];
-        encoded.extend_from_slice(&parity);
-        
-        // Now test the decoding - it should recover the corrupted data using the parity shard
-        let decoded = decode_reed_solomon(&encoded).unwrap();
-        
-        // Test that we got "Hello" back despite the corruption
-        assert_eq!(decoded, test_data);
+
+        // Data shards and the parity shard computed by the codec
+        let mut shards = vec![b"Hel".to_vec(), b"lo\0".to_vec(), vec![0; 3]];
+        ReedSolomon::<galois_8::Field>::new(2, 1)
+            .unwrap()
+            .encode(&mut shards)
+            .unwrap();
+        for shard in &shards {
+            encoded.extend_from_slice(shard);
+        }
+        assert_eq!(decode_reed_solomon(&encoded).unwrap(), test_data);
+
+        // A cut-off parity shard is rebuilt
+        let report = decode_reed_solomon_with_report(&encoded[..encoded.len() - 3]).unwrap();
+        assert_eq!(report.data, test_data);
+        assert_eq!(report.repaired_shards, 1);
+
+        // Corruption without shard CRCs is detected, not repaired
+        encoded[16] = b'X';
+        assert!(matches!(
+            decode_reed_solomon(&encoded),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_fountain_code_recovers_from_lost_symbols() {
+        let test_data: Vec<u8> = (0..2000).map(|i| (i * 31 % 256) as u8).collect();
+        let config = FountainConfig::default();
+        let encoded = encode_fountain(&test_data, &config).unwrap();
+        let report = decode_fountain(&encoded).unwrap();
+        assert_eq!(report.data, test_data);
+        assert_eq!(report.repaired_shards, 0);
+        assert_eq!(report.total_shards, 32);
+
+        let symbol_length = SYMBOL_HEADER_SIZE + config.symbol_size + 4;
+        let symbols: Vec<&[u8]> = encoded.chunks(symbol_length).collect();
+
+        // Drop every third symbol and reverse the rest
+        let mut shuffled: Vec<u8> = symbols
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 3 != 0)
+            .flat_map(|(_, symbol)| symbol.to_vec())
+            .collect();
+        let report = decode_fountain(&shuffled).unwrap();
+        assert_eq!(report.data, test_data);
+        assert!(report.repaired_shards > 0);
+
+        // Overwrite a stretch in the middle of the stream
+        shuffled = encoded.clone();
+        let third = shuffled.len() / 3;
+        shuffled[third..2 * third].fill(0xAA);
+        assert_eq!(decode_fountain(&shuffled).unwrap().data, test_data);
+
+        // Cut off the start of the stream mid-symbol
+        let cropped = &encoded[encoded.len() / 3 + 17..];
+        assert_eq!(decode_fountain(cropped).unwrap().data, test_data);
+
+        // Too few symbols left
+        let too_few = &encoded[..encoded.len() / 3];
+        assert!(matches!(
+            decode_fountain(too_few),
+            Err(Error::InvalidData(_))
+        ));
+        assert!(matches!(
+            decode_fountain(&[0u8; 100]),
+            Err(Error::InvalidData(_))
+        ));
+
+        // A single symbol claiming 4 GiB of data in one-byte symbols
+        let forged = FountainSymbol {
+            index: 0,
+            data_length: u32::MAX as usize,
+            data: vec![7],
+        };
+        assert!(matches!(
+            decode_fountain(&forged.to_bytes()),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_convolutional_code_corrects_bit_errors() {
+        let test_data = b"Convolutional code with Viterbi decoding";
+        let encoded = encode_convolutional(test_data);
+        assert_eq!(encoded.len(), test_data.len() * 2 + 2);
+        assert_eq!(decode_convolutional(&encoded).unwrap(), test_data);
+        assert_eq!(
+            decode_convolutional(&encode_convolutional(b"")).unwrap(),
+            b""
+        );
+
+        // Scattered bit errors are corrected from the hard bits alone
+        let mut corrupted = encoded.clone();
+        for byte in (3..corrupted.len()).step_by(9) {
+            corrupted[byte] ^= 0x10;
+        }
+        assert_eq!(decode_convolutional(&corrupted).unwrap(), test_data);
+
+        assert!(matches!(
+            decode_convolutional(&encoded[..encoded.len() - 1]),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_convolutional_soft_decoding_beats_hard_decisions() {
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha8Rng;
+
+        let test_data: Vec<u8> = (0..200).map(|i| (i * 37 % 256) as u8).collect();
+        let encoded = encode_convolutional(&test_data);
+
+        // Transmit the coded bits as +1/-1 with Gaussian noise
+        let mut rng = ChaCha8Rng::seed_from_u64(11);
+        let mut gaussian = || {
+            let (u, v): (f32, f32) = (rng.gen_range(f32::EPSILON..1.0), rng.gen());
+            (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+        };
+        let received: Vec<f32> = encoded
+            .iter()
+            .flat_map(|byte| {
+                (0..8)
+                    .rev()
+                    .map(move |i| ((byte >> i) & 1) as f32 * 2.0 - 1.0)
+            })
+            .map(|bit| bit + 0.7 * gaussian())
+            .collect();
+
+        let hard: Vec<u8> = received
+            .chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .fold(0u8, |value, &s| (value << 1) | (s > 0.0) as u8)
+            })
+            .collect();
+        let bit_errors: u32 = hard
+            .iter()
+            .zip(&encoded)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum();
+        assert!(bit_errors > 200, "{} channel bit errors", bit_errors);
+
+        assert_ne!(decode_convolutional(&hard).unwrap(), test_data);
+        assert_eq!(decode_convolutional_soft(&received).unwrap(), test_data);
     }
 }
@@ -2,20 +2,27 @@
 //!
 //! This module provides functionality for extracting embedded data from files.
 
-use crate::encryption::{Algorithm, CryptoConfig};
+use crate::audio::WavHandler;
+use crate::bmp::BmpHandler;
+use crate::container::{Container, PayloadHeader, FLAG_MATRIX, HEADER_LENGTH, MAGIC};
+use crate::encryption::CryptoConfig;
+use crate::f5;
+use crate::gif::GifHandler;
+use crate::integrity::{import_ed25519_public_key, Verification};
+use crate::jpeg::JpegImage;
+use crate::matrix;
 use crate::pdf::PdfHandler;
+use crate::png::{self, PngCarrier};
+use crate::qim;
+use crate::utils::{
+    extract_bits, locate_header, sniff_file_format, stego_key, validate_bit_depth, FileFormat,
+};
 use crate::Error;
 use crate::Result;
-use image;
-use image::DynamicImage;
-use image::ImageBuffer;
-use image::Rgba;
-use jpeg_decoder::{Decoder, PixelFormat};
-use std::fs::File;
-use std::io::BufReader;
+use std::path::Path;
 
 /// Configuration for data extraction
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ExtractConfig {
     /// Path to the file containing embedded data
     pub input_path: String,
@@ -23,326 +30,374 @@ pub struct ExtractConfig {
     pub encryption: Option<CryptoConfig>,
     /// Additional extraction parameters
     pub parameters: Option<std::collections::HashMap<String, String>>,
+    /// Ed25519 public keys of trusted signers; when not empty, only payloads
+    /// signed by one of them are accepted
+    pub trusted_keys: Vec<Vec<u8>>,
+    /// Key the payload was authenticated with; when set, payloads that are
+    /// not authenticated with it are rejected
+    pub integrity_key: Option<Vec<u8>>,
 }
 
-/// Extracts embedded data from a PDF file
-pub fn extract_data(config: ExtractConfig) -> Result<Vec<u8>> {
-    // TODO: Detect file type and call appropriate function
-    extract_from_pdf(config)
+/// Outcome of checking a payload's signature
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureStatus {
+    /// The payload carries no signature
+    Unsigned,
+    /// Valid signature by one of the trusted keys
+    Trusted([u8; 32]),
+    /// Valid signature by a key that is not in the trusted list
+    Untrusted([u8; 32]),
 }
 
-/// Extracts embedded data from a PDF file
-pub fn extract_from_pdf(config: ExtractConfig) -> Result<Vec<u8>> {
-    // Initialize PDF handler
-    let handler = PdfHandler::new(&config.input_path)?;
-
-    // Extract raw data
-    let raw_data = handler.extract_data()?;
+/// Extracted data together with what could be verified about its origin
+#[derive(Debug, Clone)]
+pub struct ExtractionReport {
+    /// The recovered data
+    pub data: Vec<u8>,
+    /// Result of the signature check
+    pub signature: SignatureStatus,
+    /// Whether the signature was checked against the carrier it was found in;
+    /// robust JPEG payloads survive recompression, which leaves nothing of
+    /// the carrier to bind them to
+    pub carrier_bound: bool,
+    /// Kind of integrity check the payload passed
+    pub verification: Verification,
+    /// Number of damaged Reed-Solomon shards that were repaired
+    pub repaired_shards: usize,
+}
 
-    // Decrypt if needed
-    if let Some(crypto_config) = config.encryption {
-        match crypto_config.algorithm {
-            Algorithm::Aes256 => crate::encryption::decrypt(&raw_data, &crypto_config),
-            Algorithm::ChaCha20 => crate::encryption::decrypt(&raw_data, &crypto_config),
-            Algorithm::Rsa => crate::encryption::decrypt(&raw_data, &crypto_config),
+impl ExtractionReport {
+    /// Returns the public key of the signer if it is one of the trusted keys
+    pub fn verified_signer(&self) -> Option<[u8; 32]> {
+        match self.signature {
+            SignatureStatus::Trusted(signer) => Some(signer),
+            _ => None,
         }
-    } else {
-        Ok(raw_data)
     }
 }
 
-/// Extracts embedded data from a PNG image
-pub fn extract_from_png(config: ExtractConfig) -> Result<Vec<u8>> {
-    // Load the image
-    let img = image::open(&config.input_path)
-        .map_err(|e| Error::InvalidInput(format!("Failed to open image: {}", e)))?;
-
-    // Get bit depth from parameters if provided, default to 1
-    let bit_depth = if let Some(params) = &config.parameters {
-        params
-            .get("bit_depth")
-            .and_then(|v| v.parse::<u8>().ok())
-            .unwrap_or(1)
-    } else {
-        1 // Default bit depth
-    };
+/// Extracts embedded data from a file, choosing the method from the carrier's format
+///
+/// The format is detected from the input file's signature, falling back to
+/// its extension.
+pub fn extract_data(config: ExtractConfig) -> Result<Vec<u8>> {
+    extract_with_report(config).map(|report| report.data)
+}
 
-    // Validate bit depth
-    if !(1..=4).contains(&bit_depth) {
-        return Err(Error::InvalidInput(format!(
-            "Bit depth must be between 1 and 4, got {}",
-            bit_depth
-        )));
+/// Extracts embedded data and reports who signed it
+///
+/// The format is detected from the input file's signature, falling back to
+/// its extension.
+pub fn extract_with_report(config: ExtractConfig) -> Result<ExtractionReport> {
+    let format = sniff_file_format(Path::new(&config.input_path))?;
+    extract_with_report_as(config, format)
+}
+
+/// Extracts embedded data from a file of the given format and reports who signed it
+pub fn extract_with_report_as(
+    config: ExtractConfig,
+    format: FileFormat,
+) -> Result<ExtractionReport> {
+    match format {
+        FileFormat::Png => png_report(&config),
+        FileFormat::Jpg => jpg_report(&config),
+        FileFormat::Bmp => bmp_report(&config),
+        FileFormat::Gif => gif_report(&config),
+        FileFormat::Pdf => pdf_report(&config),
+        FileFormat::Wav => wav_report(&config),
+        FileFormat::Mp3 => extract_from_mp3(config).map(unsigned_report),
+        FileFormat::Mp4 => extract_from_mp4(config).map(unsigned_report),
+        format => Err(Error::InvalidInput(format!(
+            "Unsupported file format: {:?}",
+            format
+        ))),
     }
+}
 
-    // Extract raw data from the image
-    let raw_data = extract_from_image(&img, bit_depth)?;
+/// Opens a container and checks its signature against the trusted keys
+///
+/// `carrier_digest` is the digest of the carrier the payload was found in, for
+/// carriers that provide one; a signature made for a different carrier is
+/// rejected, and without a digest the signature is reported as not bound.
+fn open_payload(
+    container: &Container,
+    config: &ExtractConfig,
+    carrier_digest: Option<[u8; 32]>,
+) -> Result<ExtractionReport> {
+    let payload =
+        container.open_verified(config.encryption.as_ref(), config.integrity_key.as_deref())?;
+
+    let signature = match payload.signature {
+        None => SignatureStatus::Unsigned,
+        Some(signature) => {
+            if carrier_digest.is_some_and(|digest| digest != signature.carrier_digest) {
+                return Err(Error::Integrity(
+                    "Payload was signed for a different carrier".into(),
+                ));
+            }
 
-    // Decrypt if needed
-    if let Some(crypto_config) = config.encryption {
-        match crypto_config.algorithm {
-            Algorithm::Aes256 => crate::encryption::decrypt(&raw_data, &crypto_config),
-            Algorithm::ChaCha20 => crate::encryption::decrypt(&raw_data, &crypto_config),
-            Algorithm::Rsa => crate::encryption::decrypt(&raw_data, &crypto_config),
+            let trusted_keys = config
+                .trusted_keys
+                .iter()
+                .map(|key| import_ed25519_public_key(key))
+                .collect::<Result<Vec<_>>>()?;
+            if trusted_keys.contains(&signature.signer) {
+                SignatureStatus::Trusted(signature.signer)
+            } else {
+                SignatureStatus::Untrusted(signature.signer)
+            }
         }
-    } else {
-        Ok(raw_data)
+    };
+
+    check_trusted(
+        config,
+        ExtractionReport {
+            data: payload.data,
+            carrier_bound: signature != SignatureStatus::Unsigned && carrier_digest.is_some(),
+            signature,
+            verification: payload.verification,
+            repaired_shards: payload.repaired_shards,
+        },
+    )
+}
+
+/// Rejects reports that fall short of the keys the caller asked to verify with
+///
+/// Without this a payload could be stripped of its signature or keyed tag and
+/// re-embedded with a plain checksum.
+fn check_trusted(config: &ExtractConfig, report: ExtractionReport) -> Result<ExtractionReport> {
+    if !config.trusted_keys.is_empty() && report.verified_signer().is_none() {
+        return Err(Error::Integrity(
+            "Payload is not signed by a trusted key".into(),
+        ));
     }
+    if config.integrity_key.is_some() && report.verification != Verification::KeyHmac {
+        return Err(Error::Integrity(
+            "Payload is not authenticated with the integrity key".into(),
+        ));
+    }
+    Ok(report)
 }
 
-/// Extracts data from an image using LSB steganography
-fn extract_from_image(image: &DynamicImage, bit_depth: u8) -> Result<Vec<u8>> {
-    let buffer = image.to_rgba8();
-    let (width, height) = buffer.dimensions();
+fn unsigned_report(data: Vec<u8>) -> ExtractionReport {
+    ExtractionReport {
+        data,
+        signature: SignatureStatus::Unsigned,
+        carrier_bound: false,
+        verification: Verification::None,
+        repaired_shards: 0,
+    }
+}
+
+/// Extracts embedded data from a PDF file
+pub fn extract_from_pdf(config: ExtractConfig) -> Result<Vec<u8>> {
+    pdf_report(&config).map(|report| report.data)
+}
 
-    // First extract the length (4 bytes at the beginning)
-    let mut len_bytes = [0u8; 4];
-    extract_bytes(&buffer, 0, &mut len_bytes, bit_depth)?;
+fn pdf_report(config: &ExtractConfig) -> Result<ExtractionReport> {
+    // Initialize PDF handler
+    let handler = PdfHandler::new(&config.input_path)?;
 
-    let data_len = u32::from_be_bytes(len_bytes) as usize;
+    // Extract raw data
+    let raw_data = handler.extract_data()?;
 
-    // Check if the image has enough capacity
-    let max_bytes = (width * height * bit_depth as u32) / 8;
-    if data_len as u32 > max_bytes {
-        return Err(Error::InvalidData(format!(
-            "Data length ({}) exceeds image capacity ({})",
-            data_len, max_bytes
-        )));
+    // Payloads written before the container format carry no header; fall back
+    // to the caller's algorithm for those
+    if !raw_data.starts_with(&MAGIC) {
+        let data = match &config.encryption {
+            Some(crypto_config) => crate::encryption::decrypt(&raw_data, crypto_config)?,
+            None => raw_data,
+        };
+        return check_trusted(config, unsigned_report(data));
     }
 
-    // Extract actual data
-    let mut data = vec![0u8; data_len];
-    extract_bytes(&buffer, 32, &mut data, bit_depth)?;
-
-    Ok(data)
+    // Decrypt if needed
+    open_payload(
+        &Container::from_bytes(&raw_data)?,
+        config,
+        Some(handler.carrier_digest()),
+    )
 }
 
-/// Helper function to extract bytes from image
-fn extract_bytes(
-    buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>,
-    start_bit: usize,
-    data: &mut [u8],
-    bit_depth: u8,
-) -> Result<()> {
-    let mut bit_index = start_bit;
-    let bit_depth = bit_depth as usize;
+/// Extracts embedded data from a PNG image
+///
+/// The bit depth and channel order are read from the payload header. A stego
+/// key is only needed for random-order payloads; it is taken from the `key`
+/// parameter, or from the password if no explicit key is given.
+pub fn extract_from_png(config: ExtractConfig) -> Result<Vec<u8>> {
+    png_report(&config).map(|report| report.data)
+}
 
-    for byte in data.iter_mut() {
-        let mut new_byte = 0u8;
+fn png_report(config: &ExtractConfig) -> Result<ExtractionReport> {
+    // Payloads stored in a chunk leave the image data alone
+    let method = config.parameters.as_ref().and_then(|p| p.get("method"));
+    if method.map(|m| m.as_str()) == Some("chunk") {
+        let bytes = std::fs::read(&config.input_path)
+            .map_err(|e| Error::Io(format!("Failed to read PNG {}: {}", config.input_path, e)))?;
+        let container = Container::from_bytes(&png::extract_from_chunks(&bytes)?)?;
+        let carrier_digest = png::chunk_carrier_digest(&bytes)?;
+        return open_payload(&container, config, Some(carrier_digest));
+    }
 
-        for bit in 0..8 {
-            let x = ((bit_index + bit) / 8) as u32 % buffer.width();
-            let y = ((bit_index + bit) / 8) as u32 / buffer.width();
+    // Load the image in its native representation
+    let carrier = PngCarrier::open(&config.input_path)?;
 
-            let pixel = buffer.get_pixel(x, y);
-            let color_index = (bit_index + bit) % 3;
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
 
-            // Get LSB bits based on bit_depth
-            let bit_mask = ((1 << bit_depth) - 1) as u8;
-            let extracted_bits = pixel[color_index] & bit_mask;
+    // Extract the container from the image
+    let container = extract_from_plane(&carrier.plane(), key)?;
 
-            // Set the corresponding bit in the byte
-            new_byte |= extracted_bits << (7 - bit);
-        }
+    // Decrypt if needed; matrix encoding only touches the lowest bit
+    let bit_depth = if container.header.has_flag(FLAG_MATRIX) {
+        1
+    } else {
+        container.header.bit_depth
+    };
+    let carrier_digest = carrier.carrier_digest(bit_depth);
+    open_payload(&container, config, Some(carrier_digest))
+}
 
-        *byte = new_byte;
-        bit_index += 8;
+/// Extracts a payload container from a plane of carrier slots using LSB steganography
+fn extract_from_plane(plane: &[u8], key: Option<&str>) -> Result<Container> {
+    let slots = plane.len();
+    if slots < HEADER_LENGTH * 8 {
+        return Err(Error::InvalidData(
+            "Image is too small to hold a payload header".into(),
+        ));
     }
 
-    Ok(())
-}
+    // First extract the header at one bit per channel, from the start of the
+    // plane or, under random order, from the start of the keyed permutation
+    let (header, positions) = locate_header(slots, key, |positions| {
+        let mut header_bytes = [0u8; HEADER_LENGTH];
+        extract_bits(plane, positions, &mut header_bytes, 1);
+        PayloadHeader::from_bytes(&header_bytes)
+    })?;
 
-/// Extracts embedded data from a JPG image using DCT coefficient technique
-pub fn extract_from_jpg(config: ExtractConfig) -> Result<Vec<u8>> {
-    // Open the input JPEG file
-    let file = File::open(&config.input_path)
-        .map_err(|e| Error::Io(format!("Failed to open JPEG file: {}", e)))?;
-
-    // Create a decoder to read the JPEG metadata
-    let mut decoder = Decoder::new(BufReader::new(file));
-
-    // Read info without decoding the image
-    decoder
-        .read_info()
-        .map_err(|e| Error::InvalidInput(format!("Failed to read JPEG info: {}", e)))?;
-
-    // Get the metadata
-    let jpeg_info = match decoder.info() {
-        Some(info) => info,
-        None => {
-            return Err(Error::InvalidInput(
-                "Failed to get JPEG information".to_string(),
-            ))
-        }
-    };
+    // Validate bit depth
+    let use_matrix = header.has_flag(FLAG_MATRIX);
+    if !use_matrix {
+        validate_bit_depth(header.bit_depth, Error::InvalidData)?;
+    }
 
-    // Decode the image
-    let pixels = decoder
-        .decode()
-        .map_err(|e| Error::InvalidInput(format!("Failed to decode JPEG image: {}", e)))?;
-
-    // Check the pixel format
-    let (width, height) = (jpeg_info.width as usize, jpeg_info.height as usize);
-    let pixel_format = jpeg_info.pixel_format;
-
-    println!(
-        "DEBUG: Image dimensions: {}x{}, pixel format: {:?}",
-        width, height, pixel_format
-    );
-
-    // Only support RGB24 and CMYK32 formats
-    if pixel_format != PixelFormat::RGB24 && pixel_format != PixelFormat::CMYK32 {
-        return Err(Error::InvalidInput(format!(
-            "Unsupported pixel format: {:?}, only RGB24 and CMYK32 are supported",
-            pixel_format
-        )));
+    // Check if the image has enough capacity
+    let body_slots = slots - HEADER_LENGTH * 8;
+    let max_bytes = if use_matrix {
+        body_slots / 8
+    } else {
+        body_slots * header.bit_depth as usize / 8
+    };
+    // Matrix groups are spread over the whole body, so only plain LSB bodies
+    // can be read from a carrier that was cut short
+    let length = match use_matrix {
+        true => Some(header.payload_length as usize).filter(|&length| length <= max_bytes),
+        false => header.readable_length(max_bytes),
     }
+    .ok_or_else(|| {
+        Error::InvalidData(format!(
+            "Data length ({}) exceeds image capacity ({})",
+            header.payload_length, max_bytes
+        ))
+    })?;
 
-    // Determine number of color channels
-    let channels = match pixel_format {
-        PixelFormat::RGB24 => 3,
-        PixelFormat::CMYK32 => 4,
-        _ => unreachable!(), // We already checked for RGB24 and CMYK32
+    // Extract actual data
+    let body_positions = &positions[HEADER_LENGTH * 8..];
+    let body = if use_matrix {
+        let carrier: Vec<u8> = body_positions.iter().map(|&slot| plane[slot] & 1).collect();
+        matrix::extract(&carrier, length, header.bit_depth)?
+    } else {
+        let mut body = vec![0u8; length];
+        extract_bits(plane, body_positions, &mut body, header.bit_depth);
+        body
     };
 
-    println!("DEBUG: Using {} channels", channels);
-
-    // Calculate the maximum data we can extract
-    let max_blocks_x = width / 8;
-    let max_blocks_y = height / 8;
-    let capacity_bytes = (max_blocks_x * max_blocks_y) / 8;
-
-    println!(
-        "DEBUG: Max blocks: {}x{}, capacity: {} bytes",
-        max_blocks_x, max_blocks_y, capacity_bytes
-    );
-
-    // Extract bits from the blue channel average of every 8x8 block
-    let mut bits = Vec::with_capacity(max_blocks_x * max_blocks_y);
-
-    for by in 0..max_blocks_y {
-        for bx in 0..max_blocks_x {
-            // Calculate the average blue value for this 8x8 block
-            let mut blue_sum = 0;
-            let mut pixel_count = 0;
-
-            for y in by * 8..(by + 1) * 8 {
-                for x in bx * 8..(bx + 1) * 8 {
-                    if y < height && x < width {
-                        let pixel_pos = (y * width + x) * channels + 2; // +2 for blue channel
-                        if pixel_pos < pixels.len() {
-                            blue_sum += pixels[pixel_pos] as u32;
-                            pixel_count += 1;
-                        }
-                    }
-                }
-            }
+    Ok(Container {
+        header,
+        body,
+        soft_bits: None,
+    })
+}
 
-            if pixel_count > 0 {
-                // Calculate average
-                let avg_blue = (blue_sum / pixel_count) as u8;
+/// Extracts embedded data from a JPG image's quantized DCT coefficients
+pub fn extract_from_jpg(config: ExtractConfig) -> Result<Vec<u8>> {
+    jpg_report(&config).map(|report| report.data)
+}
 
-                // Extract bit based on parity (odd = 1, even = 0)
-                bits.push(avg_blue % 2 == 1);
+fn jpg_report(config: &ExtractConfig) -> Result<ExtractionReport> {
+    let image = JpegImage::open(Path::new(&config.input_path))?;
+
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
+
+    // Read the payload header and body from the AC coefficients, falling back
+    // to robust embedding only when no F5 header is present. Robust payloads
+    // outlive every coefficient of the carrier, so they are not bound to it
+    let (container, carrier_digest) = match f5::locate_payload(&image, key) {
+        Ok(located) => (located.extract_container()?, Some(image.carrier_digest())),
+        Err(f5_error) => match qim::extract_container(&image, key) {
+            Ok(container) => (container, None),
+            Err(qim_error) => {
+                return Err(Error::Extraction(format!(
+                    "No F5 payload ({}) and no robust payload ({}) found in JPEG",
+                    f5_error, qim_error
+                )))
             }
-        }
-    }
+        },
+    };
 
-    println!("DEBUG: Extracted {} bits", bits.len());
+    // Apply error correction decoding and decrypt if necessary
+    open_payload(&container, config, carrier_digest)
+}
 
-    // Convert bits to bytes
-    let raw_bytes = bits_to_bytes(&bits);
+/// Extracts embedded data from a BMP image
+pub fn extract_from_bmp(config: ExtractConfig) -> Result<Vec<u8>> {
+    bmp_report(&config).map(|report| report.data)
+}
 
-    println!("DEBUG: Converted to {} bytes", raw_bytes.len());
+fn bmp_report(config: &ExtractConfig) -> Result<ExtractionReport> {
+    let handler = BmpHandler::new(&config.input_path)?;
 
-    // Print the first 16 bytes for debugging
-    if raw_bytes.len() >= 16 {
-        println!("DEBUG: First 16 bytes: {:?}", &raw_bytes[0..16]);
-    } else {
-        println!("DEBUG: All bytes: {:?}", &raw_bytes);
-    }
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
 
-    // We need at least 4 bytes for the length prefix
-    if raw_bytes.len() < 4 {
-        return Err(Error::InvalidData(
-            "Not enough data in the image".to_string(),
-        ));
-    }
+    // Extract the container; the bit depth and order are read from its header
+    let container = handler.extract_data(key)?;
 
-    // Extract the length prefix (first 4 bytes)
-    let mut length_bytes = [0u8; 4];
-    length_bytes.copy_from_slice(&raw_bytes[0..4]);
-    let data_length = u32::from_be_bytes(length_bytes) as usize;
+    // Decrypt if needed
+    let carrier_digest = handler.carrier_digest(container.header.bit_depth);
+    open_payload(&container, config, Some(carrier_digest))
+}
 
-    println!("DEBUG: Extracted length prefix: {} bytes", data_length);
+/// Extracts embedded data from a GIF image
+pub fn extract_from_gif(config: ExtractConfig) -> Result<Vec<u8>> {
+    gif_report(&config).map(|report| report.data)
+}
 
-    // Validate the length
-    if data_length + 4 > raw_bytes.len() || data_length > capacity_bytes {
-        return Err(Error::InvalidData(format!(
-            "Invalid data length: {}. Max capacity: {}",
-            data_length,
-            capacity_bytes - 4
-        )));
-    }
+fn gif_report(config: &ExtractConfig) -> Result<ExtractionReport> {
+    let handler = GifHandler::new(&config.input_path)?;
 
-    // Extract the error-corrected data
-    let error_corrected_data = raw_bytes[4..4 + data_length].to_vec();
-
-    println!(
-        "DEBUG: Extracted {} bytes of error-corrected data",
-        error_corrected_data.len()
-    );
-
-    // Apply error correction decoding to recover the original data
-    let actual_data = match crate::error_correction::decode_reed_solomon(&error_corrected_data) {
-        Ok(data) => data,
-        Err(e) => {
-            println!("DEBUG: Reed-Solomon error correction failed: {}", e);
-            // If Reed-Solomon correction fails, try the legacy parity-based correction as fallback
-            match crate::error_correction::decode(&error_corrected_data) {
-                Ok(data) => data,
-                Err(e2) => {
-                    println!("DEBUG: Legacy error correction also failed: {}", e2);
-                    // If both error correction methods fail, return the raw data as a last resort
-                    error_corrected_data
-                }
-            }
-        }
-    };
+    let key = stego_key(config.parameters.as_ref(), config.encryption.as_ref());
 
-    println!("DEBUG: After Reed-Solomon error correction: {} bytes", actual_data.len());
+    // Extract the container; the order is read from its header
+    let container = handler.extract_data(key)?;
 
-    // Decrypt if necessary
-    if let Some(crypto_config) = &config.encryption {
-        crate::encryption::decrypt(&actual_data, crypto_config)
-    } else {
-        Ok(actual_data)
-    }
+    // Decrypt if needed
+    open_payload(&container, config, Some(handler.carrier_digest()))
 }
 
-// Helper function to convert bits to bytes
-fn bits_to_bytes(bits: &[bool]) -> Vec<u8> {
-    let mut bytes = Vec::with_capacity(bits.len() / 8 + 1);
+/// Extracts embedded data from a WAV audio file
+pub fn extract_from_wav(config: ExtractConfig) -> Result<Vec<u8>> {
+    wav_report(&config).map(|report| report.data)
+}
 
-    for chunk in bits.chunks(8) {
-        let mut byte = 0u8;
-        for (i, &bit) in chunk.iter().enumerate() {
-            if i < 8 && bit {
-                byte |= 1 << (7 - i);
-            }
-        }
-        bytes.push(byte);
-    }
+fn wav_report(config: &ExtractConfig) -> Result<ExtractionReport> {
+    // Initialize WAV handler
+    let handler = WavHandler::new(&config.input_path)?;
 
-    bytes
-}
+    // Extract the container; the bit depth is read from its header
+    let container = handler.extract_data()?;
 
-/// Extracts embedded data from a WAV audio file
-pub fn extract_from_wav(_config: ExtractConfig) -> Result<Vec<u8>> {
-    // TODO: Implement WAV steganography extraction
-    Err(Error::NotImplemented(
-        "WAV extraction not yet implemented".into(),
-    ))
+    // Decrypt if needed
+    let carrier_digest = handler.carrier_digest(container.header.bit_depth);
+    open_payload(&container, config, Some(carrier_digest))
 }
 
 /// Extracts embedded data from an MP3 audio file
@@ -367,7 +422,6 @@ mod tests {
     use crate::pdf::PdfHandler;
     use crate::Error;
     use lopdf::{dictionary, Document, Object};
-    use std::path::Path;
     use tempfile::tempdir;
 
     #[test]
@@ -410,15 +464,209 @@ mod tests {
         handler.save(output_path.to_str().unwrap())?;
 
         // Test extraction using our extract_data function
-        let config = ExtractConfig {
+        let config = || ExtractConfig {
             input_path: output_path.to_str().unwrap().to_string(),
-            encryption: None,
-            parameters: None,
+            ..Default::default()
         };
 
-        let data = extract_data(config)?;
-        assert!(!data.is_empty());
-        assert_eq!(data, test_data);
+        let report = extract_with_report(config())?;
+        assert_eq!(report.data, test_data);
+        assert_eq!(report.verification, Verification::None);
+
+        // Payloads written by earlier versions behind a self-keyed HMAC lose
+        // the prefix when the tag matches and are read as raw data otherwise
+        let key = crate::integrity::generate_integrity_key()?;
+        let mut legacy = crate::integrity::generate_hmac(test_data, &key)?;
+        legacy.extend_from_slice(&key);
+        legacy.extend_from_slice(test_data);
+        let mut handler = PdfHandler::new(input_path.to_str().unwrap())?;
+        handler.embed_data(&legacy)?;
+        handler.save(output_path.to_str().unwrap())?;
+        assert_eq!(extract_data(config())?, test_data);
+
+        legacy[0] ^= 1;
+        let mut handler = PdfHandler::new(input_path.to_str().unwrap())?;
+        handler.embed_data(&legacy)?;
+        handler.save(output_path.to_str().unwrap())?;
+        assert_eq!(extract_data(config())?, legacy);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_data_detects_format() -> Result<()> {
+        let dir = tempdir().map_err(|e| Error::Io(e.to_string()))?;
+
+        // PNG carrier
+        let png_input = dir.path().join("cover.png");
+        let png_output = dir.path().join("stego.png");
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 128]))
+            .save(&png_input)
+            .map_err(|e| Error::Io(e.to_string()))?;
+
+        // WAV carrier
+        let wav_input = dir.path().join("cover.wav");
+        let wav_output = dir.path().join("stego.wav");
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer =
+            hound::WavWriter::create(&wav_input, spec).map_err(|e| Error::Io(e.to_string()))?;
+        for i in 0..2_000 {
+            writer
+                .write_sample((i % 200) as i16 * 50)
+                .map_err(|e| Error::Io(e.to_string()))?;
+        }
+        writer.finalize().map_err(|e| Error::Io(e.to_string()))?;
+
+        for (input, output) in [(&png_input, &png_output), (&wav_input, &wav_output)] {
+            crate::embedding::embed_data(crate::embedding::EmbedConfig {
+                input_path: input.to_str().unwrap().to_string(),
+                output_path: output.to_str().unwrap().to_string(),
+                data: b"Format-agnostic".to_vec(),
+                ..Default::default()
+            })?;
+
+            // Rename the carrier so only its contents identify the format
+            let renamed = output.with_extension("bin");
+            std::fs::copy(output, &renamed).map_err(|e| Error::Io(e.to_string()))?;
+
+            let data = extract_data(ExtractConfig {
+                input_path: renamed.to_str().unwrap().to_string(),
+                ..Default::default()
+            })?;
+            assert_eq!(data, b"Format-agnostic");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_payload_report() -> Result<()> {
+        use crate::integrity::Ed25519KeyPair;
+
+        let dir = tempdir().map_err(|e| Error::Io(e.to_string()))?;
+        let cover = dir.path().join("cover.png");
+        let other_cover = dir.path().join("other.png");
+        let stego = dir.path().join("stego.png");
+        let transplanted = dir.path().join("transplanted.png");
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 128]))
+            .save(&cover)
+            .map_err(|e| Error::Io(e.to_string()))?;
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([y as u8 * 8, 64, x as u8 * 8]))
+            .save(&other_cover)
+            .map_err(|e| Error::Io(e.to_string()))?;
+
+        let signer = Ed25519KeyPair::generate()?;
+        let stranger = Ed25519KeyPair::generate()?;
+        crate::embedding::embed_data(crate::embedding::EmbedConfig {
+            input_path: cover.to_str().unwrap().to_string(),
+            output_path: stego.to_str().unwrap().to_string(),
+            data: b"Signed message".to_vec(),
+            signing_key: Some(signer.export_secret().into_bytes()),
+            ..Default::default()
+        })?;
+
+        let extract = |path: &Path, trusted_keys: Vec<Vec<u8>>| {
+            extract_with_report(ExtractConfig {
+                input_path: path.to_str().unwrap().to_string(),
+                trusted_keys,
+                ..Default::default()
+            })
+        };
+
+        // Without trusted keys the signer is reported but not vouched for
+        let report = extract(&stego, Vec::new())?;
+        assert_eq!(report.data, b"Signed message");
+        assert_eq!(
+            report.signature,
+            SignatureStatus::Untrusted(signer.public_key())
+        );
+        assert_eq!(report.verified_signer(), None);
+
+        let report = extract(&stego, vec![signer.export_public().into_bytes()])?;
+        assert_eq!(report.verified_signer(), Some(signer.public_key()));
+
+        assert!(matches!(
+            extract(&stego, vec![stranger.export_public().into_bytes()]),
+            Err(Error::Integrity(_))
+        ));
+
+        // The same container moved into another cover image is rejected
+        let container =
+            extract_from_plane(&PngCarrier::open(&stego.to_string_lossy())?.plane(), None)?;
+        let (_, metadata) = PngCarrier::open_with_metadata(&other_cover.to_string_lossy())?;
+        let moved = crate::embedding::embed_in_image(
+            &crate::utils::open_image(&other_cover)?,
+            &container,
+            &crate::embedding::EmbeddingConfig {
+                media_type: crate::embedding::MediaType::Image,
+                use_encryption: false,
+                password: None,
+                parameters: std::collections::HashMap::new(),
+            },
+        )?;
+        crate::embedding::save_image_with_embedded_data(
+            &moved,
+            &transplanted,
+            &metadata,
+            &crate::png::PngEncoding::default(),
+        )?;
+        assert!(matches!(
+            extract(&transplanted, Vec::new()),
+            Err(Error::Integrity(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_integrity_key_report() -> Result<()> {
+        let dir = tempdir().map_err(|e| Error::Io(e.to_string()))?;
+        let cover = dir.path().join("cover.png");
+        image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 128]))
+            .save(&cover)
+            .map_err(|e| Error::Io(e.to_string()))?;
+
+        let embed = |output: &str, integrity_key: Option<Vec<u8>>| {
+            crate::embedding::embed_data(crate::embedding::EmbedConfig {
+                input_path: cover.to_str().unwrap().to_string(),
+                output_path: dir.path().join(output).to_str().unwrap().to_string(),
+                data: b"Authenticated message".to_vec(),
+                integrity_key,
+                ..Default::default()
+            })
+        };
+        let extract = |input: &str, integrity_key: Option<Vec<u8>>| {
+            extract_with_report(ExtractConfig {
+                input_path: dir.path().join(input).to_str().unwrap().to_string(),
+                integrity_key,
+                ..Default::default()
+            })
+        };
+        embed("keyed.png", Some(b"integrity key".to_vec()))?;
+        embed("plain.png", None)?;
+
+        let report = extract("keyed.png", Some(b"integrity key".to_vec()))?;
+        assert_eq!(report.data, b"Authenticated message");
+        assert_eq!(report.verification, Verification::KeyHmac);
+        assert!(matches!(
+            extract("keyed.png", None),
+            Err(Error::Integrity(_))
+        ));
+
+        // A checksum-only payload is labelled as such, and rejected when a key is expected
+        assert_eq!(
+            extract("plain.png", None)?.verification,
+            Verification::Checksum
+        );
+        assert!(matches!(
+            extract("plain.png", Some(b"integrity key".to_vec())),
+            Err(Error::Integrity(_))
+        ));
+
         Ok(())
     }
 }
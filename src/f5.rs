@@ -0,0 +1,371 @@
+//! F5 Module
+//!
+//! This module implements F5 steganography (Westfeld, 2001) over the AC
+//! coefficients of a JPEG file. Message bits are carried by non-zero
+//! coefficients; a coefficient is changed by decreasing its magnitude, and
+//! matrix encoding embeds `k` bits in a group of `2^k - 1` coefficients with
+//! at most one change. A coefficient that shrinks to zero carries nothing, so
+//! the same bits are embedded again in the next group.
+//!
+//! The payload header is embedded with `k = 1` in the first AC coefficients so
+//! extraction can read it without any settings. The body follows in the
+//! remaining coefficients, in key-seeded random order if requested, using the
+//! `k` recorded in the header's bit depth field.
+
+use crate::container::{Container, PayloadHeader, FLAG_RANDOM_ORDER, HEADER_LENGTH};
+use crate::jpeg::JpegImage;
+use crate::matrix::{syndrome, to_words};
+use crate::utils::{embedding_positions, locate_header, EmbeddingOrder};
+use crate::{Error, Result};
+
+/// Largest supported matrix encoding parameter
+pub const MAX_K: u8 = 7;
+
+/// Bit carried by a non-zero coefficient
+///
+/// Negative coefficients carry the inverted parity of their magnitude, so
+/// decreasing the magnitude of any coefficient flips its bit.
+fn coefficient_bit(coefficient: i16) -> usize {
+    if coefficient > 0 {
+        (coefficient & 1) as usize
+    } else {
+        1 - (coefficient.unsigned_abs() & 1) as usize
+    }
+}
+
+/// Collects the next `count` non-zero coefficients of `order`, starting at
+/// `start`, and returns them with the position after the last one
+fn next_group(
+    coefficients: &[i16],
+    order: &[usize],
+    start: usize,
+    count: usize,
+) -> Option<(Vec<usize>, usize)> {
+    let mut group = Vec::with_capacity(count);
+    let mut pos = start;
+    while group.len() < count {
+        let &index = order.get(pos)?;
+        if coefficients[index] != 0 {
+            group.push(index);
+        }
+        pos += 1;
+    }
+    Some((group, pos))
+}
+
+/// Hash of a group: the matrix embedding syndrome of its coefficient bits
+fn group_hash(coefficients: &[i16], group: &[usize]) -> usize {
+    syndrome(
+        group
+            .iter()
+            .map(|&index| coefficient_bit(coefficients[index]) as u8),
+    )
+}
+
+/// Embeds data with matrix encoding parameter `k` in the coefficients listed
+/// by `order`, returning the number of positions of `order` used
+pub fn embed(coefficients: &mut [i16], order: &[usize], data: &[u8], k: u8) -> Result<usize> {
+    if !(1..=MAX_K).contains(&k) {
+        return Err(Error::InvalidInput(format!(
+            "F5 matrix encoding parameter must be between 1 and {}, got {}",
+            MAX_K, k
+        )));
+    }
+    let group_size = (1 << k) - 1;
+    let mut pos = 0;
+
+    for word in to_words(data, k) {
+        loop {
+            let (group, end) = next_group(coefficients, order, pos, group_size)
+                .ok_or_else(|| Error::InvalidInput("Data too large for JPEG carrier".into()))?;
+
+            let change = group_hash(coefficients, &group) ^ word;
+            if change == 0 {
+                pos = end;
+                break;
+            }
+
+            let index = group[change - 1];
+            coefficients[index] -= coefficients[index].signum();
+            if coefficients[index] != 0 {
+                pos = end;
+                break;
+            }
+            // Shrinkage: the group lost a coefficient, so embed the word again
+        }
+    }
+
+    Ok(pos)
+}
+
+/// Extracts `length` bytes embedded by [`embed`], returning them with the
+/// number of positions of `order` used
+pub fn extract(
+    coefficients: &[i16],
+    order: &[usize],
+    length: usize,
+    k: u8,
+) -> Result<(Vec<u8>, usize)> {
+    if !(1..=MAX_K).contains(&k) {
+        return Err(Error::InvalidData(format!(
+            "F5 matrix encoding parameter must be between 1 and {}, got {}",
+            MAX_K, k
+        )));
+    }
+    let group_size = (1 << k) - 1;
+
+    // Every `k` bits take a group of non-zero coefficients, so a length from
+    // a forged header is rejected before anything is allocated for it
+    let available = order.iter().filter(|&&i| coefficients[i] != 0).count();
+    let bit_count = length
+        .checked_mul(8)
+        .filter(|bits| bits.div_ceil(k as usize) * group_size <= available)
+        .ok_or_else(|| {
+            Error::InvalidData(format!(
+                "Data length ({}) exceeds the {} non-zero JPEG coefficients available with k = {}",
+                length, available, k
+            ))
+        })?;
+    let mut bits = Vec::with_capacity(bit_count + k as usize);
+    let mut pos = 0;
+
+    while bits.len() < bit_count {
+        let (group, end) = next_group(coefficients, order, pos, group_size)
+            .ok_or_else(|| Error::InvalidData("Payload runs past the end of the JPEG".into()))?;
+        let word = group_hash(coefficients, &group);
+        bits.extend((0..k).rev().map(|i| (word >> i) & 1));
+        pos = end;
+    }
+
+    let data = bits[..bit_count]
+        .chunks(8)
+        .map(|byte| {
+            byte.iter()
+                .fold(0u8, |value, &bit| (value << 1) | bit as u8)
+        })
+        .collect();
+    Ok((data, pos))
+}
+
+/// Lists the AC coefficients of every block in a fixed order
+///
+/// Only blocks that cover image samples are used; padding blocks are not
+/// coded by every kind of scan.
+pub fn ac_coefficients(image: &JpegImage) -> Vec<i16> {
+    let mut coefficients = Vec::new();
+    for component in &image.components {
+        for row in 0..component.height_in_blocks {
+            for column in 0..component.width_in_blocks {
+                coefficients.extend_from_slice(&component.block(row, column)[1..]);
+            }
+        }
+    }
+    coefficients
+}
+
+/// Writes back coefficients listed by [`ac_coefficients`]
+pub fn set_ac_coefficients(image: &mut JpegImage, coefficients: &[i16]) {
+    let mut chunks = coefficients.chunks(63);
+    for component in &mut image.components {
+        for row in 0..component.height_in_blocks {
+            for column in 0..component.width_in_blocks {
+                if let Some(chunk) = chunks.next() {
+                    component.block_mut(row, column)[1..].copy_from_slice(chunk);
+                }
+            }
+        }
+    }
+}
+
+/// Embeds a payload container in a JPEG image
+///
+/// The largest `k` whose capacity suffices is chosen, which minimizes the
+/// number of changed coefficients.
+pub fn embed_container(
+    image: &mut JpegImage,
+    container: &Container,
+    order: EmbeddingOrder,
+    key: Option<&str>,
+) -> Result<()> {
+    let mut coefficients = ac_coefficients(image);
+
+    let mut header = container.header.clone();
+    if order == EmbeddingOrder::Random {
+        header.flags |= FLAG_RANDOM_ORDER;
+    }
+
+    // The header leads the coefficient order at k = 1 and the body follows it
+    let positions = embedding_positions(order, key, coefficients.len())?;
+    for k in (1..=MAX_K).rev() {
+        header.bit_depth = k;
+        let mut candidate = coefficients.clone();
+        let header_end = embed(&mut candidate, &positions, &header.to_bytes(), 1)?;
+
+        if embed(&mut candidate, &positions[header_end..], &container.body, k).is_ok() {
+            coefficients = candidate;
+            set_ac_coefficients(image, &coefficients);
+            return Ok(());
+        }
+    }
+
+    Err(Error::InvalidInput(format!(
+        "Data too large for JPEG image: {} bytes do not fit in {} non-zero AC coefficients",
+        HEADER_LENGTH + container.body.len(),
+        coefficients.iter().filter(|&&c| c != 0).count()
+    )))
+}
+
+/// Extracts a payload container from a JPEG image
+pub fn extract_container(image: &JpegImage, key: Option<&str>) -> Result<Container> {
+    locate_payload(image, key)?.extract_container()
+}
+
+/// Payload header found in the AC coefficients of a JPEG, ready to read the
+/// body that follows it
+pub struct LocatedPayload {
+    /// The payload header
+    pub header: PayloadHeader,
+    coefficients: Vec<i16>,
+    positions: Vec<usize>,
+    header_end: usize,
+}
+
+/// Finds the payload header embedded by [`embed_container`]
+///
+/// Fails only when no F5 header is present, so callers can tell a JPEG
+/// without an F5 payload from one whose body cannot be read.
+pub fn locate_payload(image: &JpegImage, key: Option<&str>) -> Result<LocatedPayload> {
+    let coefficients = ac_coefficients(image);
+    // The header leads either the plain or, under random order, the keyed
+    // coefficient order
+    let ((header, header_end), positions) = locate_header(coefficients.len(), key, |positions| {
+        let (header_bytes, header_end) = extract(&coefficients, positions, HEADER_LENGTH, 1)?;
+        Ok((PayloadHeader::from_bytes(&header_bytes)?, header_end))
+    })?;
+
+    Ok(LocatedPayload {
+        header,
+        coefficients,
+        positions,
+        header_end,
+    })
+}
+
+impl LocatedPayload {
+    /// Reads the body that follows the header
+    pub fn extract_container(self) -> Result<Container> {
+        let (body, _) = extract(
+            &self.coefficients,
+            &self.positions[self.header_end..],
+            self.header.payload_length as usize,
+            self.header.bit_depth,
+        )?;
+
+        Ok(Container {
+            header: self.header,
+            body,
+            soft_bits: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::EccScheme;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    fn test_coefficients(count: usize) -> Vec<i16> {
+        // Roughly Laplacian, with many zeros and ones as in real JPEGs
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        (0..count)
+            .map(|_| {
+                let magnitude = (rng.gen::<f64>().powi(3) * 12.0) as i16;
+                if rng.gen() {
+                    magnitude
+                } else {
+                    -magnitude
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_f5_matrix_encoding() {
+        let data = b"F5 matrix encoding";
+        let original = test_coefficients(20_000);
+        let order: Vec<usize> = (0..original.len()).rev().collect();
+
+        for k in 1..=MAX_K {
+            let mut coefficients = original.clone();
+            let used = embed(&mut coefficients, &order, data, k).unwrap();
+            let (extracted, extracted_used) =
+                extract(&coefficients, &order, data.len(), k).unwrap();
+            assert_eq!(extracted, data);
+            assert_eq!(extracted_used, used);
+
+            // Only magnitudes decrease, and never more than one step
+            for (&before, &after) in original.iter().zip(&coefficients) {
+                assert!(after.abs() <= before.abs());
+                assert!((before - after).abs() <= 1);
+                assert!(after == 0 || after.signum() == before.signum());
+            }
+        }
+    }
+
+    #[test]
+    fn test_f5_larger_k_changes_fewer_coefficients() {
+        let data = [0xA5u8; 64];
+        let original = test_coefficients(50_000);
+        let order: Vec<usize> = (0..original.len()).collect();
+
+        let changes = |k| {
+            let mut coefficients = original.clone();
+            embed(&mut coefficients, &order, &data, k).unwrap();
+            original
+                .iter()
+                .zip(&coefficients)
+                .filter(|(a, b)| a != b)
+                .count()
+        };
+        assert!(changes(4) < changes(1));
+    }
+
+    #[test]
+    fn test_f5_rejects_forged_length() {
+        let pixels: Vec<u8> = (0..64 * 64 * 3).map(|i| (i * 7 % 251) as u8).collect();
+        let mut bytes = Vec::new();
+        jpeg_encoder::Encoder::new(&mut bytes, 90)
+            .encode(&pixels, 64, 64, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+        let mut image = JpegImage::from_bytes(&bytes).unwrap();
+
+        // A header whose checksum matches but whose length no image could hold
+        let mut header = Container::seal(b"Short", None, EccScheme::None)
+            .unwrap()
+            .header;
+        header.bit_depth = 1;
+        header.payload_length = u32::MAX;
+        let mut coefficients = ac_coefficients(&image);
+        let order: Vec<usize> = (0..coefficients.len()).collect();
+        embed(&mut coefficients, &order, &header.to_bytes(), 1).unwrap();
+        set_ac_coefficients(&mut image, &coefficients);
+
+        assert!(matches!(
+            extract_container(&image, None),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_f5_capacity_exceeded() {
+        let mut coefficients = vec![0i16; 1000];
+        coefficients[10] = 3;
+        let order: Vec<usize> = (0..coefficients.len()).collect();
+        assert!(matches!(
+            embed(&mut coefficients, &order, b"too much", 1),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,439 @@
+//! GIF Module
+//!
+//! This module hides data in the palette indices of GIF images in the style of
+//! EzStego (Machado, 1996). The colours of each palette are sorted by
+//! luminance and paired off in that order, and every pixel carries one bit as
+//! the parity of its colour's position in the sorted list. Writing a bit
+//! either leaves the pixel alone or moves it to the other colour of its pair,
+//! which is its nearest neighbour in brightness. The palettes themselves are
+//! never changed, so extraction sorts them the same way.
+//!
+//! Animated GIFs carry the payload in all of their frames: the pixels of the
+//! frames are interleaved, so even a sequentially embedded payload is spread
+//! over the whole animation. Pixels showing the transparent colour are left
+//! alone. Frame count, position, delay, disposal and loop count are written
+//! back unchanged.
+
+use crate::container::{Container, PayloadHeader, FLAG_RANDOM_ORDER, HEADER_LENGTH};
+use crate::utils::{embedding_positions, locate_header, EmbeddingOrder, PaletteOrder};
+use crate::{Error, Result};
+use ::gif::{ColorOutput, DecodeOptions, Encoder, Frame, Repeat};
+use sha2::{Digest, Sha256};
+
+/// Handler for GIF operations
+pub struct GifHandler {
+    width: u16,
+    height: u16,
+    global_palette: Option<Vec<u8>>,
+    repeat: Repeat,
+    frames: Vec<Frame<'static>>,
+    /// Palette pairing of every frame
+    orders: Vec<PaletteOrder>,
+    /// Frame and pixel of every usable pixel, interleaved across frames
+    slots: Vec<(usize, usize)>,
+}
+
+impl GifHandler {
+    /// Loads a GIF file
+    pub fn new(path: &str) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::Io(format!("Failed to read GIF {}: {}", path, e)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Decodes a GIF file held in memory
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let decode_error =
+            |e: ::gif::DecodingError| Error::InvalidInput(format!("Failed to decode GIF: {}", e));
+
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes).map_err(decode_error)?;
+        let global_palette = decoder.global_palette().map(|palette| palette.to_vec());
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(decode_error)? {
+            frames.push(frame.clone());
+        }
+        if frames.is_empty() {
+            return Err(Error::InvalidInput("GIF contains no frames".into()));
+        }
+
+        let orders = frames
+            .iter()
+            .map(|frame| {
+                let palette = frame
+                    .palette
+                    .as_deref()
+                    .or(global_palette.as_deref())
+                    .ok_or_else(|| Error::InvalidInput("GIF frame has no palette".into()))?;
+                Ok(PaletteOrder::new(palette, |index| {
+                    Some(index) == frame.transparent
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Take the usable pixels of every frame in turn
+        let usable: Vec<Vec<usize>> = frames
+            .iter()
+            .zip(&orders)
+            .map(|(frame, order)| {
+                (0..frame.buffer.len())
+                    .filter(|&pixel| order.rank(frame.buffer[pixel]).is_some())
+                    .collect()
+            })
+            .collect();
+        let longest = usable.iter().map(Vec::len).max().unwrap_or(0);
+        let slots = (0..longest)
+            .flat_map(|i| {
+                usable
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(frame, pixels)| pixels.get(i).map(|&pixel| (frame, pixel)))
+            })
+            .collect();
+
+        Ok(Self {
+            width: decoder.width(),
+            height: decoder.height(),
+            global_palette,
+            repeat: decoder.repeat(),
+            frames,
+            orders,
+            slots,
+        })
+    }
+
+    /// Returns the canvas width and height in pixels
+    pub fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Returns the number of frames
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the frames, with their palette indices
+    pub fn frames(&self) -> &[Frame<'static>] {
+        &self.frames
+    }
+
+    /// Calculates how many body bytes fit in the image, excluding the payload header
+    pub fn capacity(&self) -> usize {
+        self.slots.len().saturating_sub(HEADER_LENGTH * 8) / 8
+    }
+
+    /// Computes a digest of the image that embedding leaves unchanged
+    ///
+    /// The canvas, loop count, palettes and frame settings are hashed along
+    /// with the pixels, each usable pixel standing for its colour pair.
+    pub fn carrier_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.width.to_le_bytes());
+        hasher.update(self.height.to_le_bytes());
+        hasher.update(format!("{:?}", self.repeat));
+        if let Some(palette) = &self.global_palette {
+            hasher.update(palette);
+        }
+        for (frame, order) in self.frames.iter().zip(&self.orders) {
+            hasher.update(frame.delay.to_le_bytes());
+            hasher.update([frame.dispose as u8, frame.transparent.unwrap_or(0)]);
+            hasher.update([frame.transparent.is_some() as u8]);
+            for value in [frame.left, frame.top, frame.width, frame.height] {
+                hasher.update(value.to_le_bytes());
+            }
+            if let Some(palette) = &frame.palette {
+                hasher.update(palette);
+            }
+            let pairs: Vec<u8> = frame
+                .buffer
+                .iter()
+                .map(|&index| order.pair(index))
+                .collect();
+            hasher.update(&pairs);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Embeds a payload container into the palette indices
+    pub fn embed_data(
+        &mut self,
+        container: &Container,
+        order: EmbeddingOrder,
+        key: Option<&str>,
+    ) -> Result<()> {
+        let capacity = self.capacity();
+        if self.slots.len() < HEADER_LENGTH * 8 || container.body.len() > capacity {
+            return Err(Error::InvalidInput(format!(
+                "Data too large for GIF. Maximum capacity: {} bytes",
+                capacity
+            )));
+        }
+
+        // Record the carrier settings in the header
+        let mut header = container.header.clone();
+        header.bit_depth = 1;
+        if order == EmbeddingOrder::Random {
+            header.flags |= FLAG_RANDOM_ORDER;
+        }
+
+        // Embed the header first, in the first usable pixels of the order,
+        // then the body
+        let slots = self.ordered_slots(&embedding_positions(order, key, self.slots.len())?);
+        let (header_slots, body_slots) = slots.split_at(HEADER_LENGTH * 8);
+        self.embed_bits(header_slots, &header.to_bytes());
+        self.embed_bits(body_slots, &container.body);
+
+        Ok(())
+    }
+
+    /// Extracts an embedded payload container from the palette indices
+    pub fn extract_data(&self, key: Option<&str>) -> Result<Container> {
+        if self.slots.len() < HEADER_LENGTH * 8 {
+            return Err(Error::InvalidData(
+                "Image is too small to hold a payload header".into(),
+            ));
+        }
+
+        // The header sits in the first usable pixels, or under random order in
+        // the first pixels of the keyed permutation
+        let (header, positions) = locate_header(self.slots.len(), key, |positions| {
+            let mut header_bytes = [0u8; HEADER_LENGTH];
+            let slots = self.ordered_slots(&positions[..HEADER_LENGTH * 8]);
+            self.extract_bits(&slots, &mut header_bytes);
+            PayloadHeader::from_bytes(&header_bytes)
+        })?;
+        if header.bit_depth != 1 {
+            return Err(Error::InvalidData(format!(
+                "Invalid bit depth for GIF: {}",
+                header.bit_depth
+            )));
+        }
+
+        let capacity = self.capacity();
+        let length = header.readable_length(capacity).ok_or_else(|| {
+            Error::InvalidData(format!(
+                "Data length ({}) exceeds image capacity ({})",
+                header.payload_length, capacity
+            ))
+        })?;
+
+        let mut body = vec![0u8; length];
+        self.extract_bits(
+            &self.ordered_slots(&positions[HEADER_LENGTH * 8..]),
+            &mut body,
+        );
+
+        Ok(Container {
+            header,
+            body,
+            soft_bits: None,
+        })
+    }
+
+    /// Encodes the GIF, frame by frame
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let encode_error =
+            |e: ::gif::EncodingError| Error::Encoding(format!("Failed to encode GIF: {}", e));
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = Encoder::new(
+                &mut bytes,
+                self.width,
+                self.height,
+                self.global_palette.as_deref().unwrap_or(&[]),
+            )
+            .map_err(encode_error)?;
+            // Without a loop count the animation plays once; a count of zero
+            // would make it loop forever
+            if self.repeat != Repeat::Finite(0) {
+                encoder.set_repeat(self.repeat).map_err(encode_error)?;
+            }
+            for frame in &self.frames {
+                encoder.write_frame(frame).map_err(encode_error)?;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Saves the GIF to a file
+    pub fn save(&self, path: &str) -> Result<()> {
+        let bytes = self.to_bytes()?;
+        std::fs::write(path, bytes).map_err(|e| Error::Io(format!("Failed to save GIF: {}", e)))
+    }
+
+    /// Usable pixels at `positions`, in payload order
+    fn ordered_slots(&self, positions: &[usize]) -> Vec<(usize, usize)> {
+        positions
+            .iter()
+            .map(|&position| self.slots[position])
+            .collect()
+    }
+
+    /// Writes `data` one bit per pixel, most significant bit first
+    fn embed_bits(&mut self, slots: &[(usize, usize)], data: &[u8]) {
+        let bits = data
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+        for (&(frame, pixel), bit) in slots.iter().zip(bits) {
+            let index = self.frames[frame].buffer[pixel];
+            let new_index = self.orders[frame].with_bit(index, bit);
+            if new_index != index {
+                self.frames[frame].buffer.to_mut()[pixel] = new_index;
+            }
+        }
+    }
+
+    /// Reads `data.len()` bytes, one bit per pixel
+    fn extract_bits(&self, slots: &[(usize, usize)], data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = slots[i * 8..i * 8 + 8]
+                .iter()
+                .fold(0, |value, &(frame, pixel)| {
+                    let rank = self.orders[frame]
+                        .rank(self.frames[frame].buffer[pixel])
+                        .unwrap_or(0);
+                    (value << 1) | (rank & 1) as u8
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::EccScheme;
+    use ::gif::DisposalMethod;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    /// A palette of 256 colours in shuffled luminance order
+    fn shuffled_palette(seed: u64) -> Vec<u8> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut levels: Vec<u8> = (0..=255).collect();
+        for i in (1..levels.len()).rev() {
+            levels.swap(i, rng.gen_range(0..=i));
+        }
+        levels
+            .iter()
+            .flat_map(|&level| [level, level, level / 2])
+            .collect()
+    }
+
+    /// Encodes an animation with local palettes, varying delays and a transparent colour
+    fn test_gif(frame_count: usize) -> Vec<u8> {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut bytes, 40, 30, &shuffled_palette(0)).unwrap();
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for i in 0..frame_count {
+                let (width, height) = if i == 0 { (40, 30) } else { (24, 16) };
+                let pixels: Vec<u8> = (0..width * height).map(|_| rng.gen()).collect();
+                let mut frame = Frame::from_indexed_pixels(width, height, pixels, Some(7));
+                frame.delay = 5 + i as u16 * 3;
+                frame.left = i as u16 * 2;
+                frame.dispose = DisposalMethod::Background;
+                if i % 2 == 1 {
+                    frame.palette = Some(shuffled_palette(i as u64));
+                }
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    fn colour(handler: &GifHandler, frame: usize, index: u8) -> [u8; 3] {
+        let palette = handler.frames[frame]
+            .palette
+            .as_deref()
+            .or(handler.global_palette.as_deref())
+            .unwrap();
+        let start = index as usize * 3;
+        [palette[start], palette[start + 1], palette[start + 2]]
+    }
+
+    #[test]
+    fn test_gif_embed_extract_keeps_animation() {
+        let cover_bytes = test_gif(4);
+        let cover = GifHandler::from_bytes(&cover_bytes).unwrap();
+        assert_eq!(cover.frame_count(), 4);
+        let container = Container::seal(b"Hidden in an animation", None, EccScheme::None).unwrap();
+
+        for (order, key) in [
+            (EmbeddingOrder::Sequential, None),
+            (EmbeddingOrder::Random, Some("stego key")),
+        ] {
+            let mut handler = GifHandler::from_bytes(&cover_bytes).unwrap();
+            handler.embed_data(&container, order, key).unwrap();
+            let stego = GifHandler::from_bytes(&handler.to_bytes().unwrap()).unwrap();
+
+            // Frames, timing and palettes are unchanged
+            assert_eq!(stego.dimensions(), (40, 30));
+            assert_eq!(stego.repeat, Repeat::Infinite);
+            assert_eq!(stego.global_palette, cover.global_palette);
+            assert_eq!(stego.frame_count(), cover.frame_count());
+            for (before, after) in cover.frames().iter().zip(stego.frames()) {
+                assert_eq!(before.delay, after.delay);
+                assert_eq!(before.dispose, after.dispose);
+                assert_eq!(before.transparent, after.transparent);
+                assert_eq!(
+                    (before.left, before.top, before.width, before.height),
+                    (after.left, after.top, after.width, after.height)
+                );
+                assert_eq!(before.palette, after.palette);
+            }
+
+            // Changed pixels move to a colour of neighbouring luminance, one
+            // step further where the pair skips the transparent colour, and
+            // every frame carries part of the payload
+            let mut changed_frames = std::collections::HashSet::new();
+            for (frame, (before, after)) in cover.frames().iter().zip(stego.frames()).enumerate() {
+                for (&a, &b) in before.buffer.iter().zip(after.buffer.iter()) {
+                    if a != b {
+                        assert_ne!(a, 7, "transparent pixel changed");
+                        let (ca, cb) = (colour(&cover, frame, a), colour(&cover, frame, b));
+                        assert!(ca[0].abs_diff(cb[0]) <= 2, "{:?} -> {:?}", ca, cb);
+                        changed_frames.insert(frame);
+                    }
+                }
+            }
+            assert_eq!(changed_frames.len(), 4);
+
+            let extracted = stego.extract_data(key).unwrap();
+            assert_eq!(extracted.open(None).unwrap(), b"Hidden in an animation");
+            if order == EmbeddingOrder::Random {
+                assert!(stego.extract_data(None).is_err());
+            }
+            assert_eq!(stego.carrier_digest(), cover.carrier_digest());
+        }
+    }
+
+    #[test]
+    fn test_gif_static_image_and_capacity() {
+        let cover_bytes = test_gif(1);
+        let mut handler = GifHandler::from_bytes(&cover_bytes).unwrap();
+        assert_eq!(handler.frame_count(), 1);
+        let container = Container::seal(b"Still image", None, EccScheme::None).unwrap();
+        handler
+            .embed_data(&container, EmbeddingOrder::Sequential, None)
+            .unwrap();
+        let stego = GifHandler::from_bytes(&handler.to_bytes().unwrap()).unwrap();
+        assert_eq!(stego.frame_count(), 1);
+        assert_eq!(
+            stego.extract_data(None).unwrap().open(None).unwrap(),
+            b"Still image"
+        );
+
+        // 1200 pixels, minus the transparent ones and the header
+        assert!(handler.capacity() < (1200 - HEADER_LENGTH * 8) / 8);
+        let too_large = Container::seal(&[1; 200], None, EccScheme::None).unwrap();
+        assert!(matches!(
+            handler.embed_data(&too_large, EmbeddingOrder::Sequential, None),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(GifHandler::from_bytes(b"GIF89a but not really").is_err());
+    }
+}
@@ -1,14 +1,35 @@
 //! Integrity Module
 //!
 //! This module provides functionality for verifying the integrity of embedded data
-//! using HMAC-SHA256.
+//! using HMAC-SHA256, and for proving its origin with Ed25519 signatures.
+//!
+//! Payload integrity keys are never stored next to the data: they are derived
+//! from the user's password or from a separately supplied key. Payloads
+//! without either only carry a SHA-256 checksum, which detects corruption but
+//! not deliberate tampering.
 
+use crate::encryption::{armor, dearmor, Kdf};
 use crate::{Error, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 
 const HMAC_KEY_LENGTH: usize = 32;
 const HMAC_OUTPUT_LENGTH: usize = 32;
+const ED25519_PUBLIC_LABEL: &str = "NHALE ED25519 PUBLIC KEY";
+const ED25519_SECRET_LABEL: &str = "NHALE ED25519 SECRET KEY";
+const SIGNATURE_CONTEXT: &[u8] = b"nhale-payload-signature-v1";
+const INTEGRITY_KEY_CONTEXT: &[u8] = b"nhale-payload-integrity-v1";
+
+/// Length of the salt an integrity key is derived with
+pub const INTEGRITY_SALT_LENGTH: usize = 16;
+
+/// Length of a payload checksum or HMAC tag
+pub const INTEGRITY_TAG_LENGTH: usize = HMAC_OUTPUT_LENGTH;
+
+/// Size of a serialized [`PayloadSignature`]: signer key, carrier digest and signature
+pub const SIGNATURE_BLOCK_LENGTH: usize = 32 + 32 + 64;
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -78,3 +99,243 @@ pub fn verify_hmac(data: &[u8], key: &[u8], hmac: &[u8]) -> Result<bool> {
 
     Ok(mac.verify_slice(hmac).is_ok())
 }
+
+/// Kind of integrity check a payload was verified with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verification {
+    /// No check by a payload container; data written before the container
+    /// format, or by a carrier without one
+    None,
+    /// Unkeyed SHA-256 checksum; detects corruption but not tampering
+    Checksum,
+    /// HMAC-SHA256 keyed from the password
+    PasswordHmac,
+    /// HMAC-SHA256 keyed from a supplied integrity key
+    KeyHmac,
+}
+
+/// Secret a payload's integrity key is derived from
+#[derive(Debug, Clone, Copy)]
+pub enum IntegritySecret<'a> {
+    /// Password, stretched with the payload's key derivation function
+    Password(&'a str, Kdf),
+    /// High-entropy key supplied by the user, expanded with HKDF-SHA256
+    Key(&'a [u8]),
+}
+
+impl IntegritySecret<'_> {
+    /// Returns the kind of check a key derived from this secret provides
+    pub fn verification(&self) -> Verification {
+        match self {
+            IntegritySecret::Password(..) => Verification::PasswordHmac,
+            IntegritySecret::Key(_) => Verification::KeyHmac,
+        }
+    }
+
+    /// Derives the HMAC key for the given salt
+    pub fn derive_key(&self, salt: &[u8]) -> Result<[u8; HMAC_KEY_LENGTH]> {
+        let mut key = [0u8; HMAC_KEY_LENGTH];
+        match *self {
+            IntegritySecret::Password(password, kdf) => kdf.derive_key(password, salt, &mut key)?,
+            IntegritySecret::Key(secret) => {
+                if secret.is_empty() {
+                    return Err(Error::InvalidInput(
+                        "Integrity key must not be empty".into(),
+                    ));
+                }
+                Hkdf::<Sha256>::new(Some(salt), secret)
+                    .expand(INTEGRITY_KEY_CONTEXT, &mut key)
+                    .map_err(|e| Error::Integrity(e.to_string()))?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// Computes the unkeyed SHA-256 checksum stored with keyless payloads
+pub fn payload_checksum(data: &[u8]) -> [u8; INTEGRITY_TAG_LENGTH] {
+    Sha256::digest(data).into()
+}
+
+/// An Ed25519 key pair for signing embedded payloads
+#[derive(Clone)]
+pub struct Ed25519KeyPair {
+    signing_key: SigningKey,
+}
+
+impl Ed25519KeyPair {
+    /// Generates a new random key pair
+    pub fn generate() -> Result<Self> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).map_err(|e| Error::Integrity(e.to_string()))?;
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&secret),
+        })
+    }
+
+    /// Imports a secret key exported with [`Ed25519KeyPair::export_secret`] or
+    /// given as 32 raw bytes
+    pub fn import_secret(key: &[u8]) -> Result<Self> {
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&dearmor(ED25519_SECRET_LABEL, key)?),
+        })
+    }
+
+    /// Returns the raw public key
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    /// Exports the public key in armored text form
+    pub fn export_public(&self) -> String {
+        armor(ED25519_PUBLIC_LABEL, &self.public_key())
+    }
+
+    /// Exports the secret key in armored text form
+    pub fn export_secret(&self) -> String {
+        armor(ED25519_SECRET_LABEL, &self.signing_key.to_bytes())
+    }
+}
+
+/// Imports an Ed25519 public key exported with [`Ed25519KeyPair::export_public`]
+/// or given as 32 raw bytes
+pub fn import_ed25519_public_key(key: &[u8]) -> Result<[u8; 32]> {
+    let key = dearmor(ED25519_PUBLIC_LABEL, key)?;
+    VerifyingKey::from_bytes(&key)
+        .map_err(|_| Error::InvalidInput("Invalid Ed25519 public key".into()))?;
+    Ok(key)
+}
+
+/// Ed25519 signature over a payload and the carrier it was embedded in
+///
+/// The signature covers the SHA-256 of the payload and a digest of the parts
+/// of the carrier that embedding leaves untouched, so a signed payload cannot
+/// be moved into a different cover file. Carriers without such a digest use
+/// all zeros.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadSignature {
+    /// Public key of the signer
+    pub signer: [u8; 32],
+    /// Digest of the carrier at signing time
+    pub carrier_digest: [u8; 32],
+    signature: [u8; 64],
+}
+
+impl PayloadSignature {
+    /// Signs a payload for the given carrier
+    pub fn sign(key: &Ed25519KeyPair, data: &[u8], carrier_digest: [u8; 32]) -> Self {
+        let message = signed_message(data, &carrier_digest);
+        Self {
+            signer: key.public_key(),
+            carrier_digest,
+            signature: key.signing_key.sign(&message).to_bytes(),
+        }
+    }
+
+    /// Checks that the signature is valid for `data`
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        let message = signed_message(data, &self.carrier_digest);
+        VerifyingKey::from_bytes(&self.signer)
+            .and_then(|key| key.verify(&message, &Signature::from_bytes(&self.signature)))
+            .map_err(|_| Error::Integrity("Invalid payload signature".into()))
+    }
+
+    /// Serializes the signer key, carrier digest and signature
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_BLOCK_LENGTH] {
+        let mut bytes = [0u8; SIGNATURE_BLOCK_LENGTH];
+        bytes[..32].copy_from_slice(&self.signer);
+        bytes[32..64].copy_from_slice(&self.carrier_digest);
+        bytes[64..].copy_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Parses a block written by [`PayloadSignature::to_bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < SIGNATURE_BLOCK_LENGTH {
+            return Err(Error::Integrity("Signature block is truncated".into()));
+        }
+
+        let mut signature = Self {
+            signer: [0u8; 32],
+            carrier_digest: [0u8; 32],
+            signature: [0u8; 64],
+        };
+        signature.signer.copy_from_slice(&bytes[..32]);
+        signature.carrier_digest.copy_from_slice(&bytes[32..64]);
+        signature
+            .signature
+            .copy_from_slice(&bytes[64..SIGNATURE_BLOCK_LENGTH]);
+        Ok(signature)
+    }
+}
+
+fn signed_message(data: &[u8], carrier_digest: &[u8; 32]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNATURE_CONTEXT.len() + 64);
+    message.extend_from_slice(SIGNATURE_CONTEXT);
+    message.extend_from_slice(carrier_digest);
+    message.extend_from_slice(&Sha256::digest(data));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_signature() {
+        let key = Ed25519KeyPair::generate().unwrap();
+        let carrier_digest = [7u8; 32];
+        let signature = PayloadSignature::sign(&key, b"Secret message", carrier_digest);
+
+        let parsed = PayloadSignature::from_bytes(&signature.to_bytes()).unwrap();
+        assert_eq!(parsed, signature);
+        assert_eq!(parsed.signer, key.public_key());
+        assert!(parsed.verify(b"Secret message").is_ok());
+        assert!(matches!(
+            parsed.verify(b"Forged message"),
+            Err(Error::Integrity(_))
+        ));
+
+        // Changing the carrier digest invalidates the signature
+        let mut moved = parsed.clone();
+        moved.carrier_digest = [0u8; 32];
+        assert!(moved.verify(b"Secret message").is_err());
+    }
+
+    #[test]
+    fn test_integrity_key_derivation() {
+        let salt = [1u8; INTEGRITY_SALT_LENGTH];
+        let secret = IntegritySecret::Key(b"integrity key");
+        let key = secret.derive_key(&salt).unwrap();
+        assert_eq!(secret.verification(), Verification::KeyHmac);
+        assert_eq!(key, secret.derive_key(&salt).unwrap());
+        assert_ne!(
+            key,
+            secret.derive_key(&[2u8; INTEGRITY_SALT_LENGTH]).unwrap()
+        );
+        assert_ne!(
+            key,
+            IntegritySecret::Key(b"other key")
+                .derive_key(&salt)
+                .unwrap()
+        );
+        assert!(IntegritySecret::Key(b"").derive_key(&salt).is_err());
+
+        let password = IntegritySecret::Password("password", Kdf::Pbkdf2 { iterations: 1_000 });
+        assert_eq!(password.verification(), Verification::PasswordHmac);
+        let tag = generate_hmac(b"data", &password.derive_key(&salt).unwrap()).unwrap();
+        assert!(verify_hmac(b"data", &password.derive_key(&salt).unwrap(), &tag).unwrap());
+    }
+
+    #[test]
+    fn test_ed25519_key_import_export() {
+        let key = Ed25519KeyPair::generate().unwrap();
+        let imported = Ed25519KeyPair::import_secret(key.export_secret().as_bytes()).unwrap();
+        assert_eq!(imported.public_key(), key.public_key());
+        assert_eq!(
+            import_ed25519_public_key(key.export_public().as_bytes()).unwrap(),
+            key.public_key()
+        );
+        assert!(import_ed25519_public_key(key.export_secret().as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,1649 @@
+//! JPEG Module
+//!
+//! This module reads and writes JPEG files at the level of their quantized DCT
+//! coefficients. Decoding stops after Huffman decoding, so coefficients can be
+//! changed and written back without the loss of a pixel round trip. Baseline,
+//! extended and progressive Huffman-coded files are supported. Every segment
+//! other than the entropy-coded data is kept as it is, and re-encoding an
+//! untouched file reproduces it byte for byte; Huffman tables are only
+//! regenerated when a changed scan needs a symbol the original tables cannot
+//! code.
+
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DHT: u8 = 0xC4;
+const DQT: u8 = 0xDB;
+const DRI: u8 = 0xDD;
+const SOF0: u8 = 0xC0;
+const SOF1: u8 = 0xC1;
+const SOF2: u8 = 0xC2;
+const RST0: u8 = 0xD0;
+
+/// Coefficients per 8x8 block
+pub const BLOCK_SIZE: usize = 64;
+
+/// A block of quantized DCT coefficients in zig-zag order
+pub type Block = [i16; BLOCK_SIZE];
+
+/// A colour component and its coefficient blocks
+#[derive(Debug, Clone)]
+pub struct Component {
+    /// Component identifier from the frame header
+    pub id: u8,
+    /// Horizontal sampling factor
+    pub horizontal_sampling: u8,
+    /// Vertical sampling factor
+    pub vertical_sampling: u8,
+    /// Quantization table selector
+    pub quantization_table: u8,
+    /// Blocks per row that cover the component's samples
+    pub width_in_blocks: usize,
+    /// Block rows that cover the component's samples
+    pub height_in_blocks: usize,
+    /// Blocks per row in storage, padded to whole MCUs
+    blocks_per_line: usize,
+    /// Block rows in storage, padded to whole MCUs
+    blocks_per_column: usize,
+    blocks: Vec<Block>,
+}
+
+impl Component {
+    /// Returns the block at the given block row and column
+    pub fn block(&self, row: usize, column: usize) -> &Block {
+        &self.blocks[row * self.blocks_per_line + column]
+    }
+
+    /// Returns the block at the given block row and column for modification
+    pub fn block_mut(&mut self, row: usize, column: usize) -> &mut Block {
+        &mut self.blocks[row * self.blocks_per_line + column]
+    }
+}
+
+/// Huffman table class: DC or AC
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TableClass {
+    Dc = 0,
+    Ac = 1,
+}
+
+/// A Huffman table with lookups for decoding and encoding
+#[derive(Debug, Clone)]
+struct HuffmanTable {
+    counts: [u8; 16],
+    values: Vec<u8>,
+    codes: [u16; 256],
+    sizes: [u8; 256],
+    max_code: [i32; 17],
+    value_offset: [i32; 17],
+    min_code: [i32; 17],
+}
+
+impl HuffmanTable {
+    fn new(counts: [u8; 16], values: Vec<u8>) -> Result<Self> {
+        let total: usize = counts.iter().map(|&c| c as usize).sum();
+        if total != values.len() || total > 256 {
+            return Err(Error::InvalidData("Invalid JPEG Huffman table".into()));
+        }
+
+        let mut table = Self {
+            counts,
+            values,
+            codes: [0; 256],
+            sizes: [0; 256],
+            max_code: [-1; 17],
+            value_offset: [0; 17],
+            min_code: [0; 17],
+        };
+
+        let mut code = 0i32;
+        let mut index = 0usize;
+        for length in 1..=16 {
+            let count = counts[length - 1] as usize;
+            table.value_offset[length] = index as i32;
+            table.min_code[length] = code;
+            for &symbol in &table.values[index..index + count] {
+                table.codes[symbol as usize] = code as u16;
+                table.sizes[symbol as usize] = length as u8;
+                code += 1;
+            }
+            index += count;
+            if count > 0 {
+                table.max_code[length] = code - 1;
+            }
+            if code > 1 << length {
+                return Err(Error::InvalidData("Invalid JPEG Huffman table".into()));
+            }
+            code <<= 1;
+        }
+
+        Ok(table)
+    }
+
+    fn has_symbol(&self, symbol: u8) -> bool {
+        self.sizes[symbol as usize] != 0
+    }
+
+    /// Builds the table that codes the given symbol frequencies most compactly,
+    /// following the procedure of ITU-T T.81 Annex K.2
+    fn optimal(frequencies: &[u32; 256]) -> Result<Self> {
+        // Symbol 256 is reserved so that no code consists of all ones
+        let mut freq = [0u64; 257];
+        for (f, &count) in freq.iter_mut().zip(frequencies.iter()) {
+            *f = count as u64;
+        }
+        freq[256] = 1;
+        let mut code_size = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+
+        loop {
+            let mut c1 = None;
+            let mut c2 = None;
+            for i in 0..257 {
+                if freq[i] == 0 {
+                    continue;
+                }
+                if c1.is_none_or(|c: usize| freq[i] <= freq[c]) {
+                    c2 = c1;
+                    c1 = Some(i);
+                } else if c2.is_none_or(|c: usize| freq[i] <= freq[c]) {
+                    c2 = Some(i);
+                }
+            }
+            let (Some(mut c1), Some(mut c2)) = (c1, c2) else {
+                break;
+            };
+
+            freq[c1] += freq[c2];
+            freq[c2] = 0;
+            code_size[c1] += 1;
+            while others[c1] != usize::MAX {
+                c1 = others[c1];
+                code_size[c1] += 1;
+            }
+            others[c1] = c2;
+            code_size[c2] += 1;
+            while others[c2] != usize::MAX {
+                c2 = others[c2];
+                code_size[c2] += 1;
+            }
+        }
+
+        let mut bits = [0u32; 33];
+        for &size in code_size.iter().filter(|&&size| size > 0) {
+            bits[size] += 1;
+        }
+
+        // Limit code lengths to 16 bits
+        for i in (17..=32).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+
+        // Remove the reserved symbol from the longest codes
+        let mut i = 16;
+        while bits[i] == 0 {
+            i -= 1;
+        }
+        bits[i] -= 1;
+
+        let mut counts = [0u8; 16];
+        for (count, &bit) in counts.iter_mut().zip(bits[1..=16].iter()) {
+            *count = bit as u8;
+        }
+        let mut values = Vec::new();
+        for size in 1..=32 {
+            for (symbol, _) in code_size[..256]
+                .iter()
+                .enumerate()
+                .filter(|(_, &s)| s == size)
+            {
+                values.push(symbol as u8);
+            }
+        }
+
+        Self::new(counts, values)
+    }
+
+    /// Serializes the table as the body of one DHT table definition
+    fn to_segment(&self, class: TableClass, id: u8) -> Vec<u8> {
+        let mut data = Vec::with_capacity(17 + self.values.len());
+        data.push(((class as u8) << 4) | id);
+        data.extend_from_slice(&self.counts);
+        data.extend_from_slice(&self.values);
+        data
+    }
+}
+
+/// Huffman tables currently in effect, indexed by class and identifier
+#[derive(Debug, Clone, Default)]
+struct HuffmanTables {
+    tables: [[Option<HuffmanTable>; 4]; 2],
+}
+
+impl HuffmanTables {
+    fn get(&self, class: TableClass, id: u8) -> Result<&HuffmanTable> {
+        self.tables[class as usize][id as usize]
+            .as_ref()
+            .ok_or_else(|| {
+                Error::InvalidData(format!(
+                    "JPEG scan uses undefined {:?} Huffman table {}",
+                    class, id
+                ))
+            })
+    }
+
+    /// Applies the table definitions of a DHT segment
+    fn define(&mut self, data: &[u8]) -> Result<()> {
+        let mut pos = 0;
+        while pos < data.len() {
+            if pos + 17 > data.len() {
+                return Err(Error::InvalidData("Truncated JPEG Huffman table".into()));
+            }
+            let class = data[pos] >> 4;
+            let id = data[pos] & 0x0F;
+            if class > 1 || id > 3 {
+                return Err(Error::InvalidData("Invalid JPEG Huffman table".into()));
+            }
+            let mut counts = [0u8; 16];
+            counts.copy_from_slice(&data[pos + 1..pos + 17]);
+            let total: usize = counts.iter().map(|&c| c as usize).sum();
+            pos += 17;
+            if pos + total > data.len() {
+                return Err(Error::InvalidData("Truncated JPEG Huffman table".into()));
+            }
+            let table = HuffmanTable::new(counts, data[pos..pos + total].to_vec())?;
+            self.tables[class as usize][id as usize] = Some(table);
+            pos += total;
+        }
+        Ok(())
+    }
+}
+
+/// A component taking part in a scan
+#[derive(Debug, Clone)]
+struct ScanComponent {
+    /// Index into the frame's components
+    index: usize,
+    dc_table: u8,
+    ac_table: u8,
+}
+
+/// Kind of data a scan codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScanKind {
+    /// All coefficients of each block (sequential frames)
+    Sequential,
+    /// The high bits of the DC coefficients
+    DcFirst,
+    /// One more bit of the DC coefficients
+    DcRefine,
+    /// The high bits of a band of AC coefficients
+    AcFirst,
+    /// One more bit of a band of AC coefficients
+    AcRefine,
+}
+
+/// A scan header; the entropy-coded data is regenerated from the coefficients
+#[derive(Debug, Clone)]
+struct Scan {
+    kind: ScanKind,
+    components: Vec<ScanComponent>,
+    spectral_start: u8,
+    spectral_end: u8,
+    /// Bit position of the previous scan of the band (0 for the first scan)
+    approximation_high: u8,
+    /// Bit position coded by this scan
+    approximation_low: u8,
+    /// Whether the encoder joined end-of-band codes of consecutive blocks
+    /// into runs; this is a choice of the encoder that the coefficients do
+    /// not record
+    eob_runs: bool,
+}
+
+impl Scan {
+    fn to_segment(&self, frame: &[Component]) -> Vec<u8> {
+        let mut data = vec![self.components.len() as u8];
+        for component in &self.components {
+            data.push(frame[component.index].id);
+            data.push((component.dc_table << 4) | component.ac_table);
+        }
+        data.push(self.spectral_start);
+        data.push(self.spectral_end);
+        data.push((self.approximation_high << 4) | self.approximation_low);
+        data
+    }
+}
+
+/// A segment of the file, in file order
+#[derive(Debug, Clone)]
+enum Segment {
+    /// A marker segment kept verbatim
+    Marker(u8, Vec<u8>),
+    /// A scan header followed by entropy-coded data
+    Scan(Scan),
+}
+
+/// A JPEG file decoded to quantized DCT coefficients
+#[derive(Debug, Clone)]
+pub struct JpegImage {
+    /// Image width in pixels
+    pub width: u16,
+    /// Image height in pixels
+    pub height: u16,
+    /// Colour components with their coefficients
+    pub components: Vec<Component>,
+    progressive: bool,
+    quantization_tables: [Option<[u16; BLOCK_SIZE]>; 4],
+    mcus_per_line: usize,
+    mcus_per_column: usize,
+    segments: Vec<Segment>,
+    trailer: Vec<u8>,
+}
+
+impl JpegImage {
+    /// Reads a JPEG file
+    pub fn open(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::Io(format!("Failed to read JPEG file: {}", e)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Parses a JPEG file and decodes its coefficients
+    ///
+    /// Huffman-coded baseline, extended and progressive JPEGs are supported;
+    /// lossless, hierarchical and arithmetic-coded files are not.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(&[0xFF, SOI]) {
+            return Err(Error::InvalidInput("Not a JPEG file".into()));
+        }
+
+        let mut image = Self {
+            width: 0,
+            height: 0,
+            components: Vec::new(),
+            progressive: false,
+            quantization_tables: [None; 4],
+            mcus_per_line: 0,
+            mcus_per_column: 0,
+            segments: Vec::new(),
+            trailer: Vec::new(),
+        };
+        let mut tables = HuffmanTables::default();
+        let mut restart_interval = 0usize;
+        let mut pos = 2;
+
+        loop {
+            if pos >= bytes.len() || bytes[pos] != 0xFF {
+                return Err(Error::InvalidData(
+                    "JPEG data ends before the end-of-image marker".into(),
+                ));
+            }
+            while pos < bytes.len() && bytes[pos] == 0xFF {
+                pos += 1;
+            }
+            let marker = *bytes.get(pos).ok_or_else(truncated)?;
+            pos += 1;
+
+            match marker {
+                EOI => {
+                    image.trailer = bytes[pos..].to_vec();
+                    return Ok(image);
+                }
+                SOI | 0x01 | RST0..=0xD7 => {
+                    return Err(Error::InvalidData(format!(
+                        "Unexpected JPEG marker 0x{:02X}",
+                        marker
+                    )))
+                }
+                _ => {}
+            }
+
+            if pos + 2 > bytes.len() {
+                return Err(truncated());
+            }
+            let length = u16::from_be_bytes([bytes[pos], bytes[pos + 1]]) as usize;
+            if length < 2 || pos + length > bytes.len() {
+                return Err(truncated());
+            }
+            let data = &bytes[pos + 2..pos + length];
+            pos += length;
+
+            match marker {
+                SOF0 | SOF1 => image.read_frame(data)?,
+                SOF2 => {
+                    image.progressive = true;
+                    image.read_frame(data)?
+                }
+                0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    return Err(Error::NotImplemented(format!(
+                        "Unsupported JPEG coding process (SOF{})",
+                        marker - SOF0
+                    )))
+                }
+                DHT => tables.define(data)?,
+                DQT => image.define_quantization_tables(data)?,
+                DRI => {
+                    if data.len() < 2 {
+                        return Err(truncated());
+                    }
+                    restart_interval = u16::from_be_bytes([data[0], data[1]]) as usize;
+                }
+                SOS => {
+                    let mut scan = image.read_scan_header(data)?;
+                    pos = image.decode_scan(bytes, pos, &mut scan, &tables, restart_interval)?;
+                    image.segments.push(Segment::Scan(scan));
+                    continue;
+                }
+                _ => {}
+            }
+            image.segments.push(Segment::Marker(marker, data.to_vec()));
+        }
+    }
+
+    /// Returns true for a progressive JPEG
+    pub fn is_progressive(&self) -> bool {
+        self.progressive
+    }
+
+    /// Returns the quantization table of a component, in zig-zag order like
+    /// the coefficients
+    pub fn quantization_table(&self, component: &Component) -> Option<&[u16; BLOCK_SIZE]> {
+        self.quantization_tables
+            .get(component.quantization_table as usize)?
+            .as_ref()
+    }
+
+    /// Digest of the parts of the file that embedding leaves unchanged
+    ///
+    /// Covers the frame header, the quantization tables, the first Huffman
+    /// table defined for each slot and the DC coefficients. F5 and QIM only
+    /// change AC coefficients, and the tables [`JpegImage::to_bytes`] writes in
+    /// front of a changed scan redefine slots the file already defined, so a
+    /// stego file has the digest of its cover.
+    pub fn carrier_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let mut defined = [[false; 4]; 2];
+        for segment in &self.segments {
+            let Segment::Marker(marker, data) = segment else {
+                continue;
+            };
+            match *marker {
+                SOF0 | SOF1 | SOF2 | DQT => {
+                    hasher.update([*marker]);
+                    hasher.update((data.len() as u32).to_be_bytes());
+                    hasher.update(data);
+                }
+                DHT => {
+                    let mut pos = 0;
+                    while pos + 17 <= data.len() {
+                        let (class, id) = ((data[pos] >> 4) as usize, (data[pos] & 0x0F) as usize);
+                        let total: usize =
+                            data[pos + 1..pos + 17].iter().map(|&c| c as usize).sum();
+                        let end = (pos + 17 + total).min(data.len());
+                        if class < 2 && id < 4 && !defined[class][id] {
+                            defined[class][id] = true;
+                            hasher.update([DHT]);
+                            hasher.update(&data[pos..end]);
+                        }
+                        pos = end;
+                    }
+                }
+                _ => {}
+            }
+        }
+        for component in &self.components {
+            for row in 0..component.height_in_blocks {
+                for column in 0..component.width_in_blocks {
+                    hasher.update(component.block(row, column)[0].to_be_bytes());
+                }
+            }
+        }
+        hasher.finalize().into()
+    }
+
+    /// Writes the JPEG file
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_bytes()?)
+            .map_err(|e| Error::Io(format!("Failed to write JPEG file: {}", e)))
+    }
+
+    /// Encodes the coefficients back into a JPEG file
+    ///
+    /// Scans are coded with the Huffman tables in effect at that point of the
+    /// original file. If changed coefficients need a symbol those tables lack,
+    /// optimal tables for the scan are written in front of it instead.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = vec![0xFF, SOI];
+        let mut tables = HuffmanTables::default();
+        let mut restart_interval = 0usize;
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Marker(marker, data) => {
+                    match *marker {
+                        DHT => tables.define(data)?,
+                        DRI => restart_interval = u16::from_be_bytes([data[0], data[1]]) as usize,
+                        _ => {}
+                    }
+                    write_segment(&mut out, *marker, data)?;
+                }
+                Segment::Scan(scan) => {
+                    let mut statistics = SymbolStatistics::default();
+                    self.encode_scan(scan, restart_interval, &mut statistics);
+
+                    if !statistics.covered_by(&tables) {
+                        let mut definitions = Vec::new();
+                        for (class, id, frequencies) in statistics.used_tables() {
+                            let table = HuffmanTable::optimal(frequencies)?;
+                            definitions.extend_from_slice(&table.to_segment(class, id));
+                            tables.tables[class as usize][id as usize] = Some(table);
+                        }
+                        write_segment(&mut out, DHT, &definitions)?;
+                    }
+
+                    write_segment(&mut out, SOS, &scan.to_segment(&self.components))?;
+                    let mut writer = BitWriter::new(&tables);
+                    self.encode_scan(scan, restart_interval, &mut writer);
+                    out.extend_from_slice(&writer.finish());
+                }
+            }
+        }
+
+        out.extend_from_slice(&[0xFF, EOI]);
+        out.extend_from_slice(&self.trailer);
+        Ok(out)
+    }
+
+    /// Records the table definitions of a DQT segment
+    fn define_quantization_tables(&mut self, data: &[u8]) -> Result<()> {
+        let mut pos = 0;
+        while pos < data.len() {
+            let (precision, id) = (data[pos] >> 4, (data[pos] & 0x0F) as usize);
+            let size = if precision == 0 { 1 } else { 2 };
+            if precision > 1 || id > 3 || pos + 1 + BLOCK_SIZE * size > data.len() {
+                return Err(Error::InvalidData("Invalid JPEG quantization table".into()));
+            }
+            let mut table = [0u16; BLOCK_SIZE];
+            for (i, value) in table.iter_mut().enumerate() {
+                let at = pos + 1 + i * size;
+                *value = if size == 1 {
+                    data[at] as u16
+                } else {
+                    u16::from_be_bytes([data[at], data[at + 1]])
+                };
+            }
+            self.quantization_tables[id] = Some(table);
+            pos += 1 + BLOCK_SIZE * size;
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self, data: &[u8]) -> Result<()> {
+        if !self.components.is_empty() {
+            return Err(Error::InvalidData("JPEG file has several frames".into()));
+        }
+        if data.len() < 6 {
+            return Err(truncated());
+        }
+        let precision = data[0];
+        if precision != 8 && precision != 12 {
+            return Err(Error::InvalidData(format!(
+                "Unsupported JPEG sample precision: {}",
+                precision
+            )));
+        }
+        self.height = u16::from_be_bytes([data[1], data[2]]);
+        self.width = u16::from_be_bytes([data[3], data[4]]);
+        let count = data[5] as usize;
+        if self.width == 0 || self.height == 0 || count == 0 || data.len() < 6 + count * 3 {
+            return Err(Error::InvalidData("Invalid JPEG frame header".into()));
+        }
+
+        for i in 0..count {
+            let spec = &data[6 + i * 3..9 + i * 3];
+            let (h, v) = (spec[1] >> 4, spec[1] & 0x0F);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) {
+                return Err(Error::InvalidData("Invalid JPEG sampling factor".into()));
+            }
+            self.components.push(Component {
+                id: spec[0],
+                horizontal_sampling: h,
+                vertical_sampling: v,
+                quantization_table: spec[2],
+                width_in_blocks: 0,
+                height_in_blocks: 0,
+                blocks_per_line: 0,
+                blocks_per_column: 0,
+                blocks: Vec::new(),
+            });
+        }
+
+        let h_max = self.components.iter().map(|c| c.horizontal_sampling).max();
+        let v_max = self.components.iter().map(|c| c.vertical_sampling).max();
+        let (h_max, v_max) = (h_max.unwrap_or(1) as usize, v_max.unwrap_or(1) as usize);
+        let (width, height) = (self.width as usize, self.height as usize);
+        self.mcus_per_line = width.div_ceil(8 * h_max);
+        self.mcus_per_column = height.div_ceil(8 * v_max);
+
+        for component in &mut self.components {
+            let (h, v) = (
+                component.horizontal_sampling as usize,
+                component.vertical_sampling as usize,
+            );
+            component.width_in_blocks = (width * h).div_ceil(h_max).div_ceil(8);
+            component.height_in_blocks = (height * v).div_ceil(v_max).div_ceil(8);
+            component.blocks_per_line = self.mcus_per_line * h;
+            component.blocks_per_column = self.mcus_per_column * v;
+            component.blocks =
+                vec![[0; BLOCK_SIZE]; component.blocks_per_line * component.blocks_per_column];
+        }
+        Ok(())
+    }
+
+    fn read_scan_header(&self, data: &[u8]) -> Result<Scan> {
+        if self.components.is_empty() {
+            return Err(Error::InvalidData(
+                "JPEG scan precedes the frame header".into(),
+            ));
+        }
+        let count = *data.first().ok_or_else(truncated)? as usize;
+        if count == 0 || count > 4 || data.len() < 1 + count * 2 + 3 {
+            return Err(Error::InvalidData("Invalid JPEG scan header".into()));
+        }
+
+        let mut components = Vec::with_capacity(count);
+        for i in 0..count {
+            let id = data[1 + i * 2];
+            let tables = data[2 + i * 2];
+            let index = self
+                .components
+                .iter()
+                .position(|c| c.id == id)
+                .ok_or_else(|| Error::InvalidData(format!("Unknown JPEG component {}", id)))?;
+            let (dc_table, ac_table) = (tables >> 4, tables & 0x0F);
+            if dc_table > 3 || ac_table > 3 {
+                return Err(Error::InvalidData(
+                    "Invalid JPEG Huffman table selector".into(),
+                ));
+            }
+            components.push(ScanComponent {
+                index,
+                dc_table,
+                ac_table,
+            });
+        }
+
+        let spectral = &data[1 + count * 2..];
+        let (start, end) = (spectral[0], spectral[1]);
+        let (high, low) = (spectral[2] >> 4, spectral[2] & 0x0F);
+        let kind = if !self.progressive {
+            if start != 0 || end != 63 || spectral[2] != 0 {
+                return Err(Error::InvalidData(
+                    "Invalid spectral selection for a sequential JPEG scan".into(),
+                ));
+            }
+            ScanKind::Sequential
+        } else {
+            let valid_band = if start == 0 {
+                end == 0
+            } else {
+                start <= end && end <= 63 && count == 1
+            };
+            if !valid_band || low > 13 || (high != 0 && high != low + 1) {
+                return Err(Error::InvalidData(
+                    "Invalid spectral selection for a progressive JPEG scan".into(),
+                ));
+            }
+            match (start, high) {
+                (0, 0) => ScanKind::DcFirst,
+                (0, _) => ScanKind::DcRefine,
+                (_, 0) => ScanKind::AcFirst,
+                _ => ScanKind::AcRefine,
+            }
+        };
+
+        Ok(Scan {
+            kind,
+            components,
+            spectral_start: start,
+            spectral_end: end,
+            approximation_high: high,
+            approximation_low: low,
+            eob_runs: false,
+        })
+    }
+
+    /// Lists the blocks of every MCU of a scan as (scan component, block index)
+    fn scan_mcus(&self, scan: &Scan) -> Vec<Vec<(usize, usize)>> {
+        if let [single] = scan.components.as_slice() {
+            // Non-interleaved scans code the component's own blocks one at a time
+            let component = &self.components[single.index];
+            return (0..component.height_in_blocks)
+                .flat_map(|row| {
+                    (0..component.width_in_blocks)
+                        .map(move |column| vec![(0, row * component.blocks_per_line + column)])
+                })
+                .collect();
+        }
+
+        let mut mcus = Vec::with_capacity(self.mcus_per_line * self.mcus_per_column);
+        for mcu_row in 0..self.mcus_per_column {
+            for mcu_column in 0..self.mcus_per_line {
+                let mut blocks = Vec::new();
+                for (position, scan_component) in scan.components.iter().enumerate() {
+                    let component = &self.components[scan_component.index];
+                    let (h, v) = (
+                        component.horizontal_sampling as usize,
+                        component.vertical_sampling as usize,
+                    );
+                    for y in 0..v {
+                        for x in 0..h {
+                            let row = mcu_row * v + y;
+                            let column = mcu_column * h + x;
+                            blocks.push((position, row * component.blocks_per_line + column));
+                        }
+                    }
+                }
+                mcus.push(blocks);
+            }
+        }
+        mcus
+    }
+
+    /// Decodes a scan's entropy-coded data starting at `pos` and returns the
+    /// position of the marker that follows it
+    fn decode_scan(
+        &mut self,
+        bytes: &[u8],
+        pos: usize,
+        scan: &mut Scan,
+        tables: &HuffmanTables,
+        restart_interval: usize,
+    ) -> Result<usize> {
+        let mcus = self.scan_mcus(scan);
+        let mut reader = BitReader::new(bytes, pos);
+        let mut predictions = vec![0i32; scan.components.len()];
+        let mut eob_run = 0u32;
+        let band = (scan.spectral_start as usize, scan.spectral_end as usize);
+        let low = scan.approximation_low;
+
+        for (mcu_index, mcu) in mcus.iter().enumerate() {
+            if restart_interval > 0 && mcu_index > 0 && mcu_index % restart_interval == 0 {
+                reader.restart()?;
+                predictions.iter_mut().for_each(|p| *p = 0);
+                eob_run = 0;
+            }
+
+            for &(position, block_index) in mcu {
+                let scan_component = &scan.components[position];
+                let dc_table = || tables.get(TableClass::Dc, scan_component.dc_table);
+                let ac_table = || tables.get(TableClass::Ac, scan_component.ac_table);
+                let block = &mut self.components[scan_component.index].blocks[block_index];
+                let prediction = &mut predictions[position];
+
+                match scan.kind {
+                    ScanKind::Sequential => {
+                        decode_block(&mut reader, block, dc_table()?, ac_table()?, prediction)?
+                    }
+                    ScanKind::DcFirst => {
+                        decode_dc_first(&mut reader, block, dc_table()?, prediction, low)?
+                    }
+                    ScanKind::DcRefine => {
+                        if reader.bit() == 1 {
+                            block[0] |= 1 << low;
+                        }
+                    }
+                    ScanKind::AcFirst => {
+                        decode_ac_first(&mut reader, block, ac_table()?, band, low, &mut eob_run)?
+                    }
+                    ScanKind::AcRefine => {
+                        decode_ac_refine(&mut reader, block, ac_table()?, band, low, &mut eob_run)?
+                    }
+                }
+                // A run still pending after its first block spans several blocks
+                scan.eob_runs |= eob_run > 0;
+            }
+        }
+
+        Ok(reader.next_marker())
+    }
+
+    /// Feeds a scan's symbols and bits to `sink`
+    fn encode_scan(&self, scan: &Scan, restart_interval: usize, sink: &mut impl EntropySink) {
+        let mcus = self.scan_mcus(scan);
+        let mut predictions = vec![0i32; scan.components.len()];
+        let mut eob_run = EobRun::new(scan);
+        let band = (scan.spectral_start as usize, scan.spectral_end as usize);
+        let low = scan.approximation_low;
+        let mut restarts = 0;
+
+        for (mcu_index, mcu) in mcus.iter().enumerate() {
+            if restart_interval > 0 && mcu_index > 0 && mcu_index % restart_interval == 0 {
+                eob_run.flush(sink);
+                sink.restart(restarts);
+                restarts += 1;
+                predictions.iter_mut().for_each(|p| *p = 0);
+            }
+
+            for &(position, block_index) in mcu {
+                let scan_component = &scan.components[position];
+                let block = &self.components[scan_component.index].blocks[block_index];
+                let prediction = &mut predictions[position];
+
+                match scan.kind {
+                    ScanKind::Sequential => encode_block(
+                        sink,
+                        block,
+                        scan_component.dc_table,
+                        scan_component.ac_table,
+                        prediction,
+                    ),
+                    ScanKind::DcFirst => {
+                        encode_dc_first(sink, block, scan_component.dc_table, prediction, low)
+                    }
+                    ScanKind::DcRefine => sink.value(((block[0] >> low) & 1) as i32, 1),
+                    ScanKind::AcFirst => encode_ac_first(sink, block, band, low, &mut eob_run),
+                    ScanKind::AcRefine => encode_ac_refine(sink, block, band, low, &mut eob_run),
+                }
+            }
+        }
+        eob_run.flush(sink);
+    }
+}
+
+fn truncated() -> Error {
+    Error::InvalidData("JPEG file is truncated".into())
+}
+
+fn write_segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) -> Result<()> {
+    let length = u16::try_from(data.len() + 2)
+        .map_err(|_| Error::Encoding("JPEG segment is too long".into()))?;
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(data);
+    Ok(())
+}
+
+/// Number of bits needed for the magnitude of `value`
+fn magnitude_category(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Converts `count` received bits to a signed value (T.81 figure F.12)
+fn extend(bits: u32, count: u8) -> i32 {
+    if count == 0 {
+        0
+    } else if bits < 1 << (count - 1) {
+        bits as i32 - (1 << count) + 1
+    } else {
+        bits as i32
+    }
+}
+
+fn to_coefficient(value: i32) -> Result<i16> {
+    i16::try_from(value).map_err(|_| Error::InvalidData("JPEG coefficient out of range".into()))
+}
+
+fn overrun() -> Error {
+    Error::InvalidData("JPEG AC coefficients overrun the block".into())
+}
+
+fn decode_block(
+    reader: &mut BitReader,
+    block: &mut Block,
+    dc_table: &HuffmanTable,
+    ac_table: &HuffmanTable,
+    prediction: &mut i32,
+) -> Result<()> {
+    decode_dc_first(reader, block, dc_table, prediction, 0)?;
+
+    let mut k = 1;
+    while k < BLOCK_SIZE {
+        let symbol = reader.decode(ac_table)?;
+        let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k >= BLOCK_SIZE {
+            return Err(overrun());
+        }
+        block[k] = extend(reader.receive(size), size) as i16;
+        k += 1;
+    }
+    if k > BLOCK_SIZE {
+        return Err(overrun());
+    }
+    Ok(())
+}
+
+/// Decodes a DC coefficient, or its high bits from bit `low` up
+fn decode_dc_first(
+    reader: &mut BitReader,
+    block: &mut Block,
+    table: &HuffmanTable,
+    prediction: &mut i32,
+    low: u8,
+) -> Result<()> {
+    let category = reader.decode(table)?;
+    if category > 15 {
+        return Err(Error::InvalidData("Invalid JPEG DC coefficient".into()));
+    }
+    *prediction += extend(reader.receive(category), category);
+    block[0] = to_coefficient(*prediction << low)?;
+    Ok(())
+}
+
+/// Decodes the high bits of a band of AC coefficients (T.81 G.1.2.2)
+fn decode_ac_first(
+    reader: &mut BitReader,
+    block: &mut Block,
+    table: &HuffmanTable,
+    (start, end): (usize, usize),
+    low: u8,
+    eob_run: &mut u32,
+) -> Result<()> {
+    if *eob_run > 0 {
+        *eob_run -= 1;
+        return Ok(());
+    }
+
+    let mut k = start;
+    while k <= end {
+        let symbol = reader.decode(table)?;
+        let (run, size) = (symbol >> 4, symbol & 0x0F);
+        if size == 0 {
+            if run == 15 {
+                k += 16;
+                continue;
+            }
+            *eob_run = (1 << run) + reader.receive(run) - 1;
+            break;
+        }
+        k += run as usize;
+        if k > end {
+            return Err(overrun());
+        }
+        block[k] = to_coefficient(extend(reader.receive(size), size) << low)?;
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Decodes one more bit of a band of AC coefficients (T.81 G.1.2.3)
+fn decode_ac_refine(
+    reader: &mut BitReader,
+    block: &mut Block,
+    table: &HuffmanTable,
+    (start, end): (usize, usize),
+    low: u8,
+    eob_run: &mut u32,
+) -> Result<()> {
+    let (plus, minus) = (1i16 << low, -1i16 << low);
+    let mut k = start;
+
+    if *eob_run == 0 {
+        while k <= end {
+            let symbol = reader.decode(table)?;
+            let (mut run, size) = (symbol >> 4, symbol & 0x0F);
+            let value = match size {
+                0 if run != 15 => {
+                    *eob_run = (1 << run) + reader.receive(run);
+                    break;
+                }
+                0 => 0,
+                1 if reader.bit() == 1 => plus,
+                1 => minus,
+                _ => return Err(Error::InvalidData("Invalid JPEG refinement code".into())),
+            };
+
+            // Skip `run` coefficients that are still zero, refining the
+            // non-zero ones passed on the way
+            while k <= end {
+                if block[k] != 0 {
+                    refine(reader, &mut block[k], plus, minus);
+                } else if run == 0 {
+                    break;
+                } else {
+                    run -= 1;
+                }
+                k += 1;
+            }
+            if value != 0 {
+                if k > end {
+                    return Err(overrun());
+                }
+                block[k] = value;
+            }
+            k += 1;
+        }
+    }
+
+    if *eob_run > 0 {
+        // The rest of the band stays zero; only correction bits follow
+        for coefficient in block[k.min(end + 1)..=end].iter_mut() {
+            if *coefficient != 0 {
+                refine(reader, coefficient, plus, minus);
+            }
+        }
+        *eob_run -= 1;
+    }
+    Ok(())
+}
+
+/// Applies a correction bit to a coefficient that is already non-zero
+fn refine(reader: &mut BitReader, coefficient: &mut i16, plus: i16, minus: i16) {
+    if reader.bit() == 1 && *coefficient & plus == 0 {
+        *coefficient += if *coefficient >= 0 { plus } else { minus };
+    }
+}
+
+fn encode_block(
+    sink: &mut impl EntropySink,
+    block: &Block,
+    dc_table: u8,
+    ac_table: u8,
+    prediction: &mut i32,
+) {
+    encode_dc_first(sink, block, dc_table, prediction, 0);
+
+    let mut run = 0;
+    for &coefficient in &block[1..] {
+        if coefficient == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            sink.symbol(TableClass::Ac, ac_table, 0xF0);
+            run -= 16;
+        }
+        let size = magnitude_category(coefficient as i32);
+        sink.symbol(TableClass::Ac, ac_table, (run << 4) | size);
+        sink.value(coefficient as i32, size);
+        run = 0;
+    }
+    if run > 0 {
+        sink.symbol(TableClass::Ac, ac_table, 0x00);
+    }
+}
+
+fn encode_dc_first(
+    sink: &mut impl EntropySink,
+    block: &Block,
+    table: u8,
+    prediction: &mut i32,
+    low: u8,
+) {
+    let value = (block[0] >> low) as i32;
+    let difference = value - *prediction;
+    *prediction = value;
+    let category = magnitude_category(difference);
+    sink.symbol(TableClass::Dc, table, category);
+    sink.value(difference, category);
+}
+
+fn encode_ac_first(
+    sink: &mut impl EntropySink,
+    block: &Block,
+    (start, end): (usize, usize),
+    low: u8,
+    eob_run: &mut EobRun,
+) {
+    let mut run = 0;
+    for &coefficient in &block[start..=end] {
+        let magnitude = (coefficient.unsigned_abs() >> low) as i32;
+        if magnitude == 0 {
+            run += 1;
+            continue;
+        }
+        eob_run.flush(sink);
+        while run > 15 {
+            sink.symbol(TableClass::Ac, eob_run.table, 0xF0);
+            run -= 16;
+        }
+        let value = if coefficient < 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+        let size = magnitude_category(value);
+        sink.symbol(TableClass::Ac, eob_run.table, (run << 4) | size);
+        sink.value(value, size);
+        run = 0;
+    }
+    if run > 0 {
+        eob_run.end_block(sink);
+    }
+}
+
+/// Encodes one more bit of a band of AC coefficients, following the
+/// choices of the IJG encoder (jcphuff.c) so its files round-trip exactly
+fn encode_ac_refine(
+    sink: &mut impl EntropySink,
+    block: &Block,
+    (start, end): (usize, usize),
+    low: u8,
+    eob_run: &mut EobRun,
+) {
+    let magnitude = |k: usize| block[k].unsigned_abs() >> low;
+    // Zero runs after the last newly non-zero coefficient go into the
+    // end-of-band code instead of ZRL codes
+    let last_new = (start..=end).rev().find(|&k| magnitude(k) == 1);
+
+    let mut run = 0u8;
+    let mut corrections = Vec::new();
+    for (k, &coefficient) in block.iter().enumerate().take(end + 1).skip(start) {
+        let value = coefficient.unsigned_abs() >> low;
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 && last_new.is_some_and(|last| k <= last) {
+            eob_run.flush(sink);
+            sink.symbol(TableClass::Ac, eob_run.table, 0xF0);
+            run -= 16;
+            corrections.drain(..).for_each(|bit| sink.value(bit, 1));
+        }
+        if value > 1 {
+            corrections.push((value & 1) as i32);
+            continue;
+        }
+        eob_run.flush(sink);
+        sink.symbol(TableClass::Ac, eob_run.table, (run << 4) | 1);
+        sink.value((coefficient > 0) as i32, 1);
+        corrections.drain(..).for_each(|bit| sink.value(bit, 1));
+        run = 0;
+    }
+    if run > 0 || !corrections.is_empty() {
+        eob_run.corrections.append(&mut corrections);
+        eob_run.end_block(sink);
+    }
+}
+
+/// Blocks of a progressive AC scan that end before the band does, waiting to
+/// be coded as one end-of-band run, with their correction bits
+struct EobRun {
+    table: u8,
+    join: bool,
+    length: u32,
+    corrections: Vec<i32>,
+}
+
+impl EobRun {
+    const MAX_LENGTH: u32 = 0x7FFF;
+    /// Buffered correction bits at which the IJG encoder ends a run early
+    const MAX_CORRECTIONS: usize = 1000 - BLOCK_SIZE + 1;
+
+    fn new(scan: &Scan) -> Self {
+        Self {
+            table: scan.components[0].ac_table,
+            join: scan.eob_runs,
+            length: 0,
+            corrections: Vec::new(),
+        }
+    }
+
+    fn end_block(&mut self, sink: &mut impl EntropySink) {
+        self.length += 1;
+        if !self.join
+            || self.length == Self::MAX_LENGTH
+            || self.corrections.len() > Self::MAX_CORRECTIONS
+        {
+            self.flush(sink);
+        }
+    }
+
+    fn flush(&mut self, sink: &mut impl EntropySink) {
+        if self.length > 0 {
+            let bits = magnitude_category(self.length as i32) - 1;
+            sink.symbol(TableClass::Ac, self.table, bits << 4);
+            sink.value(self.length as i32, bits);
+            self.length = 0;
+        }
+        self.corrections
+            .drain(..)
+            .for_each(|bit| sink.value(bit, 1));
+    }
+}
+
+/// Reads bits from entropy-coded data, removing byte stuffing
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    current: u8,
+    remaining: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8], pos: usize) -> Self {
+        Self {
+            bytes,
+            pos,
+            current: 0,
+            remaining: 0,
+        }
+    }
+
+    fn bit(&mut self) -> u32 {
+        if self.remaining == 0 {
+            self.current = match (self.bytes.get(self.pos), self.bytes.get(self.pos + 1)) {
+                (Some(0xFF), Some(0x00)) => {
+                    self.pos += 2;
+                    0xFF
+                }
+                // A marker ends the data; pad with zeros rather than consume it
+                (Some(0xFF), _) | (None, _) => 0,
+                (Some(&byte), _) => {
+                    self.pos += 1;
+                    byte
+                }
+            };
+            self.remaining = 8;
+        }
+        self.remaining -= 1;
+        ((self.current >> self.remaining) & 1) as u32
+    }
+
+    fn receive(&mut self, count: u8) -> u32 {
+        (0..count).fold(0, |bits, _| (bits << 1) | self.bit())
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = (code << 1) | self.bit() as i32;
+            if code <= table.max_code[length] {
+                let index = table.value_offset[length] + code - table.min_code[length];
+                return Ok(table.values[index as usize]);
+            }
+        }
+        Err(Error::InvalidData("Invalid JPEG Huffman code".into()))
+    }
+
+    /// Skips to the byte boundary and past the expected restart marker
+    fn restart(&mut self) -> Result<()> {
+        self.remaining = 0;
+        let pos = self.next_marker();
+        match self.bytes.get(pos + 1) {
+            Some(RST0..=0xD7) => {
+                self.pos = pos + 2;
+                Ok(())
+            }
+            _ => Err(Error::InvalidData("Missing JPEG restart marker".into())),
+        }
+    }
+
+    /// Finds the next marker at or after the current position
+    fn next_marker(&self) -> usize {
+        let mut pos = self.pos;
+        while pos < self.bytes.len() {
+            if self.bytes[pos] == 0xFF && self.bytes.get(pos + 1).is_some_and(|&b| b != 0) {
+                // Skip fill bytes in front of the marker
+                if self.bytes[pos + 1] != 0xFF {
+                    return pos;
+                }
+            }
+            pos += 1;
+        }
+        pos
+    }
+}
+
+/// Receives the Huffman symbols and raw bits of a scan
+trait EntropySink {
+    /// Codes a symbol with the given Huffman table
+    fn symbol(&mut self, class: TableClass, table: u8, symbol: u8);
+    /// Appends the low `size` bits representing `value` (T.81 F.1.2.1)
+    fn value(&mut self, value: i32, size: u8);
+    /// Ends a restart interval
+    fn restart(&mut self, index: usize);
+}
+
+/// Counts how often each table codes each symbol
+#[derive(Default)]
+struct SymbolStatistics {
+    frequencies: [[Option<Box<[u32; 256]>>; 4]; 2],
+}
+
+impl SymbolStatistics {
+    fn covered_by(&self, tables: &HuffmanTables) -> bool {
+        self.used_tables().all(|(class, id, frequencies)| {
+            tables.tables[class as usize][id as usize]
+                .as_ref()
+                .is_some_and(|table| {
+                    (0..=255u8).all(|s| frequencies[s as usize] == 0 || table.has_symbol(s))
+                })
+        })
+    }
+
+    fn used_tables(&self) -> impl Iterator<Item = (TableClass, u8, &[u32; 256])> {
+        [TableClass::Dc, TableClass::Ac]
+            .into_iter()
+            .flat_map(move |class| {
+                (0..4u8).filter_map(move |id| {
+                    self.frequencies[class as usize][id as usize]
+                        .as_deref()
+                        .map(|frequencies| (class, id, frequencies))
+                })
+            })
+    }
+}
+
+impl EntropySink for SymbolStatistics {
+    fn symbol(&mut self, class: TableClass, table: u8, symbol: u8) {
+        self.frequencies[class as usize][table as usize]
+            .get_or_insert_with(|| Box::new([0; 256]))[symbol as usize] += 1;
+    }
+
+    fn value(&mut self, _value: i32, _size: u8) {}
+
+    fn restart(&mut self, _index: usize) {}
+}
+
+/// Writes entropy-coded data with byte stuffing
+struct BitWriter<'a> {
+    tables: &'a HuffmanTables,
+    out: Vec<u8>,
+    current: u8,
+    filled: u8,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(tables: &'a HuffmanTables) -> Self {
+        Self {
+            tables,
+            out: Vec::new(),
+            current: 0,
+            filled: 0,
+        }
+    }
+
+    fn bits(&mut self, bits: u32, count: u8) {
+        for i in (0..count).rev() {
+            self.current = (self.current << 1) | ((bits >> i) & 1) as u8;
+            self.filled += 1;
+            if self.filled == 8 {
+                self.out.push(self.current);
+                if self.current == 0xFF {
+                    self.out.push(0x00);
+                }
+                self.current = 0;
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Pads the last byte with one bits
+    fn align(&mut self) {
+        if self.filled > 0 {
+            self.bits(0xFF, 8 - self.filled);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+impl EntropySink for BitWriter<'_> {
+    fn symbol(&mut self, class: TableClass, table: u8, symbol: u8) {
+        // Every symbol was checked against the tables before writing
+        if let Some(table) = &self.tables.tables[class as usize][table as usize] {
+            let (code, size) = (table.codes[symbol as usize], table.sizes[symbol as usize]);
+            self.bits(code as u32, size);
+        }
+    }
+
+    fn value(&mut self, value: i32, size: u8) {
+        let bits = if value < 0 { value - 1 } else { value };
+        self.bits(bits as u32 & ((1u32 << size) - 1), size);
+    }
+
+    fn restart(&mut self, index: usize) {
+        self.align();
+        self.out
+            .extend_from_slice(&[0xFF, RST0 + (index % 8) as u8]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    fn encode_test_jpeg(width: u32, height: u32, quality: u8) -> Vec<u8> {
+        encode_test_jpeg_with(width, height, ColorType::Rgb, quality, |_| {})
+    }
+
+    fn encode_test_jpeg_with(
+        width: u32,
+        height: u32,
+        color: ColorType,
+        quality: u8,
+        configure: impl FnOnce(&mut Encoder<&mut Vec<u8>>),
+    ) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x * 7 + y * 3) as u8,
+                ((x ^ y) * 5) as u8,
+                (x * y % 251) as u8,
+            ])
+        });
+        let pixels = match color {
+            ColorType::Luma => image.pixels().map(|p| p[0] ^ p[2]).collect(),
+            ColorType::Cmyk => image
+                .pixels()
+                .flat_map(|p| [p[0], p[1], p[2], p[0] ^ p[1]])
+                .collect(),
+            _ => image.into_raw(),
+        };
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::new(&mut bytes, quality);
+        configure(&mut encoder);
+        encoder
+            .encode(&pixels, width as u16, height as u16, color)
+            .unwrap();
+        bytes
+    }
+
+    /// Compares the coefficients of the blocks that cover image samples
+    fn same_coefficients(a: &JpegImage, b: &JpegImage) -> bool {
+        a.components.iter().zip(&b.components).all(|(a, b)| {
+            (0..a.height_in_blocks).all(|row| {
+                (0..a.width_in_blocks).all(|column| a.block(row, column) == b.block(row, column))
+            })
+        })
+    }
+
+    /// Replaces the scans of a sequential image with a progressive scan script
+    /// of (components, spectral start, spectral end, high bit, low bit)
+    fn make_progressive(image: &mut JpegImage, script: &[(&[usize], u8, u8, u8, u8)]) {
+        image.progressive = true;
+        image
+            .segments
+            .retain(|segment| matches!(segment, Segment::Marker(..)));
+        for segment in &mut image.segments {
+            if let Segment::Marker(marker @ (SOF0 | SOF1), _) = segment {
+                *marker = SOF2;
+            }
+        }
+        for &(components, start, end, high, low) in script {
+            let kind = match (start, high) {
+                (0, 0) => ScanKind::DcFirst,
+                (0, _) => ScanKind::DcRefine,
+                (_, 0) => ScanKind::AcFirst,
+                _ => ScanKind::AcRefine,
+            };
+            image.segments.push(Segment::Scan(Scan {
+                kind,
+                components: components
+                    .iter()
+                    .map(|&index| ScanComponent {
+                        index,
+                        dc_table: (index > 0) as u8,
+                        ac_table: (index > 0) as u8,
+                    })
+                    .collect(),
+                spectral_start: start,
+                spectral_end: end,
+                approximation_high: high,
+                approximation_low: low,
+                eob_runs: true,
+            }));
+        }
+    }
+
+    #[test]
+    fn test_jpeg_coefficient_round_trip() {
+        for (width, height, quality) in [(64, 48, 90), (37, 21, 75), (8, 8, 50)] {
+            let bytes = encode_test_jpeg(width, height, quality);
+            let jpeg = JpegImage::from_bytes(&bytes).unwrap();
+            assert_eq!((jpeg.width, jpeg.height), (width as u16, height as u16));
+            assert!(!jpeg.is_progressive());
+            assert_eq!(jpeg.to_bytes().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_jpeg_encoder_settings_round_trip() {
+        type Configure = fn(&mut Encoder<&mut Vec<u8>>);
+        let settings: [(ColorType, Configure); 6] = [
+            (ColorType::Luma, |_| {}),
+            (ColorType::Rgb, |e| {
+                e.set_sampling_factor(SamplingFactor::R_4_4_4)
+            }),
+            (ColorType::Rgb, |e| {
+                e.set_sampling_factor(SamplingFactor::R_4_2_2)
+            }),
+            (ColorType::Rgb, |e| e.set_restart_interval(3)),
+            (ColorType::Rgb, |e| e.set_optimized_huffman_tables(true)),
+            (ColorType::Cmyk, |_| {}),
+        ];
+
+        for (color, configure) in settings {
+            for progressive in [false, true] {
+                let bytes = encode_test_jpeg_with(45, 29, color, 80, |encoder| {
+                    configure(encoder);
+                    encoder.set_progressive(progressive);
+                });
+                let jpeg = JpegImage::from_bytes(&bytes).unwrap();
+                assert_eq!(jpeg.is_progressive(), progressive);
+                assert_eq!(jpeg.to_bytes().unwrap(), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_progressive_jpeg_round_trip() {
+        for scans in [2, 4, 7] {
+            let bytes = encode_test_jpeg_with(64, 40, ColorType::Rgb, 85, |encoder| {
+                encoder.set_progressive_scans(scans)
+            });
+            let progressive = JpegImage::from_bytes(&bytes).unwrap();
+            assert_eq!(progressive.to_bytes().unwrap(), bytes);
+
+            // The coefficients match those of the same image coded sequentially
+            let sequential = JpegImage::from_bytes(&encode_test_jpeg(64, 40, 85)).unwrap();
+            assert!(same_coefficients(&progressive, &sequential));
+        }
+    }
+
+    #[test]
+    fn test_successive_approximation_round_trip() {
+        let baseline = JpegImage::from_bytes(&encode_test_jpeg(72, 56, 92)).unwrap();
+
+        // The standard script of the IJG encoder, with successive approximation
+        let mut jpeg = baseline.clone();
+        make_progressive(
+            &mut jpeg,
+            &[
+                (&[0, 1, 2], 0, 0, 0, 1),
+                (&[0], 1, 5, 0, 2),
+                (&[2], 1, 63, 0, 1),
+                (&[1], 1, 63, 0, 1),
+                (&[0], 6, 63, 0, 2),
+                (&[0], 1, 63, 2, 1),
+                (&[0, 1, 2], 0, 0, 1, 0),
+                (&[2], 1, 63, 1, 0),
+                (&[1], 1, 63, 1, 0),
+                (&[0], 1, 63, 1, 0),
+            ],
+        );
+        let bytes = jpeg.to_bytes().unwrap();
+
+        let decoded = JpegImage::from_bytes(&bytes).unwrap();
+        assert!(decoded.is_progressive());
+        assert!(same_coefficients(&decoded, &baseline));
+        assert_eq!(decoded.to_bytes().unwrap(), bytes);
+
+        // An independent decoder sees the same image
+        let pixels = image::load_from_memory(&bytes).unwrap().to_rgb8();
+        let expected = image::load_from_memory(&baseline.to_bytes().unwrap())
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn test_quantization_tables() {
+        let bytes = encode_test_jpeg(16, 16, 50);
+        let jpeg = JpegImage::from_bytes(&bytes).unwrap();
+        let luma = jpeg.quantization_table(&jpeg.components[0]).unwrap();
+        let chroma = jpeg.quantization_table(&jpeg.components[1]).unwrap();
+        // Annex K tables at quality 50, in zig-zag order
+        assert_eq!(luma[..3], [16, 11, 12]);
+        assert_eq!(chroma[..3], [17, 18, 18]);
+    }
+
+    #[test]
+    fn test_jpeg_modified_coefficients() {
+        let bytes = encode_test_jpeg(64, 64, 80);
+        let mut jpeg = JpegImage::from_bytes(&bytes).unwrap();
+        let original = jpeg.components[0].block(1, 1)[5];
+
+        // An 11-bit AC value has no code in the standard tables, so new
+        // tables are written for the scan
+        jpeg.components[0].block_mut(1, 1)[5] = 1500;
+        jpeg.components[1].block_mut(0, 0)[63] = -3;
+        let bytes = jpeg.to_bytes().unwrap();
+        let modified = JpegImage::from_bytes(&bytes).unwrap();
+        assert_eq!(modified.components[0].block(1, 1)[5], 1500);
+        assert_eq!(modified.components[1].block(0, 0)[63], -3);
+        assert_ne!(original, 1500);
+        assert_eq!(modified.to_bytes().unwrap(), bytes);
+
+        // The result still decodes to pixels
+        let decoded = image::load_from_memory(&jpeg.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.width(), 64);
+    }
+
+    #[test]
+    fn test_optimal_huffman_table() {
+        let mut frequencies = [0u32; 256];
+        for (symbol, frequency) in frequencies.iter_mut().enumerate() {
+            *frequency = (symbol as u32 % 17) * 1000 + 1;
+        }
+        let table = HuffmanTable::optimal(&frequencies).unwrap();
+        assert!((0..=255u8).all(|s| table.has_symbol(s)));
+        assert!(table.sizes.iter().all(|&size| (1..=16).contains(&size)));
+    }
+}
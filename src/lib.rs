@@ -3,13 +3,24 @@
 //! This library provides a comprehensive set of tools for steganographic operations,
 //! including data embedding, extraction, and analysis across various file formats.
 
+pub mod adaptive;
+pub mod audio;
+pub mod bmp;
+pub mod compression;
+pub mod container;
 pub mod embedding;
 pub mod encryption;
 pub mod error_correction;
 pub mod extraction;
+pub mod f5;
+pub mod gif;
 pub mod integrity;
+pub mod jpeg;
+pub mod matrix;
 pub mod metadata;
 pub mod pdf;
+pub mod png;
+pub mod qim;
 pub mod utils;
 pub mod watermarking;
 
@@ -0,0 +1,231 @@
+//! Matrix Embedding Module
+//!
+//! This module implements matrix embedding with binary Hamming codes
+//! (Crandall, 1998) over a stream of carrier bits, such as the least
+//! significant bits of an image. Each group of `2^k - 1` carrier bits holds
+//! `k` message bits as its syndrome: the XOR of the (1-based) positions of its
+//! one bits. Any syndrome can be reached by flipping at most one bit of the
+//! group, so a message well below capacity needs far fewer changes than
+//! writing one bit per carrier bit.
+//!
+//! Groups are interleaved, so each one is spread over the whole region the
+//! payload occupies. The embedder only decides which carrier bits to flip;
+//! how a bit is flipped is left to the carrier. Given a cost per carrier bit
+//! it may flip two cheap bits instead of one expensive one, which is how
+//! adaptive embedding steers changes into textured regions.
+
+use crate::{Error, Result};
+
+/// Largest supported code parameter
+pub const MAX_K: u8 = 12;
+
+/// Number of carrier bits per group for code parameter `k`
+pub fn group_size(k: u8) -> usize {
+    (1 << k) - 1
+}
+
+/// Number of carrier bits needed to embed `length` bytes with parameter `k`
+pub fn required_bits(length: usize, k: u8) -> usize {
+    (length * 8).div_ceil(k as usize) * group_size(k)
+}
+
+/// Picks the largest code parameter whose capacity suffices for `length`
+/// bytes in `available` carrier bits
+///
+/// A larger `k` embeds fewer bits per change but changes fewer carrier bits
+/// overall; returns `None` if the data does not fit even with `k = 1`.
+pub fn choose_k(length: usize, available: usize) -> Option<u8> {
+    (1..=MAX_K)
+        .rev()
+        .find(|&k| required_bits(length, k) <= available)
+}
+
+/// Syndrome of a group: the XOR of the (1-based) positions of its one bits
+pub(crate) fn syndrome(bits: impl IntoIterator<Item = u8>) -> usize {
+    bits.into_iter()
+        .enumerate()
+        .filter(|&(_, bit)| bit & 1 == 1)
+        .fold(0, |syndrome, (i, _)| syndrome ^ (i + 1))
+}
+
+/// Splits data into `k`-bit words, most significant bit first, padding the
+/// last word with zeros
+pub(crate) fn to_words(data: &[u8], k: u8) -> Vec<usize> {
+    let bits: Vec<usize> = data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| ((byte >> i) & 1) as usize))
+        .collect();
+    bits.chunks(k as usize)
+        .map(|chunk| {
+            let word = chunk.iter().fold(0, |word, &bit| (word << 1) | bit);
+            word << (k as usize - chunk.len())
+        })
+        .collect()
+}
+
+fn check_k(k: u8) -> Result<()> {
+    if !(1..=MAX_K).contains(&k) {
+        return Err(Error::InvalidInput(format!(
+            "Matrix embedding parameter must be between 1 and {}, got {}",
+            MAX_K, k
+        )));
+    }
+    Ok(())
+}
+
+/// Carrier indices of a group when `groups` groups are interleaved
+///
+/// Member `m` of group `g` is carrier bit `m * groups + g`, so every group is
+/// spread over the whole region the payload occupies.
+fn group_members(group: usize, groups: usize, size: usize) -> impl Iterator<Item = usize> {
+    (0..size).map(move |member| member * groups + group)
+}
+
+/// Works out which carrier bits to flip to embed data with parameter `k`
+///
+/// `cover` holds one carrier bit per element (only the lowest bit is used).
+/// Returns the indices into `cover` that must be flipped, at most one per
+/// group.
+pub fn embed(cover: &[u8], data: &[u8], k: u8) -> Result<Vec<usize>> {
+    embed_with_costs(cover, &vec![1.0; cover.len()], data, k)
+}
+
+/// Like [`embed`], but chooses the cheapest way to reach each syndrome
+///
+/// `costs` gives the cost of flipping each carrier bit. A group whose
+/// syndrome must change by `d` can flip bit `d` or any pair of bits whose
+/// positions XOR to `d`; the cheaper option is taken, so flips gather where
+/// they cost least. The result extracts with [`extract`] without the costs.
+pub fn embed_with_costs(cover: &[u8], costs: &[f32], data: &[u8], k: u8) -> Result<Vec<usize>> {
+    check_k(k)?;
+    if required_bits(data.len(), k) > cover.len() {
+        return Err(Error::InvalidInput(format!(
+            "Data too large for matrix embedding: {} bytes need {} carrier bits with k = {}, {} available",
+            data.len(),
+            required_bits(data.len(), k),
+            k,
+            cover.len()
+        )));
+    }
+
+    let size = group_size(k);
+    let words = to_words(data, k);
+    let groups = words.len();
+    let mut flips = Vec::new();
+    for (group, word) in words.into_iter().enumerate() {
+        let members: Vec<usize> = group_members(group, groups, size).collect();
+        let change = syndrome(members.iter().map(|&i| cover[i])) ^ word;
+        if change == 0 {
+            continue;
+        }
+
+        // Positions are 1-based: flipping member `p - 1` changes the syndrome by `p`
+        let single = costs[members[change - 1]];
+        let pair = (1..=size)
+            .map(|p| (p, p ^ change))
+            .filter(|&(p, q)| p < q && q <= size)
+            .map(|(p, q)| (costs[members[p - 1]] + costs[members[q - 1]], p, q))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        match pair {
+            Some((cost, p, q)) if cost < single => {
+                flips.push(members[p - 1]);
+                flips.push(members[q - 1]);
+            }
+            _ => flips.push(members[change - 1]),
+        }
+    }
+    Ok(flips)
+}
+
+/// Extracts `length` bytes embedded by [`embed`] with parameter `k`
+pub fn extract(carrier: &[u8], length: usize, k: u8) -> Result<Vec<u8>> {
+    check_k(k).map_err(|e| Error::InvalidData(e.to_string()))?;
+    if required_bits(length, k) > carrier.len() {
+        return Err(Error::InvalidData(format!(
+            "Data length ({}) exceeds matrix embedding capacity with k = {}",
+            length, k
+        )));
+    }
+
+    let size = group_size(k);
+    let groups = (length * 8).div_ceil(k as usize);
+    let bits: Vec<u8> = (0..groups)
+        .flat_map(|group| {
+            let word = syndrome(group_members(group, groups, size).map(|i| carrier[i]));
+            (0..k).rev().map(move |i| ((word >> i) & 1) as u8)
+        })
+        .collect();
+
+    Ok(bits
+        .chunks(8)
+        .take(length)
+        .map(|byte| byte.iter().fold(0, |value, &bit| (value << 1) | bit))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_matrix_embedding_round_trip() {
+        let data = b"Matrix embedding";
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let cover: Vec<u8> = (0..50_000).map(|_| rng.gen::<u8>() & 1).collect();
+
+        let mut changes = Vec::new();
+        for k in 1..=MAX_K {
+            let flips = embed(&cover, data, k).unwrap();
+            let mut stego = cover.clone();
+            for &i in &flips {
+                stego[i] ^= 1;
+            }
+            assert_eq!(extract(&stego, data.len(), k).unwrap(), data);
+
+            // At most one change per group
+            assert!(flips.len() <= (data.len() * 8).div_ceil(k as usize));
+            changes.push(flips.len());
+
+            // Costs move the changes without affecting extraction
+            let costs: Vec<f32> = (0..cover.len())
+                .map(|_| rng.gen_range(1.0..100.0))
+                .collect();
+            let weighted = embed_with_costs(&cover, &costs, data, k).unwrap();
+            let mut stego = cover.clone();
+            for &i in &weighted {
+                stego[i] ^= 1;
+            }
+            assert_eq!(extract(&stego, data.len(), k).unwrap(), data);
+            let cost = |flips: &[usize]| flips.iter().map(|&i| costs[i]).sum::<f32>();
+            if k >= 3 {
+                assert!(cost(&weighted) < cost(&flips), "k = {}", k);
+            }
+        }
+
+        // Larger codes change far fewer bits than plain LSB replacement
+        assert!(changes[MAX_K as usize - 1] * 4 < changes[0]);
+    }
+
+    #[test]
+    fn test_matrix_embedding_capacity() {
+        assert_eq!(choose_k(10, 80), Some(1));
+        assert_eq!(choose_k(10, 79), None);
+        assert_eq!(choose_k(1, 12), Some(2));
+        assert_eq!(choose_k(1, 100_000), Some(MAX_K));
+
+        let k = choose_k(100, 5_000).unwrap();
+        assert!(required_bits(100, k) <= 5_000);
+        assert!(required_bits(100, k + 1) > 5_000);
+
+        assert!(matches!(
+            embed(&[0; 79], &[0; 10], 1),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            extract(&[0; 100], 1, 0),
+            Err(Error::InvalidData(_))
+        ));
+    }
+}
@@ -2,9 +2,14 @@
 //!
 //! This module provides functionality for working with PDF files.
 
+use crate::container::MAGIC;
 use crate::integrity;
 use crate::{Error, Result};
-use lopdf::{Dictionary, Document, Object, Stream};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use sha2::{Digest, Sha256};
+
+/// Length of the `[HMAC][HMAC key]` prefix written by earlier versions
+const LEGACY_PREFIX_LENGTH: usize = 64;
 
 /// Handler for PDF operations
 pub struct PdfHandler {
@@ -20,25 +25,16 @@ impl PdfHandler {
     }
 
     /// Embeds data into the PDF
+    ///
+    /// The data is stored as given; integrity protection is the job of the
+    /// payload container, whose key never travels with the file.
     pub fn embed_data(&mut self, data: &[u8]) -> Result<()> {
-        // Add HMAC for integrity checking
-        let hmac_key = integrity::generate_integrity_key()?;
-        let hmac = integrity::generate_hmac(data, &hmac_key)?;
-
-        // Combine HMAC, key, and data
-        let mut payload = Vec::with_capacity(hmac.len() + hmac_key.len() + data.len());
-
-        // Format: [HMAC (32 bytes)][HMAC Key (32 bytes)][Original Data]
-        payload.extend_from_slice(&hmac);
-        payload.extend_from_slice(&hmac_key);
-        payload.extend_from_slice(data);
-
         // Prepare metadata dictionary
         let mut metadata = Dictionary::new();
         metadata.set("Type", Object::Name("Metadata".as_bytes().to_vec()));
         metadata.set("Subtype", Object::Name("XML".as_bytes().to_vec()));
 
-        let mut stream = Stream::new(Dictionary::new(), payload);
+        let mut stream = Stream::new(Dictionary::new(), data.to_vec());
         stream
             .dict
             .set("Type", Object::Name("EmbeddedFile".as_bytes().to_vec()));
@@ -72,26 +68,7 @@ impl PdfHandler {
                 .get_object(*reference)
                 .map_err(|e| Error::InvalidInput(format!("Failed to get metadata object: {}", e)))?
             {
-                Object::Stream(ref stream) => {
-                    let payload = &stream.content;
-
-                    // The payload should be at least 64 bytes (32 for HMAC, 32 for key)
-                    if payload.len() < 64 {
-                        return Err(Error::Integrity("Invalid data format".into()));
-                    }
-
-                    // Extract HMAC, key, and data
-                    let hmac = &payload[0..32];
-                    let hmac_key = &payload[32..64];
-                    let data = &payload[64..];
-
-                    // Verify HMAC
-                    if !integrity::verify_hmac(data, hmac_key, hmac)? {
-                        return Err(Error::Integrity("Data integrity check failed".into()));
-                    }
-
-                    Ok(data.to_vec())
-                }
+                Object::Stream(ref stream) => Ok(strip_legacy_hmac(&stream.content).to_vec()),
                 _ => Err(Error::InvalidInput("Invalid metadata format".into())),
             }
         } else {
@@ -99,6 +76,63 @@ impl PdfHandler {
         }
     }
 
+    /// Computes a digest of the document that embedding leaves unchanged
+    ///
+    /// Every object is hashed except the payload stream, the catalog's
+    /// `Metadata` entry that points to it and the cross-reference streams and
+    /// object streams that saving rewrites. The objects are hashed as parsed
+    /// rather than as bytes, as saving serializes the whole document anew.
+    pub fn carrier_digest(&self) -> [u8; 32] {
+        let catalog_id = self
+            .doc
+            .trailer
+            .get(b"Root")
+            .and_then(Object::as_reference)
+            .ok();
+        let payload_id = self.payload_id();
+
+        let mut hasher = Sha256::new();
+        for key in [&b"Root"[..], b"Info"] {
+            if let Ok(object) = self.doc.trailer.get(key) {
+                hash_object(&mut hasher, object);
+            }
+        }
+        for (&id, object) in &self.doc.objects {
+            let skipped = object
+                .type_name()
+                .is_ok_and(|name| ["ObjStm", "XRef", "Linearized"].contains(&name));
+            if skipped || Some(id) == payload_id {
+                continue;
+            }
+            hasher.update(id.0.to_be_bytes());
+            hasher.update(id.1.to_be_bytes());
+            match object {
+                Object::Dictionary(catalog) if Some(id) == catalog_id => {
+                    let mut catalog = catalog.clone();
+                    catalog.remove(b"Metadata");
+                    hash_object(&mut hasher, &Object::Dictionary(catalog));
+                }
+                _ => hash_object(&mut hasher, object),
+            }
+        }
+        hasher.finalize().into()
+    }
+
+    /// Returns the identifier of the stream the payload is stored in
+    fn payload_id(&self) -> Option<ObjectId> {
+        let id = self
+            .doc
+            .catalog()
+            .ok()?
+            .get(b"Metadata")
+            .and_then(Object::as_reference)
+            .ok()?;
+        match self.doc.get_object(id) {
+            Ok(Object::Stream(stream)) if stream.dict.type_is(b"EmbeddedFile") => Some(id),
+            _ => None,
+        }
+    }
+
     /// Saves the PDF to a file
     pub fn save(&mut self, path: &str) -> Result<()> {
         self.doc
@@ -108,6 +142,82 @@ impl PdfHandler {
     }
 }
 
+/// Feeds an object to a hasher, tagged with its type and with the length of
+/// every variable-length part in front of it
+fn hash_object(hasher: &mut Sha256, object: &Object) {
+    fn hash_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+    fn hash_dictionary(hasher: &mut Sha256, dictionary: &Dictionary) {
+        hasher.update((dictionary.len() as u64).to_be_bytes());
+        for (key, value) in dictionary.iter() {
+            hash_bytes(hasher, key);
+            hash_object(hasher, value);
+        }
+    }
+
+    match object {
+        Object::Null => hasher.update([0]),
+        Object::Boolean(value) => hasher.update([1, *value as u8]),
+        Object::Integer(value) => {
+            hasher.update([2]);
+            hasher.update(value.to_be_bytes());
+        }
+        Object::Real(value) => {
+            // Saving writes the shortest decimal that reads back as the value
+            hasher.update([3]);
+            hash_bytes(hasher, value.to_string().as_bytes());
+        }
+        Object::Name(name) => {
+            hasher.update([4]);
+            hash_bytes(hasher, name);
+        }
+        Object::String(bytes, _) => {
+            hasher.update([5]);
+            hash_bytes(hasher, bytes);
+        }
+        Object::Array(items) => {
+            hasher.update([6]);
+            hasher.update((items.len() as u64).to_be_bytes());
+            for item in items {
+                hash_object(hasher, item);
+            }
+        }
+        Object::Dictionary(dictionary) => {
+            hasher.update([7]);
+            hash_dictionary(hasher, dictionary);
+        }
+        Object::Stream(stream) => {
+            hasher.update([8]);
+            hash_dictionary(hasher, &stream.dict);
+            hash_bytes(hasher, &stream.content);
+        }
+        Object::Reference((id, generation)) => {
+            hasher.update([9]);
+            hasher.update(id.to_be_bytes());
+            hasher.update(generation.to_be_bytes());
+        }
+    }
+}
+
+/// Strips the `[HMAC][HMAC key]` prefix written by earlier versions
+///
+/// That prefix carried its own key, so it is only a checksum. It is stripped
+/// only when it verifies; containers and any other data, which may well be 64
+/// bytes or longer, are returned unchanged.
+fn strip_legacy_hmac(payload: &[u8]) -> &[u8] {
+    if payload.starts_with(&MAGIC) || payload.len() < LEGACY_PREFIX_LENGTH {
+        return payload;
+    }
+    let (hmac, rest) = payload.split_at(32);
+    let (key, data) = rest.split_at(32);
+    match integrity::verify_hmac(data, key, hmac) {
+        Ok(true) => data,
+        _ => payload,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -143,4 +253,47 @@ mod tests {
 
         assert_eq!(extracted, test_data);
     }
+
+    #[test]
+    fn test_pdf_reads_legacy_hmac_prefix() {
+        let key = integrity::generate_integrity_key().unwrap();
+        let mut payload = integrity::generate_hmac(b"Legacy data", &key).unwrap();
+        payload.extend_from_slice(&key);
+        payload.extend_from_slice(b"Legacy data");
+        assert_eq!(strip_legacy_hmac(&payload), b"Legacy data");
+
+        // Without a matching tag there is no prefix to strip
+        payload[0] ^= 1;
+        assert_eq!(strip_legacy_hmac(&payload), &payload[..]);
+
+        // Containers and short data are returned unchanged
+        let container = [&MAGIC[..], &[0; 80]].concat();
+        assert_eq!(strip_legacy_hmac(&container), &container[..]);
+        assert_eq!(strip_legacy_hmac(b"Short"), b"Short");
+    }
+
+    #[test]
+    fn test_pdf_round_trips_raw_data() {
+        let pdf_data = include_bytes!("../tests/fixtures/test.pdf");
+        let mut temp_file = NamedTempFile::new().unwrap();
+        temp_file.write_all(pdf_data).unwrap();
+        let temp_path = temp_file.path().to_str().unwrap().to_string();
+        let output_path = temp_file
+            .path()
+            .with_extension("raw.pdf")
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // Blobs long enough to be mistaken for the legacy prefix come back intact
+        for length in [10, 63, 64, 200] {
+            let data = vec![0x41; length];
+            let mut handler = PdfHandler::new(&temp_path).unwrap();
+            handler.embed_data(&data).unwrap();
+            handler.save(&output_path).unwrap();
+
+            let handler = PdfHandler::new(&output_path).unwrap();
+            assert_eq!(handler.extract_data().unwrap(), data);
+        }
+    }
 }
@@ -0,0 +1,1664 @@
+//! PNG Module
+//!
+//! This module keeps PNG images in their native representation while data is
+//! embedded, so the output has the cover's colour type and bit depth.
+//!
+//! Grayscale and truecolour images, with or without alpha and at 8 or 16 bits
+//! per sample, carry bits in their colour samples: the gray channel of
+//! grayscale images, R, G and B otherwise, and the low byte of 16-bit
+//! samples. Alpha is never touched. Indexed images carry one bit per pixel in
+//! the parity of its palette index, with the palette sorted by luminance (see
+//! [`PaletteOrder`]), and are written back as indexed images with the same
+//! palette, transparency and bit depth. 1, 2 and 4-bit grayscale images carry
+//! bits in their samples at their own depth.
+//!
+//! PNGs are decoded without transformations: a tRNS chunk stays a chunk
+//! rather than becoming an alpha channel, and is written back with the
+//! cover's other ancillary chunks.
+//!
+//! Either way the image is seen as a plane of slots, one byte per carrier
+//! sample, which the LSB, matrix and adaptive embedders work on.
+//!
+//! Payloads can also be kept out of the pixels altogether: [`embed_in_chunk`]
+//! stores them in an ancillary chunk (a private chunk, or a tEXt, zTXt or
+//! iTXt text chunk) and leaves every other chunk of the file as it was.
+
+use crate::adaptive;
+use crate::compression::MAX_DECOMPRESSED_LENGTH;
+use crate::container::MAGIC;
+use crate::embedding::image_carrier_digest;
+use crate::error_correction::calculate_crc32;
+use crate::utils::{detect_file_format_from_bytes, FileFormat, PaletteOrder};
+use crate::{Error, Result};
+use ::png::{BitDepth, ColorType, Decoder, Transformations};
+use base64ct::{Base64, Encoding};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use image::{DynamicImage, GrayImage, ImageBuffer, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The eight bytes every PNG file starts with
+pub const PNG_SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+
+/// Type of the private chunk payloads are stored in
+///
+/// The lowercase first two letters make it ancillary and private, and the
+/// lowercase last letter marks it safe to copy, as it does not depend on the
+/// image data.
+pub const PAYLOAD_CHUNK: [u8; 4] = *b"nhAl";
+
+/// Keyword of the text chunks payloads are stored in
+pub const TEXT_KEYWORD: &str = "Comment";
+
+/// Ancillary chunks that describe the samples and no longer apply once the
+/// colour type or bit depth changes
+const FORMAT_CHUNKS: [[u8; 4]; 4] = [*b"tRNS", *b"sBIT", *b"bKGD", *b"hIST"];
+
+/// Ancillary chunks of animated PNGs, whose frames are not carried over
+const ANIMATION_CHUNKS: [[u8; 4]; 3] = [*b"acTL", *b"fcTL", *b"fdAT"];
+
+/// A PNG image prepared for embedding
+pub enum PngCarrier {
+    /// Palette indices of an indexed image
+    Indexed(Box<IndexedPng>),
+    /// Decoded samples of any other image
+    Samples(DynamicImage),
+    /// Sample values of a 1, 2 or 4-bit grayscale image
+    Gray {
+        image: GrayImage,
+        bit_depth: BitDepth,
+    },
+}
+
+impl PngCarrier {
+    /// Loads an image, keeping indexed PNGs as palette indices
+    ///
+    /// Files that are not PNGs are decoded by content like any other image.
+    pub fn open(path: &str) -> Result<Self> {
+        Self::open_with_metadata(path).map(|(carrier, _)| carrier)
+    }
+
+    /// Loads an image along with the ancillary chunks to write back on saving
+    pub fn open_with_metadata(path: &str) -> Result<(Self, PngMetadata)> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| Error::Io(format!("Failed to read image {}: {}", path, e)))?;
+        if detect_file_format_from_bytes(&bytes) != FileFormat::Png {
+            let image = image::load_from_memory(&bytes)
+                .map_err(|e| Error::InvalidInput(format!("Failed to open image: {}", e)))?;
+            return Ok((
+                PngCarrier::Samples(native_image(image)),
+                PngMetadata::default(),
+            ));
+        }
+
+        let metadata = PngMetadata::from_bytes(&bytes)?;
+        let carrier = match IndexedPng::from_bytes(&bytes)? {
+            Some(indexed) => PngCarrier::Indexed(Box::new(indexed)),
+            None => decode_samples(&bytes)?,
+        };
+        Ok((carrier, metadata))
+    }
+
+    /// Number of bits each slot holds, for images with fewer than eight
+    ///
+    /// Palette indices carry a single bit and low-depth gray samples their
+    /// bit depth; LSB matching does not apply to either.
+    pub fn slot_bits(&self) -> Option<u8> {
+        match self {
+            PngCarrier::Indexed(_) => Some(1),
+            PngCarrier::Samples(_) => None,
+            PngCarrier::Gray { bit_depth, .. } => Some(*bit_depth as u8),
+        }
+    }
+
+    /// Returns the slots that carry embedded bits, one byte per carrier sample
+    pub fn plane(&self) -> Vec<u8> {
+        match self {
+            PngCarrier::Indexed(indexed) => indexed.plane(),
+            PngCarrier::Samples(image) => sample_plane(image),
+            PngCarrier::Gray { image, .. } => image.to_vec(),
+        }
+    }
+
+    /// Writes back a plane returned by [`PngCarrier::plane`]
+    pub fn set_plane(&mut self, plane: &[u8]) {
+        match self {
+            PngCarrier::Indexed(indexed) => indexed.set_plane(plane),
+            PngCarrier::Samples(image) => set_sample_plane(image, plane),
+            PngCarrier::Gray { image, bit_depth } => {
+                let max = ((1u16 << *bit_depth as u8) - 1) as u8;
+                for (sample, &value) in image.iter_mut().zip(plane) {
+                    *sample = value & max;
+                }
+            }
+        }
+    }
+
+    /// Computes the adaptive embedding cost of every slot
+    pub fn slot_costs(&self) -> Vec<f32> {
+        match self {
+            PngCarrier::Indexed(indexed) => indexed.slot_costs(),
+            PngCarrier::Samples(image) => sample_costs(image),
+            PngCarrier::Gray { image, bit_depth } => {
+                // Costs are computed on the samples scaled to eight bits
+                let max = (1u32 << *bit_depth as u8) - 1;
+                let mut scaled = image.clone();
+                for sample in scaled.iter_mut() {
+                    *sample = (*sample as u32 * 255 / max) as u8;
+                }
+                sample_costs(&DynamicImage::ImageLuma8(scaled))
+            }
+        }
+    }
+
+    /// Computes a digest of the image that embedding at `bit_depth` leaves unchanged
+    pub fn carrier_digest(&self, bit_depth: u8) -> [u8; 32] {
+        match self {
+            PngCarrier::Indexed(indexed) => indexed.carrier_digest(),
+            PngCarrier::Samples(image) => image_carrier_digest(image, bit_depth),
+            PngCarrier::Gray { image, .. } => {
+                image_carrier_digest(&DynamicImage::ImageLuma8(image.clone()), bit_depth)
+            }
+        }
+    }
+
+    /// Saves the image in its original representation as a PNG
+    ///
+    /// The ancillary chunks of the cover are written back around the new
+    /// image data.
+    pub fn save(&self, path: &Path, metadata: &PngMetadata, encoding: &PngEncoding) -> Result<()> {
+        let bytes = match self {
+            PngCarrier::Indexed(indexed) => indexed.encode(metadata, encoding)?,
+            PngCarrier::Samples(image) => encode_samples(image, metadata, encoding)?,
+            PngCarrier::Gray { image, bit_depth } => RawPng {
+                width: image.width(),
+                height: image.height(),
+                color_type: ColorType::Grayscale,
+                bit_depth: *bit_depth,
+                data: pack_rows(image, image.width(), *bit_depth),
+                palette: None,
+                trns: None,
+            }
+            .encode(metadata, encoding)?,
+        };
+        std::fs::write(path, bytes).map_err(|e| Error::Io(format!("Failed to save image: {}", e)))
+    }
+}
+
+/// Channels per pixel and carrier channels of the layouts samples are embedded in
+fn layout(image: &DynamicImage) -> Option<(usize, usize)> {
+    match image {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLuma16(_) => Some((1, 1)),
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageLumaA16(_) => Some((2, 1)),
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgb16(_) => Some((3, 3)),
+        DynamicImage::ImageRgba8(_) | DynamicImage::ImageRgba16(_) => Some((4, 3)),
+        _ => None,
+    }
+}
+
+/// Converts images without an embeddable layout, such as floating point ones, to RGBA
+pub fn native_image(image: DynamicImage) -> DynamicImage {
+    match layout(&image) {
+        Some(_) => image,
+        None => DynamicImage::ImageRgba8(image.to_rgba8()),
+    }
+}
+
+/// The carrier samples of a raw buffer, pixel by pixel
+fn carrier_samples<T>(raw: &[T], channels: usize, used: usize) -> impl Iterator<Item = &T> {
+    raw.chunks_exact(channels)
+        .flat_map(move |pixel| &pixel[..used])
+}
+
+fn carrier_samples_mut<T>(
+    raw: &mut [T],
+    channels: usize,
+    used: usize,
+) -> impl Iterator<Item = &mut T> {
+    raw.chunks_exact_mut(channels)
+        .flat_map(move |pixel| &mut pixel[..used])
+}
+
+/// Returns the low byte of every carrier sample of an image
+///
+/// Slot `n` is channel `n % used` of pixel `n / used`, where `used` is 3 for
+/// colour images and 1 for grayscale ones. Images must have been passed
+/// through [`native_image`].
+pub fn sample_plane(image: &DynamicImage) -> Vec<u8> {
+    let (channels, used) = layout(image).expect("image has a native layout");
+    match image {
+        DynamicImage::ImageLuma8(buffer) => {
+            carrier_samples(buffer, channels, used).copied().collect()
+        }
+        DynamicImage::ImageLumaA8(buffer) => {
+            carrier_samples(buffer, channels, used).copied().collect()
+        }
+        DynamicImage::ImageRgb8(buffer) => {
+            carrier_samples(buffer, channels, used).copied().collect()
+        }
+        DynamicImage::ImageRgba8(buffer) => {
+            carrier_samples(buffer, channels, used).copied().collect()
+        }
+        DynamicImage::ImageLuma16(buffer) => carrier_samples(buffer, channels, used)
+            .map(|&sample| sample as u8)
+            .collect(),
+        DynamicImage::ImageLumaA16(buffer) => carrier_samples(buffer, channels, used)
+            .map(|&sample| sample as u8)
+            .collect(),
+        DynamicImage::ImageRgb16(buffer) => carrier_samples(buffer, channels, used)
+            .map(|&sample| sample as u8)
+            .collect(),
+        DynamicImage::ImageRgba16(buffer) => carrier_samples(buffer, channels, used)
+            .map(|&sample| sample as u8)
+            .collect(),
+        _ => unreachable!("layout covers every variant"),
+    }
+}
+
+/// Writes a plane returned by [`sample_plane`] back into the image
+pub fn set_sample_plane(image: &mut DynamicImage, plane: &[u8]) {
+    fn set8(raw: &mut [u8], channels: usize, used: usize, plane: &[u8]) {
+        for (sample, &value) in carrier_samples_mut(raw, channels, used).zip(plane) {
+            *sample = value;
+        }
+    }
+    fn set16(raw: &mut [u16], channels: usize, used: usize, plane: &[u8]) {
+        for (sample, &value) in carrier_samples_mut(raw, channels, used).zip(plane) {
+            *sample = (*sample & 0xFF00) | value as u16;
+        }
+    }
+
+    let (channels, used) = layout(image).expect("image has a native layout");
+    match image {
+        DynamicImage::ImageLuma8(buffer) => set8(buffer, channels, used, plane),
+        DynamicImage::ImageLumaA8(buffer) => set8(buffer, channels, used, plane),
+        DynamicImage::ImageRgb8(buffer) => set8(buffer, channels, used, plane),
+        DynamicImage::ImageRgba8(buffer) => set8(buffer, channels, used, plane),
+        DynamicImage::ImageLuma16(buffer) => set16(buffer, channels, used, plane),
+        DynamicImage::ImageLumaA16(buffer) => set16(buffer, channels, used, plane),
+        DynamicImage::ImageRgb16(buffer) => set16(buffer, channels, used, plane),
+        DynamicImage::ImageRgba16(buffer) => set16(buffer, channels, used, plane),
+        _ => unreachable!("layout covers every variant"),
+    }
+}
+
+/// Computes the adaptive embedding cost of every slot of [`sample_plane`]
+pub fn sample_costs(image: &DynamicImage) -> Vec<f32> {
+    let costs = adaptive::channel_costs(&image.to_rgba8());
+    match layout(image) {
+        Some((_, 1)) => costs.iter().step_by(3).copied().collect(),
+        _ => costs,
+    }
+}
+
+/// Unpacks one value per pixel from rows of single-sample pixels at up to 8 bits
+fn unpack_rows(
+    buffer: &[u8],
+    line_size: usize,
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+) -> Vec<u8> {
+    let depth = bit_depth as usize;
+    let mask = ((1u16 << depth) - 1) as u8;
+    let mut values = Vec::with_capacity(width as usize * height as usize);
+    for row in buffer.chunks(line_size).take(height as usize) {
+        for x in 0..width as usize {
+            let bit = x * depth;
+            values.push((row[bit / 8] >> (8 - depth - bit % 8)) & mask);
+        }
+    }
+    values
+}
+
+/// Packs one value per pixel into rows at up to 8 bits per pixel
+fn pack_rows(values: &[u8], width: u32, bit_depth: BitDepth) -> Vec<u8> {
+    let depth = bit_depth as usize;
+    let line_size = (width as usize * depth).div_ceil(8);
+    let mut data = vec![0u8; line_size * values.len().div_ceil(width as usize)];
+    for (row, values) in data
+        .chunks_mut(line_size)
+        .zip(values.chunks(width as usize))
+    {
+        for (x, &value) in values.iter().enumerate() {
+            let bit = x * depth;
+            row[bit / 8] |= value << (8 - depth - bit % 8);
+        }
+    }
+    data
+}
+
+/// Decodes a grayscale or truecolour PNG without expanding its samples
+///
+/// tRNS stays a chunk instead of becoming an alpha channel, and 1, 2 and
+/// 4-bit grayscale keeps its sample values.
+fn decode_samples(bytes: &[u8]) -> Result<PngCarrier> {
+    let decode_error =
+        |e: ::png::DecodingError| Error::InvalidInput(format!("Failed to decode PNG: {}", e));
+
+    let mut decoder = Decoder::new(bytes);
+    decoder.set_transformations(Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(decode_error)?;
+    let info = reader.info();
+    let (width, height, color_type, bit_depth) =
+        (info.width, info.height, info.color_type, info.bit_depth);
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).map_err(decode_error)?;
+
+    if color_type == ColorType::Grayscale && (bit_depth as u8) < 8 {
+        let samples = unpack_rows(&buffer, frame.line_size, width, height, bit_depth);
+        let image = GrayImage::from_raw(width, height, samples)
+            .ok_or_else(|| Error::InvalidInput("PNG image data is truncated".into()))?;
+        return Ok(PngCarrier::Gray { image, bit_depth });
+    }
+
+    let row_length = width as usize * color_type.samples() * (bit_depth as usize / 8);
+    let data: Vec<u8> = buffer
+        .chunks(frame.line_size)
+        .take(height as usize)
+        .flat_map(|row| &row[..row_length])
+        .copied()
+        .collect();
+    let wide: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let image = match (color_type, bit_depth) {
+        (ColorType::Grayscale, BitDepth::Eight) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayscaleAlpha, BitDepth::Eight) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::Rgb, BitDepth::Eight) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::Rgba, BitDepth::Eight) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Grayscale, BitDepth::Sixteen) => {
+            ImageBuffer::from_raw(width, height, wide).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayscaleAlpha, BitDepth::Sixteen) => {
+            ImageBuffer::from_raw(width, height, wide).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::Rgb, BitDepth::Sixteen) => {
+            ImageBuffer::from_raw(width, height, wide).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::Rgba, BitDepth::Sixteen) => {
+            ImageBuffer::from_raw(width, height, wide).map(DynamicImage::ImageRgba16)
+        }
+        _ => {
+            return Err(Error::InvalidInput(format!(
+                "Unsupported PNG format: {:?} at {} bits",
+                color_type, bit_depth as u8
+            )))
+        }
+    };
+    image
+        .map(PngCarrier::Samples)
+        .ok_or_else(|| Error::InvalidInput("PNG image data is truncated".into()))
+}
+
+/// An indexed PNG held as one palette index per pixel
+pub struct IndexedPng {
+    width: u32,
+    height: u32,
+    bit_depth: BitDepth,
+    palette: Vec<u8>,
+    trns: Option<Vec<u8>>,
+    indices: Vec<u8>,
+    order: PaletteOrder,
+    /// Pixels whose index can carry a bit
+    slots: Vec<usize>,
+}
+
+impl IndexedPng {
+    /// Decodes a PNG, returning `None` if it is not indexed
+    pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        let decode_error =
+            |e: ::png::DecodingError| Error::InvalidInput(format!("Failed to decode PNG: {}", e));
+
+        let mut decoder = Decoder::new(bytes);
+        decoder.set_transformations(Transformations::IDENTITY);
+        let mut reader = decoder.read_info().map_err(decode_error)?;
+        let info = reader.info();
+        if info.color_type != ColorType::Indexed {
+            return Ok(None);
+        }
+        let (width, height, bit_depth) = (info.width, info.height, info.bit_depth);
+        let palette = info
+            .palette
+            .as_ref()
+            .ok_or_else(|| Error::InvalidInput("Indexed PNG has no palette".into()))?
+            .to_vec();
+        let trns = info.trns.as_ref().map(|trns| trns.to_vec());
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer).map_err(decode_error)?;
+
+        let indices = unpack_rows(&buffer, frame.line_size, width, height, bit_depth);
+
+        // Colours that are not fully opaque are left alone
+        let order = PaletteOrder::new(&palette, |index| {
+            trns.as_ref()
+                .and_then(|trns| trns.get(index as usize))
+                .is_some_and(|&alpha| alpha != u8::MAX)
+        });
+        let slots = (0..indices.len())
+            .filter(|&pixel| order.rank(indices[pixel]).is_some())
+            .collect();
+
+        Ok(Some(Self {
+            width,
+            height,
+            bit_depth,
+            palette,
+            trns,
+            indices,
+            order,
+            slots,
+        }))
+    }
+
+    /// Returns the image width and height in pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Returns the number of bits per index (1, 2, 4 or 8)
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth as u8
+    }
+
+    /// Returns the palette as RGB triples
+    pub fn palette(&self) -> &[u8] {
+        &self.palette
+    }
+
+    /// Returns the palette index of every pixel
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    /// Returns the luminance rank of every usable pixel's index
+    ///
+    /// The lowest bit of a rank is the bit the pixel carries.
+    pub fn plane(&self) -> Vec<u8> {
+        self.slots
+            .iter()
+            .map(|&pixel| self.order.rank(self.indices[pixel]).unwrap_or(0) as u8)
+            .collect()
+    }
+
+    /// Writes back a plane of ranks returned by [`IndexedPng::plane`]
+    ///
+    /// Only the lowest bit of each rank may have changed.
+    pub fn set_plane(&mut self, plane: &[u8]) {
+        for (&pixel, &rank) in self.slots.iter().zip(plane) {
+            self.indices[pixel] = self.order.index(rank as usize);
+        }
+    }
+
+    /// Expands the indices to RGBA colours
+    pub fn to_rgba8(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let index = self.indices[(y * self.width + x) as usize] as usize;
+            let colour = self
+                .palette
+                .get(index * 3..index * 3 + 3)
+                .unwrap_or(&[0; 3]);
+            let alpha = self
+                .trns
+                .as_ref()
+                .and_then(|trns| trns.get(index))
+                .copied()
+                .unwrap_or(u8::MAX);
+            image::Rgba([colour[0], colour[1], colour[2], alpha])
+        })
+    }
+
+    /// Adaptive embedding cost of every slot, from the costs of its colour channels
+    fn slot_costs(&self) -> Vec<f32> {
+        let costs = adaptive::channel_costs(&self.to_rgba8());
+        self.slots
+            .iter()
+            .map(|&pixel| costs[pixel * 3..pixel * 3 + 3].iter().sum::<f32>() / 3.0)
+            .collect()
+    }
+
+    /// Computes a digest of the image that embedding leaves unchanged
+    ///
+    /// The palette and transparency are hashed along with the pixels, each
+    /// usable pixel standing for its colour pair.
+    pub fn carrier_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.width.to_be_bytes());
+        hasher.update(self.height.to_be_bytes());
+        hasher.update([self.bit_depth as u8]);
+        hasher.update(&self.palette);
+        if let Some(trns) = &self.trns {
+            hasher.update(trns);
+        }
+        let pairs: Vec<u8> = self
+            .indices
+            .iter()
+            .map(|&index| self.order.pair(index))
+            .collect();
+        hasher.update(&pairs);
+        hasher.finalize().into()
+    }
+
+    /// Encodes the image as an indexed PNG
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.encode(&PngMetadata::default(), &PngEncoding::default())
+    }
+
+    /// Encodes the image as an indexed PNG with the ancillary chunks of a cover
+    pub fn encode(&self, metadata: &PngMetadata, encoding: &PngEncoding) -> Result<Vec<u8>> {
+        RawPng {
+            width: self.width,
+            height: self.height,
+            color_type: ColorType::Indexed,
+            bit_depth: self.bit_depth,
+            data: pack_rows(&self.indices, self.width, self.bit_depth),
+            palette: Some(&self.palette),
+            trns: self.trns.as_deref(),
+        }
+        .encode(metadata, encoding)
+    }
+}
+
+/// The chunk a payload is stored in with `method=chunk`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadChunk {
+    /// A private ancillary chunk holding the payload as it is
+    Private,
+    /// A tEXt chunk holding the payload in Base64
+    Text,
+    /// A zTXt chunk holding the payload in compressed Base64
+    CompressedText,
+    /// An iTXt chunk holding the payload in compressed Base64
+    InternationalText,
+}
+
+impl PayloadChunk {
+    /// Reads the `chunk` parameter (`private`, `text`, `ztxt` or `itxt`),
+    /// defaulting to a private chunk
+    pub fn from_parameters(
+        parameters: Option<&std::collections::HashMap<String, String>>,
+    ) -> Result<Self> {
+        match parameters.and_then(|p| p.get("chunk")).map(|v| v.as_str()) {
+            None | Some("private") => Ok(PayloadChunk::Private),
+            Some("text") => Ok(PayloadChunk::Text),
+            Some("ztxt") => Ok(PayloadChunk::CompressedText),
+            Some("itxt") => Ok(PayloadChunk::InternationalText),
+            Some(other) => Err(Error::InvalidInput(format!(
+                "Unknown payload chunk '{}', expected 'private', 'text', 'ztxt' or 'itxt'",
+                other
+            ))),
+        }
+    }
+}
+
+/// A chunk of a PNG file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PngChunk {
+    /// Four-letter chunk type, such as `IDAT` or `tEXt`
+    pub kind: [u8; 4],
+    /// Offset of the chunk's length field in the file
+    pub offset: usize,
+    /// Chunk data, without the length, type and CRC
+    pub data: Vec<u8>,
+    /// Whether the stored CRC matches the type and data
+    pub crc_valid: bool,
+}
+
+impl PngChunk {
+    /// Returns the chunk type as text
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.kind).into_owned()
+    }
+
+    /// Whether decoders must understand the chunk to display the image
+    pub fn is_critical(&self) -> bool {
+        self.kind[0] & 0x20 == 0
+    }
+
+    /// Whether the chunk type is private rather than defined by the PNG specification
+    pub fn is_private(&self) -> bool {
+        self.kind[1] & 0x20 != 0
+    }
+
+    /// Whether editors that change the image data may copy the chunk unchanged
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.kind[3] & 0x20 != 0
+    }
+
+    /// Byte range of the whole chunk, including its length, type and CRC
+    fn range(&self) -> std::ops::Range<usize> {
+        self.offset..self.offset + self.data.len() + 12
+    }
+}
+
+/// Splits a PNG file into its chunks, up to and including IEND
+///
+/// CRCs are checked but a mismatch is only recorded in the chunk; bytes
+/// after IEND are ignored.
+pub fn read_chunks(bytes: &[u8]) -> Result<Vec<PngChunk>> {
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        return Err(Error::InvalidInput("Not a PNG file".into()));
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    loop {
+        let header = bytes
+            .get(offset..offset + 8)
+            .ok_or_else(|| Error::InvalidInput("PNG file ends without an IEND chunk".into()))?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let end = (offset + 12)
+            .checked_add(length)
+            .filter(|&end| end <= bytes.len())
+            .ok_or_else(|| {
+                Error::InvalidInput(format!(
+                    "Truncated {} chunk in PNG",
+                    String::from_utf8_lossy(&kind)
+                ))
+            })?;
+
+        let crc = u32::from_be_bytes([
+            bytes[end - 4],
+            bytes[end - 3],
+            bytes[end - 2],
+            bytes[end - 1],
+        ]);
+        chunks.push(PngChunk {
+            kind,
+            offset,
+            data: bytes[offset + 8..end - 4].to_vec(),
+            crc_valid: calculate_crc32(&bytes[offset + 4..end - 4]) == crc,
+        });
+
+        offset = end;
+        if &kind == b"IEND" {
+            return Ok(chunks);
+        }
+    }
+}
+
+/// Appends a chunk with its length and CRC to `output`
+pub fn write_chunk(output: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    let start = output.len();
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(&kind);
+    output.extend_from_slice(data);
+    let crc = calculate_crc32(&output[start + 4..]);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Lists the ancillary (non-critical) chunks of a PNG file, in file order
+pub fn ancillary_chunks(path: &str) -> Result<Vec<PngChunk>> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| Error::Io(format!("Failed to read PNG {}: {}", path, e)))?;
+    Ok(read_chunks(&bytes)?
+        .into_iter()
+        .filter(|chunk| !chunk.is_critical())
+        .collect())
+}
+
+fn zlib_compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| Error::Encoding(format!("Failed to compress PNG text: {}", e)))
+}
+
+fn zlib_decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_DECOMPRESSED_LENGTH as u64)
+        .read_to_end(&mut output)
+        .ok()?;
+    Some(output)
+}
+
+/// Builds the chunk that stores a payload
+fn payload_chunk(payload: &[u8], chunk: PayloadChunk) -> Result<([u8; 4], Vec<u8>)> {
+    let text = Base64::encode_string(payload);
+    let mut data = TEXT_KEYWORD.as_bytes().to_vec();
+    data.push(0);
+    Ok(match chunk {
+        PayloadChunk::Private => (PAYLOAD_CHUNK, payload.to_vec()),
+        PayloadChunk::Text => {
+            data.extend_from_slice(text.as_bytes());
+            (*b"tEXt", data)
+        }
+        PayloadChunk::CompressedText => {
+            // Compression method 0 (zlib)
+            data.push(0);
+            data.extend_from_slice(&zlib_compress(text.as_bytes())?);
+            (*b"zTXt", data)
+        }
+        PayloadChunk::InternationalText => {
+            // Compressed with method 0, then empty language tag and translated keyword
+            data.extend_from_slice(&[1, 0, 0, 0]);
+            data.extend_from_slice(&zlib_compress(text.as_bytes())?);
+            (*b"iTXt", data)
+        }
+    })
+}
+
+/// Returns the payload a chunk stores, if it holds one
+fn chunk_payload(chunk: &PngChunk) -> Option<Vec<u8>> {
+    let text = match &chunk.kind {
+        kind if *kind == PAYLOAD_CHUNK => {
+            return chunk.data.starts_with(&MAGIC).then(|| chunk.data.clone())
+        }
+        b"tEXt" | b"zTXt" | b"iTXt" => {
+            let keyword_end = chunk.data.iter().position(|&b| b == 0)?;
+            let rest = &chunk.data[keyword_end + 1..];
+            match &chunk.kind {
+                b"tEXt" => rest.to_vec(),
+                b"zTXt" => zlib_decompress(rest.get(1..)?)?,
+                _ => {
+                    let (&compressed, rest) = rest.split_first()?;
+                    let rest = rest.get(1..)?;
+                    let language_end = rest.iter().position(|&b| b == 0)?;
+                    let rest = &rest[language_end + 1..];
+                    let translated_end = rest.iter().position(|&b| b == 0)?;
+                    let text = &rest[translated_end + 1..];
+                    if compressed == 1 {
+                        zlib_decompress(text)?
+                    } else {
+                        text.to_vec()
+                    }
+                }
+            }
+        }
+        _ => return None,
+    };
+
+    let payload = Base64::decode_vec(std::str::from_utf8(&text).ok()?).ok()?;
+    payload.starts_with(&MAGIC).then_some(payload)
+}
+
+/// Stores a payload in an ancillary chunk of a PNG file
+///
+/// The chunk goes just before IEND. Any payload chunk already in the file is
+/// replaced; all other chunks, and so the pixels, are kept byte for byte.
+pub fn embed_in_chunk(bytes: &[u8], payload: &[u8], chunk: PayloadChunk) -> Result<Vec<u8>> {
+    let chunks = read_chunks(bytes)?;
+    let (kind, data) = payload_chunk(payload, chunk)?;
+
+    let mut output = PNG_SIGNATURE.to_vec();
+    for existing in &chunks {
+        if &existing.kind == b"IEND" {
+            write_chunk(&mut output, kind, &data);
+            output.extend_from_slice(&bytes[existing.offset..]);
+        } else if chunk_payload(existing).is_none() {
+            output.extend_from_slice(&bytes[existing.range()]);
+        }
+    }
+    Ok(output)
+}
+
+/// Reads a payload stored by [`embed_in_chunk`]
+pub fn extract_from_chunks(bytes: &[u8]) -> Result<Vec<u8>> {
+    read_chunks(bytes)?
+        .iter()
+        .filter(|chunk| chunk.crc_valid)
+        .find_map(chunk_payload)
+        .ok_or_else(|| Error::Extraction("No payload chunk found in PNG".into()))
+}
+
+/// Computes a digest of a PNG file that storing a payload chunk leaves unchanged
+///
+/// Every byte of the file except its payload chunks is hashed.
+pub fn chunk_carrier_digest(bytes: &[u8]) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut offset = 0;
+    for chunk in read_chunks(bytes)? {
+        if chunk_payload(&chunk).is_some() {
+            hasher.update(&bytes[offset..chunk.offset]);
+            offset = chunk.range().end;
+        }
+    }
+    hasher.update(&bytes[offset..]);
+    Ok(hasher.finalize().into())
+}
+
+/// Where an ancillary chunk sits relative to the critical chunks
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkPosition {
+    BeforePalette,
+    BeforeImageData,
+    AfterImageData,
+}
+
+/// The parts of a cover PNG that are written back around new image data
+#[derive(Debug, Clone, Default)]
+pub struct PngMetadata {
+    /// Ancillary chunks in file order
+    chunks: Vec<(ChunkPosition, PngChunk)>,
+    /// Colour type and bit depth from IHDR
+    format: Option<(u8, u8)>,
+    /// Suggested palette of a truecolour image
+    palette: Option<Vec<u8>>,
+    /// Compression level implied by the zlib header of the image data
+    zlib_level: Option<u32>,
+    /// Size the image data was split into IDAT chunks at, if it was split
+    idat_size: Option<usize>,
+    /// Whether the image data is Adam7 interlaced
+    interlaced: bool,
+}
+
+impl PngMetadata {
+    /// Collects the ancillary chunks and encoding settings of a PNG file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut metadata = PngMetadata::default();
+        let mut position = ChunkPosition::BeforePalette;
+        let mut idat_chunks = 0;
+        for chunk in read_chunks(bytes)? {
+            match &chunk.kind {
+                b"IHDR" if chunk.data.len() >= 13 => {
+                    metadata.format = Some((chunk.data[9], chunk.data[8]));
+                    metadata.interlaced = chunk.data[12] == 1;
+                }
+                b"PLTE" => {
+                    metadata.palette = Some(chunk.data.clone());
+                    position = ChunkPosition::BeforeImageData;
+                }
+                b"IDAT" => {
+                    if idat_chunks == 0 {
+                        // FLEVEL: fastest, fast, default or maximum compression
+                        metadata.zlib_level = chunk
+                            .data
+                            .get(1)
+                            .map(|flags| [1, 5, 6, 9][(flags >> 6) as usize]);
+                        metadata.idat_size = Some(chunk.data.len());
+                    }
+                    idat_chunks += 1;
+                    position = ChunkPosition::AfterImageData;
+                }
+                kind if !chunk.is_critical() && !ANIMATION_CHUNKS.contains(kind) => {
+                    metadata.chunks.push((position, chunk))
+                }
+                _ => {}
+            }
+        }
+        if idat_chunks < 2 {
+            metadata.idat_size = None;
+        }
+        Ok(metadata)
+    }
+
+    /// Returns the ancillary chunks in file order
+    pub fn chunks(&self) -> impl Iterator<Item = &PngChunk> {
+        self.chunks.iter().map(|(_, chunk)| chunk)
+    }
+}
+
+/// Filter applied to each row of image data before compression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PngFilter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+    /// Picks the filter per row that leaves the smallest residuals
+    Adaptive = 5,
+}
+
+/// How the image data of a saved PNG is compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PngEncoding {
+    /// zlib level (0-9); by default the level the cover was compressed at, or 6
+    pub zlib_level: Option<u32>,
+    /// Row filter; by default none for indexed and sub-byte images and
+    /// adaptive otherwise, as libpng does
+    pub filter: Option<PngFilter>,
+}
+
+impl PngEncoding {
+    /// Reads the `zlib_level` (0-9) and `filter` (`none`, `sub`, `up`,
+    /// `average`, `paeth` or `adaptive`) parameters
+    pub fn from_parameters(
+        parameters: Option<&std::collections::HashMap<String, String>>,
+    ) -> Result<Self> {
+        let parameter = |name: &str| parameters.and_then(|p| p.get(name)).map(|v| v.as_str());
+        let zlib_level = match parameter("zlib_level") {
+            None => None,
+            Some(value) => Some(
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|level| *level <= 9)
+                    .ok_or_else(|| {
+                        Error::InvalidInput(format!(
+                            "zlib level must be between 0 and 9, got {}",
+                            value
+                        ))
+                    })?,
+            ),
+        };
+        let filter = match parameter("filter") {
+            None => None,
+            Some("none") => Some(PngFilter::None),
+            Some("sub") => Some(PngFilter::Sub),
+            Some("up") => Some(PngFilter::Up),
+            Some("average") => Some(PngFilter::Average),
+            Some("paeth") => Some(PngFilter::Paeth),
+            Some("adaptive") => Some(PngFilter::Adaptive),
+            Some(other) => {
+                return Err(Error::InvalidInput(format!(
+                    "Unknown PNG filter '{}', expected 'none', 'sub', 'up', 'average', 'paeth' or 'adaptive'",
+                    other
+                )))
+            }
+        };
+        Ok(Self { zlib_level, filter })
+    }
+}
+
+/// Unfiltered image data ready to be written as a PNG
+struct RawPng<'a> {
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    /// Packed rows without filter bytes
+    data: Vec<u8>,
+    palette: Option<&'a [u8]>,
+    trns: Option<&'a [u8]>,
+}
+
+impl RawPng<'_> {
+    /// Writes the PNG, placing the cover's ancillary chunks where they were
+    fn encode(&self, metadata: &PngMetadata, encoding: &PngEncoding) -> Result<Vec<u8>> {
+        let bits_per_pixel = self.color_type.samples() * self.bit_depth as usize;
+        let line_size = (self.width as usize * bits_per_pixel).div_ceil(8);
+        let filter = encoding.filter.unwrap_or(
+            if self.color_type == ColorType::Indexed || (self.bit_depth as u8) < 8 {
+                PngFilter::None
+            } else {
+                PngFilter::Adaptive
+            },
+        );
+        let pixel_size = bits_per_pixel.div_ceil(8);
+        let filtered = if metadata.interlaced {
+            // Each pass is filtered as an image of its own
+            self.adam7_passes(line_size, bits_per_pixel)
+                .iter()
+                .flat_map(|(pass, pass_line_size)| {
+                    filter_rows(pass, *pass_line_size, pixel_size, filter)
+                })
+                .collect()
+        } else {
+            filter_rows(&self.data, line_size, pixel_size, filter)
+        };
+
+        let level = encoding.zlib_level.or(metadata.zlib_level).unwrap_or(6);
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+        let image_data = encoder
+            .write_all(&filtered)
+            .and_then(|_| encoder.finish())
+            .map_err(|e| Error::Encoding(format!("Failed to compress PNG data: {}", e)))?;
+
+        // Chunks that describe the samples only carry over to the same format
+        let same_format = metadata.format == Some((self.color_type as u8, self.bit_depth as u8));
+        let kept: Vec<&(ChunkPosition, PngChunk)> = metadata
+            .chunks
+            .iter()
+            .filter(|(_, chunk)| same_format || !FORMAT_CHUNKS.contains(&chunk.kind))
+            .collect();
+        let write_kept = |output: &mut Vec<u8>, position: ChunkPosition| {
+            for (_, chunk) in kept.iter().filter(|(p, _)| *p == position) {
+                write_chunk(output, chunk.kind, &chunk.data);
+            }
+        };
+
+        let mut output = PNG_SIGNATURE.to_vec();
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        header.extend_from_slice(&[
+            self.bit_depth as u8,
+            self.color_type as u8,
+            0,
+            0,
+            metadata.interlaced as u8,
+        ]);
+        write_chunk(&mut output, *b"IHDR", &header);
+
+        write_kept(&mut output, ChunkPosition::BeforePalette);
+        let palette = self
+            .palette
+            .or(metadata.palette.as_deref().filter(|_| same_format));
+        if let Some(palette) = palette {
+            write_chunk(&mut output, *b"PLTE", palette);
+        }
+        write_kept(&mut output, ChunkPosition::BeforeImageData);
+        if let Some(trns) = self.trns {
+            if !kept.iter().any(|(_, chunk)| &chunk.kind == b"tRNS") {
+                write_chunk(&mut output, *b"tRNS", trns);
+            }
+        }
+
+        let idat_size = metadata.idat_size.unwrap_or(image_data.len()).max(1);
+        for data in image_data.chunks(idat_size) {
+            write_chunk(&mut output, *b"IDAT", data);
+        }
+        write_kept(&mut output, ChunkPosition::AfterImageData);
+        write_chunk(&mut output, *b"IEND", &[]);
+        Ok(output)
+    }
+
+    /// Splits the image into its Adam7 passes, with the line size of each
+    fn adam7_passes(&self, line_size: usize, bits_per_pixel: usize) -> Vec<(Vec<u8>, usize)> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let values = (bits_per_pixel < 8).then(|| {
+            unpack_rows(
+                &self.data,
+                line_size,
+                self.width,
+                self.height,
+                self.bit_depth,
+            )
+        });
+        let pixel_size = bits_per_pixel / 8;
+
+        let mut passes = Vec::with_capacity(ADAM7.len());
+        for (x0, y0, dx, dy) in ADAM7 {
+            let pass_width = width.saturating_sub(x0).div_ceil(dx);
+            let pass_height = height.saturating_sub(y0).div_ceil(dy);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+            let pixels = (y0..height)
+                .step_by(dy)
+                .flat_map(|y| (x0..width).step_by(dx).map(move |x| (x, y)));
+            let pass = match &values {
+                Some(values) => pack_rows(
+                    &pixels
+                        .map(|(x, y)| values[y * width + x])
+                        .collect::<Vec<_>>(),
+                    pass_width as u32,
+                    self.bit_depth,
+                ),
+                None => pixels
+                    .flat_map(|(x, y)| {
+                        let start = y * line_size + x * pixel_size;
+                        &self.data[start..start + pixel_size]
+                    })
+                    .copied()
+                    .collect(),
+            };
+            passes.push((pass, (pass_width * bits_per_pixel).div_ceil(8)));
+        }
+        passes
+    }
+}
+
+/// Adam7 passes as starting column, starting row, column step and row step
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Prefixes each row with a filter byte and filters it against the row above
+fn filter_rows(data: &[u8], line_size: usize, pixel_size: usize, filter: PngFilter) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / line_size.max(1) + 1);
+    let mut previous = vec![0; line_size];
+    let mut candidate = Vec::with_capacity(line_size);
+    for row in data.chunks(line_size) {
+        let kind = match filter {
+            PngFilter::Adaptive => (0..5)
+                .min_by_key(|&kind| {
+                    candidate.clear();
+                    filter_row(kind, row, &previous, pixel_size, &mut candidate);
+                    candidate
+                        .iter()
+                        .map(|&b| (b as i8).unsigned_abs() as u64)
+                        .sum::<u64>()
+                })
+                .unwrap_or(0),
+            fixed => fixed as u8,
+        };
+        output.push(kind);
+        filter_row(kind, row, &previous, pixel_size, &mut output);
+        previous.copy_from_slice(row);
+    }
+    output
+}
+
+/// Appends a row filtered with one of the five PNG filter types
+fn filter_row(kind: u8, row: &[u8], previous: &[u8], pixel_size: usize, output: &mut Vec<u8>) {
+    for (i, &value) in row.iter().enumerate() {
+        let left = if i >= pixel_size {
+            row[i - pixel_size]
+        } else {
+            0
+        };
+        let up = previous[i];
+        let up_left = if i >= pixel_size {
+            previous[i - pixel_size]
+        } else {
+            0
+        };
+        let prediction = match kind {
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            4 => paeth(left, up, up_left),
+            _ => 0,
+        };
+        output.push(value.wrapping_sub(prediction));
+    }
+}
+
+/// The Paeth predictor: whichever neighbour is closest to `left + up - up_left`
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (a, b, c) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+    if a <= b && a <= c {
+        left
+    } else if b <= c {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Encodes an image in its native colour type and bit depth, with the
+/// ancillary chunks and interlacing of a cover
+pub fn encode_samples(
+    image: &DynamicImage,
+    metadata: &PngMetadata,
+    encoding: &PngEncoding,
+) -> Result<Vec<u8>> {
+    let converted;
+    let image = match layout(image) {
+        Some(_) => image,
+        None => {
+            converted = DynamicImage::ImageRgba8(image.to_rgba8());
+            &converted
+        }
+    };
+    let big_endian = |samples: &[u16]| -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect()
+    };
+    let (color_type, bit_depth, data) = match image {
+        DynamicImage::ImageLuma8(i) => (ColorType::Grayscale, BitDepth::Eight, i.to_vec()),
+        DynamicImage::ImageLumaA8(i) => (ColorType::GrayscaleAlpha, BitDepth::Eight, i.to_vec()),
+        DynamicImage::ImageRgb8(i) => (ColorType::Rgb, BitDepth::Eight, i.to_vec()),
+        DynamicImage::ImageRgba8(i) => (ColorType::Rgba, BitDepth::Eight, i.to_vec()),
+        DynamicImage::ImageLuma16(i) => (ColorType::Grayscale, BitDepth::Sixteen, big_endian(i)),
+        DynamicImage::ImageLumaA16(i) => {
+            (ColorType::GrayscaleAlpha, BitDepth::Sixteen, big_endian(i))
+        }
+        DynamicImage::ImageRgb16(i) => (ColorType::Rgb, BitDepth::Sixteen, big_endian(i)),
+        DynamicImage::ImageRgba16(i) => (ColorType::Rgba, BitDepth::Sixteen, big_endian(i)),
+        _ => unreachable!("every layout has a PNG colour type"),
+    };
+
+    RawPng {
+        width: image.width(),
+        height: image.height(),
+        color_type,
+        bit_depth,
+        data,
+        palette: None,
+        trns: None,
+    }
+    .encode(metadata, encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::png::Encoder;
+
+    /// Encodes an indexed PNG with a shuffled gray palette and a transparent colour
+    fn indexed_png(width: u32, height: u32, bit_depth: BitDepth) -> Vec<u8> {
+        let colours = 1usize << bit_depth as usize;
+        let palette: Vec<u8> = (0..colours)
+            .flat_map(|i| {
+                let level = ((i * 37) % colours * 255 / (colours - 1)) as u8;
+                [level, level, level]
+            })
+            .collect();
+        let mut trns = vec![u8::MAX; colours];
+        trns[1] = 0;
+
+        let indexed = IndexedPng {
+            width,
+            height,
+            bit_depth,
+            order: PaletteOrder::new(&palette, |_| false),
+            palette,
+            trns: Some(trns),
+            indices: (0..width * height)
+                .map(|i| ((i * 7 + i / width) % colours as u32) as u8)
+                .collect(),
+            slots: Vec::new(),
+        };
+        indexed.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_indexed_png_round_trip() {
+        for bit_depth in [BitDepth::Two, BitDepth::Four, BitDepth::Eight] {
+            // Odd widths leave partial bytes at the end of each row
+            let bytes = indexed_png(37, 11, bit_depth);
+            let mut indexed = IndexedPng::from_bytes(&bytes).unwrap().unwrap();
+            assert_eq!(indexed.dimensions(), (37, 11));
+            assert_eq!(indexed.bit_depth(), bit_depth as u8);
+
+            // The transparent colour carries nothing
+            assert!(indexed.slots.len() < 37 * 11);
+            assert!(indexed
+                .slots
+                .iter()
+                .all(|&pixel| indexed.indices[pixel] != 1));
+
+            // Flipping every rank's parity changes each usable pixel to its pair
+            let cover = indexed.indices.clone();
+            let digest = indexed.carrier_digest();
+            let plane: Vec<u8> = indexed.plane().iter().map(|rank| rank ^ 1).collect();
+            indexed.set_plane(&plane);
+
+            let reread = IndexedPng::from_bytes(&indexed.to_bytes().unwrap())
+                .unwrap()
+                .unwrap();
+            assert_eq!(reread.plane(), plane);
+            assert_eq!(reread.palette(), indexed.palette());
+            assert_eq!(reread.carrier_digest(), digest);
+            for (pixel, (&before, &after)) in cover.iter().zip(reread.indices()).enumerate() {
+                let moved = indexed.slots.contains(&pixel);
+                assert_eq!(before != after, moved, "pixel {}", pixel);
+            }
+        }
+
+        // Other colour types are not indexed
+        let mut gray = Vec::new();
+        image::DynamicImage::ImageLuma8(image::GrayImage::new(4, 4))
+            .write_to(
+                &mut std::io::Cursor::new(&mut gray),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+        assert!(IndexedPng::from_bytes(&gray).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sample_plane_layouts() {
+        let gray_alpha = DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(3, 2, |x, y| {
+            image::LumaA([(x + y * 3) as u8, 200])
+        }));
+        assert_eq!(sample_plane(&gray_alpha), vec![0, 1, 2, 3, 4, 5]);
+
+        let mut deep = DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(2, 1, |x, _| {
+            image::Rgba([0x1234 + x as u16, 0xABCD, 0xFF00, 0x8000])
+        }));
+        let plane = sample_plane(&deep);
+        assert_eq!(plane, vec![0x34, 0xCD, 0x00, 0x35, 0xCD, 0x00]);
+
+        // Only the low byte of colour samples is written
+        set_sample_plane(&mut deep, &[1, 2, 3, 4, 5, 6]);
+        let buffer = deep.as_rgba16().unwrap();
+        assert_eq!(buffer.get_pixel(0, 0).0, [0x1201, 0xAB02, 0xFF03, 0x8000]);
+        assert_eq!(buffer.get_pixel(1, 0).0, [0x1204, 0xAB05, 0xFF06, 0x8000]);
+        assert_eq!(sample_costs(&gray_alpha).len(), 6);
+    }
+
+    /// Encodes an RGB PNG with a gradient and a text chunk of its own
+    fn png_with_text(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::new(&mut bytes, width, height);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        encoder
+            .add_text_chunk("Title".to_string(), "Cover".to_string())
+            .unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        let pixels: Vec<u8> = (0..width * height * 3).map(|i| (i * 5) as u8).collect();
+        writer.write_image_data(&pixels).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_chunk_payload_round_trip() {
+        let cover = png_with_text(19, 13);
+        let cover_pixels = image::load_from_memory(&cover).unwrap();
+        let digest = chunk_carrier_digest(&cover).unwrap();
+        let payload = [&MAGIC[..], b"Kept out of the pixels"].concat();
+
+        for chunk in [
+            PayloadChunk::Private,
+            PayloadChunk::Text,
+            PayloadChunk::CompressedText,
+            PayloadChunk::InternationalText,
+        ] {
+            let stego = embed_in_chunk(&cover, &payload, chunk).unwrap();
+            assert_eq!(extract_from_chunks(&stego).unwrap(), payload);
+            assert_eq!(chunk_carrier_digest(&stego).unwrap(), digest);
+
+            // Every chunk has a valid CRC, and the payload sits just before IEND
+            let chunks = read_chunks(&stego).unwrap();
+            assert!(chunks.iter().all(|c| c.crc_valid));
+            assert_eq!(chunks.len(), read_chunks(&cover).unwrap().len() + 1);
+            assert_eq!(&chunks[chunks.len() - 1].kind, b"IEND");
+            assert!(chunk_payload(&chunks[chunks.len() - 2]).is_some());
+
+            // The image decodes to the same pixels
+            let pixels = image::load_from_memory(&stego).unwrap();
+            assert_eq!(pixels.as_bytes(), cover_pixels.as_bytes());
+
+            // Embedding again replaces the payload rather than adding another
+            let replaced =
+                embed_in_chunk(&stego, &[&MAGIC[..], b"Second"].concat(), chunk).unwrap();
+            assert_eq!(read_chunks(&replaced).unwrap().len(), chunks.len());
+            assert_eq!(
+                extract_from_chunks(&replaced).unwrap(),
+                [&MAGIC[..], b"Second"].concat()
+            );
+        }
+
+        assert!(matches!(
+            extract_from_chunks(&cover),
+            Err(Error::Extraction(_))
+        ));
+        assert!(read_chunks(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_ancillary_chunk_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = [&MAGIC[..], b"Listed"].concat();
+        let stego = embed_in_chunk(&png_with_text(8, 8), &payload, PayloadChunk::Private).unwrap();
+        let path = dir.path().join("stego.png");
+        std::fs::write(&path, &stego).unwrap();
+
+        let chunks = ancillary_chunks(&path.to_string_lossy()).unwrap();
+        let names: Vec<String> = chunks.iter().map(PngChunk::name).collect();
+        assert_eq!(names, ["tEXt", "nhAl"]);
+        assert!(!chunks[0].is_private() && chunks[1].is_private());
+        assert!(chunks[1].is_safe_to_copy());
+        assert_eq!(chunks[1].data, payload);
+
+        // A corrupted payload chunk is reported and not extracted
+        let mut corrupted = stego.clone();
+        let offset = chunks[1].offset + 8;
+        corrupted[offset + payload.len() - 1] ^= 1;
+        assert!(!read_chunks(&corrupted).unwrap()[3].crc_valid);
+        assert!(extract_from_chunks(&corrupted).is_err());
+    }
+
+    /// Rebuilds a PNG with extra ancillary chunks and the image data split into small chunks
+    fn png_with_metadata(bytes: &[u8]) -> Vec<u8> {
+        let chunks = read_chunks(bytes).unwrap();
+        let mut output = PNG_SIGNATURE.to_vec();
+        for chunk in &chunks {
+            match &chunk.kind {
+                b"IHDR" => {
+                    write_chunk(&mut output, chunk.kind, &chunk.data);
+                    write_chunk(&mut output, *b"gAMA", &45455u32.to_be_bytes());
+                    write_chunk(&mut output, *b"pHYs", &[0, 0, 11, 19, 0, 0, 11, 19, 1]);
+                }
+                b"IDAT" => {
+                    for data in chunk.data.chunks(32) {
+                        write_chunk(&mut output, *b"IDAT", data);
+                    }
+                }
+                b"IEND" => {
+                    write_chunk(&mut output, *b"tIME", &[7, 234, 10, 16, 12, 0, 0]);
+                    write_chunk(&mut output, *b"eXIf", b"MM\0*\0\0\0\x08\0\0");
+                    write_chunk(&mut output, chunk.kind, &chunk.data);
+                }
+                _ => write_chunk(&mut output, chunk.kind, &chunk.data),
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_saved_png_keeps_ancillary_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cover = png_with_metadata(&png_with_text(23, 17));
+        let cover_path = dir.path().join("cover.png");
+        std::fs::write(&cover_path, &cover).unwrap();
+        let kinds = |bytes: &[u8]| -> Vec<String> {
+            read_chunks(bytes)
+                .unwrap()
+                .iter()
+                .map(PngChunk::name)
+                .collect()
+        };
+
+        let (mut carrier, metadata) =
+            PngCarrier::open_with_metadata(&cover_path.to_string_lossy()).unwrap();
+        let names: Vec<String> = metadata.chunks().map(PngChunk::name).collect();
+        assert_eq!(names, ["gAMA", "pHYs", "tEXt", "tIME", "eXIf"]);
+        let plane: Vec<u8> = carrier.plane().iter().map(|value| value ^ 1).collect();
+        carrier.set_plane(&plane);
+
+        // Same chunks in the same places, with the image data split the same way
+        let stego_path = dir.path().join("stego.png");
+        carrier
+            .save(&stego_path, &metadata, &PngEncoding::default())
+            .unwrap();
+        let stego = std::fs::read(&stego_path).unwrap();
+        assert_eq!(kinds(&stego)[..5], kinds(&cover)[..5]);
+        assert_eq!(
+            kinds(&stego)[kinds(&stego).len() - 3..],
+            ["tIME", "eXIf", "IEND"]
+        );
+        assert!(read_chunks(&stego).unwrap().iter().all(|c| c.crc_valid));
+        let idat_sizes = |bytes: &[u8]| -> Vec<usize> {
+            read_chunks(bytes)
+                .unwrap()
+                .iter()
+                .filter(|c| &c.kind == b"IDAT")
+                .map(|c| c.data.len())
+                .collect()
+        };
+        let stego_sizes = idat_sizes(&stego);
+        assert!(stego_sizes.len() > 1);
+        assert!(stego_sizes[..stego_sizes.len() - 1]
+            .iter()
+            .all(|&size| size == 32));
+        assert_eq!(
+            PngCarrier::open(&stego_path.to_string_lossy())
+                .unwrap()
+                .plane(),
+            plane
+        );
+
+        // Indexed images keep their transparency chunk in place
+        let indexed = IndexedPng::from_bytes(&indexed_png(9, 7, BitDepth::Four))
+            .unwrap()
+            .unwrap();
+        let indexed_cover = png_with_metadata(&indexed.to_bytes().unwrap());
+        let indexed_metadata = PngMetadata::from_bytes(&indexed_cover).unwrap();
+        let saved = indexed
+            .encode(&indexed_metadata, &PngEncoding::default())
+            .unwrap();
+        assert_eq!(kinds(&saved), kinds(&indexed_cover));
+
+        // A colour key stays a tRNS chunk, and is dropped if the format changes
+        let mut rgb_cover = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut rgb_cover, 4, 4);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_trns(vec![0, 0, 0, 0, 0, 0]);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[9; 48]).unwrap();
+        }
+        let rgb_path = dir.path().join("rgb.png");
+        std::fs::write(&rgb_path, &rgb_cover).unwrap();
+        let (carrier, metadata) =
+            PngCarrier::open_with_metadata(&rgb_path.to_string_lossy()).unwrap();
+        assert!(matches!(
+            &carrier,
+            PngCarrier::Samples(DynamicImage::ImageRgb8(_))
+        ));
+        carrier
+            .save(&rgb_path, &metadata, &PngEncoding::default())
+            .unwrap();
+        assert!(kinds(&std::fs::read(&rgb_path).unwrap()).contains(&"tRNS".to_string()));
+        let rgba = DynamicImage::ImageRgba8(RgbaImage::new(4, 4));
+        let saved = encode_samples(&rgba, &metadata, &PngEncoding::default()).unwrap();
+        assert!(!kinds(&saved).contains(&"tRNS".to_string()));
+    }
+
+    #[test]
+    fn test_png_encoding_options() {
+        let images = [
+            DynamicImage::ImageRgb8(image::RgbImage::from_fn(13, 9, |x, y| {
+                image::Rgb([(x * 19) as u8, (y * 23) as u8, (x * y) as u8])
+            })),
+            DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(13, 9, |x, y| {
+                image::LumaA([(x * 19) as u8, (y * 23) as u8])
+            })),
+            DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(13, 9, |x, y| {
+                image::Rgba([x as u16 * 5000, y as u16 * 7000, 300, 65535 - x as u16])
+            })),
+        ];
+        let filters = [
+            PngFilter::None,
+            PngFilter::Sub,
+            PngFilter::Up,
+            PngFilter::Average,
+            PngFilter::Paeth,
+            PngFilter::Adaptive,
+        ];
+        for image in &images {
+            for (level, filter) in (0..=9).zip(filters.iter().cycle()) {
+                let encoding = PngEncoding {
+                    zlib_level: Some(level),
+                    filter: Some(*filter),
+                };
+                let bytes = encode_samples(image, &PngMetadata::default(), &encoding).unwrap();
+                let decoded = image::load_from_memory(&bytes).unwrap();
+                assert_eq!(decoded.color(), image.color());
+                assert_eq!(decoded.as_bytes(), image.as_bytes());
+            }
+        }
+
+        // Higher levels compress better
+        let size = |level| {
+            let encoding = PngEncoding {
+                zlib_level: Some(level),
+                filter: None,
+            };
+            encode_samples(&images[0], &PngMetadata::default(), &encoding)
+                .unwrap()
+                .len()
+        };
+        assert!(size(9) < size(0));
+
+        let mut parameters = std::collections::HashMap::new();
+        parameters.insert("zlib_level".to_string(), "3".to_string());
+        parameters.insert("filter".to_string(), "paeth".to_string());
+        assert_eq!(
+            PngEncoding::from_parameters(Some(&parameters)).unwrap(),
+            PngEncoding {
+                zlib_level: Some(3),
+                filter: Some(PngFilter::Paeth)
+            }
+        );
+        for (name, value) in [("zlib_level", "10"), ("filter", "median")] {
+            let mut parameters = std::collections::HashMap::new();
+            parameters.insert(name.to_string(), value.to_string());
+            assert!(PngEncoding::from_parameters(Some(&parameters)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_interlaced_png_output() {
+        let interlaced = PngMetadata {
+            interlaced: true,
+            ..PngMetadata::default()
+        };
+        let interlace_byte = |bytes: &[u8]| read_chunks(bytes).unwrap()[0].data[12];
+
+        // Sizes smaller than the 8x8 Adam7 grid leave some passes empty
+        for (width, height) in [(1, 1), (3, 2), (13, 9)] {
+            let images = [
+                DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+                    image::Rgb([(x * 19) as u8, (y * 23) as u8, (x * y) as u8])
+                })),
+                DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(width, height, |x, y| {
+                    image::Rgba([x as u16 * 5000, y as u16 * 7000, 300, 65535 - x as u16])
+                })),
+            ];
+            for image in &images {
+                let bytes = encode_samples(image, &interlaced, &PngEncoding::default()).unwrap();
+                assert_eq!(interlace_byte(&bytes), 1);
+                let decoded = image::load_from_memory(&bytes).unwrap();
+                assert_eq!(decoded.as_bytes(), image.as_bytes());
+            }
+
+            for bit_depth in [BitDepth::One, BitDepth::Two, BitDepth::Four] {
+                let indexed = IndexedPng::from_bytes(&indexed_png(width, height, bit_depth))
+                    .unwrap()
+                    .unwrap();
+                let bytes = indexed
+                    .encode(&interlaced, &PngEncoding::default())
+                    .unwrap();
+                assert_eq!(interlace_byte(&bytes), 1);
+                let decoded = IndexedPng::from_bytes(&bytes).unwrap().unwrap();
+                assert_eq!(decoded.indices(), indexed.indices());
+            }
+        }
+
+        // Interlaced covers are saved interlaced, non-interlaced ones are not
+        let dir = tempfile::tempdir().unwrap();
+        let cover = encode_samples(
+            &DynamicImage::ImageLuma8(GrayImage::from_fn(21, 11, |x, y| {
+                image::Luma([(x * 11 + y * 3) as u8])
+            })),
+            &interlaced,
+            &PngEncoding::default(),
+        )
+        .unwrap();
+        let path = dir.path().join("cover.png");
+        std::fs::write(&path, &cover).unwrap();
+        let (carrier, metadata) = PngCarrier::open_with_metadata(path.to_str().unwrap()).unwrap();
+        assert!(metadata.interlaced);
+        let output = dir.path().join("output.png");
+        carrier
+            .save(&output, &metadata, &PngEncoding::default())
+            .unwrap();
+        let saved = std::fs::read(&output).unwrap();
+        assert_eq!(interlace_byte(&saved), 1);
+        assert_eq!(
+            image::load_from_memory(&saved).unwrap().as_bytes(),
+            image::load_from_memory(&cover).unwrap().as_bytes()
+        );
+        assert!(
+            !PngMetadata::from_bytes(&png_with_text(4, 4))
+                .unwrap()
+                .interlaced
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! QIM Module
+//!
+//! This module implements robust JPEG embedding with quantization index
+//! modulation (Chen and Wornell, 2001). Each bit moves a low or mid-frequency
+//! luminance DCT coefficient onto one of two interleaved lattices whose step is
+//! a multiple of the quantization step a re-encoder would use at the target
+//! quality. Recompressing at that quality or above moves a coefficient by at
+//! most half a step, which leaves it nearer its own lattice than the other.
+//!
+//! Every bit is repeated across the image and read back by soft majority
+//! vote, so clipped or heavily textured blocks do not decide a bit on their
+//! own. The summed votes are kept as bit reliabilities for soft-decision
+//! error correction. The header is embedded first, at the step for [`HEADER_QUALITY`], and
+//! records the target quality in its bit depth field; the body fills the
+//! remaining slots, in key-seeded random order if requested.
+//!
+//! Only blocks that lie entirely inside the image are used, so the block grid
+//! is the same for any encoder that re-compresses the decoded pixels.
+
+use crate::container::{Container, PayloadHeader, FLAG_RANDOM_ORDER, HEADER_LENGTH};
+use crate::jpeg::{JpegImage, BLOCK_SIZE};
+use crate::utils::{embedding_positions, locate_header, EmbeddingOrder};
+use crate::{Error, Result};
+
+/// Target quality used when none is given
+pub const DEFAULT_TARGET_QUALITY: u8 = 75;
+
+/// Lowest supported target quality
+pub const MIN_TARGET_QUALITY: u8 = 50;
+
+/// Highest supported target quality
+pub const MAX_TARGET_QUALITY: u8 = 95;
+
+/// Quality whose steps carry the header, robust to every supported target
+pub const HEADER_QUALITY: u8 = MIN_TARGET_QUALITY;
+
+/// Number of times each header bit is repeated
+const HEADER_REPETITIONS: usize = 5;
+
+/// Lattice step as a multiple of the target quantization step
+const STEP_SCALE: f64 = 3.0;
+
+/// Zig-zag positions of the carrier coefficients with their entries in the
+/// luminance table of ITU-T T.81 Annex K
+const CARRIERS: [(usize, u16); 9] = [
+    (1, 11),
+    (2, 12),
+    (3, 14),
+    (4, 12),
+    (5, 10),
+    (6, 16),
+    (7, 14),
+    (8, 13),
+    (9, 14),
+];
+
+/// Quantization step an IJG-style encoder uses for a table entry at `quality`
+fn quantization_step(base: u16, quality: u8) -> f64 {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    ((base as u32 * scale + 50) / 100).clamp(1, 255) as f64
+}
+
+/// Moves a coefficient to the nearest point of the lattice for `bit`
+pub fn embed_bit(value: f64, step: f64, bit: u8) -> f64 {
+    let offset = bit as f64 * step / 2.0;
+    ((value - offset) / step).round() * step + offset
+}
+
+/// Returns how strongly a coefficient votes for a zero bit, from -0.5 (a one
+/// lattice point) to 0.5 (a zero lattice point)
+pub fn bit_vote(value: f64, step: f64) -> f64 {
+    let phase = (value / step).rem_euclid(1.0);
+    (phase - 0.5).abs() - phase.min(1.0 - phase)
+}
+
+/// A carrier coefficient: luma block index and position within the block
+#[derive(Clone, Copy)]
+struct Slot {
+    block: usize,
+    carrier: usize,
+}
+
+/// Luma blocks of the image that no re-encoder pads, in raster order
+fn full_blocks(image: &JpegImage) -> Result<Vec<(usize, usize)>> {
+    let luma = image
+        .components
+        .first()
+        .ok_or_else(|| Error::InvalidInput("JPEG has no components".into()))?;
+    let subsampled = image.components.iter().any(|component| {
+        component.horizontal_sampling > luma.horizontal_sampling
+            || component.vertical_sampling > luma.vertical_sampling
+    });
+    if subsampled {
+        return Err(Error::InvalidInput(
+            "Robust JPEG embedding needs a full resolution luminance component".into(),
+        ));
+    }
+
+    let rows = image.height as usize / 8;
+    let columns = image.width as usize / 8;
+    Ok((0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (row, column)))
+        .collect())
+}
+
+/// Reads the carrier coefficients as dequantized values
+struct Carriers {
+    blocks: Vec<(usize, usize)>,
+    quantization: [f64; BLOCK_SIZE],
+}
+
+impl Carriers {
+    fn new(image: &JpegImage) -> Result<Self> {
+        let blocks = full_blocks(image)?;
+        let table = image
+            .quantization_table(&image.components[0])
+            .ok_or_else(|| Error::InvalidData("Missing JPEG quantization table".into()))?;
+        let mut quantization = [0.0; BLOCK_SIZE];
+        for (step, &entry) in quantization.iter_mut().zip(table.iter()) {
+            *step = entry.max(1) as f64;
+        }
+        Ok(Self {
+            blocks,
+            quantization,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len() * CARRIERS.len()
+    }
+
+    fn slot(&self, index: usize) -> Slot {
+        Slot {
+            block: index / CARRIERS.len(),
+            carrier: index % CARRIERS.len(),
+        }
+    }
+
+    fn value(&self, image: &JpegImage, slot: Slot) -> f64 {
+        let (row, column) = self.blocks[slot.block];
+        let position = CARRIERS[slot.carrier].0;
+        image.components[0].block(row, column)[position] as f64 * self.quantization[position]
+    }
+
+    fn embed(&self, image: &mut JpegImage, slot: Slot, step: f64, bit: u8) {
+        let (row, column) = self.blocks[slot.block];
+        let position = CARRIERS[slot.carrier].0;
+        let quantization = self.quantization[position];
+        let block = image.components[0].block_mut(row, column);
+        let target = embed_bit(block[position] as f64 * quantization, step, bit);
+        block[position] = (target / quantization)
+            .round()
+            .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+    }
+
+    /// Checks that the carrier's own quantization keeps coefficients close
+    /// enough to the lattice points to be read back
+    fn check_precision(&self, quality: u8) -> Result<()> {
+        for &(position, base) in &CARRIERS {
+            if self.quantization[position] > step(base, quality) / 4.0 {
+                return Err(Error::InvalidInput(format!(
+                    "JPEG quantization is too coarse for target quality {}; \
+                     re-encode the carrier at a higher quality",
+                    quality
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lattice step for a carrier at the given target quality
+fn step(base: u16, quality: u8) -> f64 {
+    STEP_SCALE * quantization_step(base, quality)
+}
+
+/// Splits data into bits, most significant bit first
+fn to_bits(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+        .collect()
+}
+
+/// Embeds bits in the slots listed by `order`, repeating them to fill it
+fn embed_bits(
+    image: &mut JpegImage,
+    carriers: &Carriers,
+    order: &[usize],
+    bits: &[u8],
+    quality: u8,
+) {
+    for (i, &index) in order
+        .iter()
+        .enumerate()
+        .take(repetitions(order.len(), bits.len()) * bits.len())
+    {
+        let slot = carriers.slot(index);
+        let step = step(CARRIERS[slot.carrier].1, quality);
+        carriers.embed(image, slot, step, bits[i % bits.len()]);
+    }
+}
+
+/// Sums the votes of every copy of `length` bytes embedded by [`embed_bits`]
+fn extract_votes(
+    image: &JpegImage,
+    carriers: &Carriers,
+    order: &[usize],
+    length: usize,
+    quality: u8,
+) -> Vec<f64> {
+    let bit_count = length * 8;
+    let mut votes = vec![0.0; bit_count];
+    for (i, &index) in order
+        .iter()
+        .enumerate()
+        .take(repetitions(order.len(), bit_count) * bit_count)
+    {
+        let slot = carriers.slot(index);
+        let step = step(CARRIERS[slot.carrier].1, quality);
+        votes[i % bit_count] += bit_vote(carriers.value(image, slot), step);
+    }
+    votes
+}
+
+/// Decides the bytes that summed votes stand for
+fn votes_to_bytes(votes: &[f64]) -> Vec<u8> {
+    votes
+        .chunks(8)
+        .map(|byte| {
+            byte.iter()
+                .fold(0u8, |value, &vote| (value << 1) | (vote < 0.0) as u8)
+        })
+        .collect()
+}
+
+/// Number of whole copies of `bits` that fit in `slots`
+fn repetitions(slots: usize, bits: usize) -> usize {
+    slots.checked_div(bits).unwrap_or(0)
+}
+
+/// Embeds a payload container robustly in a JPEG image
+///
+/// The payload survives re-encoding at `quality` or above. The carrier itself
+/// must be quantized finely enough to hold the lattice points, which any file
+/// saved at a quality well above the target is.
+pub fn embed_container(
+    image: &mut JpegImage,
+    container: &Container,
+    quality: u8,
+    order: EmbeddingOrder,
+    key: Option<&str>,
+) -> Result<()> {
+    if !(MIN_TARGET_QUALITY..=MAX_TARGET_QUALITY).contains(&quality) {
+        return Err(Error::InvalidInput(format!(
+            "Target quality must be between {} and {}, got {}",
+            MIN_TARGET_QUALITY, MAX_TARGET_QUALITY, quality
+        )));
+    }
+    let carriers = Carriers::new(image)?;
+    carriers.check_precision(quality)?;
+
+    let header_slots = HEADER_LENGTH * 8 * HEADER_REPETITIONS;
+    let body_bits = container.body.len() * 8;
+    if carriers.len() < header_slots + body_bits {
+        return Err(Error::InvalidInput(format!(
+            "Data too large for robust JPEG embedding: {} bytes do not fit in {} carrier coefficients",
+            HEADER_LENGTH + container.body.len(),
+            carriers.len()
+        )));
+    }
+
+    let mut header = container.header.clone();
+    header.bit_depth = quality;
+    if order == EmbeddingOrder::Random {
+        header.flags |= FLAG_RANDOM_ORDER;
+    }
+
+    // The header takes the first slots of the order, the body the rest
+    let positions = embedding_positions(order, key, carriers.len())?;
+    let (header_order, body_order) = positions.split_at(header_slots);
+    embed_bits(
+        image,
+        &carriers,
+        header_order,
+        &to_bits(&header.to_bytes()),
+        HEADER_QUALITY,
+    );
+    embed_bits(
+        image,
+        &carriers,
+        body_order,
+        &to_bits(&container.body),
+        quality,
+    );
+    Ok(())
+}
+
+/// Extracts a payload container embedded by [`embed_container`]
+pub fn extract_container(image: &JpegImage, key: Option<&str>) -> Result<Container> {
+    let carriers = Carriers::new(image)?;
+    let header_slots = HEADER_LENGTH * 8 * HEADER_REPETITIONS;
+    if carriers.len() < header_slots {
+        return Err(Error::InvalidData(
+            "JPEG is too small to hold a robust payload".into(),
+        ));
+    }
+
+    // The header leads either the plain or, under random order, the keyed
+    // slot order
+    let (header, positions) = locate_header(carriers.len(), key, |positions| {
+        let header_votes = extract_votes(
+            image,
+            &carriers,
+            &positions[..header_slots],
+            HEADER_LENGTH,
+            HEADER_QUALITY,
+        );
+        PayloadHeader::from_bytes(&votes_to_bytes(&header_votes))
+    })?;
+
+    let quality = header.bit_depth;
+    let body_length = header.payload_length as usize;
+    if !(MIN_TARGET_QUALITY..=MAX_TARGET_QUALITY).contains(&quality)
+        || body_length * 8 > carriers.len() - header_slots
+    {
+        return Err(Error::InvalidData(
+            "Robust JPEG payload header is inconsistent with the image".into(),
+        ));
+    }
+
+    let votes = extract_votes(
+        image,
+        &carriers,
+        &positions[header_slots..],
+        body_length,
+        quality,
+    );
+
+    // Votes lean positive for a zero; reliabilities are positive for a one
+    Ok(Container {
+        header,
+        body: votes_to_bytes(&votes),
+        soft_bits: Some(votes.iter().map(|&vote| -vote as f32).collect()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_qim_tolerates_half_a_quantization_step() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        for quality in [MIN_TARGET_QUALITY, 75, MAX_TARGET_QUALITY] {
+            for &(_, base) in &CARRIERS {
+                let quantization = quantization_step(base, quality);
+                let step = step(base, quality);
+                for _ in 0..200 {
+                    let value = rng.gen_range(-500.0..500.0);
+                    let bit = rng.gen_range(0..2u8);
+                    let marked = embed_bit(value, step, bit);
+                    assert!((marked - value).abs() <= step / 2.0 + 1e-9);
+
+                    // Re-quantizing at the target quality keeps the bit
+                    let requantized = (marked / quantization).round() * quantization;
+                    let vote = bit_vote(requantized, step);
+                    assert_eq!(vote < 0.0, bit == 1, "quality {}", quality);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_qim_quantization_steps() {
+        // Scaling follows the IJG convention: quality 50 uses the table as is
+        assert_eq!(quantization_step(16, 50), 16.0);
+        assert_eq!(quantization_step(16, 75), 8.0);
+        assert_eq!(quantization_step(16, 100), 1.0);
+        assert_eq!(quantization_step(16, 10), 80.0);
+        assert!(bit_vote(0.0, 30.0) > 0.49);
+        assert!(bit_vote(15.0, 30.0) < -0.49);
+        assert!(bit_vote(-45.0, 30.0) < -0.49);
+    }
+}
@@ -2,8 +2,14 @@
 //!
 //! This module provides utility functions for the application.
 
+use crate::encryption::CryptoConfig;
 use crate::{Error, Result};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
 use std::fs;
+use std::io::Read;
 use std::path::Path;
 
 /// Checks if a file exists and has the correct extension
@@ -45,6 +51,302 @@ pub fn calculate_image_capacity(width: u32, height: u32) -> usize {
     available_bits / 8 // Convert to bytes
 }
 
+/// Order in which carrier positions are visited during embedding and extraction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmbeddingOrder {
+    /// Positions are used one after another from the start of the carrier
+    Sequential,
+    /// Positions are shuffled by a permutation seeded from a stego key
+    Random,
+}
+
+impl EmbeddingOrder {
+    /// Reads the `order` parameter (`sequential` or `random`), defaulting to sequential
+    pub fn from_parameters(
+        parameters: Option<&std::collections::HashMap<String, String>>,
+    ) -> Result<Self> {
+        match parameters.and_then(|p| p.get("order")).map(|v| v.as_str()) {
+            None | Some("sequential") => Ok(EmbeddingOrder::Sequential),
+            Some("random") => Ok(EmbeddingOrder::Random),
+            Some(other) => Err(Error::InvalidInput(format!(
+                "Unknown embedding order '{}', expected 'sequential' or 'random'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Returns the stego key that seeds the embedding order
+///
+/// This is the `key` parameter, or the encryption password when no key is
+/// given. An empty key counts as none, so random order is rejected rather
+/// than shuffled with an empty key.
+pub fn stego_key<'a>(
+    parameters: Option<&'a std::collections::HashMap<String, String>>,
+    encryption: Option<&'a CryptoConfig>,
+) -> Option<&'a str> {
+    parameters
+        .and_then(|p| p.get("key"))
+        .or(encryption.map(|c| &c.password))
+        .map(|k| k.as_str())
+        .filter(|k| !k.is_empty())
+}
+
+/// Returns the visiting order for `len` carrier positions
+///
+/// For [`EmbeddingOrder::Random`] the stego key seeds a ChaCha20 generator that
+/// shuffles the positions, so the same key always yields the same permutation
+/// and the payload can only be located by someone holding the key.
+pub fn embedding_positions(
+    order: EmbeddingOrder,
+    key: Option<&str>,
+    len: usize,
+) -> Result<Vec<usize>> {
+    let mut positions: Vec<usize> = (0..len).collect();
+
+    if order == EmbeddingOrder::Random {
+        let key = key.ok_or_else(|| {
+            Error::InvalidInput("Random embedding order requires a stego key or password".into())
+        })?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"nhale-embedding-order");
+        hasher.update(key.as_bytes());
+        let seed: [u8; 32] = hasher.finalize().into();
+
+        positions.shuffle(&mut ChaCha20Rng::from_seed(seed));
+    }
+
+    Ok(positions)
+}
+
+/// Finds the payload header of a carrier with `len` positions
+///
+/// `read` parses a header from positions in payload order. Under random order
+/// the header is scattered with the rest of the payload, so it is looked for
+/// at the start of the carrier and then, given a key, at the start of the
+/// keyed permutation. Returns it with the positions it was found in, or the
+/// error from the start of the carrier.
+pub fn locate_header<T>(
+    len: usize,
+    key: Option<&str>,
+    read: impl Fn(&[usize]) -> Result<T>,
+) -> Result<(T, Vec<usize>)> {
+    let sequential = embedding_positions(EmbeddingOrder::Sequential, None, len)?;
+    let error = match read(&sequential) {
+        Ok(header) => return Ok((header, sequential)),
+        Err(error) => error,
+    };
+    match key {
+        Some(_) => {
+            let random = embedding_positions(EmbeddingOrder::Random, key, len)?;
+            read(&random)
+                .map(|header| (header, random))
+                .map_err(|_| error)
+        }
+        None => Err(error),
+    }
+}
+
+/// Checks that `bit_depth` low bits per carrier sample are supported
+///
+/// `error` builds the error, so embedders can report invalid input and
+/// extractors a header with invalid data.
+pub fn validate_bit_depth(bit_depth: u8, error: fn(String) -> Error) -> Result<()> {
+    if !(1..=4).contains(&bit_depth) {
+        return Err(error(format!(
+            "Bit depth must be between 1 and 4, got {}",
+            bit_depth
+        )));
+    }
+    Ok(())
+}
+
+/// Carrier sample whose low bits hold payload bits
+pub trait LowBits: Copy {
+    /// Returns bit `position` of the sample
+    fn bit(self, position: usize) -> u8;
+
+    /// Returns the sample with bit `position` set to `value`
+    fn with_bit(self, position: usize, value: u8) -> Self;
+}
+
+impl LowBits for u8 {
+    fn bit(self, position: usize) -> u8 {
+        (self >> position) & 1
+    }
+
+    fn with_bit(self, position: usize, value: u8) -> Self {
+        (self & !(1 << position)) | (value << position)
+    }
+}
+
+impl LowBits for i32 {
+    fn bit(self, position: usize) -> u8 {
+        ((self >> position) & 1) as u8
+    }
+
+    fn with_bit(self, position: usize, value: u8) -> Self {
+        (self & !(1 << position)) | ((value as i32) << position)
+    }
+}
+
+/// Writes `data` into the low `bit_depth` bits of the samples at `offsets`
+///
+/// The bit stream is packed most significant bit first: sample `n` of the
+/// stream, `samples[offsets[n]]`, holds the next `bit_depth` bits with the
+/// earliest in its highest carrying bit.
+pub fn embed_bits<T: LowBits>(samples: &mut [T], offsets: &[usize], data: &[u8], bit_depth: u8) {
+    let bit_depth = bit_depth as usize;
+
+    for (i, &byte) in data.iter().enumerate() {
+        for bit in 0..8 {
+            let stream_bit = i * 8 + bit;
+            let sample = &mut samples[offsets[stream_bit / bit_depth]];
+            let position = bit_depth - 1 - stream_bit % bit_depth;
+
+            *sample = sample.with_bit(position, (byte >> (7 - bit)) & 1);
+        }
+    }
+}
+
+/// Reads `data.len()` bytes written by [`embed_bits`] from the samples at `offsets`
+pub fn extract_bits<T: LowBits>(samples: &[T], offsets: &[usize], data: &mut [u8], bit_depth: u8) {
+    let bit_depth = bit_depth as usize;
+
+    for (i, byte) in data.iter_mut().enumerate() {
+        let mut new_byte = 0u8;
+
+        for bit in 0..8 {
+            let stream_bit = i * 8 + bit;
+            let sample = samples[offsets[stream_bit / bit_depth]];
+            let position = bit_depth - 1 - stream_bit % bit_depth;
+
+            new_byte |= sample.bit(position) << (7 - bit);
+        }
+
+        *byte = new_byte;
+    }
+}
+
+/// How a carrier value is changed to take on new low bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LsbChange {
+    /// The low bits are overwritten (LSB replacement)
+    Replace,
+    /// The value moves to the nearest value with the wanted low bits, up or
+    /// down at random when both are as close (LSB matching, or ±1 embedding)
+    Match,
+}
+
+impl LsbChange {
+    /// Reads the `lsb` parameter (`replace` or `matching`), defaulting to replacement
+    pub fn from_parameters(
+        parameters: Option<&std::collections::HashMap<String, String>>,
+    ) -> Result<Self> {
+        match parameters.and_then(|p| p.get("lsb")).map(|v| v.as_str()) {
+            None | Some("replace") => Ok(LsbChange::Replace),
+            Some("matching") => Ok(LsbChange::Match),
+            Some(other) => Err(Error::InvalidInput(format!(
+                "Unknown LSB change '{}', expected 'replace' or 'matching'",
+                other
+            ))),
+        }
+    }
+
+    /// Returns `value` with its low `bit_depth` bits set to `bits`
+    ///
+    /// Replacement only ever raises even values and lowers odd ones, which
+    /// evens out pairs of histogram bins in a way chi-square and RS analysis
+    /// detect. Matching moves either way and leaves no such pairing.
+    pub fn apply(self, value: u8, bits: u8, bit_depth: u8, rng: &mut impl Rng) -> u8 {
+        let step = 1i16 << bit_depth;
+        let replaced = (value & !((step - 1) as u8)) | bits;
+        if self == LsbChange::Replace || replaced == value {
+            return replaced;
+        }
+
+        let value = value as i16;
+        let candidates: Vec<i16> = [-step, 0, step]
+            .iter()
+            .map(|offset| replaced as i16 + offset)
+            .filter(|candidate| (0..=255).contains(candidate))
+            .collect();
+        let nearest = candidates
+            .iter()
+            .map(|candidate| (candidate - value).abs())
+            .min()
+            .unwrap_or(0);
+        let closest: Vec<i16> = candidates
+            .into_iter()
+            .filter(|candidate| (candidate - value).abs() == nearest)
+            .collect();
+        closest
+            .choose(rng)
+            .map_or(replaced, |&candidate| candidate as u8)
+    }
+}
+
+/// Pairs the colours of a palette by luminance, in the style of EzStego
+///
+/// The colours are sorted by luminance and paired off in that order; an index
+/// carries one bit as the parity of its position in the sorted list, and
+/// writing a bit moves it at most to the other colour of its pair. Palettes
+/// are never changed, so the same order is found again on extraction.
+pub struct PaletteOrder {
+    /// Palette indices sorted by luminance, with an unpaired last colour dropped
+    sorted: Vec<u8>,
+    /// Position of every palette index in `sorted`, if it has one
+    ranks: [Option<usize>; 256],
+}
+
+impl PaletteOrder {
+    /// Sorts an RGB palette by luminance, leaving out the indices `skip` selects
+    pub fn new(palette: &[u8], skip: impl Fn(u8) -> bool) -> Self {
+        let luminance = |index: u8| {
+            let rgb = &palette[index as usize * 3..index as usize * 3 + 3];
+            299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32
+        };
+
+        let mut sorted: Vec<u8> = (0..(palette.len() / 3).min(256))
+            .map(|index| index as u8)
+            .filter(|&index| !skip(index))
+            .collect();
+        sorted.sort_by_key(|&index| (luminance(index), index));
+        sorted.truncate(sorted.len() & !1);
+
+        let mut ranks = [None; 256];
+        for (rank, &index) in sorted.iter().enumerate() {
+            ranks[index as usize] = Some(rank);
+        }
+        Self { sorted, ranks }
+    }
+
+    /// Position of a palette index in luminance order, if the index can carry a bit
+    pub fn rank(&self, index: u8) -> Option<usize> {
+        self.ranks[index as usize]
+    }
+
+    /// The palette index at a position in luminance order
+    pub fn index(&self, rank: usize) -> u8 {
+        self.sorted[rank]
+    }
+
+    /// The index a pixel must take to carry `bit`
+    pub fn with_bit(&self, index: u8, bit: u8) -> u8 {
+        match self.rank(index) {
+            Some(rank) if rank & 1 != bit as usize => self.sorted[rank ^ 1],
+            _ => index,
+        }
+    }
+
+    /// The lower index of the pair an index belongs to, which embedding never changes
+    pub fn pair(&self, index: u8) -> u8 {
+        self.rank(index)
+            .map_or(index, |rank| self.sorted[rank & !1])
+    }
+}
+
 /// Maximum allowed data size (100MB)
 const MAX_DATA_SIZE: usize = 100 * 1024 * 1024;
 
@@ -170,6 +472,35 @@ pub fn detect_file_format_from_bytes(bytes: &[u8]) -> FileFormat {
     }
 }
 
+/// Opens an image, identifying its format from the contents rather than the extension
+pub fn open_image(path: impl AsRef<Path>) -> Result<image::DynamicImage> {
+    image::io::Reader::open(path.as_ref())
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| Error::Io(e.to_string()))?
+        .decode()
+        .map_err(|e| Error::InvalidInput(format!("Failed to open image: {}", e)))
+}
+
+/// Detects the file format of an existing file
+///
+/// The leading bytes are checked for a known signature first; the extension is
+/// only used when the contents are not recognised.
+pub fn sniff_file_format(path: &Path) -> Result<FileFormat> {
+    let mut file = fs::File::open(path)
+        .map_err(|e| Error::Io(format!("Failed to open {}: {}", path.display(), e)))?;
+
+    let mut signature = Vec::with_capacity(16);
+    file.by_ref()
+        .take(16)
+        .read_to_end(&mut signature)
+        .map_err(|e| Error::Io(e.to_string()))?;
+
+    match detect_file_format_from_bytes(&signature) {
+        FileFormat::Unknown => Ok(detect_file_format(path)),
+        format => Ok(format),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,6 +554,166 @@ mod tests {
         assert!(validate_data(&large_data).is_err());
     }
 
+    #[test]
+    fn test_embedding_positions() {
+        let sequential = embedding_positions(EmbeddingOrder::Sequential, None, 100).unwrap();
+        assert_eq!(sequential, (0..100).collect::<Vec<_>>());
+
+        let first = embedding_positions(EmbeddingOrder::Random, Some("key"), 100).unwrap();
+        let second = embedding_positions(EmbeddingOrder::Random, Some("key"), 100).unwrap();
+        let other = embedding_positions(EmbeddingOrder::Random, Some("other"), 100).unwrap();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_ne!(first, sequential);
+
+        let mut sorted = first.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, sequential);
+
+        assert!(embedding_positions(EmbeddingOrder::Random, None, 100).is_err());
+    }
+
+    #[test]
+    fn test_low_bit_packing() {
+        // Two bits per sample, most significant first, at scattered offsets
+        let mut bytes = vec![0b1111_0000u8; 8];
+        let offsets = [7, 0, 5, 2];
+        embed_bits(&mut bytes, &offsets, &[0b10_01_11_00], 2);
+        assert_eq!(
+            bytes,
+            [
+                0b1111_0001,
+                0xF0,
+                0b1111_0000,
+                0xF0,
+                0xF0,
+                0b1111_0011,
+                0xF0,
+                0b1111_0010
+            ]
+        );
+        let mut data = [0u8];
+        extract_bits(&bytes, &offsets, &mut data, 2);
+        assert_eq!(data, [0b10_01_11_00]);
+
+        // Negative samples keep their sign
+        let mut samples = vec![-2i32; 16];
+        let offsets: Vec<usize> = (0..16).collect();
+        embed_bits(&mut samples, &offsets, b"Hi", 1);
+        assert!(samples.iter().all(|&sample| sample == -2 || sample == -1));
+        let mut data = [0u8; 2];
+        extract_bits(&samples, &offsets, &mut data, 1);
+        assert_eq!(&data, b"Hi");
+
+        assert!(validate_bit_depth(4, Error::InvalidInput).is_ok());
+        assert!(matches!(
+            validate_bit_depth(5, Error::InvalidData),
+            Err(Error::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_stego_key() {
+        let encryption = CryptoConfig {
+            password: "password".to_string(),
+            ..Default::default()
+        };
+        let mut parameters = std::collections::HashMap::new();
+        assert_eq!(stego_key(None, None), None);
+        assert_eq!(stego_key(None, Some(&encryption)), Some("password"));
+
+        // The key parameter wins over the password, and empty keys are none
+        parameters.insert("key".to_string(), "stego key".to_string());
+        assert_eq!(
+            stego_key(Some(&parameters), Some(&encryption)),
+            Some("stego key")
+        );
+        parameters.insert("key".to_string(), String::new());
+        assert_eq!(stego_key(Some(&parameters), None), None);
+        assert_eq!(stego_key(None, Some(&CryptoConfig::default())), None);
+    }
+
+    #[test]
+    fn test_locate_header() {
+        // A "header" is found where its first position is 42
+        let read = |positions: &[usize]| match positions[0] {
+            42 => Ok(positions[0]),
+            _ => Err(Error::InvalidData("No header".into())),
+        };
+        let key = (0..)
+            .map(|i| format!("key {}", i))
+            .find(|key| {
+                embedding_positions(EmbeddingOrder::Random, Some(key), 100).unwrap()[0] == 42
+            })
+            .unwrap();
+
+        let (header, positions) = locate_header(100, Some(&key), read).unwrap();
+        assert_eq!(header, 42);
+        assert_eq!(
+            positions,
+            embedding_positions(EmbeddingOrder::Random, Some(&key), 100).unwrap()
+        );
+        assert!(locate_header(100, None, read).is_err());
+
+        // The start of the carrier is tried first
+        let read = |positions: &[usize]| Ok::<_, Error>(positions[0]);
+        let (header, positions) = locate_header(100, Some(&key), read).unwrap();
+        assert_eq!(header, 0);
+        assert_eq!(positions, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_lsb_change() {
+        let mut rng = ChaCha20Rng::seed_from_u64(5);
+
+        assert_eq!(LsbChange::Replace.apply(0b1010, 1, 1, &mut rng), 0b1011);
+        assert_eq!(LsbChange::Replace.apply(0b1011, 0b10, 2, &mut rng), 0b1010);
+
+        // Matching moves by one in either direction and stays in range
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..100 {
+            seen.insert(LsbChange::Match.apply(100, 1, 1, &mut rng));
+        }
+        assert_eq!(seen, [99, 101].into_iter().collect());
+        assert_eq!(LsbChange::Match.apply(0, 1, 1, &mut rng), 1);
+        assert_eq!(LsbChange::Match.apply(255, 0, 1, &mut rng), 254);
+        assert_eq!(LsbChange::Match.apply(100, 0, 1, &mut rng), 100);
+
+        // Deeper bit planes move to the nearest value with the wanted bits
+        assert_eq!(LsbChange::Match.apply(0b0111, 0b00, 2, &mut rng), 0b1000);
+        assert_eq!(LsbChange::Match.apply(254, 0b01, 2, &mut rng), 253);
+
+        let mut parameters = std::collections::HashMap::new();
+        assert_eq!(
+            LsbChange::from_parameters(Some(&parameters)).unwrap(),
+            LsbChange::Replace
+        );
+        parameters.insert("lsb".to_string(), "matching".to_string());
+        assert_eq!(
+            LsbChange::from_parameters(Some(&parameters)).unwrap(),
+            LsbChange::Match
+        );
+        parameters.insert("lsb".to_string(), "flip".to_string());
+        assert!(LsbChange::from_parameters(Some(&parameters)).is_err());
+    }
+
+    #[test]
+    fn test_sniff_file_format() {
+        let dir = tempdir().unwrap();
+
+        // Contents win over a misleading extension
+        let path = dir.path().join("image.pdf");
+        fs::write(&path, b"\x89PNG\r\n\x1a\n0000000000").unwrap();
+        assert_eq!(sniff_file_format(&path).unwrap(), FileFormat::Png);
+
+        // Unrecognised contents fall back to the extension
+        let path = dir.path().join("audio.wav");
+        fs::write(&path, b"short").unwrap();
+        assert_eq!(sniff_file_format(&path).unwrap(), FileFormat::Wav);
+
+        assert!(sniff_file_format(&dir.path().join("missing.png")).is_err());
+    }
+
     #[test]
     fn test_check_file_exists() {
         assert!(check_file_exists("Cargo.toml").is_ok());
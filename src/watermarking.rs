@@ -62,8 +62,6 @@ pub fn verify_watermark(_image: &DynamicImage, _config: &WatermarkConfig) -> Res
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn test_watermark_flow() {
         // TODO: Add tests for watermarking once implemented